@@ -0,0 +1,462 @@
+/*
+ *     Copyright (C) 2024-2025 Christian Nagel and contributors
+ *
+ *     This file is part of escl-mock-server.
+ *
+ *     escl-mock-server is free software: you can redistribute it and/or modify it under the terms of
+ *     the GNU General Public License as published by the Free Software Foundation, either
+ *     version 3 of the License, or (at your option) any later version.
+ *
+ *     escl-mock-server is distributed in the hope that it will be useful, but WITHOUT ANY
+ *     WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ *     FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ *     You should have received a copy of the GNU General Public License along with eSCLKt.
+ *     If not, see <https://www.gnu.org/licenses/>.
+ *
+ *     SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header;
+use actix_web::middleware::Next;
+use actix_web::{web, Error, HttpRequest, HttpResponse};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use clap::ValueEnum;
+use md5::{Digest, Md5};
+use ring::digest::{digest, SHA256};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Display, Formatter};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+/// How long a Digest nonce is accepted. Older nonces are answered with `stale=true`.
+const NONCE_LIFETIME: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum AuthScheme {
+    Basic,
+    Digest,
+}
+
+impl Display for AuthScheme {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            AuthScheme::Basic => "basic",
+            AuthScheme::Digest => "digest",
+        })
+    }
+}
+
+/// Checks the credentials of requests to the eSCL endpoints and the admin page.
+pub(crate) struct Authenticator {
+    scheme: AuthScheme,
+    realm: String,
+    users: BTreeMap<String, String>,
+    opaque: String,
+    /// Digest nonces handed out, with the time they were issued.
+    nonces: Mutex<HashMap<String, Instant>>,
+}
+
+// 认证失败的原因，只记录在日志中，不返回给客户端
+enum Failure {
+    Missing,
+    Malformed,
+    WrongScheme,
+    UnknownUser(String),
+    WrongPassword(String),
+    StaleNonce(String),
+}
+
+impl Authenticator {
+    pub(crate) fn new(scheme: AuthScheme, realm: String, users: BTreeMap<String, String>) -> Self {
+        Authenticator {
+            scheme,
+            realm,
+            users,
+            opaque: Uuid::new_v4().simple().to_string(),
+            nonces: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub(crate) fn scheme(&self) -> AuthScheme {
+        self.scheme
+    }
+
+    pub(crate) fn realm(&self) -> &str {
+        &self.realm
+    }
+
+    fn check(&self, req: &HttpRequest) -> Result<(), Failure> {
+        let Some(authorization) = req.headers().get(header::AUTHORIZATION) else {
+            return Err(Failure::Missing);
+        };
+        let authorization = authorization.to_str().map_err(|_| Failure::Malformed)?;
+        let (scheme, credentials) = authorization.split_once(' ').ok_or(Failure::Malformed)?;
+        match self.scheme {
+            AuthScheme::Basic if scheme.eq_ignore_ascii_case("basic") => {
+                self.check_basic(credentials.trim())
+            }
+            AuthScheme::Digest if scheme.eq_ignore_ascii_case("digest") => {
+                self.check_digest(req, credentials.trim())
+            }
+            _ => Err(Failure::WrongScheme),
+        }
+    }
+
+    fn check_basic(&self, credentials: &str) -> Result<(), Failure> {
+        let decoded = BASE64.decode(credentials).map_err(|_| Failure::Malformed)?;
+        let decoded = String::from_utf8(decoded).map_err(|_| Failure::Malformed)?;
+        let (user, password) = decoded.split_once(':').ok_or(Failure::Malformed)?;
+        match self.users.get(user) {
+            None => Err(Failure::UnknownUser(user.to_owned())),
+            Some(expected) if expected != password => Err(Failure::WrongPassword(user.to_owned())),
+            Some(_) => Ok(()),
+        }
+    }
+
+    // RFC 7616，支持 qop=auth 和不带 qop 的 RFC 2069 形式
+    fn check_digest(&self, req: &HttpRequest, credentials: &str) -> Result<(), Failure> {
+        let params = parse_params(credentials);
+        let param = |name: &str| params.get(name).map(String::as_str);
+        let (Some(user), Some(nonce), Some(uri), Some(response)) = (
+            param("username"),
+            param("nonce"),
+            param("uri"),
+            param("response"),
+        ) else {
+            return Err(Failure::Malformed);
+        };
+        if param("realm") != Some(self.realm.as_str()) {
+            return Err(Failure::Malformed);
+        }
+        // 摘要中的 URI 必须是本次请求的 URI，否则响应可能被用于其他资源
+        let request_uri = req
+            .uri()
+            .path_and_query()
+            .map_or(req.path(), |uri| uri.as_str());
+        if uri != request_uri {
+            return Err(Failure::Malformed);
+        }
+        let hash: fn(&str) -> String = match param("algorithm").unwrap_or("MD5") {
+            algorithm if algorithm.eq_ignore_ascii_case("MD5") => md5_hex,
+            algorithm if algorithm.eq_ignore_ascii_case("SHA-256") => sha256_hex,
+            _ => return Err(Failure::Malformed),
+        };
+        let Some(password) = self.users.get(user) else {
+            return Err(Failure::UnknownUser(user.to_owned()));
+        };
+
+        let ha1 = hash(&format!("{user}:{}:{password}", self.realm));
+        let ha2 = hash(&format!("{}:{uri}", req.method()));
+        let expected = match param("qop") {
+            Some("auth") => {
+                let (Some(nc), Some(cnonce)) = (param("nc"), param("cnonce")) else {
+                    return Err(Failure::Malformed);
+                };
+                hash(&format!("{ha1}:{nonce}:{nc}:{cnonce}:auth:{ha2}"))
+            }
+            None => hash(&format!("{ha1}:{nonce}:{ha2}")),
+            Some(_) => return Err(Failure::Malformed),
+        };
+        if !response.eq_ignore_ascii_case(&expected) {
+            return Err(Failure::WrongPassword(user.to_owned()));
+        }
+        // 密码正确但 nonce 过期时客户端应直接用新 nonce 重试，不需要再次询问用户
+        let issued = self.nonces.lock().unwrap().get(nonce).copied();
+        match issued {
+            Some(issued) if issued.elapsed() <= NONCE_LIFETIME => Ok(()),
+            _ => Err(Failure::StaleNonce(user.to_owned())),
+        }
+    }
+
+    fn challenge(&self, stale: bool) -> HttpResponse {
+        let mut response = HttpResponse::Unauthorized();
+        match self.scheme {
+            AuthScheme::Basic => {
+                response.append_header((
+                    header::WWW_AUTHENTICATE,
+                    format!(r#"Basic realm="{}", charset="UTF-8""#, self.realm),
+                ));
+            }
+            AuthScheme::Digest => {
+                let nonce = self.new_nonce();
+                // 按 RFC 7616 先提供 SHA-256，只支持 MD5 的客户端使用第二个质询
+                for algorithm in ["SHA-256", "MD5"] {
+                    response.append_header((
+                        header::WWW_AUTHENTICATE,
+                        format!(
+                            r#"Digest realm="{}", qop="auth", algorithm={algorithm}, nonce="{nonce}", opaque="{}"{}"#,
+                            self.realm,
+                            self.opaque,
+                            if stale { ", stale=true" } else { "" }
+                        ),
+                    ));
+                }
+            }
+        }
+        response
+            .content_type("text/plain")
+            .body("401 Unauthorized: this scanner requires authentication")
+    }
+
+    fn new_nonce(&self) -> String {
+        let nonce = Uuid::new_v4().simple().to_string();
+        let mut nonces = self.nonces.lock().unwrap();
+        nonces.retain(|_, issued| issued.elapsed() <= NONCE_LIFETIME);
+        nonces.insert(nonce.clone(), Instant::now());
+        nonce
+    }
+}
+
+/// Middleware of the protected resources. Passes every request when
+/// authentication isn't configured.
+pub(crate) async fn authenticate(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    match authorize(req.request()) {
+        Ok(()) => next
+            .call(req)
+            .await
+            .map(ServiceResponse::map_into_boxed_body),
+        Err(challenge) => Ok(req.into_response(challenge)),
+    }
+}
+
+/// Checks the credentials of a request to a protected resource, returns the
+/// challenge to answer it with if they are missing or wrong. Used directly by
+/// handlers that protect only some of their operations, e.g. WS-Scan.
+pub(crate) fn authorize(req: &HttpRequest) -> Result<(), HttpResponse> {
+    // CORS 预检请求不带凭据
+    let authenticator = req.app_data::<web::Data<Authenticator>>();
+    let Some(authenticator) =
+        authenticator.filter(|_| req.method() != actix_web::http::Method::OPTIONS)
+    else {
+        return Ok(());
+    };
+    let stale = match authenticator.check(req) {
+        Ok(()) => return Ok(()),
+        Err(Failure::Missing) => {
+            debug!(path = req.path(), "Sending authentication challenge");
+            false
+        }
+        Err(Failure::StaleNonce(user)) => {
+            debug!(
+                path = req.path(),
+                user, "Digest nonce expired, sending a new one"
+            );
+            true
+        }
+        Err(failure) => {
+            let (reason, user) = match failure {
+                Failure::Malformed => ("malformed credentials", None),
+                Failure::WrongScheme => ("wrong authentication scheme", None),
+                Failure::UnknownUser(user) => ("unknown user", Some(user)),
+                Failure::WrongPassword(user) => ("wrong password", Some(user)),
+                Failure::Missing | Failure::StaleNonce(_) => unreachable!(),
+            };
+            // 请求的路径和客户端地址已在请求 span 中
+            warn!(user, reason, "Authentication failed");
+            false
+        }
+    };
+    Err(authenticator.challenge(stale))
+}
+
+// 解析 Digest 参数: key=value 或 key="value"，以逗号分隔
+fn parse_params(input: &str) -> HashMap<String, String> {
+    let mut params = HashMap::new();
+    let mut rest = input.trim();
+    while let Some((key, after)) = rest.split_once('=') {
+        let key = key
+            .trim()
+            .trim_start_matches(',')
+            .trim()
+            .to_ascii_lowercase();
+        let after = after.trim_start();
+        let (value, remaining) = match after.strip_prefix('"') {
+            Some(quoted) => {
+                let mut value = String::new();
+                let mut chars = quoted.char_indices();
+                let mut end = quoted.len();
+                while let Some((index, c)) = chars.next() {
+                    match c {
+                        '\\' => {
+                            if let Some((_, escaped)) = chars.next() {
+                                value.push(escaped);
+                            }
+                        }
+                        '"' => {
+                            end = index + 1;
+                            break;
+                        }
+                        _ => value.push(c),
+                    }
+                }
+                (value, &quoted[end..])
+            }
+            None => {
+                let end = after.find(',').unwrap_or(after.len());
+                (after[..end].trim().to_owned(), &after[end..])
+            }
+        };
+        params.insert(key, value);
+        rest = remaining.trim_start().trim_start_matches(',');
+    }
+    params
+}
+
+fn md5_hex(input: &str) -> String {
+    hex(&Md5::digest(input.as_bytes()))
+}
+
+fn sha256_hex(input: &str) -> String {
+    hex(digest(&SHA256, input.as_bytes()).as_ref())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn authenticator(realm: &str, password: &str, nonce: &str) -> Authenticator {
+        let users = BTreeMap::from([("Mufasa".to_owned(), password.to_owned())]);
+        let authenticator = Authenticator::new(AuthScheme::Digest, realm.to_owned(), users);
+        authenticator
+            .nonces
+            .lock()
+            .unwrap()
+            .insert(nonce.to_owned(), Instant::now());
+        authenticator
+    }
+
+    fn request(authorization: &str) -> HttpRequest {
+        TestRequest::get()
+            .uri("/dir/index.html")
+            .insert_header((header::AUTHORIZATION, authorization))
+            .to_http_request()
+    }
+
+    // RFC 2617 第 3.5 节的示例
+    const MD5_AUTHORIZATION: &str = concat!(
+        r#"Digest username="Mufasa", realm="testrealm@host.com", "#,
+        r#"nonce="dcd98b7102dd2f0e8b11d0f600bfb0c093", uri="/dir/index.html", qop=auth, nc=00000001, "#,
+        r#"cnonce="0a4f113b", response="6629fae49393a05397450978507c4ef1", "#,
+        r#"opaque="5ccc069c403ebaf9f0171e9517f40e41""#,
+    );
+
+    #[test]
+    fn parses_quoted_and_token_params() {
+        let params = parse_params(
+            r#"Username="Mufasa", qop=auth,nc=00000001 , uri="/a,b", realm="say \"hi\"""#,
+        );
+        assert_eq!(params["username"], "Mufasa");
+        assert_eq!(params["qop"], "auth");
+        assert_eq!(params["nc"], "00000001");
+        assert_eq!(params["uri"], "/a,b");
+        assert_eq!(params["realm"], r#"say "hi""#);
+        assert_eq!(params.len(), 5);
+    }
+
+    #[test]
+    fn parses_empty_and_unterminated_values() {
+        assert!(parse_params("").is_empty());
+        let params = parse_params(r#"opaque="", nonce="abc"#);
+        assert_eq!(params["opaque"], "");
+        assert_eq!(params["nonce"], "abc");
+    }
+
+    #[test]
+    fn accepts_md5_digest() {
+        let authenticator = authenticator(
+            "testrealm@host.com",
+            "Circle Of Life",
+            "dcd98b7102dd2f0e8b11d0f600bfb0c093",
+        );
+        assert!(authenticator.check(&request(MD5_AUTHORIZATION)).is_ok());
+    }
+
+    // RFC 7616 第 3.9.1 节的示例
+    #[test]
+    fn accepts_sha256_digest() {
+        let nonce = "7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v";
+        let authenticator = authenticator("http-auth@example.org", "Circle of Life", nonce);
+        let authorization = [
+            r#"Digest username="Mufasa""#,
+            r#"realm="http-auth@example.org""#,
+            r#"uri="/dir/index.html""#,
+            "algorithm=SHA-256",
+            &format!(r#"nonce="{nonce}""#),
+            "nc=00000001",
+            r#"cnonce="f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ""#,
+            "qop=auth",
+            r#"response="753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1""#,
+            r#"opaque="FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS""#,
+        ]
+        .join(", ");
+        assert!(authenticator.check(&request(&authorization)).is_ok());
+    }
+
+    #[test]
+    fn rejects_wrong_password() {
+        let authenticator = authenticator(
+            "testrealm@host.com",
+            "Hakuna Matata",
+            "dcd98b7102dd2f0e8b11d0f600bfb0c093",
+        );
+        let result = authenticator.check(&request(MD5_AUTHORIZATION));
+        assert!(matches!(result, Err(Failure::WrongPassword(user)) if user == "Mufasa"));
+    }
+
+    #[test]
+    fn rejects_response_for_another_uri() {
+        let authenticator = authenticator(
+            "testrealm@host.com",
+            "Circle Of Life",
+            "dcd98b7102dd2f0e8b11d0f600bfb0c093",
+        );
+        let req = TestRequest::get()
+            .uri("/eSCL/ScannerStatus")
+            .insert_header((header::AUTHORIZATION, MD5_AUTHORIZATION))
+            .to_http_request();
+        assert!(matches!(authenticator.check(&req), Err(Failure::Malformed)));
+    }
+
+    #[test]
+    fn reports_unknown_nonce_as_stale() {
+        let authenticator = authenticator("testrealm@host.com", "Circle Of Life", "another nonce");
+        let result = authenticator.check(&request(MD5_AUTHORIZATION));
+        assert!(matches!(result, Err(Failure::StaleNonce(user)) if user == "Mufasa"));
+    }
+
+    #[test]
+    fn checks_basic_credentials() {
+        let users = BTreeMap::from([("alice".to_owned(), "secret".to_owned())]);
+        let authenticator = Authenticator::new(AuthScheme::Basic, "scanner".to_owned(), users);
+        let basic = |credentials: &str| request(&format!("Basic {}", BASE64.encode(credentials)));
+        assert!(authenticator.check(&basic("alice:secret")).is_ok());
+        assert!(matches!(
+            authenticator.check(&basic("alice:wrong")),
+            Err(Failure::WrongPassword(_))
+        ));
+        assert!(matches!(
+            authenticator.check(&basic("bob:secret")),
+            Err(Failure::UnknownUser(_))
+        ));
+        assert!(matches!(
+            authenticator.check(&request(MD5_AUTHORIZATION)),
+            Err(Failure::WrongScheme)
+        ));
+    }
+}
@@ -0,0 +1,1170 @@
+/*
+ *     Copyright (C) 2024-2025 Christian Nagel and contributors
+ *
+ *     This file is part of escl-mock-server.
+ *
+ *     escl-mock-server is free software: you can redistribute it and/or modify it under the terms of
+ *     the GNU General Public License as published by the Free Software Foundation, either
+ *     version 3 of the License, or (at your option) any later version.
+ *
+ *     escl-mock-server is distributed in the hope that it will be useful, but WITHOUT ANY
+ *     WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ *     FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ *     You should have received a copy of the GNU General Public License along with eSCLKt.
+ *     If not, see <https://www.gnu.org/licenses/>.
+ *
+ *     SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+use crate::identity::DeviceIdentity;
+use crate::version::EsclVersion;
+use crate::xml::XmlWriter;
+use roxmltree::{Document, Node};
+use serde::Deserialize;
+use std::fmt::{Display, Formatter};
+
+pub(crate) const ESCL_NS: &str = "http://schemas.hp.com/imaging/escl/2011/05/03";
+pub(crate) const PWG_NS: &str = "http://www.pwg.org/schemas/2010/12/sm";
+const XSI_NS: &str = "http://www.w3.org/2001/XMLSchema-instance";
+
+/// `scan:ColorMode` values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum ColorMode {
+    BlackAndWhite1,
+    Grayscale8,
+    Grayscale16,
+    #[serde(rename = "RGB24")]
+    Rgb24,
+    #[serde(rename = "RGB48")]
+    Rgb48,
+}
+
+impl ColorMode {
+    const ALL: [ColorMode; 5] = [
+        ColorMode::BlackAndWhite1,
+        ColorMode::Grayscale8,
+        ColorMode::Grayscale16,
+        ColorMode::Rgb24,
+        ColorMode::Rgb48,
+    ];
+
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            ColorMode::BlackAndWhite1 => "BlackAndWhite1",
+            ColorMode::Grayscale8 => "Grayscale8",
+            ColorMode::Grayscale16 => "Grayscale16",
+            ColorMode::Rgb24 => "RGB24",
+            ColorMode::Rgb48 => "RGB48",
+        }
+    }
+
+    /// Parses the `scan:ColorMode` value sent in ScanSettings.
+    pub(crate) fn from_name(name: &str) -> Option<ColorMode> {
+        ColorMode::ALL
+            .into_iter()
+            .find(|mode| mode.as_str() == name)
+    }
+
+    pub(crate) fn bits_per_pixel(&self) -> u32 {
+        match self {
+            ColorMode::BlackAndWhite1 => 1,
+            ColorMode::Grayscale8 => 8,
+            ColorMode::Grayscale16 => 16,
+            ColorMode::Rgb24 => 24,
+            ColorMode::Rgb48 => 48,
+        }
+    }
+
+    /// Value of the mDNS `cs` TXT record this mode belongs to.
+    fn txt_name(&self) -> &'static str {
+        match self {
+            ColorMode::BlackAndWhite1 => "binary",
+            ColorMode::Grayscale8 | ColorMode::Grayscale16 => "grayscale",
+            ColorMode::Rgb24 | ColorMode::Rgb48 => "color",
+        }
+    }
+}
+
+impl Display for ColorMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// `scan:Intent` values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum Intent {
+    Document,
+    TextAndGraphic,
+    Photo,
+    Preview,
+    Object,
+    BusinessCard,
+}
+
+impl Intent {
+    const ALL: [Intent; 6] = [
+        Intent::Document,
+        Intent::TextAndGraphic,
+        Intent::Photo,
+        Intent::Preview,
+        Intent::Object,
+        Intent::BusinessCard,
+    ];
+
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            Intent::Document => "Document",
+            Intent::TextAndGraphic => "TextAndGraphic",
+            Intent::Photo => "Photo",
+            Intent::Preview => "Preview",
+            Intent::Object => "Object",
+            Intent::BusinessCard => "BusinessCard",
+        }
+    }
+}
+
+impl Display for Intent {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Resolution {
+    pub x: u32,
+    pub y: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ResolutionRange {
+    pub min: u32,
+    pub max: u32,
+    pub normal: u32,
+    pub step: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum SupportedResolutions {
+    Discrete(Vec<Resolution>),
+    Range {
+        x: ResolutionRange,
+        y: ResolutionRange,
+    },
+}
+
+/// One `scan:SettingProfile`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SettingProfile {
+    pub color_modes: Vec<ColorMode>,
+    /// MIME types, served both as `pwg:DocumentFormat` and `scan:DocumentFormatExt`.
+    pub document_formats: Vec<String>,
+    pub resolutions: SupportedResolutions,
+    pub color_spaces: Vec<String>,
+    pub ccd_channels: Vec<String>,
+    pub binary_renderings: Vec<String>,
+}
+
+impl Default for SettingProfile {
+    fn default() -> Self {
+        SettingProfile {
+            color_modes: vec![
+                ColorMode::BlackAndWhite1,
+                ColorMode::Grayscale8,
+                ColorMode::Rgb24,
+            ],
+            document_formats: vec!["application/pdf".to_owned(), "image/jpeg".to_owned()],
+            resolutions: SupportedResolutions::Discrete(
+                [100, 200, 300, 600]
+                    .into_iter()
+                    .map(|dpi| Resolution { x: dpi, y: dpi })
+                    .collect(),
+            ),
+            color_spaces: strings(&["CMYK", "YCC", "sRGB"]),
+            ccd_channels: strings(&["Red", "Green", "Blue", "NTSC", "GrayCcd", "GrayCcdEmulated"]),
+            binary_renderings: strings(&["Halftone", "Threshold"]),
+        }
+    }
+}
+
+/// Risky margins in 1/300 inch.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct Margins {
+    pub left: u32,
+    pub right: u32,
+    pub top: u32,
+    pub bottom: u32,
+}
+
+/// Capabilities of one input source (`PlatenInputCaps`, `AdfSimplexInputCaps`, ...).
+/// Sizes are in 1/300 inch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct InputCaps {
+    pub min_width: u32,
+    pub max_width: u32,
+    pub min_height: u32,
+    pub max_height: u32,
+    pub max_scan_regions: u32,
+    pub setting_profiles: Vec<SettingProfile>,
+    pub intents: Vec<Intent>,
+    pub supported_edges: Vec<String>,
+    pub max_optical_x_resolution: Option<u32>,
+    pub max_optical_y_resolution: Option<u32>,
+    pub margins: Margins,
+    pub max_physical_width: u32,
+    pub max_physical_height: u32,
+}
+
+impl InputCaps {
+    /// A letter sized input with the default setting profile.
+    pub(crate) fn new(max_width: u32, max_height: u32) -> Self {
+        InputCaps {
+            min_width: 16,
+            max_width,
+            min_height: 16,
+            max_height,
+            max_scan_regions: 1,
+            setting_profiles: vec![SettingProfile::default()],
+            intents: vec![
+                Intent::Document,
+                Intent::TextAndGraphic,
+                Intent::Photo,
+                Intent::Preview,
+            ],
+            supported_edges: strings(&["TopEdge", "LeftEdge", "BottomEdge", "RightEdge"]),
+            max_optical_x_resolution: Some(2400),
+            max_optical_y_resolution: Some(2400),
+            margins: Margins::default(),
+            max_physical_width: max_width,
+            max_physical_height: max_height,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct AdfCaps {
+    pub simplex: InputCaps,
+    pub duplex: Option<InputCaps>,
+    pub feeder_capacity: Option<u32>,
+    pub options: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct StoredJobRequestSupport {
+    pub max_stored_job_requests: u32,
+    pub timeout_in_seconds: u32,
+}
+
+/// Typed `scan:ScannerCapabilities` document.
+///
+/// Identity and URIs are not part of the model, they are filled in when the
+/// document is rendered for a request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScannerCapabilities {
+    /// Also decides which optional elements are rendered.
+    pub(crate) version: EsclVersion,
+    pub(crate) platen: Option<InputCaps>,
+    pub(crate) camera: Option<InputCaps>,
+    pub(crate) adf: Option<AdfCaps>,
+    pub(crate) blank_page_detection: bool,
+    pub(crate) stored_job_request_support: Option<StoredJobRequestSupport>,
+}
+
+impl Default for ScannerCapabilities {
+    fn default() -> Self {
+        ScannerCapabilities::builder().build()
+    }
+}
+
+/// Builds the common shapes of [`ScannerCapabilities`]: every enabled input
+/// source shares one setting profile and set of intents.
+pub struct CapabilitiesBuilder {
+    version: EsclVersion,
+    platen: bool,
+    adf: bool,
+    adf_duplex: bool,
+    camera: bool,
+    profile: SettingProfile,
+    intents: Vec<Intent>,
+    supported_edges: Vec<String>,
+    max_optical_resolution: Option<u32>,
+    margins: Margins,
+    feeder_capacity: u32,
+    blank_page_detection: bool,
+    stored_job_request_support: bool,
+}
+
+impl CapabilitiesBuilder {
+    pub fn version(mut self, version: EsclVersion) -> Self {
+        self.version = version;
+        self
+    }
+
+    pub fn platen(mut self, enabled: bool) -> Self {
+        self.platen = enabled;
+        self
+    }
+
+    pub fn adf(mut self, enabled: bool) -> Self {
+        self.adf = enabled;
+        self
+    }
+
+    /// Only has an effect when the ADF is enabled.
+    pub fn adf_duplex(mut self, enabled: bool) -> Self {
+        self.adf_duplex = enabled;
+        self
+    }
+
+    pub fn camera(mut self, enabled: bool) -> Self {
+        self.camera = enabled;
+        self
+    }
+
+    pub fn color_modes(mut self, color_modes: Vec<ColorMode>) -> Self {
+        self.profile.color_modes = color_modes;
+        self
+    }
+
+    pub fn document_formats(mut self, document_formats: Vec<String>) -> Self {
+        self.profile.document_formats = document_formats;
+        self
+    }
+
+    /// Square discrete resolutions in DPI.
+    pub fn resolutions(mut self, resolutions: &[u32]) -> Self {
+        self.profile.resolutions = SupportedResolutions::Discrete(
+            resolutions
+                .iter()
+                .map(|&dpi| Resolution { x: dpi, y: dpi })
+                .collect(),
+        );
+        self
+    }
+
+    pub fn intents(mut self, intents: Vec<Intent>) -> Self {
+        self.intents = intents;
+        self
+    }
+
+    pub fn feeder_capacity(mut self, feeder_capacity: u32) -> Self {
+        self.feeder_capacity = feeder_capacity;
+        self
+    }
+
+    /// Optional profile elements, empty lists are left out of the document.
+    pub fn color_spaces(mut self, color_spaces: &[&str]) -> Self {
+        self.profile.color_spaces = strings(color_spaces);
+        self
+    }
+
+    pub fn ccd_channels(mut self, ccd_channels: &[&str]) -> Self {
+        self.profile.ccd_channels = strings(ccd_channels);
+        self
+    }
+
+    pub fn binary_renderings(mut self, binary_renderings: &[&str]) -> Self {
+        self.profile.binary_renderings = strings(binary_renderings);
+        self
+    }
+
+    pub fn supported_edges(mut self, supported_edges: &[&str]) -> Self {
+        self.supported_edges = strings(supported_edges);
+        self
+    }
+
+    /// `None` leaves out `MaxOpticalXResolution` and `MaxOpticalYResolution`.
+    pub fn max_optical_resolution(mut self, resolution: Option<u32>) -> Self {
+        self.max_optical_resolution = resolution;
+        self
+    }
+
+    /// Risky margins of every input source in 1/300 inch.
+    pub fn margins(mut self, left: u32, right: u32, top: u32, bottom: u32) -> Self {
+        self.margins = Margins {
+            left,
+            right,
+            top,
+            bottom,
+        };
+        self
+    }
+
+    pub fn blank_page_detection(mut self, enabled: bool) -> Self {
+        self.blank_page_detection = enabled;
+        self
+    }
+
+    pub fn stored_job_request_support(mut self, enabled: bool) -> Self {
+        self.stored_job_request_support = enabled;
+        self
+    }
+
+    pub fn build(self) -> ScannerCapabilities {
+        let input = |max_width, max_height| InputCaps {
+            setting_profiles: vec![self.profile.clone()],
+            intents: self.intents.clone(),
+            supported_edges: self.supported_edges.clone(),
+            max_optical_x_resolution: self.max_optical_resolution,
+            max_optical_y_resolution: self.max_optical_resolution,
+            margins: self.margins,
+            ..InputCaps::new(max_width, max_height)
+        };
+
+        // 尺寸单位为 1/300 英寸：平板为 Letter/A4，ADF 支持较长的纸张
+        let adf = self.adf.then(|| {
+            let mut options = vec!["DetectPaperLoaded".to_owned()];
+            if self.adf_duplex {
+                options.push("Duplex".to_owned());
+            }
+            AdfCaps {
+                simplex: input(2550, 4200),
+                duplex: self.adf_duplex.then(|| InputCaps {
+                    max_optical_x_resolution: self.max_optical_resolution.map(|dpi| dpi / 2),
+                    ..input(2550, 4200)
+                }),
+                feeder_capacity: Some(self.feeder_capacity),
+                options,
+            }
+        });
+
+        ScannerCapabilities {
+            version: self.version,
+            platen: self.platen.then(|| input(2550, 3507)),
+            camera: self.camera.then(|| input(2550, 3507)),
+            adf,
+            blank_page_detection: self.blank_page_detection,
+            stored_job_request_support: self.stored_job_request_support.then_some(
+                StoredJobRequestSupport {
+                    max_stored_job_requests: 0,
+                    timeout_in_seconds: 0,
+                },
+            ),
+        }
+    }
+}
+
+impl ScannerCapabilities {
+    /// Starts from the default device: platen and duplex ADF, three color
+    /// modes, PDF and JPEG, 100 to 600 DPI.
+    pub fn builder() -> CapabilitiesBuilder {
+        CapabilitiesBuilder {
+            version: EsclVersion::LATEST,
+            platen: true,
+            adf: true,
+            adf_duplex: true,
+            camera: false,
+            profile: SettingProfile::default(),
+            intents: vec![
+                Intent::Document,
+                Intent::TextAndGraphic,
+                Intent::Photo,
+                Intent::Preview,
+            ],
+            supported_edges: strings(&["TopEdge", "LeftEdge", "BottomEdge", "RightEdge"]),
+            max_optical_resolution: Some(2400),
+            margins: Margins::default(),
+            feeder_capacity: 20,
+            blank_page_detection: true,
+            stored_job_request_support: true,
+        }
+    }
+
+    /// All input caps in document order.
+    pub(crate) fn inputs(&self) -> impl Iterator<Item = &InputCaps> {
+        self.platen.iter().chain(self.camera.iter()).chain(
+            self.adf
+                .iter()
+                .flat_map(|adf| std::iter::once(&adf.simplex).chain(adf.duplex.iter())),
+        )
+    }
+
+    fn profiles(&self) -> impl Iterator<Item = &SettingProfile> {
+        self.inputs()
+            .flat_map(|input| input.setting_profiles.iter())
+    }
+
+    pub fn supports_duplex(&self) -> bool {
+        self.adf.as_ref().is_some_and(|adf| adf.duplex.is_some())
+    }
+
+    /// mDNS `pdl` TXT value.
+    pub(crate) fn txt_pdl(&self) -> String {
+        let mut formats: Vec<&str> = Vec::new();
+        for format in self
+            .profiles()
+            .flat_map(|profile| profile.document_formats.iter())
+        {
+            if !formats.contains(&format.as_str()) {
+                formats.push(format);
+            }
+        }
+        formats.join(",")
+    }
+
+    /// mDNS `cs` TXT value.
+    pub(crate) fn txt_cs(&self) -> String {
+        let modes: Vec<ColorMode> = self
+            .profiles()
+            .flat_map(|profile| profile.color_modes.iter().copied())
+            .collect();
+        ["color", "grayscale", "binary"]
+            .into_iter()
+            .filter(|name| modes.iter().any(|mode| mode.txt_name() == *name))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    /// mDNS `is` TXT value.
+    pub(crate) fn txt_is(&self) -> String {
+        let sources = [
+            ("platen", self.platen.is_some()),
+            ("adf", self.adf.is_some()),
+            ("camera", self.camera.is_some()),
+        ];
+        sources
+            .into_iter()
+            .filter_map(|(name, present)| present.then_some(name))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    /// mDNS `duplex` TXT value.
+    pub(crate) fn txt_duplex(&self) -> &'static str {
+        if self.supports_duplex() {
+            "T"
+        } else {
+            "F"
+        }
+    }
+
+    /// Checks the consistency rules the XML structure alone can't express.
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if self.inputs().next().is_none() {
+            return Err("capabilities must enable at least one input source".to_owned());
+        }
+        for input in self.inputs() {
+            if input.min_width > input.max_width || input.min_height > input.max_height {
+                return Err("capabilities: minimum size exceeds maximum size".to_owned());
+            }
+            if input.setting_profiles.is_empty() {
+                return Err("capabilities: every input source needs a setting profile".to_owned());
+            }
+            for profile in &input.setting_profiles {
+                if profile.color_modes.is_empty() {
+                    return Err("capabilities: color_modes must not be empty".to_owned());
+                }
+                if profile.document_formats.is_empty() {
+                    return Err("capabilities: document_formats must not be empty".to_owned());
+                }
+                if let Some(format) = profile.document_formats.iter().find(|f| !f.contains('/')) {
+                    return Err(format!("capabilities: {format:?} is not a MIME type"));
+                }
+                match &profile.resolutions {
+                    SupportedResolutions::Discrete(resolutions) if resolutions.is_empty() => {
+                        return Err("capabilities: resolutions must not be empty".to_owned());
+                    }
+                    SupportedResolutions::Discrete(resolutions)
+                        if resolutions.iter().any(|r| r.x == 0 || r.y == 0) =>
+                    {
+                        return Err("capabilities: resolutions must be positive".to_owned());
+                    }
+                    SupportedResolutions::Range { x, y }
+                        if x.min > x.max || y.min > y.max || x.step == 0 || y.step == 0 =>
+                    {
+                        return Err("capabilities: invalid resolution range".to_owned());
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Renders the spec document for `identity`, with URIs below `base_url`
+    /// and the admin console below the device's `scope`.
+    pub(crate) fn to_xml(&self, identity: &DeviceIdentity, base_url: &str, scope: &str) -> String {
+        let mut xml = XmlWriter::new();
+        let schema_location = format!("{ESCL_NS} eSCL.xsd");
+        xml.open_with(
+            "scan:ScannerCapabilities",
+            &[
+                ("xmlns:scan", ESCL_NS),
+                ("xmlns:pwg", PWG_NS),
+                ("xmlns:xsi", XSI_NS),
+                ("xsi:schemaLocation", &schema_location),
+            ],
+        );
+        xml.leaf("pwg:Version", self.version);
+        xml.leaf("pwg:MakeAndModel", identity.make_and_model());
+        xml.leaf("pwg:SerialNumber", &identity.serial_number);
+        xml.leaf("scan:UUID", identity.uuid);
+        xml.leaf("scan:AdminURI", format!("{base_url}{scope}/admin"));
+        xml.leaf("scan:IconURI", format!("{base_url}/icon.png"));
+
+        if let Some(platen) = &self.platen {
+            xml.open("scan:Platen");
+            write_input(&mut xml, "scan:PlatenInputCaps", platen, self.version);
+            xml.close("scan:Platen");
+        }
+        if let Some(camera) = &self.camera {
+            xml.open("scan:Camera");
+            write_input(&mut xml, "scan:CameraInputCaps", camera, self.version);
+            xml.close("scan:Camera");
+        }
+        if let Some(adf) = &self.adf {
+            xml.open("scan:Adf");
+            write_input(
+                &mut xml,
+                "scan:AdfSimplexInputCaps",
+                &adf.simplex,
+                self.version,
+            );
+            if let Some(duplex) = &adf.duplex {
+                write_input(&mut xml, "scan:AdfDuplexInputCaps", duplex, self.version);
+            }
+            if let Some(capacity) = adf.feeder_capacity {
+                xml.leaf("scan:FeederCapacity", capacity);
+            }
+            if !adf.options.is_empty() {
+                xml.open("scan:AdfOptions");
+                for option in &adf.options {
+                    xml.leaf("scan:AdfOption", option);
+                }
+                xml.close("scan:AdfOptions");
+            }
+            xml.close("scan:Adf");
+        }
+        if self.blank_page_detection && self.version.has_blank_page_detection() {
+            xml.leaf("scan:BlankPageDetection", true);
+            xml.leaf("scan:BlankPageDetectionAndRemoval", true);
+        }
+        if let Some(support) = &self.stored_job_request_support {
+            xml.open("scan:StoredJobRequestSupport");
+            // 规范中的元素名就是这种大小写
+            xml.leaf("scan:MaxStoredjobRequests", support.max_stored_job_requests);
+            xml.leaf("scan:TimeoutInSeconds", support.timeout_in_seconds);
+            xml.close("scan:StoredJobRequestSupport");
+        }
+        xml.close("scan:ScannerCapabilities");
+        xml.finish()
+    }
+
+    /// Reads a capabilities document, e.g. a `--scannercaps` file.
+    ///
+    /// Placeholders like `DYNAMIC_UUID` are accepted wherever text is expected.
+    pub fn from_xml(content: &str) -> Result<Self, String> {
+        let document = Document::parse(content).map_err(|e| e.to_string())?;
+        let root = document.root_element();
+        if root.tag_name().name() != "ScannerCapabilities" {
+            return Err(format!(
+                "root element is <{}>, expected <scan:ScannerCapabilities>",
+                root.tag_name().name()
+            ));
+        }
+        let parser = Parser {
+            document: &document,
+        };
+
+        let adf = match child(root, "Adf") {
+            Some(adf) => Some(AdfCaps {
+                simplex: parser.input(parser.required(adf, "AdfSimplexInputCaps")?)?,
+                duplex: child(adf, "AdfDuplexInputCaps")
+                    .map(|duplex| parser.input(duplex))
+                    .transpose()?,
+                feeder_capacity: parser.number(adf, "FeederCapacity")?,
+                options: child(adf, "AdfOptions")
+                    .map(|options| texts(options, "AdfOption"))
+                    .unwrap_or_default(),
+            }),
+            None => None,
+        };
+        let stored_job_request_support = match child(root, "StoredJobRequestSupport") {
+            Some(support) => Some(StoredJobRequestSupport {
+                max_stored_job_requests: parser
+                    .number(support, "MaxStoredjobRequests")?
+                    .unwrap_or(0),
+                timeout_in_seconds: parser.number(support, "TimeoutInSeconds")?.unwrap_or(0),
+            }),
+            None => None,
+        };
+
+        let version = match child(root, "Version") {
+            Some(version) => version
+                .text()
+                .unwrap_or_default()
+                .parse()
+                .map_err(|e: String| parser.error(version, &e))?,
+            None => EsclVersion::LATEST,
+        };
+
+        Ok(ScannerCapabilities {
+            version,
+            platen: match child(root, "Platen") {
+                Some(platen) => Some(parser.input(parser.required(platen, "PlatenInputCaps")?)?),
+                None => None,
+            },
+            camera: match child(root, "Camera") {
+                Some(camera) => Some(parser.input(parser.required(camera, "CameraInputCaps")?)?),
+                None => None,
+            },
+            adf,
+            blank_page_detection: child(root, "BlankPageDetection")
+                .and_then(|detection| detection.text())
+                .is_some_and(|text| text.trim() == "true"),
+            stored_job_request_support,
+        })
+    }
+}
+
+fn write_input(xml: &mut XmlWriter, name: &str, input: &InputCaps, version: EsclVersion) {
+    xml.open(name);
+    xml.leaf("scan:MinWidth", input.min_width);
+    xml.leaf("scan:MaxWidth", input.max_width);
+    xml.leaf("scan:MinHeight", input.min_height);
+    xml.leaf("scan:MaxHeight", input.max_height);
+    xml.leaf("scan:MaxScanRegions", input.max_scan_regions);
+    xml.open("scan:SettingProfiles");
+    for profile in &input.setting_profiles {
+        write_profile(xml, profile, version);
+    }
+    xml.close("scan:SettingProfiles");
+    if !input.intents.is_empty() {
+        xml.open("scan:SupportedIntents");
+        for intent in &input.intents {
+            xml.leaf("scan:Intent", intent);
+        }
+        xml.close("scan:SupportedIntents");
+    }
+    if !input.supported_edges.is_empty() {
+        xml.open("scan:EdgeAutoDetection");
+        for edge in &input.supported_edges {
+            xml.leaf("scan:SupportedEdge", edge);
+        }
+        xml.close("scan:EdgeAutoDetection");
+    }
+    if let Some(resolution) = input.max_optical_x_resolution {
+        xml.leaf("scan:MaxOpticalXResolution", resolution);
+    }
+    if let Some(resolution) = input.max_optical_y_resolution {
+        xml.leaf("scan:MaxOpticalYResolution", resolution);
+    }
+    xml.leaf("scan:RiskyLeftMargin", input.margins.left);
+    xml.leaf("scan:RiskyRightMargin", input.margins.right);
+    xml.leaf("scan:RiskyTopMargin", input.margins.top);
+    xml.leaf("scan:RiskyBottomMargin", input.margins.bottom);
+    xml.leaf("scan:MaxPhysicalWidth", input.max_physical_width);
+    xml.leaf("scan:MaxPhysicalHeight", input.max_physical_height);
+    xml.close(name);
+}
+
+fn write_profile(xml: &mut XmlWriter, profile: &SettingProfile, version: EsclVersion) {
+    xml.open("scan:SettingProfile");
+    xml.open("scan:ColorModes");
+    for mode in &profile.color_modes {
+        xml.leaf("scan:ColorMode", mode);
+    }
+    xml.close("scan:ColorModes");
+    xml.open("scan:DocumentFormats");
+    for format in &profile.document_formats {
+        xml.leaf("pwg:DocumentFormat", format);
+    }
+    if version.has_document_format_ext() {
+        for format in &profile.document_formats {
+            xml.leaf("scan:DocumentFormatExt", format);
+        }
+    }
+    xml.close("scan:DocumentFormats");
+    xml.open("scan:SupportedResolutions");
+    match &profile.resolutions {
+        SupportedResolutions::Discrete(resolutions) => {
+            xml.open("scan:DiscreteResolutions");
+            for resolution in resolutions {
+                xml.open("scan:DiscreteResolution");
+                xml.leaf("scan:XResolution", resolution.x);
+                xml.leaf("scan:YResolution", resolution.y);
+                xml.close("scan:DiscreteResolution");
+            }
+            xml.close("scan:DiscreteResolutions");
+        }
+        SupportedResolutions::Range { x, y } => {
+            xml.open("scan:ResolutionRange");
+            for (name, range) in [("scan:XResolutionRange", x), ("scan:YResolutionRange", y)] {
+                xml.open(name);
+                xml.leaf("scan:Min", range.min);
+                xml.leaf("scan:Max", range.max);
+                xml.leaf("scan:Normal", range.normal);
+                xml.leaf("scan:Step", range.step);
+                xml.close(name);
+            }
+            xml.close("scan:ResolutionRange");
+        }
+    }
+    xml.close("scan:SupportedResolutions");
+    write_list(
+        xml,
+        "scan:ColorSpaces",
+        "scan:ColorSpace",
+        &profile.color_spaces,
+    );
+    write_list(
+        xml,
+        "scan:CcdChannels",
+        "scan:CcdChannel",
+        &profile.ccd_channels,
+    );
+    write_list(
+        xml,
+        "scan:BinaryRenderings",
+        "scan:BinaryRendering",
+        &profile.binary_renderings,
+    );
+    xml.close("scan:SettingProfile");
+}
+
+fn write_list(xml: &mut XmlWriter, list: &str, item: &str, values: &[String]) {
+    if values.is_empty() {
+        return;
+    }
+    xml.open(list);
+    for value in values {
+        xml.leaf(item, value);
+    }
+    xml.close(list);
+}
+
+fn strings(values: &[&str]) -> Vec<String> {
+    values.iter().map(|value| (*value).to_owned()).collect()
+}
+
+// 按本地名称查找子元素，忽略命名空间前缀
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children()
+        .find(|child| child.is_element() && child.tag_name().name() == name)
+}
+
+fn children<'a, 'input>(
+    node: Node<'a, 'input>,
+    name: &'a str,
+) -> impl Iterator<Item = Node<'a, 'input>> + 'a {
+    node.children()
+        .filter(move |child| child.is_element() && child.tag_name().name() == name)
+}
+
+fn texts(node: Node, name: &str) -> Vec<String> {
+    children(node, name)
+        .filter_map(|child| child.text())
+        .map(|text| text.trim().to_owned())
+        .collect()
+}
+
+// 错误信息中带上行号，方便定位手工编辑的能力文件
+struct Parser<'a, 'input> {
+    document: &'a Document<'input>,
+}
+
+impl<'a, 'input> Parser<'a, 'input> {
+    fn error(&self, node: Node, message: &str) -> String {
+        let position = self.document.text_pos_at(node.range().start);
+        format!(
+            "line {}: <{}> {message}",
+            position.row,
+            node.tag_name().name()
+        )
+    }
+
+    fn required(&self, node: Node<'a, 'input>, name: &str) -> Result<Node<'a, 'input>, String> {
+        child(node, name).ok_or_else(|| self.error(node, &format!("is missing <{name}>")))
+    }
+
+    fn number(&self, node: Node, name: &str) -> Result<Option<u32>, String> {
+        match child(node, name) {
+            Some(element) => element
+                .text()
+                .unwrap_or_default()
+                .trim()
+                .parse()
+                .map(Some)
+                .map_err(|_| self.error(element, "expected a non-negative integer")),
+            None => Ok(None),
+        }
+    }
+
+    fn required_number(&self, node: Node, name: &str) -> Result<u32, String> {
+        self.number(node, name)?
+            .ok_or_else(|| self.error(node, &format!("is missing <{name}>")))
+    }
+
+    fn input(&self, node: Node<'a, 'input>) -> Result<InputCaps, String> {
+        let setting_profiles = match child(node, "SettingProfiles") {
+            Some(profiles) => children(profiles, "SettingProfile")
+                .map(|profile| self.profile(profile))
+                .collect::<Result<Vec<_>, _>>()?,
+            None => Vec::new(),
+        };
+        let intents = match child(node, "SupportedIntents") {
+            Some(intents) => children(intents, "Intent")
+                .map(|intent| {
+                    let text = intent.text().unwrap_or_default().trim();
+                    Intent::ALL
+                        .into_iter()
+                        .find(|known| known.as_str() == text)
+                        .ok_or_else(|| self.error(intent, &format!("has unknown intent {text:?}")))
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+            None => Vec::new(),
+        };
+        let max_width = self.required_number(node, "MaxWidth")?;
+        let max_height = self.required_number(node, "MaxHeight")?;
+
+        Ok(InputCaps {
+            min_width: self.number(node, "MinWidth")?.unwrap_or(1),
+            max_width,
+            min_height: self.number(node, "MinHeight")?.unwrap_or(1),
+            max_height,
+            max_scan_regions: self.number(node, "MaxScanRegions")?.unwrap_or(1),
+            setting_profiles,
+            intents,
+            supported_edges: child(node, "EdgeAutoDetection")
+                .map(|edges| texts(edges, "SupportedEdge"))
+                .unwrap_or_default(),
+            max_optical_x_resolution: self.number(node, "MaxOpticalXResolution")?,
+            max_optical_y_resolution: self.number(node, "MaxOpticalYResolution")?,
+            margins: Margins {
+                left: self.number(node, "RiskyLeftMargin")?.unwrap_or(0),
+                right: self.number(node, "RiskyRightMargin")?.unwrap_or(0),
+                top: self.number(node, "RiskyTopMargin")?.unwrap_or(0),
+                bottom: self.number(node, "RiskyBottomMargin")?.unwrap_or(0),
+            },
+            max_physical_width: self.number(node, "MaxPhysicalWidth")?.unwrap_or(max_width),
+            max_physical_height: self
+                .number(node, "MaxPhysicalHeight")?
+                .unwrap_or(max_height),
+        })
+    }
+
+    fn profile(&self, node: Node) -> Result<SettingProfile, String> {
+        let color_modes = match child(node, "ColorModes") {
+            Some(modes) => children(modes, "ColorMode")
+                .map(|mode| {
+                    let text = mode.text().unwrap_or_default().trim();
+                    ColorMode::ALL
+                        .into_iter()
+                        .find(|known| known.as_str() == text)
+                        .ok_or_else(|| {
+                            self.error(mode, &format!("has unknown color mode {text:?}"))
+                        })
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+            None => Vec::new(),
+        };
+
+        // DocumentFormat 和 DocumentFormatExt 通常重复列出相同的类型
+        let mut document_formats: Vec<String> = Vec::new();
+        if let Some(formats) = child(node, "DocumentFormats") {
+            for format in texts(formats, "DocumentFormat")
+                .into_iter()
+                .chain(texts(formats, "DocumentFormatExt"))
+            {
+                if !document_formats.contains(&format) {
+                    document_formats.push(format);
+                }
+            }
+        }
+
+        let supported = self.required(node, "SupportedResolutions")?;
+        let resolutions = if let Some(discrete) = child(supported, "DiscreteResolutions") {
+            SupportedResolutions::Discrete(
+                children(discrete, "DiscreteResolution")
+                    .map(|resolution| {
+                        Ok(Resolution {
+                            x: self.required_number(resolution, "XResolution")?,
+                            y: self.required_number(resolution, "YResolution")?,
+                        })
+                    })
+                    .collect::<Result<Vec<_>, String>>()?,
+            )
+        } else {
+            let range = self.required(supported, "ResolutionRange")?;
+            let parse_range = |name: &str| -> Result<ResolutionRange, String> {
+                let node = self.required(range, name)?;
+                Ok(ResolutionRange {
+                    min: self.required_number(node, "Min")?,
+                    max: self.required_number(node, "Max")?,
+                    normal: self.required_number(node, "Normal")?,
+                    step: self.number(node, "Step")?.unwrap_or(1),
+                })
+            };
+            SupportedResolutions::Range {
+                x: parse_range("XResolutionRange")?,
+                y: parse_range("YResolutionRange")?,
+            }
+        };
+
+        Ok(SettingProfile {
+            color_modes,
+            document_formats,
+            resolutions,
+            color_spaces: child(node, "ColorSpaces")
+                .map(|spaces| texts(spaces, "ColorSpace"))
+                .unwrap_or_default(),
+            ccd_channels: child(node, "CcdChannels")
+                .map(|channels| texts(channels, "CcdChannel"))
+                .unwrap_or_default(),
+            binary_renderings: child(node, "BinaryRenderings")
+                .map(|renderings| texts(renderings, "BinaryRendering"))
+                .unwrap_or_default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::identity::IdentityFile;
+    use crate::profile::Profile;
+    use clap::ValueEnum;
+
+    fn round_trip(capabilities: &ScannerCapabilities) -> ScannerCapabilities {
+        let identity = DeviceIdentity::from_partial(IdentityFile::default());
+        let xml = capabilities.to_xml(&identity, "http://localhost:8080", "/eSCL");
+        ScannerCapabilities::from_xml(&xml).unwrap()
+    }
+
+    #[test]
+    fn round_trips_builder_capabilities() {
+        let capabilities = ScannerCapabilities::builder().build();
+        assert_eq!(round_trip(&capabilities), capabilities);
+
+        let capabilities = ScannerCapabilities::builder()
+            .camera(true)
+            .adf_duplex(false)
+            .margins(10, 20, 30, 40)
+            .max_optical_resolution(None)
+            .stored_job_request_support(false)
+            .build();
+        assert_eq!(capabilities.platen.as_ref().unwrap().margins.bottom, 40);
+        assert_eq!(round_trip(&capabilities), capabilities);
+    }
+
+    #[test]
+    fn round_trips_resolution_ranges() {
+        let range = ResolutionRange {
+            min: 75,
+            max: 1200,
+            normal: 300,
+            step: 25,
+        };
+        let mut capabilities = ScannerCapabilities::builder().adf(false).build();
+        capabilities.platen.as_mut().unwrap().setting_profiles[0].resolutions =
+            SupportedResolutions::Range { x: range, y: range };
+        assert_eq!(round_trip(&capabilities), capabilities);
+    }
+
+    // 旧版本不输出的元素在模型中可能仍然打开，因此比较生成的文档
+    #[test]
+    fn round_trips_profile_documents() {
+        let identity = DeviceIdentity::from_partial(IdentityFile::default());
+        for profile in Profile::value_variants() {
+            let xml =
+                profile
+                    .capabilities()
+                    .build()
+                    .to_xml(&identity, "http://localhost:8080", "/eSCL");
+            let parsed = ScannerCapabilities::from_xml(&xml).unwrap();
+            assert_eq!(
+                parsed.to_xml(&identity, "http://localhost:8080", "/eSCL"),
+                xml,
+                "{profile:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn txt_values_of_platen_and_duplex_adf() {
+        let capabilities = ScannerCapabilities::builder().build();
+        assert_eq!(capabilities.txt_pdl(), "application/pdf,image/jpeg");
+        assert_eq!(capabilities.txt_cs(), "color,grayscale,binary");
+        assert_eq!(capabilities.txt_is(), "platen,adf");
+        assert_eq!(capabilities.txt_duplex(), "T");
+    }
+
+    #[test]
+    fn txt_values_of_simplex_sources() {
+        let capabilities = ScannerCapabilities::builder()
+            .adf_duplex(false)
+            .camera(true)
+            .color_modes(vec![ColorMode::Grayscale16, ColorMode::Rgb48])
+            .document_formats(vec!["image/jpeg".to_owned(), "image/png".to_owned()])
+            .build();
+        assert_eq!(capabilities.txt_pdl(), "image/jpeg,image/png");
+        assert_eq!(capabilities.txt_cs(), "color,grayscale");
+        assert_eq!(capabilities.txt_is(), "platen,adf,camera");
+        assert_eq!(capabilities.txt_duplex(), "F");
+    }
+
+    #[test]
+    fn validate_rejects_inconsistent_capabilities() {
+        assert!(ScannerCapabilities::builder().build().validate().is_ok());
+        let error = ScannerCapabilities::builder()
+            .platen(false)
+            .adf(false)
+            .build()
+            .validate();
+        assert_eq!(
+            error,
+            Err("capabilities must enable at least one input source".to_owned())
+        );
+        let error = ScannerCapabilities::builder()
+            .color_modes(Vec::new())
+            .build()
+            .validate();
+        assert_eq!(
+            error,
+            Err("capabilities: color_modes must not be empty".to_owned())
+        );
+        let error = ScannerCapabilities::builder()
+            .document_formats(vec!["pdf".to_owned()])
+            .build()
+            .validate();
+        assert_eq!(
+            error,
+            Err(r#"capabilities: "pdf" is not a MIME type"#.to_owned())
+        );
+        let error = ScannerCapabilities::builder()
+            .resolutions(&[300, 0])
+            .build()
+            .validate();
+        assert_eq!(
+            error,
+            Err("capabilities: resolutions must be positive".to_owned())
+        );
+    }
+
+    #[test]
+    fn from_xml_reports_missing_elements_with_line() {
+        let error = ScannerCapabilities::from_xml(
+            "<scan:ScannerCapabilities xmlns:scan=\"http://schemas.hp.com/imaging/escl/2011/05/03\">\n\
+             <scan:Platen>\n\
+             <scan:PlatenInputCaps><scan:MaxHeight>3507</scan:MaxHeight></scan:PlatenInputCaps>\n\
+             </scan:Platen>\n\
+             </scan:ScannerCapabilities>",
+        )
+        .unwrap_err();
+        assert_eq!(error, "line 3: <PlatenInputCaps> is missing <MaxWidth>");
+    }
+
+    #[test]
+    fn color_mode_names_and_depths() {
+        assert_eq!(ColorMode::from_name("RGB24"), Some(ColorMode::Rgb24));
+        assert_eq!(
+            ColorMode::from_name("BlackAndWhite1").map(|mode| mode.bits_per_pixel()),
+            Some(1)
+        );
+        assert_eq!(ColorMode::from_name("Grayscale4"), None);
+        for mode in ColorMode::ALL {
+            assert_eq!(ColorMode::from_name(mode.as_str()), Some(mode));
+        }
+    }
+}
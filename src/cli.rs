@@ -17,51 +17,136 @@
  *     SPDX-License-Identifier: GPL-3.0-or-later
  */
 
+use crate::auth::AuthScheme;
+use crate::logging::{LogFormat, LogLevel};
+use crate::network::BindAddress;
+use crate::profile::Profile;
+use crate::version::EsclVersion;
 use clap::error::ErrorKind;
-use clap::{arg, CommandFactory, Parser};
-use std::fmt::{Display, Formatter};
+use clap::{CommandFactory, Parser};
+use std::net::IpAddr;
 
-/// Search for a pattern in a file and display the lines that contain it.
-#[derive(Parser)]
+// 命令行参数覆盖配置文件中的值，因此这里不设置默认值，默认值见 config.rs
+
+/// An HTTP server mocking a scanner supporting the eSCL protocol.
+#[derive(Parser, Clone)]
 #[command(about, version)]
 pub(crate) struct Cli {
-    #[arg(short = 'a', long = "bindaddr", default_value = "127.0.0.1")]
-    pub(crate) binding_address: String,
-    #[arg(short = 's', long = "scope", default_value = "/eSCL")]
-    pub(crate) scope: String,
+    /// Address to bind to [default: 127.0.0.1]
+    #[arg(short = 'a', long = "bindaddr")]
+    pub(crate) binding_address: Option<String>,
+    /// Advertise on this network interface. Repeat for several, defaults to all interfaces
+    /// when bound to 0.0.0.0 and to the bind address otherwise
+    #[arg(long = "interface", value_name = "NAME")]
+    pub(crate) interfaces: Vec<String>,
+    /// Address put into mDNS records instead of the interface addresses. Repeat for several
+    #[arg(long = "advertise-addr", value_name = "IP")]
+    pub(crate) advertise_addrs: Vec<IpAddr>,
+    /// Path of the eSCL resources [default: /eSCL]
+    #[arg(short = 's', long = "scope")]
+    pub(crate) scope: Option<String>,
     #[arg(short = 'c', long = "scannercaps")]
     pub(crate) scanner_caps_file: Option<String>,
+    /// Image served for scanned pages. Repeat to serve a different image per page.
     #[arg(short = 'i', long = "image")]
-    pub(crate) served_image: Option<String>,
-    #[arg(value_parser = clap::value_parser!(u16).range(1..), short = 'p', long = "port", default_value = "8080")]
-    pub(crate) port: u16,
-}
-
-impl Display for Cli {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        writeln!(
-            f,
-            "Binding to {}:{}\nScope is: \"{}\"\nPossible URL: http://{0}:{1}/{2}",
-            self.binding_address, self.port, self.scope
-        )
-    }
+    pub(crate) served_images: Vec<String>,
+    /// TOML file with the device identity (uuid, serial_number, make, model, friendly_name,
+    /// firmware_version, model_number, manufacturer_url, model_url). Missing values are
+    /// generated randomly per instance.
+    #[arg(long = "identity")]
+    pub(crate) identity_file: Option<String>,
+    /// TOML configuration file. Command line options override its values.
+    #[arg(long = "config")]
+    pub(crate) config_file: Option<String>,
+    /// Don't apply changes to the config, capabilities and image files while running
+    #[arg(long = "no-reload")]
+    pub(crate) no_reload: bool,
+    /// Imitate a real scanner model: capabilities, identity, mDNS TXT records and quirks
+    #[arg(long = "profile", value_enum)]
+    pub(crate) profile: Option<Profile>,
+    /// eSCL version to emulate, from 2.0 to 2.97 [default: 2.97]
+    #[arg(long = "escl-version")]
+    pub(crate) escl_version: Option<EsclVersion>,
+    /// HTTP port, 0 picks a free port [default: 8080]
+    #[arg(short = 'p', long = "port")]
+    pub(crate) port: Option<u16>,
+    /// Serve HTTPS next to HTTP and advertise _uscans._tcp
+    #[arg(long = "tls")]
+    pub(crate) tls: bool,
+    /// Serve HTTPS only, implies --tls
+    #[arg(long = "tls-only")]
+    pub(crate) tls_only: bool,
+    /// HTTPS port, 0 picks a free port [default: 8443]
+    #[arg(long = "tls-port")]
+    pub(crate) tls_port: Option<u16>,
+    /// PEM certificate chain for HTTPS, a self-signed certificate is generated without it
+    #[arg(long = "tls-cert", value_name = "FILE", requires = "tls_key")]
+    pub(crate) tls_cert: Option<String>,
+    /// PEM private key of --tls-cert
+    #[arg(long = "tls-key", value_name = "FILE", requires = "tls_cert")]
+    pub(crate) tls_key: Option<String>,
+    /// Require HTTP authentication for the eSCL endpoints and /admin [default: basic when users
+    /// are given]
+    #[arg(long = "auth", value_enum)]
+    pub(crate) auth: Option<AuthScheme>,
+    /// User allowed to scan, as USER:PASSWORD. Repeat for several
+    #[arg(long = "auth-user", value_name = "USER:PASSWORD")]
+    pub(crate) auth_users: Vec<String>,
+    /// Don't advertise the scanner over mDNS
+    #[arg(long = "no-mdns")]
+    pub(crate) no_mdns: bool,
+    /// mDNS service instance name [default: the friendly name]
+    #[arg(long = "mdns-name", value_name = "NAME")]
+    pub(crate) mdns_name: Option<String>,
+    /// mDNS host name, must end with .local. [default: escl-mock-scanner.local.]
+    #[arg(long = "mdns-hostname", value_name = "HOST")]
+    pub(crate) mdns_hostname: Option<String>,
+    /// Additional _uscan._tcp TXT record, replaces a generated key with the same name. Repeat for
+    /// several
+    #[arg(long = "txt", value_name = "KEY=VALUE")]
+    pub(crate) txt: Vec<String>,
+    /// Don't answer SSDP searches or send SSDP announcements
+    #[arg(long = "no-ssdp")]
+    pub(crate) no_ssdp: bool,
+    /// Don't answer WS-Discovery probes or send WS-Discovery announcements
+    #[arg(long = "no-wsd")]
+    pub(crate) no_wsd: bool,
+    /// Write the bound addresses to this file once the server accepts connections
+    #[arg(long = "ready-file")]
+    pub(crate) ready_file: Option<String>,
+    /// [default: info]
+    #[arg(long = "log-level", value_enum)]
+    pub(crate) log_level: Option<LogLevel>,
+    /// [default: pretty]
+    #[arg(long = "log-format", value_enum)]
+    pub(crate) log_format: Option<LogFormat>,
+    /// Only show warnings and job lifecycle events
+    #[arg(short = 'q', long = "quiet")]
+    pub(crate) quiet: bool,
+    /// Check outgoing eSCL documents and incoming ScanSettings against the schema and log violations
+    #[arg(long = "validate-xml")]
+    pub(crate) validate_xml: bool,
+    /// Reject scan tickets that don't match the schema with 400 Bad Request
+    #[arg(long = "strict")]
+    pub(crate) strict: bool,
+    /// Track what each client does and report pass/fail checks at /_report and on shutdown
+    #[arg(long = "report")]
+    pub(crate) report: bool,
+    /// On SIGINT/SIGTERM wait up to this many seconds for in-flight requests such as
+    /// NextDocument downloads [default: 0]
+    #[arg(long = "drain-timeout", value_name = "SECONDS")]
+    pub(crate) drain_timeout: Option<u64>,
 }
 
-
-
 fn validate_addr(args: &Cli) {
-    println!("Validating address: {}", args.binding_address);
-
     // 简化的地址验证 - 只检查基本格式
-    use std::net::IpAddr;
-    if args.binding_address.parse::<IpAddr>().is_err() {
-        println!("Invalid IP address format: {}", args.binding_address);
-        Cli::command()
-            .error(ErrorKind::ValueValidation, "Invalid address")
-            .exit()
+    if let Some(address) = &args.binding_address {
+        if let Err(e) = address.parse::<BindAddress>() {
+            Cli::command()
+                .error(ErrorKind::ValueValidation, format!("Invalid address: {e}"))
+                .exit()
+        }
     }
-
-    println!("Address validation passed");
 }
 
 pub(crate) fn parse_cli() -> Cli {
@@ -0,0 +1,1302 @@
+/*
+ *     Copyright (C) 2024-2025 Christian Nagel and contributors
+ *
+ *     This file is part of escl-mock-server.
+ *
+ *     escl-mock-server is free software: you can redistribute it and/or modify it under the terms of
+ *     the GNU General Public License as published by the Free Software Foundation, either
+ *     version 3 of the License, or (at your option) any later version.
+ *
+ *     escl-mock-server is distributed in the hope that it will be useful, but WITHOUT ANY
+ *     WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ *     FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ *     You should have received a copy of the GNU General Public License along with eSCLKt.
+ *     If not, see <https://www.gnu.org/licenses/>.
+ *
+ *     SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+use crate::auth::AuthScheme;
+use crate::capabilities::{CapabilitiesBuilder, ColorMode, Intent, ScannerCapabilities};
+use crate::cli::Cli;
+use crate::identity::{DeviceIdentity, IdentityFile};
+use crate::logging::{LogFormat, LogLevel};
+use crate::model::ScanSource;
+use crate::network::BindAddress;
+use crate::profile::{Profile, Quirks, TxtLayout, GENERIC_TXT};
+use crate::schema;
+use crate::version::EsclVersion;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::IpAddr;
+use std::path::Path;
+use std::time::Duration;
+
+/// Contents of the file passed with `--config`.
+///
+/// Every value is optional. Command line options override the top level
+/// sections, `[[device]]` entries override both for their device.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ConfigFile {
+    /// Built-in device personality, the other settings adjust it.
+    pub profile: Option<Profile>,
+    #[serde(default)]
+    pub network: NetworkSection,
+    #[serde(default)]
+    pub identity: IdentityFile,
+    #[serde(default)]
+    pub capabilities: CapabilitiesSection,
+    #[serde(default)]
+    pub sources: SourcesSection,
+    #[serde(default)]
+    pub discovery: DiscoverySection,
+    #[serde(default)]
+    pub faults: FaultsSection,
+    #[serde(default)]
+    pub logging: LoggingSection,
+    #[serde(default)]
+    pub validation: ValidationSection,
+    #[serde(default)]
+    pub report: ReportSection,
+    #[serde(default)]
+    pub shutdown: ShutdownSection,
+    #[serde(default)]
+    pub tls: TlsSection,
+    #[serde(default)]
+    pub auth: AuthSection,
+    /// One entry per virtual scanner. Without entries a single device is
+    /// created from the top level sections.
+    #[serde(default, rename = "device")]
+    pub devices: Vec<DeviceEntry>,
+}
+
+/// `[network]`
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct NetworkSection {
+    pub bind_address: Option<String>,
+    /// Network interfaces to advertise on, by name. Defaults to all when
+    /// bound to `0.0.0.0`, otherwise to the bind address.
+    pub interfaces: Option<Vec<String>>,
+    /// Addresses put into mDNS records and URLs instead of the interface addresses.
+    pub advertise_addrs: Option<Vec<IpAddr>>,
+    pub port: Option<u16>,
+    pub scope: Option<String>,
+}
+
+/// `[capabilities]`
+///
+/// Either a complete ScannerCapabilities XML `file`, served as is, or
+/// adjustments of the built-in capabilities.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct CapabilitiesSection {
+    pub file: Option<String>,
+    /// Emulated eSCL version, decides which optional elements are served.
+    pub escl_version: Option<EsclVersion>,
+    pub platen: Option<bool>,
+    pub adf: Option<bool>,
+    pub adf_duplex: Option<bool>,
+    pub camera: Option<bool>,
+    pub color_modes: Option<Vec<ColorMode>>,
+    pub document_formats: Option<Vec<String>>,
+    /// Discrete resolutions in DPI, X and Y resolution are the same.
+    pub resolutions: Option<Vec<u32>>,
+    pub intents: Option<Vec<Intent>>,
+    pub feeder_capacity: Option<u32>,
+}
+
+/// `[sources]`
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct SourcesSection {
+    /// Pages produced by a platen job.
+    pub platen_pages: Option<u32>,
+    /// Sheets in the simulated ADF tray. Duplex jobs produce two pages per sheet.
+    pub adf_pages: Option<u32>,
+    /// Images served for scanned pages, used in turn.
+    pub images: Option<Vec<String>>,
+}
+
+/// `[discovery]`
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct DiscoverySection {
+    /// Advertise the device over mDNS.
+    pub mdns: Option<bool>,
+    /// Answer SSDP M-SEARCH and send NOTIFY messages for the device.
+    pub ssdp: Option<bool>,
+    /// Answer WS-Discovery Probe and Resolve and send Hello and Bye for the device.
+    pub wsd: Option<bool>,
+    /// mDNS host name, must end with `.local.`.
+    pub hostname: Option<String>,
+    /// mDNS service instance name, defaults to the friendly name.
+    pub instance_name: Option<String>,
+    /// Additional `_uscan._tcp` TXT records. They replace generated keys with the same name.
+    #[serde(default)]
+    pub txt: BTreeMap<String, String>,
+}
+
+/// `[faults]`
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct FaultsSection {
+    /// Delay before answering `POST ScanJobs`.
+    pub scan_job_delay_ms: Option<u64>,
+    /// Delay before a NextDocument response starts.
+    pub next_document_delay_ms: Option<u64>,
+    /// Report the scanner as busy: ScannerStatus says Processing and new jobs get 503.
+    pub busy: Option<bool>,
+}
+
+/// `[logging]`
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct LoggingSection {
+    pub level: Option<LogLevel>,
+    pub format: Option<LogFormat>,
+    pub quiet: Option<bool>,
+}
+
+/// `[validation]`
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ValidationSection {
+    /// Check outgoing eSCL documents and incoming ScanSettings against the schema.
+    pub xml: Option<bool>,
+    /// Reject scan tickets that don't match the schema with 400.
+    pub strict: Option<bool>,
+}
+
+/// `[report]`
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ReportSection {
+    /// Track client behaviour and serve the conformance report at `/_report`.
+    pub enabled: Option<bool>,
+}
+
+/// `[shutdown]`
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ShutdownSection {
+    /// How long SIGINT/SIGTERM wait for in-flight requests, e.g. NextDocument downloads.
+    pub drain_timeout_secs: Option<u64>,
+}
+
+/// `[tls]`
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct TlsSection {
+    /// Serve HTTPS next to HTTP.
+    pub enabled: Option<bool>,
+    /// HTTPS port, 0 picks a free port.
+    pub port: Option<u16>,
+    /// PEM certificate chain. Without `cert` and `key` a self-signed certificate is generated.
+    pub cert: Option<String>,
+    /// PEM private key of `cert`.
+    pub key: Option<String>,
+    /// Don't serve plain HTTP at all.
+    pub only: Option<bool>,
+}
+
+/// `[auth]`
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct AuthSection {
+    /// Defaults to `basic` when users are configured.
+    pub scheme: Option<AuthScheme>,
+    pub realm: Option<String>,
+    /// User names and their passwords.
+    #[serde(default)]
+    pub users: BTreeMap<String, String>,
+}
+
+/// A `[[device]]` entry. Unset values fall back to the top level settings.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct DeviceEntry {
+    pub profile: Option<Profile>,
+    pub port: Option<u16>,
+    pub tls_port: Option<u16>,
+    pub scope: Option<String>,
+    pub identity: Option<IdentityFile>,
+    #[serde(default)]
+    pub capabilities: CapabilitiesSection,
+    #[serde(default)]
+    pub sources: SourcesSection,
+    #[serde(default)]
+    pub discovery: DiscoverySection,
+    #[serde(default)]
+    pub faults: FaultsSection,
+}
+
+impl ConfigFile {
+    pub(crate) fn load(path: &str) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("couldn't read config file {path}: {e}"))?;
+        toml::from_str(&content).map_err(|e| format!("invalid config file {path}: {e}"))
+    }
+}
+
+// 各个配置段的合并：`over` 中设置的值优先
+impl CapabilitiesSection {
+    fn merge(&self, over: &CapabilitiesSection) -> CapabilitiesSection {
+        CapabilitiesSection {
+            file: over.file.clone().or_else(|| self.file.clone()),
+            escl_version: over.escl_version.or(self.escl_version),
+            platen: over.platen.or(self.platen),
+            adf: over.adf.or(self.adf),
+            adf_duplex: over.adf_duplex.or(self.adf_duplex),
+            camera: over.camera.or(self.camera),
+            color_modes: over
+                .color_modes
+                .clone()
+                .or_else(|| self.color_modes.clone()),
+            document_formats: over
+                .document_formats
+                .clone()
+                .or_else(|| self.document_formats.clone()),
+            resolutions: over
+                .resolutions
+                .clone()
+                .or_else(|| self.resolutions.clone()),
+            intents: over.intents.clone().or_else(|| self.intents.clone()),
+            feeder_capacity: over.feeder_capacity.or(self.feeder_capacity),
+        }
+    }
+
+    fn is_adjusted(&self) -> bool {
+        self.escl_version.is_some()
+            || self.platen.is_some()
+            || self.adf.is_some()
+            || self.adf_duplex.is_some()
+            || self.camera.is_some()
+            || self.color_modes.is_some()
+            || self.document_formats.is_some()
+            || self.resolutions.is_some()
+            || self.intents.is_some()
+            || self.feeder_capacity.is_some()
+    }
+
+    /// Returns the capabilities model and, for a `file`, its content which is served verbatim.
+    fn resolve(
+        self,
+        base: CapabilitiesBuilder,
+    ) -> Result<(ScannerCapabilities, Option<String>), String> {
+        if let Some(file) = &self.file {
+            if self.escl_version.is_some() {
+                return Err(format!(
+                    "the eSCL version can't be set together with capabilities file {file}, \
+                     change its <pwg:Version> instead"
+                ));
+            }
+            if self.is_adjusted() {
+                return Err(format!(
+                    "capabilities.file {file} can't be combined with other capabilities settings"
+                ));
+            }
+            let content = std::fs::read_to_string(file)
+                .map_err(|e| format!("couldn't read scanner capabilities {file}: {e}"))?;
+            let capabilities = parse_capabilities(&content, file)?;
+            return Ok((capabilities, Some(content)));
+        }
+
+        let mut builder = base;
+        if let Some(version) = self.escl_version {
+            builder = builder.version(version);
+        }
+        if let Some(platen) = self.platen {
+            builder = builder.platen(platen);
+        }
+        if let Some(adf) = self.adf {
+            builder = builder.adf(adf);
+        }
+        if let Some(duplex) = self.adf_duplex {
+            builder = builder.adf_duplex(duplex);
+        }
+        if let Some(camera) = self.camera {
+            builder = builder.camera(camera);
+        }
+        if let Some(color_modes) = self.color_modes {
+            builder = builder.color_modes(color_modes);
+        }
+        if let Some(formats) = self.document_formats {
+            builder = builder.document_formats(formats);
+        }
+        if let Some(resolutions) = &self.resolutions {
+            builder = builder.resolutions(resolutions);
+        }
+        if let Some(intents) = self.intents {
+            builder = builder.intents(intents);
+        }
+        if let Some(capacity) = self.feeder_capacity {
+            builder = builder.feeder_capacity(capacity);
+        }
+        let capabilities = builder.build();
+        capabilities.validate()?;
+        Ok((capabilities, None))
+    }
+}
+
+/// Checks a capabilities document like a `capabilities.file`. `name` tells
+/// where it came from in errors.
+pub(crate) fn parse_capabilities(content: &str, name: &str) -> Result<ScannerCapabilities, String> {
+    schema::validate_as(content, "scan:ScannerCapabilities").map_err(|errors| {
+        format!(
+            "scanner capabilities {name} don't match the eSCL schema:\n{}",
+            schema::describe(&errors)
+        )
+    })?;
+    let capabilities = ScannerCapabilities::from_xml(content)
+        .map_err(|e| format!("scanner capabilities {name}: {e}"))?;
+    capabilities
+        .validate()
+        .map_err(|e| format!("scanner capabilities {name}: {e}"))?;
+    Ok(capabilities)
+}
+
+impl SourcesSection {
+    fn merge(&self, over: &SourcesSection) -> SourcesSection {
+        SourcesSection {
+            platen_pages: over.platen_pages.or(self.platen_pages),
+            adf_pages: over.adf_pages.or(self.adf_pages),
+            images: over.images.clone().or_else(|| self.images.clone()),
+        }
+    }
+}
+
+impl DiscoverySection {
+    fn merge(&self, over: &DiscoverySection) -> DiscoverySection {
+        let mut txt = self.txt.clone();
+        txt.extend(over.txt.clone());
+        DiscoverySection {
+            mdns: over.mdns.or(self.mdns),
+            ssdp: over.ssdp.or(self.ssdp),
+            wsd: over.wsd.or(self.wsd),
+            hostname: over.hostname.clone().or_else(|| self.hostname.clone()),
+            instance_name: over
+                .instance_name
+                .clone()
+                .or_else(|| self.instance_name.clone()),
+            txt,
+        }
+    }
+}
+
+impl FaultsSection {
+    fn merge(&self, over: &FaultsSection) -> FaultsSection {
+        FaultsSection {
+            scan_job_delay_ms: over.scan_job_delay_ms.or(self.scan_job_delay_ms),
+            next_document_delay_ms: over.next_document_delay_ms.or(self.next_document_delay_ms),
+            busy: over.busy.or(self.busy),
+        }
+    }
+}
+
+/// Fully resolved settings of the whole process.
+#[derive(Debug, Clone)]
+pub(crate) struct Settings {
+    pub bind_address: BindAddress,
+    pub interfaces: Vec<String>,
+    pub advertise_addrs: Vec<IpAddr>,
+    pub log_level: LogLevel,
+    pub log_format: LogFormat,
+    pub quiet: bool,
+    pub validation: ValidationSettings,
+    /// Client conformance report at `/_report` and on shutdown.
+    pub report: bool,
+    /// Zero stops immediately, dropping open connections.
+    pub drain_timeout: Duration,
+    /// HTTPS listeners, `None` serves plain HTTP only.
+    pub tls: Option<TlsSettings>,
+    /// Authentication of the eSCL endpoints and the admin page, `None` allows everyone.
+    pub auth: Option<AuthSettings>,
+    pub devices: Vec<DeviceSettings>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct TlsSettings {
+    /// Certificate and key files, `None` generates a self-signed certificate.
+    pub cert: Option<(String, String)>,
+    /// Serve HTTPS only, without the plain HTTP listeners.
+    pub only: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct AuthSettings {
+    pub scheme: AuthScheme,
+    pub realm: String,
+    pub users: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct ValidationSettings {
+    /// Log schema violations of outgoing documents and incoming tickets.
+    pub xml: bool,
+    /// Reject invalid tickets instead of only logging them.
+    pub strict: bool,
+}
+
+/// Fully resolved settings of one virtual scanner.
+#[derive(Debug, Clone)]
+pub(crate) struct DeviceSettings {
+    pub profile: Option<Profile>,
+    pub port: u16,
+    /// Only used when TLS is enabled.
+    pub tls_port: u16,
+    pub scope: String,
+    pub capabilities: ScannerCapabilities,
+    /// Content of a capabilities file, served instead of the rendered model.
+    pub capabilities_document: Option<String>,
+    /// Path of that file.
+    pub capabilities_file: Option<String>,
+    pub images: Vec<String>,
+    pub identity: DeviceIdentity,
+    pub sources: SourceSettings,
+    pub discovery: DiscoverySettings,
+    pub faults: FaultSettings,
+    pub quirks: Quirks,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SourceSettings {
+    pub platen_pages: u32,
+    pub adf_pages: u32,
+    /// Taken from the capabilities.
+    pub adf_duplex: bool,
+}
+
+impl SourceSettings {
+    /// Number of pages a job produces.
+    pub(crate) fn pages(&self, source: ScanSource, duplex: bool) -> u32 {
+        match source {
+            ScanSource::Platen => self.platen_pages,
+            ScanSource::Adf if duplex && self.adf_duplex => self.adf_pages.saturating_mul(2),
+            ScanSource::Adf => self.adf_pages,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct DiscoverySettings {
+    pub mdns: bool,
+    pub ssdp: bool,
+    pub wsd: bool,
+    pub txt_layout: TxtLayout,
+    pub hostname: String,
+    pub instance_name: String,
+    pub txt: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct FaultSettings {
+    pub scan_job_delay: Duration,
+    pub next_document_delay: Duration,
+    pub busy: bool,
+}
+
+// NextDocument 同样接受以 / 开头的相对路径，这里使用相同的规则
+fn image_exists(image: &str) -> bool {
+    Path::new(image).is_file()
+        || image
+            .strip_prefix(['/', '\\'])
+            .is_some_and(|relative| Path::new(relative).is_file())
+}
+
+/// Combines the command line options and the optional config file into the
+/// settings of the process and the list of devices to serve.
+pub(crate) fn resolve(args: &Cli, file: Option<ConfigFile>) -> Result<Settings, String> {
+    let file = file.unwrap_or_default();
+
+    // 命令行参数覆盖配置文件的顶层配置段
+    let bind_address = args
+        .binding_address
+        .clone()
+        .or(file.network.bind_address)
+        .unwrap_or_else(|| "127.0.0.1".to_owned());
+    let bind_address: BindAddress = bind_address
+        .parse()
+        .map_err(|e| format!("network.bind_address: {e}"))?;
+    let port = args.port.or(file.network.port).unwrap_or(8080);
+    let tls_port = args.tls_port.or(file.tls.port).unwrap_or(8443);
+    let scope = args
+        .scope
+        .clone()
+        .or(file.network.scope)
+        .unwrap_or_else(|| "/eSCL".to_owned());
+
+    let profile = args.profile.or(file.profile);
+    let mut identity = file.identity;
+    if let Some(path) = &args.identity_file {
+        identity = identity.merge(IdentityFile::from_file(path)?);
+    }
+    let capabilities = file.capabilities.merge(&CapabilitiesSection {
+        file: args.scanner_caps_file.clone(),
+        escl_version: args.escl_version,
+        ..CapabilitiesSection::default()
+    });
+    let mut txt = BTreeMap::new();
+    for record in &args.txt {
+        let Some((key, value)) = record.split_once('=') else {
+            return Err(format!("--txt {record:?} must be KEY=VALUE"));
+        };
+        txt.insert(key.to_owned(), value.to_owned());
+    }
+    let discovery = file.discovery.merge(&DiscoverySection {
+        mdns: args.no_mdns.then_some(false),
+        ssdp: args.no_ssdp.then_some(false),
+        wsd: args.no_wsd.then_some(false),
+        hostname: args.mdns_hostname.clone(),
+        instance_name: args.mdns_name.clone(),
+        txt,
+    });
+    let sources = file.sources.merge(&SourcesSection {
+        images: (!args.served_images.is_empty()).then(|| args.served_images.clone()),
+        ..SourcesSection::default()
+    });
+
+    let entries = if file.devices.is_empty() {
+        vec![DeviceEntry::default()]
+    } else {
+        file.devices
+    };
+    let multiple = entries.len() > 1;
+
+    let mut devices = Vec::with_capacity(entries.len());
+    for (index, entry) in entries.into_iter().enumerate() {
+        let device = resolve_device(
+            entry,
+            DeviceDefaults {
+                profile,
+                port,
+                tls_port,
+                scope: &scope,
+                identity: &identity,
+                capabilities: &capabilities,
+                sources: &sources,
+                discovery: &discovery,
+                faults: &file.faults,
+                multiple,
+            },
+        )
+        .map_err(|e| format!("device {index}: {e}"))?;
+        devices.push(device);
+    }
+    let tls = resolve_tls(args, &file.tls)?;
+    let auth = resolve_auth(args, file.auth)?;
+    validate_unique(&devices, tls.as_ref())?;
+
+    let interfaces = if args.interfaces.is_empty() {
+        file.network.interfaces.unwrap_or_default()
+    } else {
+        args.interfaces.clone()
+    };
+    let advertise_addrs = if args.advertise_addrs.is_empty() {
+        file.network.advertise_addrs.unwrap_or_default()
+    } else {
+        args.advertise_addrs.clone()
+    };
+
+    Ok(Settings {
+        bind_address,
+        interfaces,
+        advertise_addrs,
+        log_level: args
+            .log_level
+            .or(file.logging.level)
+            .unwrap_or(LogLevel::Info),
+        log_format: args
+            .log_format
+            .or(file.logging.format)
+            .unwrap_or(LogFormat::Pretty),
+        quiet: args.quiet || file.logging.quiet.unwrap_or(false),
+        validation: ValidationSettings {
+            xml: args.validate_xml || file.validation.xml.unwrap_or(false),
+            strict: args.strict || file.validation.strict.unwrap_or(false),
+        },
+        report: args.report || file.report.enabled.unwrap_or(false),
+        drain_timeout: Duration::from_secs(
+            args.drain_timeout
+                .or(file.shutdown.drain_timeout_secs)
+                .unwrap_or(0),
+        ),
+        tls,
+        auth,
+        devices,
+    })
+}
+
+/// Loads the config file again and resolves it like [`resolve`]. Devices
+/// without a configured UUID keep their `current` one instead of a new random
+/// identity.
+pub(crate) fn reload(args: &Cli, current: &[DeviceSettings]) -> Result<Settings, String> {
+    let mut file = args
+        .config_file
+        .as_deref()
+        .map(ConfigFile::load)
+        .transpose()?
+        .unwrap_or_default();
+    if file.devices.is_empty() {
+        if let Some(device) = current.first() {
+            file.identity.uuid.get_or_insert(device.identity.uuid);
+        }
+    } else {
+        for (entry, device) in file.devices.iter_mut().zip(current) {
+            entry
+                .identity
+                .get_or_insert_with(IdentityFile::default)
+                .uuid
+                .get_or_insert(device.identity.uuid);
+        }
+    }
+    resolve(args, Some(file))
+}
+
+fn resolve_tls(args: &Cli, section: &TlsSection) -> Result<Option<TlsSettings>, String> {
+    let only = args.tls_only || section.only.unwrap_or(false);
+    let enabled = args.tls || only || section.enabled.unwrap_or(false);
+    let cert = args.tls_cert.clone().or_else(|| section.cert.clone());
+    let key = args.tls_key.clone().or_else(|| section.key.clone());
+    if !enabled {
+        if cert.is_some() || key.is_some() {
+            return Err(
+                "tls.cert and tls.key require TLS to be enabled with --tls or tls.enabled"
+                    .to_owned(),
+            );
+        }
+        return Ok(None);
+    }
+    let cert = match (cert, key) {
+        (Some(cert), Some(key)) => Some((cert, key)),
+        (None, None) => None,
+        _ => return Err("tls.cert and tls.key must be set together".to_owned()),
+    };
+    Ok(Some(TlsSettings { cert, only }))
+}
+
+fn resolve_auth(args: &Cli, section: AuthSection) -> Result<Option<AuthSettings>, String> {
+    let mut users = section.users;
+    for user in &args.auth_users {
+        let Some((name, password)) = user.split_once(':') else {
+            return Err(format!("--auth-user {user:?} must be USER:PASSWORD"));
+        };
+        users.insert(name.to_owned(), password.to_owned());
+    }
+    let scheme = args.auth.or(section.scheme);
+    if users.is_empty() {
+        return match scheme {
+            Some(scheme) => Err(format!(
+                "{scheme} authentication needs at least one user in auth.users or --auth-user"
+            )),
+            None => Ok(None),
+        };
+    }
+    if let Some(name) = users
+        .keys()
+        .find(|name| name.is_empty() || name.contains(':'))
+    {
+        return Err(format!(
+            "auth user name {name:?} must be non-empty and not contain ':'"
+        ));
+    }
+    let realm = section
+        .realm
+        .unwrap_or_else(|| "eSCL Mock Scanner".to_owned());
+    if realm.contains('"') {
+        return Err(format!("auth.realm {realm:?} must not contain '\"'"));
+    }
+    Ok(Some(AuthSettings {
+        scheme: scheme.unwrap_or(AuthScheme::Basic),
+        realm,
+        users,
+    }))
+}
+
+/// Resolves a device embedded in another process. It gets an ephemeral port
+/// and isn't announced over mDNS.
+pub(crate) fn resolve_embedded(mut entry: DeviceEntry) -> Result<DeviceSettings, String> {
+    entry.port = Some(0);
+    entry.tls_port = Some(0);
+    entry.discovery.mdns = Some(false);
+    entry.discovery.ssdp = Some(false);
+    entry.discovery.wsd = Some(false);
+    resolve_device(
+        entry,
+        DeviceDefaults {
+            profile: None,
+            port: 0,
+            tls_port: 0,
+            scope: "/eSCL",
+            identity: &IdentityFile::default(),
+            capabilities: &CapabilitiesSection::default(),
+            sources: &SourcesSection::default(),
+            discovery: &DiscoverySection::default(),
+            faults: &FaultsSection::default(),
+            multiple: false,
+        },
+    )
+}
+
+struct DeviceDefaults<'a> {
+    profile: Option<Profile>,
+    port: u16,
+    tls_port: u16,
+    scope: &'a str,
+    identity: &'a IdentityFile,
+    capabilities: &'a CapabilitiesSection,
+    sources: &'a SourcesSection,
+    discovery: &'a DiscoverySection,
+    faults: &'a FaultsSection,
+    multiple: bool,
+}
+
+fn resolve_device(entry: DeviceEntry, defaults: DeviceDefaults) -> Result<DeviceSettings, String> {
+    let profile = entry.profile.or(defaults.profile);
+    let port = entry.port.unwrap_or(defaults.port);
+    let tls_port = entry.tls_port.unwrap_or(defaults.tls_port);
+    let scope = entry.scope.unwrap_or_else(|| defaults.scope.to_owned());
+    if !scope.starts_with('/') {
+        return Err(format!("scope {scope:?} must start with '/'"));
+    }
+
+    // 多设备时，顶层身份只能提供默认值，UUID、序列号和名称必须每台设备不同
+    let base_identity = if defaults.multiple {
+        IdentityFile {
+            uuid: None,
+            serial_number: None,
+            friendly_name: None,
+            ..defaults.identity.clone()
+        }
+    } else {
+        defaults.identity.clone()
+    };
+    // 配置文件和命令行中的值覆盖预设中的值
+    let profile_identity = profile
+        .map(|profile| profile.identity())
+        .unwrap_or_default();
+    let identity = DeviceIdentity::from_partial(
+        profile_identity
+            .merge(base_identity)
+            .merge(entry.identity.unwrap_or_default()),
+    );
+    identity.validate()?;
+
+    let base_capabilities = match profile {
+        Some(profile) => profile.capabilities(),
+        None => ScannerCapabilities::builder(),
+    };
+    let capabilities_section = defaults.capabilities.merge(&entry.capabilities);
+    let capabilities_file = capabilities_section.file.clone();
+    let (capabilities, capabilities_document) = capabilities_section.resolve(base_capabilities)?;
+
+    // 生成的文档同样需要符合规范，失败说明能力设置的组合有问题
+    if capabilities_document.is_none() {
+        let rendered = capabilities.to_xml(&identity, "http://localhost", &scope);
+        schema::validate_as(&rendered, "scan:ScannerCapabilities").map_err(|errors| {
+            format!(
+                "generated capabilities don't match the eSCL schema:\n{}",
+                schema::describe(&errors)
+            )
+        })?;
+    }
+
+    let sources = defaults.sources.merge(&entry.sources);
+    let images = sources.images.unwrap_or_default();
+    if let Some(missing) = images.iter().find(|image| !image_exists(image)) {
+        return Err(format!("sources.images: {missing} doesn't exist"));
+    }
+    let sources = SourceSettings {
+        platen_pages: sources.platen_pages.unwrap_or(1),
+        adf_pages: sources.adf_pages.unwrap_or(5),
+        adf_duplex: capabilities.supports_duplex(),
+    };
+    if sources.platen_pages == 0 || sources.adf_pages == 0 {
+        return Err("sources.platen_pages and sources.adf_pages must be at least 1".to_owned());
+    }
+
+    let mut discovery_defaults = defaults.discovery.clone();
+    if defaults.multiple {
+        discovery_defaults.instance_name = None;
+    }
+    let discovery = discovery_defaults.merge(&entry.discovery);
+    let discovery = DiscoverySettings {
+        mdns: discovery.mdns.unwrap_or(true),
+        ssdp: discovery.ssdp.unwrap_or(true),
+        wsd: discovery.wsd.unwrap_or(true),
+        txt_layout: profile.map_or(GENERIC_TXT, |profile| profile.txt_layout()),
+        hostname: discovery
+            .hostname
+            .unwrap_or_else(|| "escl-mock-scanner.local.".to_owned()),
+        instance_name: discovery
+            .instance_name
+            .unwrap_or_else(|| identity.friendly_name.clone()),
+        txt: discovery.txt,
+    };
+    if !discovery.hostname.ends_with(".local.") {
+        return Err(format!(
+            "discovery.hostname {:?} must end with \".local.\"",
+            discovery.hostname
+        ));
+    }
+    if discovery.instance_name.trim().is_empty() {
+        return Err("discovery.instance_name must not be empty".to_owned());
+    }
+    if let Some(key) = discovery
+        .txt
+        .keys()
+        .find(|key| key.is_empty() || key.contains('='))
+    {
+        return Err(format!(
+            "discovery.txt key {key:?} must be non-empty and not contain '='"
+        ));
+    }
+    // 每条 TXT 记录 (key=value) 最长 255 字节
+    if let Some((key, _)) = discovery
+        .txt
+        .iter()
+        .find(|(key, value)| key.len() + value.len() + 1 > 255)
+    {
+        return Err(format!(
+            "discovery.txt record {key:?} is longer than 255 bytes"
+        ));
+    }
+
+    let faults = defaults.faults.merge(&entry.faults);
+    let faults = FaultSettings {
+        scan_job_delay: Duration::from_millis(faults.scan_job_delay_ms.unwrap_or(0)),
+        next_document_delay: Duration::from_millis(faults.next_document_delay_ms.unwrap_or(0)),
+        busy: faults.busy.unwrap_or(false),
+    };
+
+    Ok(DeviceSettings {
+        profile,
+        port,
+        tls_port,
+        scope,
+        capabilities,
+        capabilities_document,
+        capabilities_file,
+        images,
+        identity,
+        sources,
+        discovery,
+        faults,
+        quirks: profile.map(|profile| profile.quirks()).unwrap_or_default(),
+    })
+}
+
+// 同一端口上的设备必须使用不同的路径，名称和UUID也不能重复，否则客户端会混淆
+fn validate_unique(devices: &[DeviceSettings], tls: Option<&TlsSettings>) -> Result<(), String> {
+    let mut endpoints = HashSet::new();
+    let mut tls_endpoints = HashSet::new();
+    // 所有设备共用一张端口表，一个端口只能提供 HTTP 或 HTTPS 中的一种
+    let mut protocols = HashMap::new();
+    let mut serve = |port: u16, protocol: &'static str| -> Result<(), String> {
+        if port == 0 {
+            return Ok(());
+        }
+        match protocols.insert(port, protocol) {
+            Some(other) if other != protocol => Err(format!(
+                "port {port} can't serve both HTTP and HTTPS, set a different port or tls_port"
+            )),
+            _ => Ok(()),
+        }
+    };
+    let mut uuids = HashSet::new();
+    let mut names = HashSet::new();
+    let mut instances = HashSet::new();
+    for device in devices {
+        let plain = tls.is_none_or(|tls| !tls.only);
+        if plain && !endpoints.insert((device.port, device.scope.as_str())) {
+            return Err(format!(
+                "two devices are configured for port {} and scope {:?}",
+                device.port, device.scope
+            ));
+        }
+        if tls.is_some() && !tls_endpoints.insert((device.tls_port, device.scope.as_str())) {
+            return Err(format!(
+                "two devices are configured for TLS port {} and scope {:?}",
+                device.tls_port, device.scope
+            ));
+        }
+        if plain {
+            serve(device.port, "HTTP")?;
+        }
+        if tls.is_some() {
+            serve(device.tls_port, "HTTPS")?;
+        }
+        if !uuids.insert(device.identity.uuid) {
+            return Err(format!("duplicate device uuid {}", device.identity.uuid));
+        }
+        if !names.insert(device.identity.friendly_name.as_str()) {
+            return Err(format!(
+                "duplicate device friendly name {:?}",
+                device.identity.friendly_name
+            ));
+        }
+        if device.discovery.mdns && !instances.insert(device.discovery.instance_name.as_str()) {
+            return Err(format!(
+                "duplicate mDNS instance name {:?}",
+                device.discovery.instance_name
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn resolve_toml(args: &[&str], toml: &str) -> Result<Settings, String> {
+        let args = Cli::parse_from(std::iter::once("escl-mock-server").chain(args.iter().copied()));
+        resolve(&args, Some(toml::from_str(toml).unwrap()))
+    }
+
+    #[test]
+    fn defaults_without_config_file() {
+        let args = Cli::parse_from(["escl-mock-server"]);
+        let settings = resolve(&args, None).unwrap();
+        assert_eq!(settings.bind_address, BindAddress::LOCALHOST);
+        assert_eq!(settings.log_level, LogLevel::Info);
+        assert_eq!(settings.drain_timeout, Duration::ZERO);
+        assert!(settings.tls.is_none() && settings.auth.is_none());
+        let [device] = &settings.devices[..] else {
+            panic!("expected one device");
+        };
+        assert_eq!((device.port, device.tls_port), (8080, 8443));
+        assert_eq!(device.scope, "/eSCL");
+        assert_eq!(device.sources.platen_pages, 1);
+        assert_eq!(device.sources.adf_pages, 5);
+        assert!(device.discovery.mdns && device.discovery.ssdp && device.discovery.wsd);
+        assert_eq!(device.discovery.hostname, "escl-mock-scanner.local.");
+        assert_eq!(
+            device.discovery.instance_name,
+            device.identity.friendly_name
+        );
+    }
+
+    #[test]
+    fn command_line_overrides_file() {
+        let toml = r#"
+            profile = "canon-pixma"
+            [network]
+            bind_address = "0.0.0.0"
+            port = 9000
+            scope = "/scanner"
+            [logging]
+            level = "debug"
+            quiet = true
+            [discovery]
+            mdns = true
+            instance_name = "From file"
+            txt = { note = "file", ty = "File" }
+            [shutdown]
+            drain_timeout_secs = 5
+            "#;
+        let settings = resolve_toml(&[], toml).unwrap();
+        let device = &settings.devices[0];
+        assert_eq!(device.port, 9000);
+        assert_eq!(device.profile, Some(Profile::CanonPixma));
+        assert_eq!(device.discovery.instance_name, "From file");
+        assert_eq!(settings.log_level, LogLevel::Debug);
+        assert!(settings.quiet);
+
+        let settings = resolve_toml(
+            &[
+                "--port",
+                "9100",
+                "--bindaddr",
+                "::1",
+                "--profile",
+                "hp-officejet",
+                "--log-level",
+                "warn",
+                "--no-mdns",
+                "--mdns-name",
+                "From CLI",
+                "--txt",
+                "ty=CLI",
+                "--drain-timeout",
+                "1",
+            ],
+            toml,
+        )
+        .unwrap();
+        let device = &settings.devices[0];
+        assert_eq!(device.port, 9100);
+        assert_eq!(device.scope, "/scanner");
+        assert_eq!(settings.bind_address.to_string(), "::1");
+        assert_eq!(device.profile, Some(Profile::HpOfficejet));
+        assert_eq!(device.identity.make, "HP");
+        assert_eq!(settings.log_level, LogLevel::Warn);
+        // 布尔选项只能打开，不能关闭配置文件中的设置
+        assert!(settings.quiet);
+        assert!(!device.discovery.mdns);
+        assert_eq!(device.discovery.instance_name, "From CLI");
+        assert_eq!(device.discovery.txt["ty"], "CLI");
+        assert_eq!(device.discovery.txt["note"], "file");
+        assert_eq!(settings.drain_timeout, Duration::from_secs(1));
+    }
+
+    #[test]
+    fn devices_fall_back_to_top_level_sections() {
+        let settings = resolve_toml(
+            &["--image", "res/example_image.jpg"],
+            r#"
+            [identity]
+            make = "Acme"
+            friendly_name = "Shared"
+            serial_number = "SHARED"
+            [sources]
+            adf_pages = 3
+            [faults]
+            scan_job_delay_ms = 250
+            [discovery]
+            instance_name = "Shared"
+            [[device]]
+            port = 9001
+            [[device]]
+            port = 9002
+            profile = "brother-mfc"
+            identity = { friendly_name = "Second" }
+            sources = { adf_pages = 7 }
+            faults = { busy = true }
+            "#,
+        )
+        .unwrap();
+        let [first, second] = &settings.devices[..] else {
+            panic!("expected two devices");
+        };
+        assert_eq!((first.port, second.port), (9001, 9002));
+        assert_eq!(first.identity.make, "Acme");
+        // 多台设备时顶层的名称和序列号不能共用
+        assert_ne!(first.identity.friendly_name, "Shared");
+        assert_ne!(first.identity.serial_number, "SHARED");
+        assert_eq!(first.discovery.instance_name, first.identity.friendly_name);
+        assert_eq!(second.identity.friendly_name, "Second");
+        assert_eq!(second.discovery.instance_name, "Second");
+        // 配置文件中的身份覆盖预设
+        assert_eq!(second.identity.make, "Acme");
+        assert_eq!(second.identity.model, "MFC-L2750DW series");
+        assert_eq!((first.sources.adf_pages, second.sources.adf_pages), (3, 7));
+        assert_eq!(first.images, ["res/example_image.jpg"]);
+        assert_eq!(second.images, ["res/example_image.jpg"]);
+        assert_eq!(first.faults.scan_job_delay, Duration::from_millis(250));
+        assert_eq!(second.faults.scan_job_delay, Duration::from_millis(250));
+        assert!(!first.faults.busy && second.faults.busy);
+    }
+
+    #[test]
+    fn rejects_duplicate_identities() {
+        let uuid = "0d3f4c5e-1a2b-4c3d-8e9f-001122334455";
+        let error = resolve_toml(
+            &[],
+            &format!(
+                r#"
+                [[device]]
+                port = 9001
+                identity = {{ uuid = "{uuid}" }}
+                [[device]]
+                port = 9002
+                identity = {{ uuid = "{uuid}" }}
+                "#
+            ),
+        )
+        .unwrap_err();
+        assert_eq!(error, format!("duplicate device uuid {uuid}"));
+
+        let error = resolve_toml(
+            &[],
+            r#"
+            [[device]]
+            port = 9001
+            identity = { friendly_name = "Scanner" }
+            [[device]]
+            port = 9002
+            identity = { friendly_name = "Scanner" }
+            "#,
+        )
+        .unwrap_err();
+        assert_eq!(error, r#"duplicate device friendly name "Scanner""#);
+
+        let instances = r#"
+            [[device]]
+            port = 9001
+            discovery = { instance_name = "Scanner" }
+            [[device]]
+            port = 9002
+            discovery = { instance_name = "Scanner" }
+            "#;
+        let error = resolve_toml(&[], instances).unwrap_err();
+        assert_eq!(error, r#"duplicate mDNS instance name "Scanner""#);
+        // 不通过 mDNS 宣告时实例名称不会冲突
+        assert!(resolve_toml(&["--no-mdns"], instances).is_ok());
+    }
+
+    #[test]
+    fn reports_invalid_values() {
+        let error = resolve_toml(&[], "[network]\nscope = \"eSCL\"").unwrap_err();
+        assert_eq!(error, r#"device 0: scope "eSCL" must start with '/'"#);
+
+        let error = resolve_toml(&[], "[network]\nbind_address = \"localhost\"").unwrap_err();
+        assert!(error.starts_with("network.bind_address: "), "{error}");
+
+        let error = resolve_toml(&["--txt", "ty"], "").unwrap_err();
+        assert_eq!(error, r#"--txt "ty" must be KEY=VALUE"#);
+
+        let error = resolve_toml(&[], "[discovery]\nhostname = \"scanner\"").unwrap_err();
+        assert_eq!(
+            error,
+            r#"device 0: discovery.hostname "scanner" must end with ".local.""#
+        );
+
+        let error = resolve_toml(&[], "[sources]\nimages = [\"missing.jpg\"]").unwrap_err();
+        assert_eq!(error, "device 0: sources.images: missing.jpg doesn't exist");
+
+        let error = resolve_toml(&[], "[tls]\ncert = \"cert.pem\"\nkey = \"key.pem\"").unwrap_err();
+        assert_eq!(
+            error,
+            "tls.cert and tls.key require TLS to be enabled with --tls or tls.enabled"
+        );
+
+        let error = resolve_toml(&["--auth", "digest"], "").unwrap_err();
+        assert_eq!(
+            error,
+            "digest authentication needs at least one user in auth.users or --auth-user"
+        );
+
+        assert!(toml::from_str::<ConfigFile>("[network]\nprot = 8080").is_err());
+    }
+
+    #[test]
+    fn devices_on_one_port_need_different_scopes() {
+        let settings = resolve_toml(
+            &[],
+            r#"
+            [[device]]
+            port = 9001
+            [[device]]
+            port = 9001
+            scope = "/scanner2"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(settings.devices.len(), 2);
+
+        let error = resolve_toml(
+            &[],
+            r#"
+            [[device]]
+            port = 9001
+            [[device]]
+            port = 9001
+            "#,
+        )
+        .unwrap_err();
+        assert_eq!(
+            error,
+            r#"two devices are configured for port 9001 and scope "/eSCL""#
+        );
+    }
+
+    #[test]
+    fn rejects_http_and_https_on_one_port_across_devices() {
+        let error = resolve_toml(
+            &[],
+            r#"
+            [tls]
+            enabled = true
+            [[device]]
+            port = 9001
+            tls_port = 9443
+            [[device]]
+            port = 9002
+            tls_port = 9001
+            "#,
+        )
+        .unwrap_err();
+        assert_eq!(
+            error,
+            "port 9001 can't serve both HTTP and HTTPS, set a different port or tls_port"
+        );
+
+        // 只提供 HTTPS 时普通端口不会被使用
+        let settings = resolve_toml(
+            &[],
+            r#"
+            [tls]
+            only = true
+            [[device]]
+            port = 9001
+            tls_port = 9443
+            [[device]]
+            port = 9002
+            tls_port = 9001
+            "#,
+        )
+        .unwrap();
+        assert_eq!(settings.devices[1].tls_port, 9001);
+    }
+
+    #[test]
+    fn reload_reports_unreadable_and_invalid_files() {
+        let path = std::env::temp_dir().join(format!("escl-reload-{}.toml", std::process::id()));
+        let path_str = path.to_str().unwrap();
+        let args = Cli::parse_from(["escl-mock-server", "--config", path_str]);
+        let current = resolve(&Cli::parse_from(["escl-mock-server"]), None).unwrap();
+
+        let error = reload(&args, &current.devices).unwrap_err();
+        assert!(error.starts_with(&format!("couldn't read config file {path_str}: ")));
+
+        std::fs::write(&path, "[network\nport = 9000").unwrap();
+        let error = reload(&args, &current.devices).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert!(error.starts_with(&format!("invalid config file {path_str}: ")));
+    }
+
+    #[test]
+    fn reload_keeps_generated_device_uuids() {
+        let path =
+            std::env::temp_dir().join(format!("escl-reload-uuid-{}.toml", std::process::id()));
+        let args = Cli::parse_from(["escl-mock-server", "--config", path.to_str().unwrap()]);
+        let current =
+            resolve_toml(&[], "[[device]]\nport = 9001\n[[device]]\nport = 9002").unwrap();
+
+        std::fs::write(&path, "[[device]]\nport = 9001\n[[device]]\nport = 9002").unwrap();
+        let reloaded = reload(&args, &current.devices);
+        std::fs::write(&path, "[network]\nport = 9001").unwrap();
+        let single = reload(&args, &current.devices);
+        std::fs::remove_file(&path).unwrap();
+
+        let uuids = |settings: &Settings| -> Vec<uuid::Uuid> {
+            settings
+                .devices
+                .iter()
+                .map(|device| device.identity.uuid)
+                .collect()
+        };
+        assert_eq!(uuids(&reloaded.unwrap()), uuids(&current));
+        assert_eq!(uuids(&single.unwrap()), uuids(&current)[..1]);
+    }
+
+    #[test]
+    fn duplex_adf_doubles_pages_without_overflow() {
+        let mut sources = SourceSettings {
+            platen_pages: 1,
+            adf_pages: 5,
+            adf_duplex: true,
+        };
+        assert_eq!(sources.pages(ScanSource::Platen, true), 1);
+        assert_eq!(sources.pages(ScanSource::Adf, false), 5);
+        assert_eq!(sources.pages(ScanSource::Adf, true), 10);
+        sources.adf_pages = u32::MAX;
+        assert_eq!(sources.pages(ScanSource::Adf, true), u32::MAX);
+        sources.adf_duplex = false;
+        assert_eq!(sources.pages(ScanSource::Adf, true), u32::MAX);
+    }
+}
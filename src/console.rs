@@ -0,0 +1,449 @@
+/*
+ *     Copyright (C) 2024-2025 Christian Nagel and contributors
+ *
+ *     This file is part of escl-mock-server.
+ *
+ *     escl-mock-server is free software: you can redistribute it and/or modify it under the terms of
+ *     the GNU General Public License as published by the Free Software Foundation, either
+ *     version 3 of the License, or (at your option) any later version.
+ *
+ *     escl-mock-server is distributed in the hope that it will be useful, but WITHOUT ANY
+ *     WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ *     FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ *     You should have received a copy of the GNU General Public License along with eSCLKt.
+ *     If not, see <https://www.gnu.org/licenses/>.
+ *
+ *     SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+use crate::model::ScanJob;
+use crate::wsscan;
+use crate::{AppState, PortDevices};
+use actix_web::web::Bytes;
+use futures::Stream;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::fmt::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use tokio::time::MissedTickBehavior;
+use tracing::{debug, warn};
+
+/// Requests kept for the transcript, older ones are dropped.
+const TRANSCRIPT_CAPACITY: usize = 1000;
+/// Requests shown in the console.
+const RECENT_REQUESTS: usize = 50;
+/// How often the event stream checks the device for changes.
+const SNAPSHOT_INTERVAL: Duration = Duration::from_millis(500);
+/// Intervals without changes before a keep-alive comment is sent.
+const KEEPALIVE_INTERVALS: u32 = 30;
+
+static NEXT_UPLOAD: AtomicU32 = AtomicU32::new(1);
+
+/// A request as shown in the admin console and written to the transcript.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct RequestRecord {
+    pub id: u64,
+    /// RFC 3339 time the request was received.
+    pub time: String,
+    pub peer: String,
+    pub method: String,
+    pub uri: String,
+    pub user_agent: String,
+    pub status: u16,
+    pub elapsed_ms: f64,
+}
+
+/// The last requests a device answered.
+#[derive(Default)]
+pub(crate) struct RequestLog {
+    entries: Mutex<VecDeque<RequestRecord>>,
+}
+
+impl RequestLog {
+    pub(crate) fn record(&self, record: RequestRecord) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() == TRANSCRIPT_CAPACITY {
+            entries.pop_front();
+        }
+        entries.push_back(record);
+    }
+
+    // 最新的请求在前
+    fn recent(&self) -> Vec<RequestRecord> {
+        self.entries
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .take(RECENT_REQUESTS)
+            .cloned()
+            .collect()
+    }
+
+    /// One line per request, oldest first.
+    pub(crate) fn transcript(&self) -> String {
+        let mut transcript = String::new();
+        for record in self.entries.lock().unwrap().iter() {
+            let _ = writeln!(
+                transcript,
+                "{} #{} {} \"{} {}\" {} {:.1}ms \"{}\"",
+                record.time,
+                record.id,
+                record.peer,
+                record.method,
+                record.uri,
+                record.status,
+                record.elapsed_ms,
+                record.user_agent
+            );
+        }
+        transcript
+    }
+}
+
+pub(crate) fn timestamp() -> String {
+    OffsetDateTime::now_utc()
+        .format(&Rfc3339)
+        .unwrap_or_default()
+}
+
+#[derive(Serialize)]
+struct Snapshot<'a> {
+    device: DeviceView<'a>,
+    state: &'static str,
+    version: String,
+    sources: SourcesView,
+    faults: FaultsView,
+    images: &'a [String],
+    jobs: Vec<JobView>,
+    requests: Vec<RequestRecord>,
+}
+
+#[derive(Serialize)]
+struct DeviceView<'a> {
+    name: &'a str,
+    make_and_model: String,
+    serial_number: &'a str,
+    uuid: String,
+    firmware_version: &'a str,
+    scope: &'a str,
+}
+
+#[derive(Serialize)]
+struct SourcesView {
+    platen: bool,
+    adf: bool,
+    camera: bool,
+    adf_duplex: bool,
+    platen_pages: u32,
+    adf_sheets: u32,
+}
+
+#[derive(Serialize)]
+struct FaultsView {
+    busy: bool,
+    scan_job_delay_ms: u128,
+    next_document_delay_ms: u128,
+}
+
+#[derive(Serialize)]
+struct JobView {
+    uuid: String,
+    id: u32,
+    state: &'static str,
+    source: String,
+    retrieved_pages: u32,
+    max_pages: u32,
+    /// Started with the scan button and not yet claimed by the destination.
+    waiting_for_destination: bool,
+}
+
+impl JobView {
+    fn new(uuid: &uuid::Uuid, job: &ScanJob) -> Self {
+        JobView {
+            uuid: uuid.to_string(),
+            id: job.id,
+            state: job.state.pwg_state().0,
+            source: job.scan_source.to_string(),
+            retrieved_pages: job.retrieved_pages,
+            max_pages: job.max_pages,
+            waiting_for_destination: job.destination_token.is_some(),
+        }
+    }
+}
+
+/// A device served on the same port, for the console's device selector.
+#[derive(Serialize)]
+pub(crate) struct DeviceEntry<'a> {
+    name: &'a str,
+    uuid: String,
+    scope: &'a str,
+    console: String,
+}
+
+// 端口上的第一台设备同时提供根路径的 /admin
+pub(crate) fn devices(devices: &PortDevices) -> Vec<DeviceEntry<'_>> {
+    devices
+        .iter()
+        .map(|(scope, data)| DeviceEntry {
+            name: &data.identity.friendly_name,
+            uuid: data.identity.uuid.to_string(),
+            scope,
+            console: format!("{scope}/admin"),
+        })
+        .collect()
+}
+
+/// State of the device shown by the console, as JSON.
+async fn snapshot(data: &AppState) -> String {
+    let live = data.live();
+    let (state, mut jobs) = {
+        let jobs = data.scan_jobs.lock().await;
+        let views: Vec<JobView> = jobs
+            .iter()
+            .map(|(uuid, job)| JobView::new(uuid, job))
+            .collect();
+        (wsscan::scanner_state(data, &jobs), views)
+    };
+    jobs.sort_by_key(|job| std::cmp::Reverse(job.id));
+    let identity = &data.identity;
+    let snapshot = Snapshot {
+        device: DeviceView {
+            name: &identity.friendly_name,
+            make_and_model: identity.make_and_model(),
+            serial_number: &identity.serial_number,
+            uuid: identity.uuid.to_string(),
+            firmware_version: &identity.firmware_version,
+            scope: &data.scope,
+        },
+        state,
+        version: live.capabilities.version.to_string(),
+        sources: SourcesView {
+            platen: live.capabilities.platen.is_some(),
+            adf: live.capabilities.adf.is_some(),
+            camera: live.capabilities.camera.is_some(),
+            adf_duplex: live.sources.adf_duplex,
+            platen_pages: live.sources.platen_pages,
+            adf_sheets: live.sources.adf_pages,
+        },
+        faults: FaultsView {
+            busy: live.faults.busy,
+            scan_job_delay_ms: live.faults.scan_job_delay.as_millis(),
+            next_document_delay_ms: live.faults.next_document_delay.as_millis(),
+        },
+        images: &live.images,
+        jobs,
+        requests: data.requests.recent(),
+    };
+    serde_json::to_string(&snapshot).unwrap_or_default()
+}
+
+/// Server-sent events with a snapshot of the device each time it changes.
+pub(crate) fn events(data: Arc<AppState>) -> impl Stream<Item = Result<Bytes, Infallible>> {
+    let mut interval = tokio::time::interval(SNAPSHOT_INTERVAL);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    futures::stream::unfold(
+        (data, interval, String::new()),
+        |(data, mut interval, last)| async move {
+            let mut idle = 0;
+            loop {
+                interval.tick().await;
+                let current = snapshot(&data).await;
+                if current != last {
+                    let event = Bytes::from(format!("data: {current}\n\n"));
+                    return Some((Ok(event), (data, interval, current)));
+                }
+                // 长时间没有数据时代理可能关闭连接，注释行同时用于发现已断开的客户端
+                idle += 1;
+                if idle == KEEPALIVE_INTERVALS {
+                    return Some((
+                        Ok(Bytes::from_static(b": keep-alive\n\n")),
+                        (data, interval, last),
+                    ));
+                }
+            }
+        },
+    )
+}
+
+// 上传的图片保存在进程专用的临时目录中，退出时删除
+fn upload_dir() -> PathBuf {
+    std::env::temp_dir().join(format!("escl-mock-server-{}", std::process::id()))
+}
+
+/// Saves an image uploaded in the console and returns its path.
+pub(crate) async fn store_upload(data: &AppState, image: &[u8]) -> std::io::Result<String> {
+    let dir = upload_dir();
+    tokio::fs::create_dir_all(&dir).await?;
+    let path = dir.join(format!(
+        "{}-{}.jpg",
+        data.identity.uuid,
+        NEXT_UPLOAD.fetch_add(1, Ordering::Relaxed)
+    ));
+    tokio::fs::write(&path, image).await?;
+    Ok(path.to_string_lossy().into_owned())
+}
+
+/// Deletes the images uploaded in the console.
+pub(crate) fn remove_uploads() {
+    let dir = upload_dir();
+    if !dir.exists() {
+        return;
+    }
+    match std::fs::remove_dir_all(&dir) {
+        Ok(()) => debug!(path = %dir.display(), "Uploaded images removed"),
+        Err(e) => warn!(path = %dir.display(), error = %e, "Couldn't remove uploaded images"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::Cli;
+    use crate::config;
+    use crate::metrics::Metrics;
+    use crate::report::ConformanceReport;
+    use actix_web::http::StatusCode;
+    use actix_web::test::{call_service, init_service, read_body, TestRequest};
+    use actix_web::{web, App};
+    use clap::Parser;
+
+    fn record(id: u64) -> RequestRecord {
+        RequestRecord {
+            id,
+            time: "2026-10-18T09:30:00Z".to_owned(),
+            peer: "192.0.2.10:50000".to_owned(),
+            method: "GET".to_owned(),
+            uri: "/eSCL/ScannerStatus".to_owned(),
+            user_agent: "sane-airscan/0.99".to_owned(),
+            status: 200,
+            elapsed_ms: 1.25,
+        }
+    }
+
+    fn state() -> web::Data<AppState> {
+        let args = Cli::parse_from(["escl-mock-server"]);
+        let settings = config::resolve(&args, None).unwrap();
+        web::Data::new(AppState::new(
+            &settings.devices[0],
+            settings.validation,
+            settings.bind_address,
+            None,
+            Arc::new(Metrics::new()),
+            Arc::new(ConformanceReport::new(false)),
+        ))
+    }
+
+    #[test]
+    fn request_log_drops_oldest_requests() {
+        let log = RequestLog::default();
+        let total = TRANSCRIPT_CAPACITY as u64 + 5;
+        for id in 1..=total {
+            log.record(record(id));
+        }
+
+        let recent = log.recent();
+        assert_eq!(recent.len(), RECENT_REQUESTS);
+        assert_eq!(recent[0].id, total);
+        assert_eq!(
+            recent[RECENT_REQUESTS - 1].id,
+            total - RECENT_REQUESTS as u64 + 1
+        );
+
+        let transcript = log.transcript();
+        assert_eq!(transcript.lines().count(), TRANSCRIPT_CAPACITY);
+        assert!(transcript.starts_with("2026-10-18T09:30:00Z #6 "));
+        assert!(transcript.ends_with(&format!("#{total} 192.0.2.10:50000 \"GET /eSCL/ScannerStatus\" 200 1.2ms \"sane-airscan/0.99\"\n")));
+    }
+
+    #[test]
+    fn transcript_has_one_line_per_request() {
+        let log = RequestLog::default();
+        assert_eq!(log.transcript(), "");
+        log.record(record(1));
+        log.record(RequestRecord {
+            method: "POST".to_owned(),
+            uri: "/eSCL/ScanJobs".to_owned(),
+            status: 201,
+            elapsed_ms: 12.0,
+            ..record(2)
+        });
+        assert_eq!(
+            log.transcript(),
+            "2026-10-18T09:30:00Z #1 192.0.2.10:50000 \"GET /eSCL/ScannerStatus\" 200 1.2ms \"sane-airscan/0.99\"\n\
+             2026-10-18T09:30:00Z #2 192.0.2.10:50000 \"POST /eSCL/ScanJobs\" 201 12.0ms \"sane-airscan/0.99\"\n"
+        );
+        assert_eq!(
+            log.recent().iter().map(|r| r.id).collect::<Vec<_>>(),
+            [2, 1]
+        );
+    }
+
+    #[actix_web::test]
+    async fn faults_keep_unspecified_settings() {
+        let data = state();
+        let app = init_service(
+            App::new()
+                .app_data(data.clone())
+                .service(crate::admin_console()),
+        )
+        .await;
+
+        let request = TestRequest::post()
+            .uri("/admin/faults?busy=true&scan_job_delay_ms=250")
+            .to_request();
+        let response = call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            read_body(response).await,
+            "busy: true\nscan_job_delay_ms: 250\nnext_document_delay_ms: 0\n"
+        );
+
+        let request = TestRequest::post()
+            .uri("/admin/faults?busy=false")
+            .to_request();
+        call_service(&app, request).await;
+        let faults = &data.live().faults;
+        assert!(!faults.busy);
+        assert_eq!(faults.scan_job_delay, Duration::from_millis(250));
+    }
+
+    #[actix_web::test]
+    async fn upload_accepts_only_jpeg_images() {
+        let data = state();
+        let images = data.live().images.clone();
+        let app = init_service(
+            App::new()
+                .app_data(data.clone())
+                .service(crate::admin_console()),
+        )
+        .await;
+
+        let request = TestRequest::post()
+            .uri("/admin/images")
+            .set_payload("\u{89}PNG\r\n\u{1a}\n")
+            .to_request();
+        let response = call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+        let mut image = std::fs::read("res/example_image.jpg").unwrap();
+        image.resize(32 * 1024 * 1024 + 1, 0);
+        let request = TestRequest::post()
+            .uri("/admin/images")
+            .set_payload(image)
+            .to_request();
+        let response = call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(read_body(response).await, "Images are limited to 32 MiB\n");
+
+        // 被拒绝的上传不改变提供的图片
+        assert_eq!(data.live().images, images);
+    }
+}
@@ -17,53 +17,85 @@
  *     SPDX-License-Identifier: GPL-3.0-or-later
  */
 
-use crate::model::{ScanJob, ScanSource};
-use crate::AppState;
+use crate::auth::{self, Authenticator};
+use crate::capabilities::ColorMode;
+use crate::config;
+use crate::console::{self, RequestRecord};
+use crate::eventing;
+use crate::logging::JOB_TARGET;
+use crate::model::{self, JobState, ScanJob, ScanSource};
+use crate::network;
+use crate::report::ClientEvent;
+use crate::schema;
+use crate::wsd;
+use crate::wsd::FaultCode;
+use crate::wsscan::{self, ButtonError};
+use crate::{AppState, LiveSettings, PortDevices};
+use actix_web::body::{BoxBody, MessageBody};
 use actix_web::http::{header, StatusCode};
-use actix_web::{get, post, web, HttpRequest, HttpResponse, Responder};
-use std::str::FromStr;
-use tokio_util::io::ReaderStream;
-use uuid::Uuid;
-use chrono::Local;
+use actix_web::middleware::Next;
+use actix_web::{delete, get, post, put, web, HttpMessage, HttpRequest, HttpResponse, Responder};
 use actix_web::{
     dev::{ServiceRequest, ServiceResponse, Transform},
-    Error, Result
+    Error, Result,
 };
 use futures::future::LocalBoxFuture;
-use std::future::{Ready, ready};
+use futures::TryStreamExt;
+use serde::Deserialize;
+use std::future::{ready, Ready};
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio_util::io::ReaderStream;
+use tracing::{debug, info, info_span, trace, warn, Instrument};
+use uuid::Uuid;
 
-// 智能获取服务器IP地址的辅助函数
-fn get_server_address(req: &HttpRequest) -> (String, String) {
-    // 首先尝试从Host头获取
-    if let Some(host_header) = req.headers().get("Host").and_then(|h| h.to_str().ok()) {
-        // 分离IP和端口
-        if let Some(colon_pos) = host_header.rfind(':') {
-            let ip = &host_header[..colon_pos];
-            let port = &host_header[colon_pos + 1..];
-            return (ip.to_string(), port.to_string());
-        } else {
-            return (host_header.to_string(), "8080".to_string());
+// 拆分 Host 头中的主机和端口，IPv6 地址带方括号: [fe80::1]:8080
+fn split_host_port(host: &str) -> (&str, Option<&str>) {
+    if let Some(rest) = host.strip_prefix('[') {
+        if let Some((ip, after)) = rest.split_once(']') {
+            return (ip, after.strip_prefix(':'));
         }
     }
-    
-    // 如果没有Host头，尝试从连接信息获取
-    let connection_info = req.connection_info();
-    let host = connection_info.host();
-    
-    if let Some(colon_pos) = host.rfind(':') {
-        let ip = &host[..colon_pos];
-        let port = &host[colon_pos + 1..];
-        (ip.to_string(), port.to_string())
-    } else {
-        (host.to_string(), "8080".to_string())
+    match host.rsplit_once(':') {
+        // 不带方括号的 IPv6 地址没有端口
+        Some((name, port)) if !name.contains(':') => (name, Some(port)),
+        _ => (host, None),
     }
 }
 
+// 智能获取服务器IP地址的辅助函数，IPv6 地址不带方括号
+fn get_server_address(req: &HttpRequest) -> (String, String) {
+    let host = request_host(req);
+    let (ip, port) = split_host_port(&host);
+    let port = port.map_or_else(|| req.app_config().local_addr().port().to_string(), str::to_owned);
+    (ip.to_owned(), port)
+}
+
+// Host 头，缺失时使用实际监听的地址
+fn request_host(req: &HttpRequest) -> String {
+    req.headers()
+        .get("Host")
+        .and_then(|h| h.to_str().ok())
+        .map(str::to_owned)
+        .unwrap_or_else(|| network::url_authority(req.app_config().local_addr()))
+}
+
+// 请求所用的协议，TLS 监听器上为 https
+fn request_scheme(req: &HttpRequest) -> &'static str {
+    if req.connection_info().scheme() == "https" { "https" } else { "http" }
+}
+
 // 获取完整的服务器URL前缀
 fn get_server_url_prefix(req: &HttpRequest) -> String {
     let (ip, port) = get_server_address(req);
-    let scheme = if req.connection_info().scheme() == "https" { "https" } else { "http" };
-    format!("{}://{}:{}", scheme, ip, port)
+    let scheme = request_scheme(req);
+    if ip.contains(':') {
+        format!("{}://[{}]:{}", scheme, ip, port)
+    } else {
+        format!("{}://{}:{}", scheme, ip, port)
+    }
 }
 
 // 全局请求记录中间件
@@ -90,6 +122,34 @@ pub struct LoggingMiddlewareService<S> {
     service: S,
 }
 
+static NEXT_REQUEST_ID: AtomicU64 = AtomicU64::new(1);
+
+// 根据User-Agent识别客户端类型
+fn client_kind(user_agent: &str) -> &'static str {
+    if user_agent.contains("NAPS2") {
+        "naps2"
+    } else if user_agent.contains("WSD") {
+        "windows-wsd"
+    } else if user_agent.contains("Microsoft") {
+        "microsoft"
+    } else {
+        "other"
+    }
+}
+
+// 标记重要的eSCL端点
+fn endpoint_kind(path: &str) -> &'static str {
+    match path {
+        p if p.contains("ScannerCapabilities") => "capabilities",
+        p if p.contains("NextDocument") => "next-document",
+        p if p.contains("ScanJobs") => "scan-jobs",
+        p if p.contains("ScannerStatus") => "status",
+        p if p.contains("icon") => "icon",
+        "/" => "root",
+        _ => "other",
+    }
+}
+
 impl<S, B> actix_web::dev::Service<ServiceRequest> for LoggingMiddlewareService<S>
 where
     S: actix_web::dev::Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
@@ -103,135 +163,206 @@ where
     actix_web::dev::forward_ready!(service);
 
     fn call(&self, req: ServiceRequest) -> Self::Future {
-        let timestamp = Local::now().format("%H:%M:%S%.3f");
-        let method = req.method().to_string();
-        let uri = req.uri().to_string();
-        let path = req.path().to_string();
-        let peer_addr = req.peer_addr();
-        
+        let request_id = NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed);
+        let started = Instant::now();
+
         // 获取User-Agent用于识别客户端类型
         let user_agent = req.headers()
             .get("User-Agent")
             .and_then(|h| h.to_str().ok())
             .unwrap_or("Unknown")
             .to_string();
-        
+
         let host = req.headers()
             .get("Host")
             .and_then(|h| h.to_str().ok())
             .unwrap_or("Unknown")
             .to_string();
-            
-        println!("\n🌐 === [{timestamp}] 新的HTTP请求 === 🌐");
-        println!("📡 方法: {method}");
-        println!("🔗 URI: {uri}");
-        println!("📍 路径: {path}");
-        if let Some(peer) = peer_addr {
-            println!("👤 客户端IP: {peer}");
-        }
-        println!("🏠 主机头: {host}");
-        println!("🖥️ 客户端: {user_agent}");
-        
-        // 标记特殊的客户端
-        if user_agent.contains("NAPS2") {
-            println!("🎯 检测到 NAPS2 扫描软件！");
-        } else if user_agent.contains("WSD") {
-            println!("🔍 检测到 Windows 设备发现请求！");
-        } else if user_agent.contains("Microsoft") {
-            println!("🪟 检测到 Microsoft 相关请求！");
-        }
-        
-        // 标记重要的eSCL端点
-        let endpoint_type = match path.as_str() {
-            p if p.contains("ScannerCapabilities") => "📋 扫描仪能力查询",
-            p if p.contains("ScanJobs") => "📤 扫描任务操作",
-            p if p.contains("ScannerStatus") => "💡 扫描仪状态查询", 
-            p if p.contains("NextDocument") => "📥 获取扫描文档",
-            p if p.contains("icon") => "🖼️ 设备图标请求",
-            "/" => "🏠 根目录访问",
-            _ => "❓ 其他请求",
+
+        let peer_ip = req
+            .peer_addr()
+            .map_or_else(|| "unknown".to_owned(), |addr| addr.ip().to_string());
+
+        let span = info_span!(
+            "request",
+            id = request_id,
+            method = %req.method(),
+            path = %req.path(),
+            peer = req.peer_addr().map(tracing::field::display),
+            user_agent = %user_agent,
+            job = tracing::field::Empty,
+        );
+
+        // 使用路由模式作为端点标签，避免UUID导致标签数量膨胀
+        let endpoint = req.match_pattern().unwrap_or_else(|| "unmatched".to_owned());
+        let method = req.method().to_string();
+        let uri = req.uri().to_string();
+        let time = console::timestamp();
+
+        let fut = {
+            let _entered = span.enter();
+            debug!(
+                uri = %req.uri(),
+                host = %host,
+                client = client_kind(&user_agent),
+                endpoint = endpoint_kind(req.path()),
+                "Request received"
+            );
+            self.service.call(req)
         };
-        println!("🎯 请求类型: {endpoint_type}");
-        
-        println!("🌐 ================================= 🌐\n");
-
-        let fut = self.service.call(req);
-        Box::pin(async move {
-            let res = fut.await?;
-            let status = res.status();
-            
-            println!("\n📤 === [{timestamp}] 响应发送 === 📤");
-            println!("📊 状态码: {status}");
-            
-            if status.is_success() {
-                println!("✅ 请求处理成功");
-            } else if status == StatusCode::NOT_FOUND {
-                println!("⚠️ 404 - 端点未找到！可能是客户端探测");
-            } else {
-                println!("❌ 请求处理异常");
+
+        Box::pin(
+            async move {
+                let res = fut.await?;
+                let status = res.status();
+                let elapsed_ms = started.elapsed().as_secs_f64() * 1000.0;
+
+                // 路由完成后请求中包含 scope 的数据，因此指标记录到对应的虚拟设备
+                if let Some(data) = res.request().app_data::<web::Data<AppState>>() {
+                    data.metrics.observe_request(&endpoint, &method, status.as_u16());
+                    let events = res
+                        .request()
+                        .extensions_mut()
+                        .remove::<Vec<ClientEvent>>()
+                        .unwrap_or_default();
+                    data.report.record(&peer_ip, &user_agent, events);
+                    // 控制台自身的轮询和页面请求不记录
+                    let path = uri.strip_prefix(data.scope.as_str()).unwrap_or(&uri);
+                    if !(method == "GET" && path.starts_with("/admin")) {
+                        data.requests.record(RequestRecord {
+                            id: request_id,
+                            time,
+                            peer: peer_ip,
+                            method,
+                            uri,
+                            user_agent,
+                            status: status.as_u16(),
+                            elapsed_ms,
+                        });
+                    }
+                }
+
+                if status.is_server_error() {
+                    warn!(status = status.as_u16(), elapsed_ms, "Request failed");
+                } else {
+                    info!(status = status.as_u16(), elapsed_ms, "Request completed");
+                }
+
+                Ok(res)
             }
-            
-            println!("📤 ========================== 📤\n");
-            
-            Ok(res)
-        })
+            .instrument(span),
+        )
+    }
+}
+
+// 检查发出的 eSCL 文档是否符合规范，只记录问题，不修改响应
+pub(crate) async fn validate_outgoing_xml(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let res = next.call(req).await?;
+    let is_xml = res
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.contains("xml") && !value.starts_with("multipart/"));
+    if !is_xml {
+        return Ok(res.map_into_boxed_body());
+    }
+
+    let (req, res) = res.into_parts();
+    let (res, body) = res.into_parts();
+    let body = actix_web::body::to_bytes(body)
+        .await
+        .map_err(|e| actix_web::error::ErrorInternalServerError(e.into()))?;
+    if let Err(errors) = schema::validate(&String::from_utf8_lossy(&body)) {
+        for error in &errors {
+            warn!(path = req.path(), error = %error, "Outgoing XML doesn't match the eSCL schema");
+        }
+    }
+    Ok(ServiceResponse::new(req, res.set_body(body)).map_into_boxed_body())
+}
+
+// 记录客户端的行为，由 LoggingMiddleware 归属到发出请求的客户端
+pub(crate) fn record_event(req: &HttpRequest, event: ClientEvent) {
+    let mut extensions = req.extensions_mut();
+    match extensions.get_mut::<Vec<ClientEvent>>() {
+        Some(events) => events.push(event),
+        None => {
+            extensions.insert(vec![event]);
+        }
+    }
+}
+
+// 检查客户端发送的扫描参数，严格模式下拒绝不符合规范的请求
+fn check_scan_settings(
+    req: &HttpRequest,
+    data: &AppState,
+    body: &web::Bytes,
+    endpoint: &str,
+) -> Option<HttpResponse> {
+    let result = schema::validate_as(&String::from_utf8_lossy(body), "scan:ScanSettings");
+    let first_error = result.as_ref().err().map(|errors| errors[0].to_string());
+    record_event(req, ClientEvent::TicketChecked(first_error));
+    if !data.validation.xml && !data.validation.strict {
+        return None;
+    }
+    let errors = result.err()?;
+    for error in &errors {
+        warn!(endpoint, error = %error, "ScanSettings don't match the eSCL schema");
     }
+    data.validation.strict.then(|| {
+        HttpResponse::BadRequest()
+            .content_type("text/plain; charset=utf-8")
+            .body(format!("Invalid ScanSettings:\n{}\n", schema::describe(&errors)))
+    })
 }
 
 // 添加详细的请求日志记录函数
 fn log_request_details(req: &HttpRequest, endpoint_name: &str) {
-    println!("\n🔵 === {} REQUEST === 🔵", endpoint_name);
-    println!("📍 Method: {}", req.method());
-    println!("📍 URI: {}", req.uri());
-    println!("📍 Path: {}", req.path());
-    println!("📍 Query: {:?}", req.query_string());
-    println!("📍 Version: {:?}", req.version());
-    
-    println!("📍 Headers:");
+    debug!(
+        endpoint = endpoint_name,
+        query = req.query_string(),
+        version = ?req.version(),
+        "Handling request"
+    );
     for (name, value) in req.headers().iter() {
-        println!("     {}: {:?}", name, value);
-    }
-    
-    if let Some(peer) = req.peer_addr() {
-        println!("📍 Client IP: {}", peer);
+        trace!(header = %name, value = ?value, "Request header");
     }
-    
-    println!("🔵 === END {} === 🔵\n", endpoint_name);
 }
 
 // 添加请求体日志记录函数
 fn log_request_body(body: &web::Bytes, endpoint_name: &str) {
     if !body.is_empty() {
-        println!("📝 === {} REQUEST BODY === 📝", endpoint_name);
-        if let Ok(body_str) = std::str::from_utf8(body) {
-            println!("{}", body_str);
-        } else {
-            println!("Binary data ({} bytes)", body.len());
+        match std::str::from_utf8(body) {
+            Ok(body_str) => trace!(endpoint = endpoint_name, body = body_str, "Request body"),
+            Err(_) => trace!(endpoint = endpoint_name, bytes = body.len(), "Binary request body"),
         }
-        println!("📝 === END BODY === 📝\n");
     }
 }
 
 #[get("/ScannerCapabilities")]
 async fn scanner_capabilities(req: HttpRequest, data: web::Data<AppState>) -> impl Responder {
     log_request_details(&req, "ScannerCapabilities");
-
-    println!("ScannerCaps downloaded");
+    record_event(&req, ClientEvent::CapabilitiesFetched);
 
     // 获取主机信息以动态替换URL
-    let host = req.headers()
-        .get("Host")
-        .and_then(|h| h.to_str().ok())
-        .unwrap_or("localhost:8080");
+    let host = request_host(&req);
     
-    let scheme = if req.connection_info().scheme() == "https" { "https" } else { "http" };
-    let admin_uri = format!("{}://{}/admin", scheme, host);
-    let icon_uri = format!("{}://{}/icon.png", scheme, host);
-    
-    // 动态替换URL
-    let scanner_caps = data.scanner_caps
-        .replace("DYNAMIC_ADMIN_URI", &admin_uri)
-        .replace("DYNAMIC_ICON_URI", &icon_uri);
+    let scheme = request_scheme(&req);
+    let base_url = format!("{}://{}", scheme, host);
+
+    // 自定义能力文件原样返回，只替换其中的占位符
+    let live = data.live();
+    let scanner_caps = match &live.capabilities_document {
+        Some(document) => document
+            .replace("DYNAMIC_ADMIN_URI", &format!("{base_url}{}/admin", data.scope))
+            .replace("DYNAMIC_ICON_URI", &format!("{base_url}/icon.png"))
+            .replace("DYNAMIC_MAKE_AND_MODEL", &data.identity.make_and_model())
+            .replace("DYNAMIC_SERIAL_NUMBER", &data.identity.serial_number)
+            .replace("DYNAMIC_UUID", &data.identity.uuid.to_string()),
+        None => live.capabilities.to_xml(&data.identity, &base_url, &data.scope),
+    };
 
     HttpResponse::build(StatusCode::OK)
         .content_type("text/xml")
@@ -239,23 +370,70 @@ async fn scanner_capabilities(req: HttpRequest, data: web::Data<AppState>) -> im
 }
 
 #[get("/ScannerStatus")]
-async fn scanner_status(req: HttpRequest) -> impl Responder {
+async fn scanner_status(req: HttpRequest, data: web::Data<AppState>) -> impl Responder {
     log_request_details(&req, "ScannerStatus");
+    record_event(&req, ClientEvent::StatusPolled);
 
-    println!("ScannerStatus requested");
+    let quirks = &data.quirks;
+    let live = data.live();
 
-    let status_xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+    // 模拟忙碌的扫描仪
+    let state = if live.faults.busy { "Processing" } else { "Idle" };
+
+    // 不同厂商的状态文档包含的元素不同
+    let mut optional = String::new();
+    if quirks.status_state_reasons {
+        optional.push_str(r#"
+    <scan:ScannerStateReasons>
+        <scan:ScannerStateReason>None</scan:ScannerStateReason>
+    </scan:ScannerStateReasons>"#);
+    }
+    if quirks.status_adf_state && live.capabilities.adf.is_some() {
+        optional.push_str(r#"
+    <scan:AdfState>ScannerAdfLoaded</scan:AdfState>"#);
+    }
+    if quirks.status_jobs {
+        let scope = req.path().trim_end_matches("/ScannerStatus");
+        let jobs_guard = data.scan_jobs.lock().await;
+        let mut jobs: Vec<_> = jobs_guard.iter().collect();
+        jobs.sort_by_key(|(_, job)| std::cmp::Reverse(job.created));
+        optional.push_str(r#"
+    <scan:Jobs>"#);
+        for (uuid, job) in jobs {
+            let (job_state, reason) = job.state.pwg_state();
+            optional.push_str(&format!(r#"
+        <scan:JobInfo>
+            <pwg:JobUri>{scope}/ScanJobs/{uuid}</pwg:JobUri>
+            <pwg:JobUuid>{uuid}</pwg:JobUuid>
+            <scan:Age>{age}</scan:Age>
+            <pwg:ImagesCompleted>{completed}</pwg:ImagesCompleted>
+            <pwg:ImagesToTransfer>{remaining}</pwg:ImagesToTransfer>
+            <pwg:JobState>{job_state}</pwg:JobState>
+            <pwg:JobStateReasons>
+                <pwg:JobStateReason>{reason}</pwg:JobStateReason>
+            </pwg:JobStateReasons>
+        </scan:JobInfo>"#,
+                age = job.created.elapsed().as_secs(),
+                completed = job.retrieved_pages,
+                remaining = match job.state {
+                    JobState::Processing => job.max_pages.saturating_sub(job.retrieved_pages),
+                    _ => 0,
+                },
+            ));
+        }
+        optional.push_str(r#"
+    </scan:Jobs>"#);
+    }
+
+    let status_xml = format!(r#"<?xml version="1.0" encoding="UTF-8"?>
 <scan:ScannerStatus xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" 
                     xmlns:scan="http://schemas.hp.com/imaging/escl/2011/05/03" 
                     xmlns:pwg="http://www.pwg.org/schemas/2010/12/sm" 
                     xsi:schemaLocation="http://schemas.hp.com/imaging/escl/2011/05/03 eSCL.xsd">
-    <pwg:Version>2.0</pwg:Version>
-    <pwg:State>Idle</pwg:State>
-    <scan:ScannerState>Idle</scan:ScannerState>
-    <scan:ScannerStateReasons>
-        <scan:ScannerStateReason>None</scan:ScannerStateReason>
-    </scan:ScannerStateReasons>
-</scan:ScannerStatus>"#;
+    <pwg:Version>{version}</pwg:Version>
+    <pwg:State>{state}</pwg:State>
+    <scan:ScannerState>{state}</scan:ScannerState>{optional}
+</scan:ScannerStatus>"#, version = live.capabilities.version);
 
     HttpResponse::build(StatusCode::OK)
         .content_type("text/xml")
@@ -266,8 +444,6 @@ async fn scanner_status(req: HttpRequest) -> impl Responder {
 async fn scanner_icon(req: HttpRequest) -> impl Responder {
     log_request_details(&req, "ScannerIcon");
 
-    println!("Scanner icon requested");
-    
     // 返回一个简单的 1x1 像素透明 PNG
     let png_data = vec![
         0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A, 0x00, 0x00, 0x00, 0x0D,
@@ -287,45 +463,79 @@ async fn scanner_icon(req: HttpRequest) -> impl Responder {
 async fn scan_job(req: HttpRequest, body: web::Bytes, data: web::Data<AppState>) -> impl Responder {
     log_request_details(&req, "ScanJobs");
     log_request_body(&body, "ScanJobs");
-    
+
+    let live = data.live();
+    if !live.faults.scan_job_delay.is_zero() {
+        tokio::time::sleep(live.faults.scan_job_delay).await;
+    }
+    if let Some(rejection) = check_scan_settings(&req, &data, &body, "ScanJobs") {
+        return rejection;
+    }
+    if live.faults.busy {
+        warn!("Scanner is configured as busy, rejecting scan job");
+        return HttpResponse::ServiceUnavailable().finish();
+    }
+
     let full_url = req.full_url();
     let generated_uuid = Uuid::new_v4();
     
     // 尝试解析扫描请求以确定扫描源
-    let scan_source = if let Ok(body_str) = std::str::from_utf8(&body) {
-        println!("🔍 Analyzing scan request body...");
-        if body_str.contains("<scan:InputSource>Adf</scan:InputSource>") 
+    let (scan_source, duplex, color_mode) = if let Ok(body_str) = std::str::from_utf8(&body) {
+        let source = if body_str.contains("<scan:InputSource>Adf</scan:InputSource>") 
            || body_str.contains("Feeder") 
            || body_str.contains("ADF") {
-            println!("✅ Detected ADF scan request");
             ScanSource::Adf
         } else {
-            println!("✅ Detected Platen scan request");
             ScanSource::Platen
-        }
+        };
+        (source, body_str.contains("<scan:Duplex>true</scan:Duplex>"), requested_color_mode(body_str))
     } else {
-        println!("⚠️ Could not parse scan request, defaulting to Platen");
-        ScanSource::Platen
+        warn!("Scan request body is not valid UTF-8, defaulting to Platen");
+        (ScanSource::Platen, false, None)
     };
-    
-    // 保存扫描源信息
+    let max_pages = live.sources.pages(scan_source, duplex);
+
+    tracing::Span::current().record("job", tracing::field::display(generated_uuid));
+    info!(target: JOB_TARGET, job = %generated_uuid, source = ?scan_source, duplex, max_pages, "Scan job created");
+
+    // 保存扫描任务
     {
-        let mut sources_guard = data.scan_sources.lock().await;
-        sources_guard.insert(generated_uuid, scan_source);
+        let mut jobs_guard = data.scan_jobs.lock().await;
+        let mut job = ScanJob::new(data.next_job_id(), scan_source, max_pages);
+        job.color_mode = color_mode;
+        model::insert_job(&mut jobs_guard, generated_uuid, job);
+        wsscan::job_changed(&data, &jobs_guard, generated_uuid).await;
     }
+    data.metrics.job_created(scan_source);
+    record_event(&req, ClientEvent::JobCreated { job: generated_uuid, pages: max_pages });
 
+    // 部分厂商只返回路径而不是完整的URL
+    let location = if data.quirks.absolute_location {
+        format!("{full_url}/{generated_uuid}")
+    } else {
+        format!("{}/{generated_uuid}", req.path())
+    };
     HttpResponse::build(StatusCode::CREATED)
-        .insert_header((header::LOCATION, format!("{full_url}/{generated_uuid}")))
+        .insert_header((header::LOCATION, location))
         .finish()
 }
 
+// ScanSettings 中的 scan:ColorMode，未指定或未知时返回 None
+fn requested_color_mode(settings: &str) -> Option<ColorMode> {
+    let start = settings.find("<scan:ColorMode>")? + "<scan:ColorMode>".len();
+    let end = start + settings[start..].find("</scan:ColorMode>")?;
+    ColorMode::from_name(settings[start..end].trim())
+}
+
 // 添加 ScanBufferInfo 端点 - Windows 11 第三阶段验证必需
 #[actix_web::route("/ScanBufferInfo", method = "PUT")]
-async fn scan_buffer_info(req: HttpRequest, body: web::Bytes, _data: web::Data<AppState>) -> impl Responder {
+async fn scan_buffer_info(req: HttpRequest, body: web::Bytes, data: web::Data<AppState>) -> impl Responder {
     log_request_details(&req, "ScanBufferInfo");
     log_request_body(&body, "ScanBufferInfo");
-    
-    println!("📋 ScanBufferInfo validation request received");
+
+    if let Some(rejection) = check_scan_settings(&req, &data, &body, "ScanBufferInfo") {
+        return rejection;
+    }
     
     // 解析扫描设置以进行验证
     let body_str = String::from_utf8_lossy(&body);
@@ -337,7 +547,7 @@ async fn scan_buffer_info(req: HttpRequest, body: web::Bytes, _data: web::Data<A
         || body_str.contains("XResolution");
     
     if !has_valid_settings {
-        println!("❌ Invalid scan settings provided");
+        warn!("Invalid scan settings provided to ScanBufferInfo");
         return HttpResponse::build(StatusCode::CONFLICT)
             .content_type("text/xml")
             .body(r#"<?xml version="1.0" encoding="UTF-8"?>
@@ -346,8 +556,6 @@ async fn scan_buffer_info(req: HttpRequest, body: web::Bytes, _data: web::Data<A
 </scan:ClientErrorDetails>"#);
     }
     
-    println!("✅ Scan settings validated successfully");
-    
     // 返回扫描缓冲区信息
     let scan_buffer_info = r#"<?xml version="1.0" encoding="UTF-8"?>
 <scan:ScanBufferInfo xmlns:scan="http://schemas.hp.com/imaging/escl/2011/05/03" 
@@ -369,26 +577,24 @@ async fn scan_buffer_info(req: HttpRequest, body: web::Bytes, _data: web::Data<A
 
 // 添加Windows设备验证端点
 #[actix_web::route("/eSCL/DeviceCapabilities", method = "GET")]
-async fn device_capabilities(req: HttpRequest) -> impl Responder {
+async fn device_capabilities(req: HttpRequest, data: web::Data<AppState>) -> impl Responder {
     log_request_details(&req, "DeviceCapabilities");
-    println!("Windows device capabilities requested");
-    
-    // 重定向到标准的ScannerCapabilities
+
+    // 重定向到同一设备的ScannerCapabilities
     HttpResponse::MovedPermanently()
-        .insert_header(("Location", "/eSCL/ScannerCapabilities"))
+        .insert_header(("Location", format!("{}/ScannerCapabilities", data.scope)))
         .finish()
 }
 
 // 添加Windows可能需要的设备识别端点
 #[get("/eSCL/DeviceUUID")]
-async fn device_uuid(req: HttpRequest) -> impl Responder {
+async fn device_uuid(req: HttpRequest, data: web::Data<AppState>) -> impl Responder {
     log_request_details(&req, "DeviceUUID");
-    println!("Device UUID requested");
-    
-    let uuid_xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+
+    let uuid_xml = format!(r#"<?xml version="1.0" encoding="UTF-8"?>
 <scan:DeviceUUID xmlns:scan="http://schemas.hp.com/imaging/escl/2011/05/03">
-    550e8400-e29b-41d4-a716-446655440000
-</scan:DeviceUUID>"#;
+    {}
+</scan:DeviceUUID>"#, data.identity.uuid);
 
     HttpResponse::Ok()
         .content_type("text/xml")
@@ -397,19 +603,17 @@ async fn device_uuid(req: HttpRequest) -> impl Responder {
 
 // 添加Windows设备添加时的验证端点
 #[actix_web::route("/eSCL/Validate", method = "POST")]
-async fn validate_device(req: HttpRequest, body: web::Bytes) -> impl Responder {
+async fn validate_device(req: HttpRequest, body: web::Bytes, data: web::Data<AppState>) -> impl Responder {
     log_request_details(&req, "ValidateDevice");
     log_request_body(&body, "ValidateDevice");
     
-    println!("🔍 Windows device validation request received");
-    
     // Windows可能发送验证请求来确认设备兼容性
-    let validation_response = r#"<?xml version="1.0" encoding="UTF-8"?>
+    let validation_response = format!(r#"<?xml version="1.0" encoding="UTF-8"?>
 <scan:ValidationResponse xmlns:scan="http://schemas.hp.com/imaging/escl/2011/05/03">
     <scan:Valid>true</scan:Valid>
-    <scan:SupportedVersion>2.97</scan:SupportedVersion>
+    <scan:SupportedVersion>{}</scan:SupportedVersion>
     <scan:DeviceReady>true</scan:DeviceReady>
-</scan:ValidationResponse>"#;
+</scan:ValidationResponse>"#, data.live().capabilities.version);
 
     HttpResponse::Ok()
         .content_type("text/xml")
@@ -420,20 +624,20 @@ async fn validate_device(req: HttpRequest, body: web::Bytes) -> impl Responder {
 #[get("/eSCL/Configuration")]
 async fn device_configuration(req: HttpRequest) -> impl Responder {
     log_request_details(&req, "DeviceConfiguration");
-    println!("Device configuration requested");
-    
-    let config_xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+
+    let local_addr = req.app_config().local_addr();
+    let config_xml = format!(r#"<?xml version="1.0" encoding="UTF-8"?>
 <scan:DeviceConfiguration xmlns:scan="http://schemas.hp.com/imaging/escl/2011/05/03">
     <scan:DeviceSettings>
         <scan:AutoPowerOff>false</scan:AutoPowerOff>
         <scan:PowerSaveMode>false</scan:PowerSaveMode>
         <scan:NetworkSettings>
-            <scan:IPAddress>192.168.44.128</scan:IPAddress>
-            <scan:Port>8080</scan:Port>
-            <scan:Protocol>HTTP</scan:Protocol>
+            <scan:IPAddress>{}</scan:IPAddress>
+            <scan:Port>{}</scan:Port>
+            <scan:Protocol>{}</scan:Protocol>
         </scan:NetworkSettings>
     </scan:DeviceSettings>
-</scan:DeviceConfiguration>"#;
+</scan:DeviceConfiguration>"#, local_addr.ip(), local_addr.port(), request_scheme(&req).to_uppercase());
 
     HttpResponse::Ok()
         .content_type("text/xml")
@@ -447,119 +651,303 @@ async fn next_doc(
     data: web::Data<AppState>,
 ) -> impl Responder {
     log_request_details(&req, "NextDocument");
-    
-    let full_url = req.full_url();
 
-    println!("📄 Document is requested (UUID: {})", path.as_str());
-    let mut data_guard = data.scan_jobs.lock().await;
-    let uuid = &Uuid::from_str(&path.into_inner()).unwrap();
+    let _timer = data.metrics.next_document_seconds.start_timer();
+    let full_url = req.full_url();
 
-    // 获取扫描源信息
-    let scan_source = {
-        let sources_guard = data.scan_sources.lock().await;
-        sources_guard.get(uuid).cloned().unwrap_or(ScanSource::Platen)
+    tracing::Span::current().record("job", path.as_str());
+    let Ok(uuid) = Uuid::from_str(&path.into_inner()) else {
+        return HttpResponse::NotFound().finish();
     };
 
-    // 获取或创建扫描任务，并检查页面限制
-    let (current_page, max_pages, scan_source_type) = match data_guard.get_mut(uuid) {
-        None => {
-            // 新的扫描任务 - 准备返回第一页
-            let max_pages = match scan_source {
-                ScanSource::Platen => 1,
-                ScanSource::Adf => 5,  // ADF模拟最多5页
-            };
-            data_guard.insert(*uuid, ScanJob { 
-                retrieved_pages: 1,  // 即将返回第一页
-                scan_source: scan_source.clone(),
-                max_pages,
-            });
-            (1, max_pages, scan_source)
-        }
-        Some(job) => {
-            // 计算下一页的页码
-            let next_page = job.retrieved_pages + 1;
-            
-            // 检查是否超出页面限制
-            if next_page > job.max_pages {
-                println!("No more pages available for {:?} source (requested page {} of {})", 
-                        job.scan_source, next_page, job.max_pages);
-                return HttpResponse::NotFound().finish();
-            }
-            
-            // 更新页面计数
-            job.retrieved_pages = next_page;
-            (next_page, job.max_pages, job.scan_source.clone())
-        }
+    let Ok((current_page, max_pages, scan_source)) = take_page(&data, uuid).await else {
+        return HttpResponse::NotFound().finish();
     };
+    record_event(&req, ClientEvent::PageRetrieved { job: uuid });
+
+    let live = data.live();
+    if !live.faults.next_document_delay.is_zero() {
+        tokio::time::sleep(live.faults.next_document_delay).await;
+    }
 
-    println!("Serving page {} of {} for {:?} source", current_page, max_pages, scan_source_type);
+    data.metrics.page_served(scan_source);
+    info!(
+        target: JOB_TARGET,
+        job = %uuid,
+        page = current_page,
+        max_pages,
+        source = %scan_source,
+        "Serving page"
+    );
 
     // 尝试使用指定的图片文件，如果失败则回退到内置图片
-    if let Some(image_path) = &data.image_path {
-        // 如果路径以 / 开头，尝试转换为相对路径
-        let corrected_path = if image_path.starts_with("/res/") {
-            image_path.strip_prefix("/").unwrap_or(image_path)
-        } else if image_path.starts_with("\\res\\") {
-            image_path.strip_prefix("\\").unwrap_or(image_path)
-        } else {
-            image_path
-        };
+    if let Some(image_path) = page_image(&live, current_page) {
+        let corrected_path = corrected_image_path(image_path);
         
         match tokio::fs::File::open(corrected_path).await {
             Ok(file) => {
-                println!("Using custom image from: {}", corrected_path);
-                let stream = ReaderStream::new(file);
+                debug!(path = corrected_path, "Using custom image");
+                let stream = counted_stream(file, &data);
                 return HttpResponse::Ok()
                     .content_type("image/jpeg")
                     .insert_header((header::CONTENT_LOCATION, format!("{full_url}")))
                     .streaming(stream);
             }
             Err(e) => {
-                println!("Failed to open custom image '{}': {}. Trying original path...", corrected_path, e);
+                warn!(path = corrected_path, error = %e, "Failed to open custom image");
                 
                 // 如果修正的路径也失败，尝试原始路径
                 if corrected_path != image_path {
                     match tokio::fs::File::open(image_path).await {
                         Ok(file) => {
-                            println!("Using custom image from original path: {}", image_path);
-                            let stream = ReaderStream::new(file);
+                            debug!(path = image_path, "Using custom image from original path");
+                            let stream = counted_stream(file, &data);
                             return HttpResponse::Ok()
                                 .content_type("image/jpeg")
                                 .insert_header((header::CONTENT_LOCATION, format!("{full_url}")))
                                 .streaming(stream);
                         }
                         Err(e2) => {
-                            println!("Also failed to open original path '{}': {}. Using default image.", image_path, e2);
+                            warn!(path = image_path, error = %e2, "Failed to open original image path");
                         }
                     }
-                } else {
-                    println!("Using default image.");
                 }
+                warn!("Falling back to the embedded default image");
                 // 继续执行，使用默认图片
             }
         }
     }
     
     // 使用内置的默认图片
-    println!("Using default embedded image");
+    let image = DEFAULT_IMAGE;
+    data.metrics.bytes_served.inc_by(image.len() as u64);
     HttpResponse::Ok()
         .content_type("image/jpeg")
         .insert_header((header::CONTENT_LOCATION, format!("{full_url}")))
-        .body(&include_bytes!("../res/example_image.jpg")[..])
+        .body(image)
+}
+
+/// Why [`take_page`] returned no page.
+pub(crate) enum PageError {
+    UnknownJob,
+    NoMorePages,
+}
+
+// NextDocument 和 WS-Scan 的 RetrieveImage 共用：取出任务的下一页，返回页码、总页数和扫描源
+pub(crate) async fn take_page(data: &AppState, uuid: Uuid) -> Result<(u32, u32, ScanSource), PageError> {
+    let mut jobs_guard = data.scan_jobs.lock().await;
+    let Some(job) = jobs_guard.get_mut(&uuid) else {
+        warn!(target: JOB_TARGET, job = %uuid, "Page requested for unknown job");
+        return Err(PageError::UnknownJob);
+    };
+
+    // 计算下一页的页码
+    let next_page = job.retrieved_pages + 1;
+
+    // 检查是否超出页面限制
+    if job.state != JobState::Processing || next_page > job.max_pages {
+        info!(
+            target: JOB_TARGET,
+            job = %uuid,
+            source = %job.scan_source,
+            state = ?job.state,
+            requested_page = next_page,
+            max_pages = job.max_pages,
+            "No more pages available"
+        );
+        return Err(PageError::NoMorePages);
+    }
+
+    // 更新页面计数
+    job.retrieved_pages = next_page;
+    if next_page == job.max_pages {
+        job.finish(JobState::Completed);
+        data.metrics.job_completed(job.scan_source);
+        info!(target: JOB_TARGET, job = %uuid, pages = next_page, "Scan job completed");
+    }
+    let page = (next_page, job.max_pages, job.scan_source);
+    wsscan::job_changed(data, &jobs_guard, uuid).await;
+    Ok(page)
+}
+
+const DEFAULT_IMAGE: &[u8] = include_bytes!("../res/example_image.jpg");
+
+// 多张图片时按页码轮流使用
+fn page_image(live: &LiveSettings, page: u32) -> Option<&str> {
+    match live.images.len() {
+        0 => None,
+        count => Some(&live.images[(page as usize - 1) % count]),
+    }
+}
+
+// 如果路径以 / 开头，尝试转换为相对路径
+fn corrected_image_path(image_path: &str) -> &str {
+    if image_path.starts_with("/res/") {
+        image_path.strip_prefix("/").unwrap_or(image_path)
+    } else if image_path.starts_with("\\res\\") {
+        image_path.strip_prefix("\\").unwrap_or(image_path)
+    } else {
+        image_path
+    }
+}
+
+/// Reads the image NextDocument serves for `page`, falling back to the embedded one.
+pub(crate) async fn read_page_image(data: &AppState, page: u32) -> Vec<u8> {
+    if let Some(image_path) = page_image(&data.live(), page) {
+        for path in [corrected_image_path(image_path), image_path] {
+            if let Ok(image) = tokio::fs::read(path).await {
+                return image;
+            }
+        }
+    }
+    DEFAULT_IMAGE.to_vec()
+}
+
+// 从 JPEG 的 SOF 段读取图片尺寸 (宽, 高)
+pub(crate) fn jpeg_dimensions(image: &[u8]) -> Option<(u32, u32)> {
+    if image.get(..2)? != [0xFF, 0xD8] {
+        return None;
+    }
+    let mut pos = 2;
+    while pos + 4 <= image.len() {
+        if image[pos] != 0xFF {
+            return None;
+        }
+        let marker = image[pos + 1];
+        if marker == 0xFF {
+            pos += 1;
+            continue;
+        }
+        let length = u16::from_be_bytes([image[pos + 2], image[pos + 3]]) as usize;
+        // SOF0..SOF15，排除 DHT (C4)、JPG (C8) 和 DAC (CC)
+        if (0xC0..=0xCF).contains(&marker) && ![0xC4, 0xC8, 0xCC].contains(&marker) {
+            let segment = image.get(pos + 5..pos + 9)?;
+            let height = u16::from_be_bytes([segment[0], segment[1]]);
+            let width = u16::from_be_bytes([segment[2], segment[3]]);
+            return Some((width.into(), height.into()));
+        }
+        pos += 2 + length;
+    }
+    None
+}
+
+// 最近一次 NextDocument 返回的页面信息，eSCL 2.5 起支持
+#[get("/ScanJobs/{uuid}/ScanImageInfo")]
+async fn scan_image_info(
+    req: HttpRequest,
+    path: web::Path<String>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    log_request_details(&req, "ScanImageInfo");
+
+    let version = data.live().capabilities.version;
+    if !version.has_scan_image_info() {
+        debug!(%version, "ScanImageInfo is not part of the emulated eSCL version");
+        return HttpResponse::NotFound().finish();
+    }
+
+    tracing::Span::current().record("job", path.as_str());
+    let Ok(uuid) = Uuid::from_str(&path.into_inner()) else {
+        return HttpResponse::NotFound().finish();
+    };
+    let (page, color_mode) = {
+        let jobs_guard = data.scan_jobs.lock().await;
+        match jobs_guard.get(&uuid) {
+            Some(job) if job.retrieved_pages > 0 => (job.retrieved_pages, job.color_mode),
+            _ => return HttpResponse::NotFound().finish(),
+        }
+    };
+
+    let image = read_page_image(&data, page).await;
+    let (width, height) = jpeg_dimensions(&image).unwrap_or((2550, 3300));
+    let job_uri = req.path().trim_end_matches("/ScanImageInfo");
+    // 不知道颜色模式时无法确定每行字节数，省略这个可选元素
+    let bytes_per_line = color_mode
+        .map(|mode| format!("\n    <scan:ActualBytesPerLine>{}</scan:ActualBytesPerLine>", (width * mode.bits_per_pixel()).div_ceil(8)))
+        .unwrap_or_default();
+
+    let info_xml = format!(r#"<?xml version="1.0" encoding="UTF-8"?>
+<scan:ScanImageInfo xmlns:scan="http://schemas.hp.com/imaging/escl/2011/05/03"
+                    xmlns:pwg="http://www.pwg.org/schemas/2010/12/sm">
+    <pwg:JobUri>{job_uri}</pwg:JobUri>
+    <pwg:JobUuid>{uuid}</pwg:JobUuid>
+    <scan:ActualWidth>{width}</scan:ActualWidth>
+    <scan:ActualHeight>{height}</scan:ActualHeight>{bytes_per_line}
+</scan:ScanImageInfo>"#);
+
+    HttpResponse::Ok()
+        .content_type("text/xml")
+        .body(info_xml)
+}
+
+// 统计实际发送的图片字节数
+fn counted_stream(
+    file: tokio::fs::File,
+    data: &web::Data<AppState>,
+) -> impl futures::Stream<Item = std::io::Result<web::Bytes>> {
+    let bytes_served = data.metrics.bytes_served.clone();
+    ReaderStream::new(file).inspect_ok(move |chunk| bytes_served.inc_by(chunk.len() as u64))
+}
+
+// 取消或删除扫描任务
+#[actix_web::route("/ScanJobs/{uuid}", method = "DELETE")]
+async fn cancel_job(
+    req: HttpRequest,
+    path: web::Path<String>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    log_request_details(&req, "CancelJob");
+
+    tracing::Span::current().record("job", path.as_str());
+    let Ok(uuid) = Uuid::from_str(&path.into_inner()) else {
+        return HttpResponse::NotFound().finish();
+    };
+
+    if !cancel_scan_job(&data, uuid).await {
+        return HttpResponse::NotFound().finish();
+    }
+    record_event(&req, ClientEvent::JobDeleted { job: uuid });
+
+    HttpResponse::Ok().finish()
+}
+
+// 取消仍在进行的任务，任务不存在时返回 false
+pub(crate) async fn cancel_scan_job(data: &AppState, uuid: Uuid) -> bool {
+    let mut jobs_guard = data.scan_jobs.lock().await;
+    let Some(job) = jobs_guard.get_mut(&uuid) else {
+        return false;
+    };
+
+    // 已取消的任务保留在任务表中，以便状态查询仍能报告它们
+    if job.state == JobState::Processing {
+        job.finish(JobState::Canceled);
+        data.metrics.job_canceled(job.scan_source);
+        info!(
+            target: JOB_TARGET,
+            job = %uuid,
+            retrieved_pages = job.retrieved_pages,
+            max_pages = job.max_pages,
+            "Scan job canceled"
+        );
+        wsscan::job_changed(data, &jobs_guard, uuid).await;
+    } else {
+        info!(target: JOB_TARGET, job = %uuid, state = ?job.state, "Delete requested for finished job");
+    }
+    true
 }
 
 // 添加设备信息端点 - Windows 11 可能需要
 #[get("/DeviceInfo")]
-async fn device_info(req: HttpRequest) -> impl Responder {
+async fn device_info(req: HttpRequest, data: web::Data<AppState>) -> impl Responder {
     log_request_details(&req, "DeviceInfo");
 
-    println!("DeviceInfo requested");
+    let identity = &data.identity;
 
     // 获取服务器地址信息
     let (server_ip, _) = get_server_address(&req);
     let url_prefix = get_server_url_prefix(&req);
-    let device_uri = format!("{}/eSCL", url_prefix);
-    let admin_uri = format!("{}/admin", url_prefix);
+    let device_uri = format!("{url_prefix}{}", data.scope);
+    let admin_uri = format!("{}{}/admin", url_prefix, data.scope);
 
     let icon_uri = format!("{}/icon.png", url_prefix);
     
@@ -568,21 +956,31 @@ async fn device_info(req: HttpRequest) -> impl Responder {
                  xmlns:scan="http://schemas.hp.com/imaging/escl/2011/05/03"
                  xmlns:pwg="http://www.pwg.org/schemas/2010/12/sm"
                  xsi:schemaLocation="http://schemas.hp.com/imaging/escl/2011/05/03 eSCL.xsd">
-    <pwg:MakeAndModel>eSCL Scanner</pwg:MakeAndModel>
-    <pwg:SerialNumber>ESC-MOCK-001</pwg:SerialNumber>
-    <scan:UUID>550e8400-e29b-41d4-a716-446655440000</scan:UUID>
-    <scan:DeviceURI>{}</scan:DeviceURI>
-    <scan:AdminURI>{}</scan:AdminURI>
-    <scan:IconURI>{}</scan:IconURI>
-    <scan:Manufacturer>MockCompany</scan:Manufacturer>
-    <scan:ModelName>eSCL Scanner</scan:ModelName>
-    <scan:ModelNumber>v2024</scan:ModelNumber>
-    <scan:FirmwareVersion>1.0.0</scan:FirmwareVersion>
+    <pwg:MakeAndModel>{make_and_model}</pwg:MakeAndModel>
+    <pwg:SerialNumber>{serial}</pwg:SerialNumber>
+    <scan:UUID>{uuid}</scan:UUID>
+    <scan:DeviceURI>{device_uri}</scan:DeviceURI>
+    <scan:AdminURI>{admin_uri}</scan:AdminURI>
+    <scan:IconURI>{icon_uri}</scan:IconURI>
+    <scan:Manufacturer>{make}</scan:Manufacturer>
+    <scan:ModelName>{model}</scan:ModelName>
+    <scan:ModelNumber>{model_number}</scan:ModelNumber>
+    <scan:FirmwareVersion>{firmware}</scan:FirmwareVersion>
     <scan:DeviceCategory>Scanner</scan:DeviceCategory>
-    <scan:NetworkProtocol>HTTP</scan:NetworkProtocol>
-    <scan:IPAddress>{}</scan:IPAddress>
-    <scan:MACAddress>00:11:22:33:44:55</scan:MACAddress>
-</scan:DeviceInfo>"#, device_uri, admin_uri, icon_uri, server_ip);
+    <scan:NetworkProtocol>{protocol}</scan:NetworkProtocol>
+    <scan:IPAddress>{server_ip}</scan:IPAddress>
+    <scan:MACAddress>{mac_address}</scan:MACAddress>
+</scan:DeviceInfo>"#,
+        make_and_model = identity.make_and_model(),
+        serial = identity.serial_number,
+        uuid = identity.uuid,
+        make = identity.make,
+        model = identity.model,
+        firmware = identity.firmware_version,
+        model_number = identity.model_number,
+        mac_address = identity.mac_address(),
+        protocol = request_scheme(&req).to_uppercase(),
+    );
 
     HttpResponse::build(StatusCode::OK)
         .content_type("text/xml")
@@ -591,11 +989,11 @@ async fn device_info(req: HttpRequest) -> impl Responder {
 
 // 添加根路径端点
 #[get("/")]
-async fn root_info(req: HttpRequest) -> impl Responder {
+async fn root_info(req: HttpRequest, data: web::Data<AppState>) -> impl Responder {
     log_request_details(&req, "RootInfo");
 
-    println!("Root info requested");
-    
+    let identity = &data.identity;
+
     // 获取服务器地址信息
     let url_prefix = get_server_url_prefix(&req);
     
@@ -607,16 +1005,16 @@ async fn root_info(req: HttpRequest) -> impl Responder {
     </specVersion>
     <device>
         <deviceType>urn:schemas-upnp-org:device:Scanner:1</deviceType>
-        <friendlyName>eSCL Scanner</friendlyName>
-        <manufacturer>MockCompany</manufacturer>
-        <manufacturerURL>http://www.mockcompany.com</manufacturerURL>
+        <friendlyName>{friendly_name}</friendlyName>
+        <manufacturer>{make}</manufacturer>
+        <manufacturerURL>{manufacturer_url}</manufacturerURL>
         <modelDescription>eSCL网络扫描仪</modelDescription>
-        <modelName>eSCL Scanner</modelName>
-        <modelNumber>v2024</modelNumber>
-        <modelURL>{}/admin</modelURL>
-        <serialNumber>ESC-MOCK-001</serialNumber>
-        <UDN>uuid:550e8400-e29b-41d4-a716-446655440000</UDN>
-        <presentationURL>{}/admin</presentationURL>
+        <modelName>{model}</modelName>
+        <modelNumber>{model_number}</modelNumber>
+        <modelURL>{model_url}</modelURL>
+        <serialNumber>{serial}</serialNumber>
+        <UDN>uuid:{uuid}</UDN>
+        <presentationURL>{url_prefix}{scope}/admin</presentationURL>
         <iconList>
             <icon>
                 <mimetype>image/png</mimetype>
@@ -630,13 +1028,23 @@ async fn root_info(req: HttpRequest) -> impl Responder {
             <service>
                 <serviceType>urn:schemas-hp-com:service:imaging:ScanService:1</serviceType>
                 <serviceId>urn:schemas-hp-com:serviceId:ScanService</serviceId>
-                <SCPDURL>/eSCL/ScannerCapabilities</SCPDURL>
-                <controlURL>/eSCL</controlURL>
-                <eventSubURL>/eSCL</eventSubURL>
+                <SCPDURL>{scope}/ScannerCapabilities</SCPDURL>
+                <controlURL>{scope}</controlURL>
+                <eventSubURL>{scope}</eventSubURL>
             </service>
         </serviceList>
     </device>
-</root>"#, url_prefix, url_prefix);
+</root>"#,
+        friendly_name = identity.friendly_name,
+        make = identity.make,
+        model = identity.model,
+        serial = identity.serial_number,
+        uuid = identity.uuid,
+        model_number = identity.model_number,
+        manufacturer_url = identity.manufacturer_url,
+        model_url = identity.model_url,
+        scope = data.scope,
+    );
 
     HttpResponse::build(StatusCode::OK)
         .content_type("text/xml")
@@ -645,16 +1053,12 @@ async fn root_info(req: HttpRequest) -> impl Responder {
 
 // 改进的 WSD 设备描述端点
 #[get("/wsd")]
-async fn wsd_description(req: HttpRequest) -> impl Responder {
+async fn wsd_description(req: HttpRequest, data: web::Data<AppState>) -> impl Responder {
     log_request_details(&req, "WSDDescription");
 
-    println!("WSD description requested");
-    
     // 获取客户端IP用于响应
-    let host = req.headers()
-        .get("Host")
-        .and_then(|h| h.to_str().ok())
-        .unwrap_or("localhost:8080");
+    let host = request_host(&req);
+    let scheme = request_scheme(&req);
     
     let wsd_xml = format!(r#"<?xml version="1.0" encoding="UTF-8"?>
 <soap:Envelope 
@@ -667,14 +1071,13 @@ async fn wsd_description(req: HttpRequest) -> impl Responder {
     <soap:Header>
         <wsa:To>urn:schemas-xmlsoap-org:ws:2005:04:discovery</wsa:To>
         <wsa:Action>http://schemas.xmlsoap.org/ws/2005/04/discovery/ProbeMatches</wsa:Action>
-        <wsa:MessageID>urn:uuid:550e8400-e29b-41d4-a716-446655440000</wsa:MessageID>
-        <wsa:RelatesTo>urn:uuid:550e8400-e29b-41d4-a716-446655440001</wsa:RelatesTo>
+        <wsa:MessageID>{message_id}</wsa:MessageID>
     </soap:Header>
     <soap:Body>
         <wsd:ProbeMatches>
             <wsd:ProbeMatch>
                 <wsa:EndpointReference>
-                    <wsa:Address>urn:uuid:550e8400-e29b-41d4-a716-446655440000</wsa:Address>
+                    <wsa:Address>urn:uuid:{uuid}</wsa:Address>
                 </wsa:EndpointReference>
                 <wsd:Types>wsdp:Device pnpx:NetworkDevice scan:Scanner</wsd:Types>
                 <wsd:Scopes>
@@ -682,12 +1085,12 @@ async fn wsd_description(req: HttpRequest) -> impl Responder {
                     http://schemas.microsoft.com/windows/pnpx/2005/10/category/scanner
                     http://schemas.microsoft.com/windows/pnpx/2005/10/category/imaging
                 </wsd:Scopes>
-                <wsd:XAddrs>http://{}/wsd</wsd:XAddrs>
+                <wsd:XAddrs>{scheme}://{host}{scope}/wsd</wsd:XAddrs>
                 <wsd:MetadataVersion>1</wsd:MetadataVersion>
             </wsd:ProbeMatch>
         </wsd:ProbeMatches>
     </soap:Body>
-</soap:Envelope>"#, host);
+</soap:Envelope>"#, uuid = data.identity.uuid, scope = data.scope, message_id = wsd::new_message_id());
 
     HttpResponse::build(StatusCode::OK)
         .content_type("application/soap+xml; charset=utf-8")
@@ -696,62 +1099,78 @@ async fn wsd_description(req: HttpRequest) -> impl Responder {
 }
 
 // Windows可能会查询的WS-Discovery端点
+// 设备和扫描服务的地址都由它处理，按 wsa:Action 分派
+#[actix_web::routes]
 #[post("/wsd")]
-async fn wsd_post(req: HttpRequest, body: web::Bytes) -> impl Responder {
+#[post("/wsd/scan")]
+#[post("/wsd/subscription")]
+async fn wsd_post(req: HttpRequest, body: web::Bytes, data: web::Data<AppState>) -> impl Responder {
     log_request_details(&req, "WSD_POST");
     log_request_body(&body, "WSD_POST");
     
-    println!("WS-Discovery POST request received");
     
     // 解析请求以确定响应类型
     let body_str = String::from_utf8_lossy(&body);
-    let host = req.headers()
-        .get("Host")
-        .and_then(|h| h.to_str().ok())
-        .unwrap_or("localhost:8080");
+    let document = roxmltree::Document::parse(&body_str);
+    let Some(envelope) = document.as_ref().ok().and_then(wsd::Envelope::parse) else {
+        warn!("WSD request is not a SOAP envelope with an action");
+        return wsd::fault(
+            None,
+            FaultCode::Sender,
+            "wsa:MessageInformationHeaderRequired",
+            "Expected a SOAP envelope with wsa:Action",
+        );
+    };
+    let host = request_host(&req);
+    let scheme = request_scheme(&req);
+    // 响应的 RelatesTo 是请求的 MessageID
+    let message_id = wsd::new_message_id();
+    let relates_to = wsd::relates_to(envelope.message_id.as_deref());
     
-    let response = if body_str.contains("GetMetadataRequest") {
-        // 响应GetMetadata请求
+    let identity = &data.identity;
+
+    let response = if let Some(response_action) = metadata_response_action(&envelope.action) {
+        // 响应 WS-Transfer Get 或 GetMetadata 请求
         format!(r#"<?xml version="1.0" encoding="UTF-8"?>
 <soap:Envelope xmlns:soap="http://www.w3.org/2003/05/soap-envelope"
                xmlns:wsa="http://schemas.xmlsoap.org/ws/2004/08/addressing"
                xmlns:wsx="http://schemas.xmlsoap.org/ws/2004/09/mex"
                xmlns:wsdp="http://schemas.xmlsoap.org/ws/2006/02/devprof"
                xmlns:pnpx="http://schemas.microsoft.com/windows/pnpx/2005/10"
-               xmlns:scan="http://schemas.hp.com/imaging/escl/2011/05/03">
+               xmlns:wscn="http://schemas.microsoft.com/windows/2006/08/wdp/scan">
     <soap:Header>
         <wsa:To>http://schemas.xmlsoap.org/ws/2004/08/addressing/role/anonymous</wsa:To>
-        <wsa:Action>http://schemas.xmlsoap.org/ws/2004/09/mex/GetMetadataResponse</wsa:Action>
-        <wsa:MessageID>urn:uuid:550e8400-e29b-41d4-a716-446655440002</wsa:MessageID>
-        <wsa:RelatesTo>urn:uuid:550e8400-e29b-41d4-a716-446655440003</wsa:RelatesTo>
+        <wsa:Action>{response_action}</wsa:Action>
+        <wsa:MessageID>{message_id}</wsa:MessageID>
+        {relates_to}
     </soap:Header>
     <soap:Body>
         <wsx:Metadata>
             <wsx:MetadataSection Dialect="http://schemas.xmlsoap.org/ws/2006/02/devprof/ThisModel">
                 <wsdp:ThisModel>
-                    <wsdp:Manufacturer>MockCompany</wsdp:Manufacturer>
-                    <wsdp:ManufacturerUrl>http://www.mockcompany.com</wsdp:ManufacturerUrl>
-                    <wsdp:ModelName>eSCL Mock Scanner</wsdp:ModelName>
-                    <wsdp:ModelNumber>1.0</wsdp:ModelNumber>
-                    <wsdp:ModelUrl>http://www.mockcompany.com</wsdp:ModelUrl>
-                    <wsdp:PresentationUrl>http://{}/</wsdp:PresentationUrl>
+                    <wsdp:Manufacturer>{make}</wsdp:Manufacturer>
+                    <wsdp:ManufacturerUrl>{manufacturer_url}</wsdp:ManufacturerUrl>
+                    <wsdp:ModelName>{model}</wsdp:ModelName>
+                    <wsdp:ModelNumber>{model_number}</wsdp:ModelNumber>
+                    <wsdp:ModelUrl>{model_url}</wsdp:ModelUrl>
+                    <wsdp:PresentationUrl>{scheme}://{host}/</wsdp:PresentationUrl>
                 </wsdp:ThisModel>
             </wsx:MetadataSection>
             <wsx:MetadataSection Dialect="http://schemas.xmlsoap.org/ws/2006/02/devprof/ThisDevice">
                 <wsdp:ThisDevice>
-                    <wsdp:FriendlyName>eSCL Mock Scanner</wsdp:FriendlyName>
-                    <wsdp:FirmwareVersion>1.0.0</wsdp:FirmwareVersion>
-                    <wsdp:SerialNumber>550e8400-e29b-41d4-a716-446655440000</wsdp:SerialNumber>
+                    <wsdp:FriendlyName>{friendly_name}</wsdp:FriendlyName>
+                    <wsdp:FirmwareVersion>{firmware}</wsdp:FirmwareVersion>
+                    <wsdp:SerialNumber>{serial}</wsdp:SerialNumber>
                 </wsdp:ThisDevice>
             </wsx:MetadataSection>
             <wsx:MetadataSection Dialect="http://schemas.xmlsoap.org/ws/2006/02/devprof/Relationship">
                 <wsdp:Relationship Type="http://schemas.xmlsoap.org/ws/2006/02/devprof/host">
                     <wsdp:Hosted>
                         <wsa:EndpointReference>
-                            <wsa:Address>urn:uuid:550e8400-e29b-41d4-a716-446655440000</wsa:Address>
+                            <wsa:Address>{scheme}://{host}{scope}/wsd/scan</wsa:Address>
                         </wsa:EndpointReference>
-                        <wsdp:Types>scan:ScannerServiceType</wsdp:Types>
-                        <wsdp:ServiceId>urn:uuid:550e8400-e29b-41d4-a716-446655440000</wsdp:ServiceId>
+                        <wsdp:Types>wscn:ScannerServiceType</wsdp:Types>
+                        <wsdp:ServiceId>urn:uuid:{uuid}</wsdp:ServiceId>
                     </wsdp:Hosted>
                 </wsdp:Relationship>
             </wsx:MetadataSection>
@@ -760,51 +1179,41 @@ async fn wsd_post(req: HttpRequest, body: web::Bytes) -> impl Responder {
             </wsx:MetadataSection>
         </wsx:Metadata>
     </soap:Body>
-</soap:Envelope>"#, host)
-    } else if body_str.contains("ScanAvailableEvent") {
-        // 响应扫描可用事件订阅
-        format!(r#"<?xml version="1.0" encoding="UTF-8"?>
-<soap:Envelope xmlns:soap="http://www.w3.org/2003/05/soap-envelope"
-               xmlns:wsa="http://schemas.xmlsoap.org/ws/2004/08/addressing"
-               xmlns:wse="http://schemas.xmlsoap.org/ws/2004/08/eventing"
-               xmlns:scan="http://schemas.microsoft.com/windows/2006/08/wdp/scan">
-    <soap:Header>
-        <wsa:To>http://schemas.xmlsoap.org/ws/2004/08/addressing/role/anonymous</wsa:To>
-        <wsa:Action>http://schemas.xmlsoap.org/ws/2004/08/eventing/SubscribeResponse</wsa:Action>
-        <wsa:MessageID>urn:uuid:550e8400-e29b-41d4-a716-446655440004</wsa:MessageID>
-        <wsa:RelatesTo>urn:uuid:550e8400-e29b-41d4-a716-446655440005</wsa:RelatesTo>
-    </soap:Header>
-    <soap:Body>
-        <wse:SubscribeResponse>
-            <wse:SubscriptionManager>
-                <wsa:Address>http://{}/wsd/subscription</wsa:Address>
-            </wse:SubscriptionManager>
-            <wse:Expires>P0Y0M0DT0H5M0S</wse:Expires>
-        </wse:SubscribeResponse>
-    </soap:Body>
-</soap:Envelope>"#, host)
+</soap:Envelope>"#,
+            make = identity.make,
+            model = identity.model,
+            friendly_name = identity.friendly_name,
+            firmware = identity.firmware_version,
+            serial = identity.serial_number,
+            uuid = identity.uuid,
+            model_number = identity.model_number,
+            manufacturer_url = identity.manufacturer_url,
+            model_url = identity.model_url,
+            scope = data.scope,
+        )
+    } else if let Some(response) = eventing::handle(
+        &data.subscriptions,
+        &envelope,
+        &format!("{scheme}://{host}{}/wsd/subscription", data.scope),
+    )
+    .await
+    {
+        return response;
+    } else if let Some(challenge) =
+        wsscan::operation(&envelope.action).and_then(|_| auth::authorize(&req).err())
+    {
+        // 元数据 Get 和订阅不需要认证，WS-Scan 操作与 eSCL 一样要过认证
+        return challenge;
+    } else if let Some(response) = wsscan::handle(&req, &data, &envelope).await {
+        return response;
     } else {
-        // 默认响应
-        format!(r#"<?xml version="1.0" encoding="UTF-8"?>
-<soap:Envelope xmlns:soap="http://www.w3.org/2003/05/soap-envelope"
-               xmlns:wsa="http://schemas.xmlsoap.org/ws/2004/08/addressing"
-               xmlns:wsd="http://schemas.xmlsoap.org/ws/2005/04/discovery">
-    <soap:Header>
-        <wsa:To>http://schemas.xmlsoap.org/ws/2004/08/addressing/role/anonymous</wsa:To>
-        <wsa:Action>http://schemas.xmlsoap.org/ws/2005/04/discovery/Hello</wsa:Action>
-        <wsa:MessageID>urn:uuid:550e8400-e29b-41d4-a716-446655440006</wsa:MessageID>
-    </soap:Header>
-    <soap:Body>
-        <wsd:Hello>
-            <wsa:EndpointReference>
-                <wsa:Address>urn:uuid:550e8400-e29b-41d4-a716-446655440000</wsa:Address>
-            </wsa:EndpointReference>
-            <wsd:Types>wsdp:Device pnpx:NetworkDevice</wsd:Types>
-            <wsd:XAddrs>http://{}/wsd</wsd:XAddrs>
-            <wsd:MetadataVersion>1</wsd:MetadataVersion>
-        </wsd:Hello>
-    </soap:Body>
-</soap:Envelope>"#, host)
+        warn!(action = envelope.action, "Unsupported WSD action");
+        return wsd::fault(
+            envelope.message_id.as_deref(),
+            FaultCode::Sender,
+            "wsa:ActionNotSupported",
+            &format!("{} is not supported", envelope.action),
+        );
     };
 
     HttpResponse::Ok()
@@ -813,16 +1222,31 @@ async fn wsd_post(req: HttpRequest, body: web::Bytes) -> impl Responder {
         .body(response)
 }
 
+// 设备元数据可以通过 WS-Transfer Get 或 WS-MetadataExchange 获取
+fn metadata_response_action(action: &str) -> Option<&'static str> {
+    match action {
+        "http://schemas.xmlsoap.org/ws/2004/09/transfer/Get" => Some("http://schemas.xmlsoap.org/ws/2004/09/transfer/GetResponse"),
+        "http://schemas.xmlsoap.org/ws/2004/09/mex/GetMetadata/Request" => {
+            Some("http://schemas.xmlsoap.org/ws/2004/09/mex/GetMetadata/Response")
+        }
+        _ => None,
+    }
+}
+
 // 添加 Windows 设备元数据端点
 #[get("/device.xml")]
-async fn device_metadata(req: HttpRequest) -> impl Responder {
+async fn device_metadata(req: HttpRequest, data: web::Data<AppState>) -> impl Responder {
     log_request_details(&req, "DeviceMetadata");
 
-    println!("Device metadata requested");
-    
-    // 获取服务器地址信息
+    // 描述的是客户端连接所用的地址，Host 是主机名时按连接的协议族判断 IP 版本
     let (server_ip, _) = get_server_address(&req);
-    
+    let described = server_ip
+        .split('%')
+        .next()
+        .and_then(|ip| ip.parse::<IpAddr>().ok())
+        .or_else(|| req.peer_addr().map(|peer| peer.ip().to_canonical()));
+    let ip_version = if matches!(described, Some(IpAddr::V6(_))) { 6 } else { 4 };
+
     let device_xml = format!(r#"<?xml version="1.0" encoding="UTF-8"?>
 <root xmlns="urn:schemas-upnp-org:device-1-0" 
       xmlns:pnpx="http://schemas.microsoft.com/windows/pnpx/2005/10" 
@@ -833,23 +1257,23 @@ async fn device_metadata(req: HttpRequest) -> impl Responder {
     </specVersion>
     <device>
         <pnpx:X_deviceCategory>Scanners</pnpx:X_deviceCategory>
-        <pnpx:X_hardwareId>PnPX_ServiceId:550e8400-e29b-41d4-a716-446655440000</pnpx:X_hardwareId>
+        <pnpx:X_hardwareId>PnPX_ServiceId:{uuid}</pnpx:X_hardwareId>
         <pnpx:X_compatibleId>PnPX_ServiceId:eSCL_Scanner</pnpx:X_compatibleId>
         <pnpx:X_physicalLocation></pnpx:X_physicalLocation>
         <pnpx:X_networkInterfaceLuid>0</pnpx:X_networkInterfaceLuid>
-        <pnpx:X_ipAddress>{}</pnpx:X_ipAddress>
-        <pnpx:X_ipVersion>4</pnpx:X_ipVersion>
+        <pnpx:X_ipAddress>{server_ip}</pnpx:X_ipAddress>
+        <pnpx:X_ipVersion>{ip_version}</pnpx:X_ipVersion>
         <df:X_deviceCategory>Multimedia.Scanner</df:X_deviceCategory>
         <deviceType>urn:schemas-upnp-org:device:Scanner:1</deviceType>
-        <friendlyName>eSCL Mock Scanner</friendlyName>
-        <manufacturer>MockCompany</manufacturer>
-        <manufacturerURL>http://www.mockcompany.com</manufacturerURL>
+        <friendlyName>{friendly_name}</friendlyName>
+        <manufacturer>{make}</manufacturer>
+        <manufacturerURL>{manufacturer_url}</manufacturerURL>
         <modelDescription>eSCL Mock Scanner for Testing</modelDescription>
-        <modelName>eSCL-Mock-Scanner</modelName>
-        <modelNumber>1.0</modelNumber>
-        <modelURL>http://www.mockcompany.com</modelURL>
-        <serialNumber>550e8400-e29b-41d4-a716-446655440000</serialNumber>
-        <UDN>uuid:550e8400-e29b-41d4-a716-446655440000</UDN>
+        <modelName>{model}</modelName>
+        <modelNumber>{model_number}</modelNumber>
+        <modelURL>{model_url}</modelURL>
+        <serialNumber>{serial}</serialNumber>
+        <UDN>uuid:{uuid}</UDN>
         <iconList>
             <icon>
                 <mimetype>image/png</mimetype>
@@ -863,14 +1287,24 @@ async fn device_metadata(req: HttpRequest) -> impl Responder {
             <service>
                 <serviceType>urn:schemas-hp-com:service:imaging:ScanService:1</serviceType>
                 <serviceId>urn:schemas-hp-com:serviceId:ScanService</serviceId>
-                <SCPDURL>/eSCL/ScannerCapabilities</SCPDURL>
-                <controlURL>/eSCL</controlURL>
-                <eventSubURL>/eSCL</eventSubURL>
+                <SCPDURL>{scope}/ScannerCapabilities</SCPDURL>
+                <controlURL>{scope}</controlURL>
+                <eventSubURL>{scope}</eventSubURL>
             </service>
         </serviceList>
         <presentationURL>/</presentationURL>
     </device>
-</root>"#, server_ip);
+</root>"#,
+        uuid = data.identity.uuid,
+        friendly_name = data.identity.friendly_name,
+        make = data.identity.make,
+        model = data.identity.model,
+        serial = data.identity.serial_number,
+        model_number = data.identity.model_number,
+        manufacturer_url = data.identity.manufacturer_url,
+        model_url = data.identity.model_url,
+        scope = data.scope,
+    );
 
     HttpResponse::build(StatusCode::OK)
         .content_type("text/xml")
@@ -879,11 +1313,9 @@ async fn device_metadata(req: HttpRequest) -> impl Responder {
 
 // 添加 SSDP 发现支持
 #[get("/ssdp")]
-async fn ssdp_description(req: HttpRequest) -> impl Responder {
+async fn ssdp_description(req: HttpRequest, data: web::Data<AppState>) -> impl Responder {
     log_request_details(&req, "SSDPDescription");
 
-    println!("SSDP description requested");
-    
     let url_prefix = get_server_url_prefix(&req);
     
     let ssdp_response = format!(
@@ -894,14 +1326,15 @@ async fn ssdp_description(req: HttpRequest) -> impl Responder {
          LOCATION: {}/device.xml\r\n\
          SERVER: Windows/10 UPnP/1.0 eSCL-Mock-Server/1.0\r\n\
          ST: urn:schemas-upnp-org:device:Scanner:1\r\n\
-         USN: uuid:550e8400-e29b-41d4-a716-446655440000::urn:schemas-upnp-org:device:Scanner:1\r\n\
+         USN: uuid:{}::urn:schemas-upnp-org:device:Scanner:1\r\n\
          BOOTID.UPNP.ORG: 1\r\n\
          CONFIGID.UPNP.ORG: 1\r\n\r\n",
         std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
             .unwrap()
             .as_secs(),
-        url_prefix
+        url_prefix,
+        data.identity.uuid
     );
 
     HttpResponse::build(StatusCode::OK)
@@ -910,10 +1343,9 @@ async fn ssdp_description(req: HttpRequest) -> impl Responder {
 }
 
 pub(crate) async fn not_found(req: HttpRequest) -> impl Responder {
-    log_request_details(&req, "⚠️ NOT_FOUND");
+    log_request_details(&req, "NotFound");
 
-    println!("❌ MISSING ENDPOINT: {} {} (This endpoint might be needed by Windows!)", 
-             req.method(), req.uri());
+    warn!(method = %req.method(), uri = %req.uri(), "Missing endpoint requested, a client might depend on it");
     
     // 提供一些建议的端点
     let suggested_endpoints = vec![
@@ -945,14 +1377,12 @@ pub(crate) async fn not_found(req: HttpRequest) -> impl Responder {
 #[get("/favicon.ico")]
 async fn favicon(req: HttpRequest) -> impl Responder {
     log_request_details(&req, "Favicon");
-    println!("Favicon requested - returning empty response");
     HttpResponse::NotFound().finish()
 }
 
 #[get("/robots.txt")]
 async fn robots_txt(req: HttpRequest) -> impl Responder {
     log_request_details(&req, "RobotsTxt");
-    println!("Robots.txt requested");
     HttpResponse::Ok()
         .content_type("text/plain")
         .body("User-agent: *\nDisallow: /")
@@ -962,8 +1392,7 @@ async fn robots_txt(req: HttpRequest) -> impl Responder {
 #[get("/description.xml")]
 async fn description_xml(req: HttpRequest) -> impl Responder {
     log_request_details(&req, "DescriptionXML");
-    println!("Description.xml requested");
-    
+
     // 重定向到device.xml
     HttpResponse::MovedPermanently()
         .insert_header(("Location", "/device.xml"))
@@ -973,54 +1402,79 @@ async fn description_xml(req: HttpRequest) -> impl Responder {
 // 添加Windows扫描仪安装相关的端点
 
 #[get("/eSCL")]
-async fn escl_root(req: HttpRequest) -> impl Responder {
+async fn escl_root(req: HttpRequest, data: web::Data<AppState>) -> impl Responder {
     log_request_details(&req, "eSCL_Root");
-    println!("eSCL root endpoint requested - redirecting to capabilities");
-    
-    // 重定向到ScannerCapabilities
+
+    // 重定向到该端口第一台设备的ScannerCapabilities
     HttpResponse::MovedPermanently()
-        .insert_header(("Location", "/eSCL/ScannerCapabilities"))
+        .insert_header(("Location", format!("{}/ScannerCapabilities", data.scope)))
         .finish()
 }
 
+// TLS 状态的 JSON 描述，/ssl 和 /tls 使用不同的键名
+fn tls_status(data: &AppState, enabled_key: &str) -> String {
+    let ports = data.ports();
+    match &data.tls {
+        Some(certificate) => format!(
+            r#"{{"{enabled_key}": true, "https_port": {}, "http_enabled": {}, "self_signed": {}, "fingerprint_sha256": "{}", "certificate": "/tls/certificate.pem"}}"#,
+            ports.https.unwrap_or_default(),
+            ports.http.is_some(),
+            certificate.is_self_signed(),
+            certificate.fingerprint()
+        ),
+        None => format!(r#"{{"{enabled_key}": false, "message": "HTTP only, start with --tls to enable HTTPS"}}"#),
+    }
+}
+
 // Windows可能检查的SSL/TLS信息
 #[get("/ssl")]
-async fn ssl_info(req: HttpRequest) -> impl Responder {
+async fn ssl_info(req: HttpRequest, data: web::Data<AppState>) -> impl Responder {
     log_request_details(&req, "SSL_Info");
-    println!("SSL info requested");
-    
+
     HttpResponse::Ok()
         .content_type("application/json")
-        .body(r#"{"ssl_enabled": false, "message": "HTTP only mock server"}"#)
+        .body(tls_status(&data, "ssl_enabled"))
 }
 
 #[get("/tls")]
-async fn tls_info(req: HttpRequest) -> impl Responder {
+async fn tls_info(req: HttpRequest, data: web::Data<AppState>) -> impl Responder {
     log_request_details(&req, "TLS_Info");
-    println!("TLS info requested");
-    
+
     HttpResponse::Ok()
         .content_type("application/json")
-        .body(r#"{"tls_enabled": false, "message": "HTTP only mock server"}"#)
+        .body(tls_status(&data, "tls_enabled"))
+}
+
+// 服务器证书，客户端可以导入自签名证书
+#[get("/tls/certificate.pem")]
+async fn tls_certificate(req: HttpRequest, data: web::Data<AppState>) -> impl Responder {
+    log_request_details(&req, "TLS_Certificate");
+
+    match &data.tls {
+        Some(certificate) => HttpResponse::Ok()
+            .content_type("application/x-pem-file")
+            .body(certificate.pem().to_owned()),
+        None => HttpResponse::NotFound().finish(),
+    }
 }
 
 // Windows设备驱动信息
 #[get("/driver")]
-async fn driver_info(req: HttpRequest) -> impl Responder {
+async fn driver_info(req: HttpRequest, data: web::Data<AppState>) -> impl Responder {
     log_request_details(&req, "Driver_Info");
-    println!("Driver info requested");
-    
-    let driver_xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+
+    let driver_xml = format!(r#"<?xml version="1.0" encoding="UTF-8"?>
 <DriverInfo>
-    <Manufacturer>MockCompany</Manufacturer>
-    <Model>eSCL Mock Scanner</Model>
+    <Manufacturer>{}</Manufacturer>
+    <Model>{}</Model>
     <DriverVersion>1.0.0</DriverVersion>
-    <eSCLVersion>2.63</eSCLVersion>
+    <eSCLVersion>{}</eSCLVersion>
     <SupportedProtocols>
-        <Protocol>HTTP</Protocol>
+        <Protocol>HTTP</Protocol>{}
         <Protocol>eSCL</Protocol>
     </SupportedProtocols>
-</DriverInfo>"#;
+</DriverInfo>"#, data.identity.make, data.identity.model, data.live().capabilities.version,
+        if data.tls.is_some() { "\n        <Protocol>HTTPS</Protocol>" } else { "" });
 
     HttpResponse::Ok()
         .content_type("text/xml")
@@ -1029,64 +1483,89 @@ async fn driver_info(req: HttpRequest) -> impl Responder {
 
 // 可能的Windows PnP查询
 #[get("/pnp")]
-async fn pnp_info(req: HttpRequest) -> impl Responder {
+async fn pnp_info(req: HttpRequest, data: web::Data<AppState>) -> impl Responder {
     log_request_details(&req, "PnP_Info");
-    println!("PnP info requested");
-    
+
     HttpResponse::Ok()
         .content_type("text/plain")
-        .body("PnP ID: MockCompany_eSCL_Scanner")
+        .body(format!(
+            "PnP ID: {}_{}",
+            data.identity.make.replace(' ', "_"),
+            data.identity.model.replace(' ', "_")
+        ))
 }
 
 // Windows可能查询的端口信息
 #[get("/port")]
-async fn port_info(req: HttpRequest) -> impl Responder {
+async fn port_info(req: HttpRequest, data: web::Data<AppState>) -> impl Responder {
     log_request_details(&req, "Port_Info");
-    println!("Port info requested");
-    
+
+    let ports = data.ports();
+    let json_port = |port: Option<u16>| port.map_or_else(|| r#""not_supported""#.to_owned(), |port| port.to_string());
     HttpResponse::Ok()
         .content_type("application/json")
-        .body(r#"{"http_port": 8080, "https_port": "not_supported"}"#)
+        .body(format!(
+            r#"{{"http_port": {}, "https_port": {}}}"#,
+            json_port(ports.http),
+            json_port(ports.https)
+        ))
 }
 
 // HTTPS重定向处理
 #[get("/https")]
-async fn https_info(req: HttpRequest) -> impl Responder {
+async fn https_info(req: HttpRequest, data: web::Data<AppState>) -> impl Responder {
     log_request_details(&req, "HTTPS_Info");
-    println!("HTTPS endpoint requested");
-    
-    HttpResponse::Ok()
-        .content_type("text/plain")
-        .body("This mock server runs on HTTP only. HTTPS not supported.")
+
+    let Some(https_port) = data.ports().https else {
+        return HttpResponse::Ok()
+            .content_type("text/plain")
+            .body("This mock server runs on HTTP only. Start it with --tls to enable HTTPS.");
+    };
+    // 保留客户端使用的主机名，只替换协议和端口
+    let (ip, _) = get_server_address(&req);
+    let location = if ip.contains(':') {
+        format!("https://[{ip}]:{https_port}/")
+    } else {
+        format!("https://{ip}:{https_port}/")
+    };
+    HttpResponse::Found()
+        .insert_header((header::LOCATION, location))
+        .finish()
 }
 
 // 可能的认证端点
 #[get("/auth")]
-async fn auth_info(req: HttpRequest) -> impl Responder {
+async fn auth_info(req: HttpRequest, auth: Option<web::Data<Authenticator>>) -> impl Responder {
     log_request_details(&req, "Auth");
-    println!("Auth endpoint requested");
-    
+
+    let body = match auth {
+        Some(auth) => format!(
+            r#"{{"auth_required": true, "method": "{}", "realm": "{}"}}"#,
+            auth.scheme(),
+            auth.realm()
+        ),
+        None => r#"{"auth_required": false, "method": "none"}"#.to_owned(),
+    };
     HttpResponse::Ok()
         .content_type("application/json")
-        .body(r#"{"auth_required": false, "method": "none"}"#)
+        .body(body)
 }
 
 // 添加Windows 11可能需要的额外端点
 
 // Windows可能查询的系统信息
 #[get("/system")]
-async fn system_info(req: HttpRequest) -> impl Responder {
+async fn system_info(req: HttpRequest, data: web::Data<AppState>) -> impl Responder {
     log_request_details(&req, "SystemInfo");
-    println!("System info requested");
-    
-    let system_xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+
+    let system_xml = format!(r#"<?xml version="1.0" encoding="UTF-8"?>
 <SystemInfo>
     <OSVersion>Mock OS 1.0</OSVersion>
-    <FirmwareVersion>1.0.0</FirmwareVersion>
+    <FirmwareVersion>{}</FirmwareVersion>
     <SystemUptime>86400</SystemUptime>
     <MemoryUsage>50</MemoryUsage>
     <NetworkStatus>Connected</NetworkStatus>
-</SystemInfo>"#;
+</SystemInfo>"#, data.identity.firmware_version);
 
     HttpResponse::Ok()
         .content_type("text/xml")
@@ -1095,35 +1574,33 @@ async fn system_info(req: HttpRequest) -> impl Responder {
 
 // Windows设备发现时的辅助端点
 #[get("/discovery")]
-async fn discovery_info(req: HttpRequest) -> impl Responder {
+async fn discovery_info(req: HttpRequest, data: web::Data<AppState>) -> impl Responder {
     log_request_details(&req, "DiscoveryInfo");
-    println!("Discovery info requested");
-    
+
     HttpResponse::Ok()
         .content_type("application/json")
-        .body(r#"{"device_type": "scanner", "protocol": "eSCL", "version": "2.97"}"#)
+        .body(format!(
+            r#"{{"device_type": "scanner", "protocol": "eSCL", "version": "{}"}}"#,
+            data.live().capabilities.version
+        ))
 }
 
 // Windows可能查询的网络配置
 #[get("/network")]
-async fn network_info(req: HttpRequest) -> impl Responder {
+async fn network_info(req: HttpRequest, data: web::Data<AppState>) -> impl Responder {
     log_request_details(&req, "NetworkInfo");
-    println!("Network info requested");
-    
-    let host = req.headers()
-        .get("Host")
-        .and_then(|h| h.to_str().ok())
-        .unwrap_or("localhost:8080");
+
+    let host = request_host(&req);
     
     let network_xml = format!(r#"<?xml version="1.0" encoding="UTF-8"?>
 <NetworkInfo>
     <HostAddress>{}</HostAddress>
-    <Protocol>HTTP</Protocol>
+    <Protocol>{}</Protocol>
     <ConnectionType>Ethernet</ConnectionType>
-    <IPv4Enabled>true</IPv4Enabled>
-    <IPv6Enabled>false</IPv6Enabled>
+    <IPv4Enabled>{}</IPv4Enabled>
+    <IPv6Enabled>{}</IPv6Enabled>
     <DHCPEnabled>true</DHCPEnabled>
-</NetworkInfo>"#, host);
+</NetworkInfo>"#, host, request_scheme(&req).to_uppercase(), data.bind_address.ipv4_enabled(), data.bind_address.ipv6_enabled());
 
     HttpResponse::Ok()
         .content_type("text/xml")
@@ -1132,13 +1609,12 @@ async fn network_info(req: HttpRequest) -> impl Responder {
 
 // Windows可能验证的功能端点
 #[get("/capabilities")]
-async fn general_capabilities(req: HttpRequest) -> impl Responder {
+async fn general_capabilities(req: HttpRequest, data: web::Data<AppState>) -> impl Responder {
     log_request_details(&req, "GeneralCapabilities");
-    println!("General capabilities requested");
-    
-    // 重定向到标准的ScannerCapabilities
+
+    // 重定向到该端口第一台设备的ScannerCapabilities
     HttpResponse::MovedPermanently()
-        .insert_header(("Location", "/eSCL/ScannerCapabilities"))
+        .insert_header(("Location", format!("{}/ScannerCapabilities", data.scope)))
         .finish()
 }
 
@@ -1146,19 +1622,36 @@ async fn general_capabilities(req: HttpRequest) -> impl Responder {
 #[get("/health")]
 async fn health_check(req: HttpRequest) -> impl Responder {
     log_request_details(&req, "HealthCheck");
-    println!("Health check requested");
-    
+
     HttpResponse::Ok()
         .content_type("application/json")
         .body(r#"{"status": "healthy", "uptime": 86400, "scanner_ready": true}"#)
 }
 
+// Prometheus 指标端点
+#[get("/metrics")]
+async fn metrics(data: web::Data<AppState>) -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(data.metrics.render())
+}
+
+// 客户端一致性报告
+#[get("/_report")]
+async fn conformance_report(data: web::Data<AppState>) -> impl Responder {
+    if !data.report.enabled() {
+        return HttpResponse::NotFound().finish();
+    }
+    HttpResponse::Ok()
+        .content_type("text/plain; charset=utf-8")
+        .body(data.report.render())
+}
+
 // 处理可能的OPTIONS预检请求
 #[actix_web::route("/{path:.*}", method = "OPTIONS")]
 async fn handle_options(req: HttpRequest) -> impl Responder {
     log_request_details(&req, "OPTIONS_Request");
-    println!("OPTIONS preflight request");
-    
+
     HttpResponse::Ok()
         .insert_header(("Access-Control-Allow-Origin", "*"))
         .insert_header(("Access-Control-Allow-Methods", "GET, POST, PUT, DELETE, OPTIONS"))
@@ -1167,45 +1660,232 @@ async fn handle_options(req: HttpRequest) -> impl Responder {
         .finish()
 }
 
-// 添加管理页面端点 - mDNS adminurl指向的页面
-#[get("/admin")]
+const ADMIN_PAGE: &str = include_str!("../res/admin.html");
+/// Largest scan image accepted by the admin console.
+const MAX_UPLOAD_SIZE: usize = 32 * 1024 * 1024;
+
+// 管理控制台，mDNS adminurl 指向的页面。页面内容由同一控制台下 events 推送的状态生成
+#[get("")]
 async fn admin_page(req: HttpRequest) -> impl Responder {
     log_request_details(&req, "AdminPage");
-    println!("Admin page requested");
-    
-    let (server_ip, server_port) = get_server_address(&req);
-    
-    let admin_html = format!(r#"<!DOCTYPE html>
-<html>
-<head>
-    <title>eSCL Scanner - Administration</title>
-    <meta charset="UTF-8">
-</head>
-<body>
-    <h1>eSCL Scanner</h1>
-    <h2>Device Administration</h2>
-    <p><strong>Status:</strong> Ready</p>
-    <p><strong>IP Address:</strong> {}</p>
-    <p><strong>Port:</strong> {}</p>
-    <p><strong>eSCL Version:</strong> 2.97</p>
-    <p><strong>Supported Features:</strong></p>
-    <ul>
-        <li>Flatbed (Platen) scanning</li>
-        <li>ADF (Auto Document Feeder) scanning</li>
-        <li>Duplex scanning</li>
-        <li>Color, Grayscale, Binary modes</li>
-        <li>PDF and JPEG output formats</li>
-    </ul>
-    <p><strong>eSCL Endpoints:</strong></p>
-    <ul>
-        <li><a href="/eSCL/ScannerCapabilities">Scanner Capabilities</a></li>
-        <li><a href="/eSCL/ScannerStatus">Scanner Status</a></li>
-        <li><a href="/device.xml">Device Description</a></li>
-    </ul>
-</body>
-</html>"#, server_ip, server_port);
 
     HttpResponse::Ok()
         .content_type("text/html; charset=utf-8")
-        .body(admin_html)
+        .body(ADMIN_PAGE)
+}
+
+// 同一端口上的设备，控制台用来在设备之间切换
+#[get("/devices")]
+async fn admin_devices(devices: web::Data<PortDevices>) -> impl Responder {
+    HttpResponse::Ok().json(console::devices(&devices))
+}
+
+// 设备状态、任务和最近的请求，变化时通过 SSE 推送
+#[get("/events")]
+async fn admin_events(data: web::Data<AppState>) -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        .streaming(console::events(data.into_inner()))
+}
+
+#[get("/transcript")]
+async fn admin_transcript(req: HttpRequest, data: web::Data<AppState>) -> impl Responder {
+    log_request_details(&req, "AdminTranscript");
+
+    HttpResponse::Ok()
+        .content_type("text/plain; charset=utf-8")
+        .insert_header((header::CONTENT_DISPOSITION, "attachment; filename=\"escl-mock-transcript.txt\""))
+        .body(data.requests.transcript())
+}
+
+#[derive(Debug, Deserialize)]
+struct AdfQuery {
+    sheets: u32,
+}
+
+// 输稿器中的纸张数，之后创建的任务生效
+#[post("/adf")]
+async fn admin_adf(req: HttpRequest, query: web::Query<AdfQuery>, data: web::Data<AppState>) -> impl Responder {
+    log_request_details(&req, "AdminAdf");
+
+    if query.sheets == 0 {
+        return HttpResponse::BadRequest().body("sheets must be at least 1\n");
+    }
+    data.update_live(|live| live.sources.adf_pages = query.sheets);
+    info!(sheets = query.sheets, "ADF loaded from the admin console");
+    HttpResponse::Ok()
+        .content_type("text/plain; charset=utf-8")
+        .body(format!("adf sheets: {}\n", query.sheets))
+}
+
+#[derive(Debug, Deserialize)]
+struct FaultsQuery {
+    busy: Option<bool>,
+    scan_job_delay_ms: Option<u64>,
+    next_document_delay_ms: Option<u64>,
+}
+
+// 未指定的故障设置保持不变
+#[post("/faults")]
+async fn admin_faults(req: HttpRequest, query: web::Query<FaultsQuery>, data: web::Data<AppState>) -> impl Responder {
+    log_request_details(&req, "AdminFaults");
+
+    let faults = data.update_live(|live| {
+        if let Some(busy) = query.busy {
+            live.faults.busy = busy;
+        }
+        if let Some(delay) = query.scan_job_delay_ms {
+            live.faults.scan_job_delay = Duration::from_millis(delay);
+        }
+        if let Some(delay) = query.next_document_delay_ms {
+            live.faults.next_document_delay = Duration::from_millis(delay);
+        }
+        live.faults.clone()
+    });
+    info!(?faults, "Faults changed from the admin console");
+    HttpResponse::Ok()
+        .content_type("text/plain; charset=utf-8")
+        .body(format!(
+            "busy: {}\nscan_job_delay_ms: {}\nnext_document_delay_ms: {}\n",
+            faults.busy,
+            faults.scan_job_delay.as_millis(),
+            faults.next_document_delay.as_millis()
+        ))
+}
+
+#[derive(Debug, Deserialize)]
+struct ImagesQuery {
+    /// Add the image to the served ones instead of replacing them.
+    #[serde(default)]
+    append: bool,
+}
+
+// 请求体是 JPEG 图片，NextDocument 以 image/jpeg 返回
+#[post("/images")]
+async fn admin_upload_image(
+    req: HttpRequest,
+    query: web::Query<ImagesQuery>,
+    payload: web::Payload,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    log_request_details(&req, "AdminImages");
+
+    let image = match payload.to_bytes_limited(MAX_UPLOAD_SIZE).await {
+        Ok(Ok(image)) => image,
+        Ok(Err(e)) => return HttpResponse::BadRequest().body(format!("{e}\n")),
+        Err(_) => {
+            return HttpResponse::PayloadTooLarge().body(format!("Images are limited to {} MiB\n", MAX_UPLOAD_SIZE >> 20));
+        }
+    };
+    let Some((width, height)) = jpeg_dimensions(&image) else {
+        return HttpResponse::UnsupportedMediaType().body("Only JPEG images can be served\n");
+    };
+    let path = match console::store_upload(&data, &image).await {
+        Ok(path) => path,
+        Err(e) => {
+            warn!(error = %e, "Couldn't store uploaded image");
+            return HttpResponse::InternalServerError().body(format!("Couldn't store the image: {e}\n"));
+        }
+    };
+    let served = data.update_live(|live| {
+        if !query.append {
+            live.images.clear();
+        }
+        live.images.push(path.clone());
+        live.images.len()
+    });
+    info!(path, width, height, append = query.append, "Image uploaded from the admin console");
+    HttpResponse::Created()
+        .content_type("text/plain; charset=utf-8")
+        .body(format!("image: {path}\nsize: {width}x{height}\nserved images: {served}\n"))
+}
+
+// 恢复为内置图片
+#[delete("/images")]
+async fn admin_reset_images(req: HttpRequest, data: web::Data<AppState>) -> impl Responder {
+    log_request_details(&req, "AdminImages");
+
+    data.update_live(|live| live.images.clear());
+    info!("Served images reset to the built-in image from the admin console");
+    HttpResponse::NoContent().finish()
+}
+
+// 自定义能力文件原样返回，包括其中的占位符，便于编辑后保存
+#[get("/capabilities")]
+async fn admin_capabilities(req: HttpRequest, data: web::Data<AppState>) -> impl Responder {
+    log_request_details(&req, "AdminCapabilities");
+
+    let live = data.live();
+    let document = match &live.capabilities_document {
+        Some(document) => document.clone(),
+        None => live
+            .capabilities
+            .to_xml(
+                &data.identity,
+                &format!("{}://{}", request_scheme(&req), request_host(&req)),
+                &data.scope,
+            ),
+    };
+    HttpResponse::Ok().content_type("text/xml").body(document)
+}
+
+// 与能力文件相同的检查，通过后替换设备的能力
+#[put("/capabilities")]
+async fn admin_replace_capabilities(req: HttpRequest, body: String, data: web::Data<AppState>) -> impl Responder {
+    log_request_details(&req, "AdminCapabilities");
+
+    let capabilities = match config::parse_capabilities(&body, "from the admin console") {
+        Ok(capabilities) => capabilities,
+        Err(e) => {
+            warn!(error = %e, "Rejected capabilities from the admin console");
+            return HttpResponse::BadRequest().body(format!("{e}\n"));
+        }
+    };
+    let version = capabilities.version;
+    data.update_live(|live| {
+        live.sources.adf_duplex = capabilities.supports_duplex();
+        live.capabilities = capabilities;
+        live.capabilities_document = Some(body);
+    });
+    info!(%version, "Capabilities replaced from the admin console");
+    // mDNS 的 vers、pdl、cs、is 和 duplex 来自能力
+    data.reannounce(None);
+    HttpResponse::NoContent().finish()
+}
+
+#[derive(Debug, Deserialize)]
+struct ScanButtonQuery {
+    destination: Option<String>,
+    source: Option<String>,
+}
+
+// 模拟设备面板上的 "扫描到计算机" 按钮，任务可以通过 NextDocument 获取
+#[post("/scan-button")]
+async fn scan_button(req: HttpRequest, query: web::Query<ScanButtonQuery>, data: web::Data<AppState>) -> impl Responder {
+    log_request_details(&req, "ScanButton");
+
+    let scan = match wsscan::press_scan_button(&data, query.destination.as_deref(), query.source.as_deref()).await {
+        Ok(scan) => scan,
+        Err(ButtonError::UnknownSource(source)) => {
+            return HttpResponse::BadRequest().body(format!("The scanner has no input source {source}\n"));
+        }
+        Err(ButtonError::UnknownDestination(name)) => {
+            return HttpResponse::NotFound().body(format!("No scan destination {name} is registered\n"));
+        }
+        Err(ButtonError::Busy) => return HttpResponse::ServiceUnavailable().body("The scanner is busy\n"),
+    };
+
+    let location = format!("{}://{}{}/ScanJobs/{}", request_scheme(&req), request_host(&req), data.scope, scan.uuid);
+    let destination = scan.destination.map_or_else(
+        || "none".to_owned(),
+        |destination| format!("{} ({})", destination.display_name, destination.client_context),
+    );
+    HttpResponse::Created()
+        .insert_header((header::LOCATION, location.clone()))
+        .content_type("text/plain; charset=utf-8")
+        .body(format!(
+            "job: {location}\nsource: {}\npages: {}\ndestination: {destination}\nnotified subscriptions: {}\n",
+            scan.source, scan.max_pages, scan.notified
+        ))
 }
@@ -0,0 +1,648 @@
+/*
+ *     Copyright (C) 2024-2025 Christian Nagel and contributors
+ *
+ *     This file is part of escl-mock-server.
+ *
+ *     escl-mock-server is free software: you can redistribute it and/or modify it under the terms of
+ *     the GNU General Public License as published by the Free Software Foundation, either
+ *     version 3 of the License, or (at your option) any later version.
+ *
+ *     escl-mock-server is distributed in the hope that it will be useful, but WITHOUT ANY
+ *     WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ *     FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ *     You should have received a copy of the GNU General Public License along with eSCLKt.
+ *     If not, see <https://www.gnu.org/licenses/>.
+ *
+ *     SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+use crate::wsd::{
+    self, child, fault, text, Envelope, FaultCode, NS_ADDRESSING, NS_EVENTING, NS_SCAN,
+};
+use crate::xml::{escape, XmlWriter};
+use actix_web::HttpResponse;
+use roxmltree::Node;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+const PUSH_MODE: &str = "http://schemas.xmlsoap.org/ws/2004/08/eventing/DeliveryModes/Push";
+const ACTION_DIALECT: &str = "http://schemas.xmlsoap.org/ws/2006/02/devprof/Action";
+const SCAN_AVAILABLE_EVENT: &str = "ScanAvailableEvent";
+/// Expiry of subscriptions that don't ask for one.
+const DEFAULT_EXPIRY: Duration = Duration::from_secs(3600);
+/// Longest expiry granted, longer requests get this one in `wse:Expires`.
+const MAX_EXPIRY: Duration = Duration::from_secs(24 * 3600);
+/// Subscribers that don't answer in time miss the event.
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A scan destination registered with a ScanAvailableEvent subscription,
+/// e.g. a PC in the device's "scan to computer" list.
+#[derive(Debug, Clone)]
+pub(crate) struct Destination {
+    pub display_name: String,
+    pub client_context: String,
+    pub token: String,
+}
+
+struct Subscription {
+    notify_to: String,
+    /// `wsa:ReferenceParameters` of `NotifyTo`, sent as headers with every event.
+    reference_parameters: String,
+    /// Requested event actions, empty for all events.
+    actions: Vec<String>,
+    expires: Instant,
+    destinations: Vec<Destination>,
+}
+
+impl Subscription {
+    fn wants(&self, action: &str) -> bool {
+        self.actions.is_empty() || self.actions.iter().any(|wanted| wanted == action)
+    }
+}
+
+/// The event subscriptions of one device, keyed by `wse:Identifier`.
+pub(crate) struct Subscriptions {
+    entries: Mutex<HashMap<String, Subscription>>,
+    /// Last scanner state sent with ScannerStatusSummaryEvent.
+    scanner_state: Mutex<&'static str>,
+}
+
+impl Default for Subscriptions {
+    fn default() -> Self {
+        Subscriptions {
+            entries: Mutex::new(HashMap::new()),
+            scanner_state: Mutex::new("Idle"),
+        }
+    }
+}
+
+impl Subscriptions {
+    /// Sends the event `{NS_SCAN}/{event}` to every subscriber asking for it.
+    /// `body` is the indented content of `soap:Body`.
+    pub(crate) async fn publish(&self, event: &'static str, body: &str) {
+        self.send(event, body, |_| true).await;
+    }
+
+    /// The first scan destination whose display name or client context is
+    /// `name`, any destination without a name.
+    pub(crate) async fn destination(&self, name: Option<&str>) -> Option<Destination> {
+        let action = format!("{NS_SCAN}/{SCAN_AVAILABLE_EVENT}");
+        let mut entries = self.entries.lock().await;
+        remove_expired(&mut entries);
+        let destination = entries
+            .values()
+            .filter(|subscription| subscription.wants(&action))
+            .flat_map(|subscription| &subscription.destinations)
+            .find(|destination| {
+                name.is_none_or(|name| {
+                    destination.display_name == name || destination.client_context == name
+                })
+            })
+            .cloned();
+        destination
+    }
+
+    /// Sends ScanAvailableEvent to the subscriber of `destination`, returns
+    /// the number of subscriptions notified.
+    pub(crate) async fn scan_available(
+        &self,
+        destination: &Destination,
+        scan_identifier: Uuid,
+    ) -> usize {
+        let mut xml = XmlWriter::fragment(2);
+        xml.open("wscn:ScanAvailableEvent");
+        xml.leaf("wscn:ClientContext", &destination.client_context);
+        xml.leaf("wscn:ScanIdentifier", scan_identifier);
+        xml.close("wscn:ScanAvailableEvent");
+        self.send(SCAN_AVAILABLE_EVENT, &xml.finish(), |subscription| {
+            subscription
+                .destinations
+                .iter()
+                .any(|registered| registered.token == destination.token)
+        })
+        .await
+    }
+
+    async fn send(
+        &self,
+        event: &'static str,
+        body: &str,
+        recipient: impl Fn(&Subscription) -> bool,
+    ) -> usize {
+        let action = format!("{NS_SCAN}/{event}");
+        let mut entries = self.entries.lock().await;
+        remove_expired(&mut entries);
+        let mut sent = 0;
+        for (id, subscription) in entries
+            .iter()
+            .filter(|(_, subscription)| subscription.wants(&action) && recipient(subscription))
+        {
+            let message = wsd::soap_message(
+                &subscription.notify_to,
+                &action,
+                &subscription.reference_parameters,
+                body,
+            );
+            debug!(subscription = %id, event, "Sending event");
+            actix_web::rt::spawn(deliver(subscription.notify_to.clone(), event, message));
+            sent += 1;
+        }
+        sent
+    }
+
+    pub(crate) async fn is_empty(&self) -> bool {
+        self.entries.lock().await.is_empty()
+    }
+
+    /// Records the scanner state, true if it differs from the last one.
+    pub(crate) async fn scanner_state_changed(&self, state: &'static str) -> bool {
+        let mut last = self.scanner_state.lock().await;
+        std::mem::replace(&mut *last, state) != state
+    }
+}
+
+// 过期的订阅在下次访问时删除
+fn remove_expired(entries: &mut HashMap<String, Subscription>) {
+    let now = Instant::now();
+    entries.retain(|id, subscription| {
+        let alive = subscription.expires > now;
+        if !alive {
+            info!(subscription = %id, notify_to = subscription.notify_to, "Event subscription expired");
+        }
+        alive
+    });
+}
+
+async fn deliver(url: String, event: &'static str, message: String) {
+    let client = awc::Client::builder().timeout(DELIVERY_TIMEOUT).finish();
+    let result = client
+        .post(&url)
+        .content_type("application/soap+xml; charset=utf-8")
+        .send_body(message)
+        .await;
+    match result {
+        Ok(response) if response.status().is_success() => debug!(url, event, "Event delivered"),
+        Ok(response) => warn!(
+            url,
+            event,
+            status = response.status().as_u16(),
+            "Subscriber rejected event"
+        ),
+        Err(e) => warn!(url, event, error = %e, "Couldn't deliver event"),
+    }
+}
+
+// xs:duration，例如 PT1H 或 P0Y0M0DT0H5M0S，年和月按 365 天和 30 天计算
+fn parse_duration(value: &str) -> Option<Duration> {
+    let rest = value.strip_prefix('P')?;
+    let (date, time) = rest.split_once('T').unwrap_or((rest, ""));
+    let units: [(&str, &[(char, f64)]); 2] = [
+        (
+            date,
+            &[('Y', 31_536_000.0), ('M', 2_592_000.0), ('D', 86_400.0)],
+        ),
+        (time, &[('H', 3_600.0), ('M', 60.0), ('S', 1.0)]),
+    ];
+    let mut seconds = 0.0;
+    for (part, part_units) in units {
+        let mut remaining = part_units.iter();
+        let mut number = String::new();
+        for c in part.chars() {
+            if c.is_ascii_digit() || c == '.' {
+                number.push(c);
+                continue;
+            }
+            // 单位必须按顺序出现
+            let (_, factor) = remaining.find(|(unit, _)| *unit == c)?;
+            seconds += number.parse::<f64>().ok()? * factor;
+            number.clear();
+        }
+        if !number.is_empty() {
+            return None;
+        }
+    }
+    // 超出 Duration 范围的时长按最大值处理，由调用方截断为 MAX_EXPIRY
+    let duration = Duration::try_from_secs_f64(seconds).unwrap_or(Duration::MAX);
+    (!duration.is_zero()).then_some(duration)
+}
+
+fn format_duration(duration: Duration) -> String {
+    format!("PT{}S", duration.as_secs())
+}
+
+/// A request the subscription manager can't fulfill, as WS-Eventing fault subcode and reason.
+type Rejection = (&'static str, String);
+
+fn expiry(parent: Node) -> Result<Duration, Rejection> {
+    match text(parent, NS_EVENTING, "Expires") {
+        None => Ok(DEFAULT_EXPIRY),
+        Some(value) if !value.starts_with('P') => Err((
+            "wse:UnsupportedExpirationType",
+            format!("{value} is not a duration"),
+        )),
+        Some(value) => parse_duration(&value)
+            .map(|duration| duration.min(MAX_EXPIRY))
+            .ok_or((
+                "wse:InvalidExpirationTime",
+                format!("invalid duration {value}"),
+            )),
+    }
+}
+
+fn deadline(expires: Duration) -> Result<Instant, Rejection> {
+    Instant::now().checked_add(expires).ok_or((
+        "wse:InvalidExpirationTime",
+        format!("{} is too long", format_duration(expires)),
+    ))
+}
+
+/// `element` and its children as the client wrote them. Namespaces the
+/// subtree uses from declarations on ancestors are declared on `element`.
+fn copy_element(element: Node) -> String {
+    let source = &element.document().input_text()[element.range()];
+    let name_end = source
+        .find(|c: char| c.is_whitespace() || c == '>' || c == '/')
+        .unwrap_or(source.len());
+    let (start, rest) = source.split_at(name_end);
+    let declared = declared_prefixes(rest);
+
+    let mut used: Vec<&str> = Vec::new();
+    for node in element.descendants().filter(|node| node.is_element()) {
+        let attributes = node
+            .attributes()
+            .filter_map(|attribute| attribute.namespace());
+        used.extend(node.tag_name().namespace().into_iter().chain(attributes));
+    }
+    let mut declarations = String::new();
+    for namespace in element.namespaces() {
+        let prefix = namespace.name();
+        if prefix == Some("xml") || declared.contains(&prefix) || !used.contains(&namespace.uri()) {
+            continue;
+        }
+        let uri = escape(namespace.uri());
+        match prefix {
+            Some(prefix) => declarations.push_str(&format!(" xmlns:{prefix}=\"{uri}\"")),
+            None => declarations.push_str(&format!(" xmlns=\"{uri}\"")),
+        }
+    }
+    format!("{start}{declarations}{rest}")
+}
+
+// 元素开始标签中自己声明的命名空间前缀，默认命名空间为 None
+fn declared_prefixes(attributes: &str) -> Vec<Option<&str>> {
+    let mut prefixes = Vec::new();
+    let mut rest = attributes;
+    loop {
+        rest = rest.trim_start();
+        if rest.starts_with(['>', '/']) {
+            return prefixes;
+        }
+        let Some((name, value)) = rest.split_once('=') else {
+            return prefixes;
+        };
+        match name.trim_end() {
+            "xmlns" => prefixes.push(None),
+            name => prefixes.extend(name.strip_prefix("xmlns:").map(Some)),
+        }
+        let value = value.trim_start();
+        let Some(quote) = value.chars().next() else {
+            return prefixes;
+        };
+        let Some(end) = value[1..].find(quote) else {
+            return prefixes;
+        };
+        rest = &value[end + 2..];
+    }
+}
+
+fn parse_subscribe(subscribe: Node) -> Result<(Subscription, Duration), Rejection> {
+    let delivery = child(subscribe, NS_EVENTING, "Delivery")
+        .ok_or(("wse:InvalidMessage", "missing Delivery".to_owned()))?;
+    if let Some(mode) = delivery.attribute("Mode").filter(|mode| *mode != PUSH_MODE) {
+        return Err((
+            "wse:DeliveryModeRequestedUnavailable",
+            format!("delivery mode {mode} is not supported"),
+        ));
+    }
+    let notify_to = child(delivery, NS_EVENTING, "NotifyTo")
+        .ok_or(("wse:InvalidMessage", "missing NotifyTo".to_owned()))?;
+    let address = text(notify_to, NS_ADDRESSING, "Address")
+        .ok_or(("wse:InvalidMessage", "missing NotifyTo address".to_owned()))?;
+
+    // 引用参数原样作为事件的头部发回，通常是订阅方的 Identifier
+    let mut reference_parameters = String::new();
+    if let Some(parameters) = child(notify_to, NS_ADDRESSING, "ReferenceParameters") {
+        for parameter in parameters.children().filter(|node| node.is_element()) {
+            reference_parameters.push_str("\n        ");
+            reference_parameters.push_str(&copy_element(parameter));
+        }
+    }
+
+    let actions = match child(subscribe, NS_EVENTING, "Filter") {
+        Some(filter)
+            if filter
+                .attribute("Dialect")
+                .is_some_and(|dialect| dialect != ACTION_DIALECT) =>
+        {
+            return Err((
+                "wse:FilteringNotSupported",
+                "only action filters are supported".to_owned(),
+            ));
+        }
+        Some(filter) => filter
+            .text()
+            .unwrap_or_default()
+            .split_whitespace()
+            .map(str::to_owned)
+            .collect(),
+        None => Vec::new(),
+    };
+
+    let destinations = child(subscribe, NS_SCAN, "ScanDestinations")
+        .map(|destinations| {
+            destinations
+                .children()
+                .filter(|node| {
+                    node.tag_name().namespace() == Some(NS_SCAN)
+                        && node.tag_name().name() == "ScanDestination"
+                })
+                .map(|destination| Destination {
+                    display_name: text(destination, NS_SCAN, "ClientDisplayName")
+                        .unwrap_or_default(),
+                    client_context: text(destination, NS_SCAN, "ClientContext").unwrap_or_default(),
+                    token: format!("Client_{}", Uuid::new_v4().simple()),
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let expires = expiry(subscribe)?;
+    let subscription = Subscription {
+        notify_to: address,
+        reference_parameters,
+        actions,
+        expires: deadline(expires)?,
+        destinations,
+    };
+    Ok((subscription, expires))
+}
+
+/// Answers Subscribe, Renew, GetStatus and Unsubscribe, `None` for other
+/// actions. `manager_url` is the address of the subscription manager.
+pub(crate) async fn handle(
+    subscriptions: &Subscriptions,
+    envelope: &Envelope<'_, '_>,
+    manager_url: &str,
+) -> Option<HttpResponse> {
+    let operation = envelope
+        .action
+        .strip_prefix(NS_EVENTING)?
+        .strip_prefix('/')?;
+    if !["Subscribe", "Renew", "GetStatus", "Unsubscribe"].contains(&operation) {
+        return None;
+    }
+    let relates_to = envelope.message_id.as_deref();
+    let result = match operation {
+        "Subscribe" => subscribe(subscriptions, envelope, manager_url).await,
+        _ => manage(subscriptions, envelope, operation).await,
+    };
+    Some(match result {
+        Ok(body) => HttpResponse::Ok()
+            .content_type("application/soap+xml; charset=utf-8")
+            .insert_header(("Cache-Control", "no-cache"))
+            .body(wsd::soap_response(
+                &format!("{NS_EVENTING}/{operation}Response"),
+                relates_to,
+                &body,
+            )),
+        Err((subcode, reason)) => {
+            warn!(operation, reason, "Event subscription request rejected");
+            fault(relates_to, FaultCode::Sender, subcode, &reason)
+        }
+    })
+}
+
+async fn subscribe(
+    subscriptions: &Subscriptions,
+    envelope: &Envelope<'_, '_>,
+    manager_url: &str,
+) -> Result<String, Rejection> {
+    let request = child(envelope.body, NS_EVENTING, "Subscribe")
+        .ok_or(("wse:InvalidMessage", "missing Subscribe".to_owned()))?;
+    let (subscription, expires) = parse_subscribe(request)?;
+    let id = wsd::new_message_id();
+    info!(
+        subscription = %id,
+        notify_to = subscription.notify_to,
+        events = ?subscription.actions,
+        destinations = subscription.destinations.len(),
+        expires_secs = expires.as_secs(),
+        "Event subscription created"
+    );
+
+    let mut xml = XmlWriter::fragment(2);
+    xml.open("wse:SubscribeResponse");
+    xml.open("wse:SubscriptionManager");
+    xml.leaf("wsa:Address", manager_url);
+    xml.open("wsa:ReferenceParameters");
+    xml.leaf("wse:Identifier", &id);
+    xml.close("wsa:ReferenceParameters");
+    xml.close("wse:SubscriptionManager");
+    xml.leaf("wse:Expires", format_duration(expires));
+    // ScanAvailableEvent 的每个扫描目标得到一个 DestinationToken
+    if !subscription.destinations.is_empty() {
+        xml.open("wscn:DestinationResponses");
+        for destination in &subscription.destinations {
+            xml.open("wscn:DestinationResponse");
+            xml.leaf("wscn:ClientContext", &destination.client_context);
+            xml.leaf("wscn:DestinationToken", &destination.token);
+            xml.close("wscn:DestinationResponse");
+        }
+        xml.close("wscn:DestinationResponses");
+    }
+    xml.close("wse:SubscribeResponse");
+
+    let mut entries = subscriptions.entries.lock().await;
+    remove_expired(&mut entries);
+    entries.insert(id, subscription);
+    Ok(xml.finish())
+}
+
+// 订阅管理器的请求通过头部中的 Identifier 指定订阅
+async fn manage(
+    subscriptions: &Subscriptions,
+    envelope: &Envelope<'_, '_>,
+    operation: &str,
+) -> Result<String, Rejection> {
+    let id = text(envelope.header, NS_EVENTING, "Identifier").unwrap_or_default();
+    let mut entries = subscriptions.entries.lock().await;
+    remove_expired(&mut entries);
+    let Some(subscription) = entries.get_mut(&id) else {
+        let subcode = if operation == "Renew" {
+            "wse:UnableToRenew"
+        } else {
+            "wsa:DestinationUnreachable"
+        };
+        return Err((subcode, format!("no subscription {id}")));
+    };
+
+    let mut xml = XmlWriter::fragment(2);
+    match operation {
+        "Renew" => {
+            let request = child(envelope.body, NS_EVENTING, "Renew");
+            let expires = request.map_or(Ok(DEFAULT_EXPIRY), expiry)?;
+            subscription.expires = deadline(expires)?;
+            info!(subscription = %id, expires_secs = expires.as_secs(), "Event subscription renewed");
+            xml.open("wse:RenewResponse");
+            xml.leaf("wse:Expires", format_duration(expires));
+            xml.close("wse:RenewResponse");
+        }
+        "GetStatus" => {
+            xml.open("wse:GetStatusResponse");
+            xml.leaf(
+                "wse:Expires",
+                format_duration(subscription.expires - Instant::now()),
+            );
+            xml.close("wse:GetStatusResponse");
+        }
+        _ => {
+            entries.remove(&id);
+            info!(subscription = %id, "Event subscription ended");
+        }
+    }
+    Ok(xml.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use roxmltree::Document;
+
+    fn renew_expiry(expires: &str) -> Result<Duration, Rejection> {
+        let xml = format!(
+            r#"<wse:Renew xmlns:wse="{NS_EVENTING}"><wse:Expires>{expires}</wse:Expires></wse:Renew>"#
+        );
+        let document = Document::parse(&xml).unwrap();
+        expiry(document.root_element())
+    }
+
+    #[test]
+    fn copies_reference_parameters_verbatim() {
+        let xml = format!(
+            r#"<soap:Envelope xmlns:soap="{}" xmlns:wsa="{NS_ADDRESSING}" xmlns:wse="{NS_EVENTING}" xmlns:sub="urn:example:subscriber">
+<soap:Body><wse:Subscribe><wse:Delivery Mode="{PUSH_MODE}"><wse:NotifyTo>
+<wsa:Address>http://192.0.2.1:5357/events</wsa:Address>
+<wsa:ReferenceParameters>
+<sub:Identifier>urn:uuid:abc</sub:Identifier>
+<Token>plain</Token>
+<sub:Again xmlns:sub="urn:example:subscriber">again</sub:Again>
+<x:Context xmlns:x="urn:x" x:kind='a>b'><x:Item n="1">one &amp; two</x:Item><sub:Nested/><!-- note --></x:Context>
+</wsa:ReferenceParameters>
+</wse:NotifyTo></wse:Delivery></wse:Subscribe></soap:Body></soap:Envelope>"#,
+            wsd::NS_SOAP
+        );
+        let document = Document::parse(&xml).unwrap();
+        let subscribe = document
+            .descendants()
+            .find(|node| node.tag_name().name() == "Subscribe")
+            .unwrap();
+        let (subscription, _) = parse_subscribe(subscribe).unwrap();
+        assert_eq!(
+            subscription.reference_parameters,
+            concat!(
+                "\n        <sub:Identifier xmlns:sub=\"urn:example:subscriber\">urn:uuid:abc</sub:Identifier>",
+                "\n        <Token>plain</Token>",
+                "\n        <sub:Again xmlns:sub=\"urn:example:subscriber\">again</sub:Again>",
+                "\n        <x:Context xmlns:sub=\"urn:example:subscriber\" xmlns:x=\"urn:x\" x:kind='a>b'>",
+                "<x:Item n=\"1\">one &amp; two</x:Item><sub:Nested/><!-- note --></x:Context>",
+            )
+        );
+
+        // 事件消息中的头部与订阅时的参数相同
+        let message = wsd::soap_message(
+            &subscription.notify_to,
+            "urn:event",
+            &subscription.reference_parameters,
+            "",
+        );
+        let event = Document::parse(&message).unwrap();
+        let header = child(event.root_element(), wsd::NS_SOAP, "Header").unwrap();
+        let token = header
+            .children()
+            .find(|node| node.tag_name().name() == "Token")
+            .unwrap();
+        assert_eq!(token.tag_name().namespace(), None);
+        let context = child(header, "urn:x", "Context").unwrap();
+        assert_eq!(context.attribute(("urn:x", "kind")), Some("a>b"));
+        assert_eq!(
+            text(header, "urn:example:subscriber", "Identifier").as_deref(),
+            Some("urn:uuid:abc")
+        );
+    }
+
+    #[test]
+    fn parses_xs_durations() {
+        assert_eq!(parse_duration("PT1H"), Some(Duration::from_secs(3600)));
+        assert_eq!(
+            parse_duration("P0Y0M0DT0H5M0S"),
+            Some(Duration::from_secs(300))
+        );
+        assert_eq!(parse_duration("P1D"), Some(Duration::from_secs(86_400)));
+        assert_eq!(
+            parse_duration("P1MT30S"),
+            Some(Duration::from_secs(2_592_030))
+        );
+        assert_eq!(parse_duration("PT1.5S"), Some(Duration::from_millis(1500)));
+    }
+
+    #[test]
+    fn rejects_malformed_durations() {
+        for value in [
+            "P", "PT", "1H", "PT5", "P1H", "PT1M1H", "PT1X", "PT1..5S", "PT0S",
+        ] {
+            assert_eq!(parse_duration(value), None, "{value}");
+        }
+        assert_eq!(
+            renew_expiry("PT5"),
+            Err((
+                "wse:InvalidExpirationTime",
+                "invalid duration PT5".to_owned()
+            ))
+        );
+        assert_eq!(
+            renew_expiry("2030-01-01T00:00:00Z"),
+            Err((
+                "wse:UnsupportedExpirationType",
+                "2030-01-01T00:00:00Z is not a duration".to_owned()
+            ))
+        );
+    }
+
+    #[test]
+    fn caps_long_expiry() {
+        assert_eq!(renew_expiry("PT30M"), Ok(Duration::from_secs(1800)));
+        assert_eq!(renew_expiry("P10Y"), Ok(MAX_EXPIRY));
+        let overflowing = format!("P{}Y", "9".repeat(400));
+        assert_eq!(parse_duration(&overflowing), Some(Duration::MAX));
+        assert_eq!(renew_expiry(&overflowing), Ok(MAX_EXPIRY));
+        assert!(deadline(MAX_EXPIRY).is_ok());
+        assert_eq!(
+            deadline(Duration::MAX),
+            Err((
+                "wse:InvalidExpirationTime",
+                format!("PT{}S is too long", u64::MAX)
+            ))
+        );
+    }
+
+    #[test]
+    fn renew_without_expires_gets_default() {
+        let xml = format!(r#"<wse:Renew xmlns:wse="{NS_EVENTING}"/>"#);
+        let document = Document::parse(&xml).unwrap();
+        assert_eq!(expiry(document.root_element()), Ok(DEFAULT_EXPIRY));
+    }
+}
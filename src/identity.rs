@@ -0,0 +1,221 @@
+/*
+ *     Copyright (C) 2024-2025 Christian Nagel and contributors
+ *
+ *     This file is part of escl-mock-server.
+ *
+ *     escl-mock-server is free software: you can redistribute it and/or modify it under the terms of
+ *     the GNU General Public License as published by the Free Software Foundation, either
+ *     version 3 of the License, or (at your option) any later version.
+ *
+ *     escl-mock-server is distributed in the hope that it will be useful, but WITHOUT ANY
+ *     WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ *     FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ *     You should have received a copy of the GNU General Public License along with eSCLKt.
+ *     If not, see <https://www.gnu.org/licenses/>.
+ *
+ *     SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+use serde::Deserialize;
+use std::fmt::{Display, Formatter};
+use uuid::Uuid;
+
+/// The identity a mock scanner presents in every document and advertisement.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct DeviceIdentity {
+    pub uuid: Uuid,
+    pub serial_number: String,
+    pub make: String,
+    pub model: String,
+    pub friendly_name: String,
+    pub firmware_version: String,
+    pub model_number: String,
+    pub manufacturer_url: String,
+    pub model_url: String,
+}
+
+/// Identity values read from a file. Missing values are generated.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct IdentityFile {
+    pub uuid: Option<Uuid>,
+    pub serial_number: Option<String>,
+    pub make: Option<String>,
+    pub model: Option<String>,
+    pub friendly_name: Option<String>,
+    pub firmware_version: Option<String>,
+    pub model_number: Option<String>,
+    pub manufacturer_url: Option<String>,
+    pub model_url: Option<String>,
+}
+
+impl IdentityFile {
+    /// Loads identity values from a TOML file.
+    pub(crate) fn from_file(path: &str) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("couldn't read identity file {path}: {e}"))?;
+        toml::from_str(&content).map_err(|e| format!("invalid identity file {path}: {e}"))
+    }
+
+    /// Values set in `over` take precedence.
+    pub(crate) fn merge(self, over: IdentityFile) -> IdentityFile {
+        IdentityFile {
+            uuid: over.uuid.or(self.uuid),
+            serial_number: over.serial_number.or(self.serial_number),
+            make: over.make.or(self.make),
+            model: over.model.or(self.model),
+            friendly_name: over.friendly_name.or(self.friendly_name),
+            firmware_version: over.firmware_version.or(self.firmware_version),
+            model_number: over.model_number.or(self.model_number),
+            manufacturer_url: over.manufacturer_url.or(self.manufacturer_url),
+            model_url: over.model_url.or(self.model_url),
+        }
+    }
+}
+
+impl DeviceIdentity {
+    /// Fills missing values randomly, so two instances never look like the same device.
+    pub(crate) fn from_partial(partial: IdentityFile) -> Self {
+        let uuid = partial.uuid.unwrap_or_else(Uuid::new_v4);
+        // 使用UUID的前几位生成序列号和名称后缀，保证同一实例内一致
+        let short_id = uuid.simple().to_string()[..8].to_uppercase();
+        let model = partial
+            .model
+            .unwrap_or_else(|| "eSCL Mock Scanner".to_owned());
+        let make = partial.make.unwrap_or_else(|| "MockCompany".to_owned());
+        // 未配置网址时按厂商名生成，例如 http://www.mockcompany.com
+        let manufacturer_url = partial
+            .manufacturer_url
+            .unwrap_or_else(|| format!("http://www.{}.com", url_segment(&make)));
+        let model_url = partial
+            .model_url
+            .unwrap_or_else(|| format!("{manufacturer_url}/{}", url_segment(&model)));
+
+        DeviceIdentity {
+            uuid,
+            serial_number: partial
+                .serial_number
+                .unwrap_or_else(|| format!("ESC-MOCK-{short_id}")),
+            make,
+            friendly_name: partial
+                .friendly_name
+                .unwrap_or_else(|| format!("{model} ({})", &short_id[..4])),
+            model_number: partial.model_number.unwrap_or_else(|| model.clone()),
+            model,
+            firmware_version: partial
+                .firmware_version
+                .unwrap_or_else(|| "1.0.0".to_owned()),
+            manufacturer_url,
+            model_url,
+        }
+    }
+
+    // 身份字段会直接写入XML文档，因此拒绝标记字符
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        let fields = [
+            ("serial_number", &self.serial_number),
+            ("make", &self.make),
+            ("model", &self.model),
+            ("friendly_name", &self.friendly_name),
+            ("firmware_version", &self.firmware_version),
+            ("model_number", &self.model_number),
+            ("manufacturer_url", &self.manufacturer_url),
+            ("model_url", &self.model_url),
+        ];
+        for (name, value) in fields {
+            if value.trim().is_empty() {
+                return Err(format!("identity field `{name}` must not be empty"));
+            }
+            if value.contains(['<', '>', '&', '"']) {
+                return Err(format!(
+                    "identity field `{name}` must not contain XML markup characters: {value:?}"
+                ));
+            }
+        }
+        for (name, url) in [
+            ("manufacturer_url", &self.manufacturer_url),
+            ("model_url", &self.model_url),
+        ] {
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                return Err(format!(
+                    "identity field `{name}` must be an http or https URL: {url:?}"
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// `pwg:MakeAndModel` / mDNS `ty` value.
+    pub(crate) fn make_and_model(&self) -> String {
+        format!("{} {}", self.make, self.model)
+    }
+
+    /// Locally administered unicast MAC address derived from the UUID, so it
+    /// is stable for a configured UUID and differs between instances.
+    pub(crate) fn mac_address(&self) -> String {
+        let bytes = self.uuid.as_bytes();
+        // 设置本地管理位，清除组播位
+        let first = (bytes[0] | 0x02) & !0x01;
+        std::iter::once(first)
+            .chain(bytes[1..6].iter().copied())
+            .map(|byte| format!("{byte:02X}"))
+            .collect::<Vec<_>>()
+            .join(":")
+    }
+}
+
+// 厂商或型号名中的字母和数字，小写
+fn url_segment(name: &str) -> String {
+    name.chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+impl Display for DeviceIdentity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "\"{}\" ({}, serial {}, uuid {}, firmware {})",
+            self.friendly_name,
+            self.make_and_model(),
+            self.serial_number,
+            self.uuid,
+            self.firmware_version
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn derives_urls_and_mac_address() {
+        let identity = DeviceIdentity::from_partial(IdentityFile {
+            uuid: Some("550e8400-e29b-41d4-a716-446655440000".parse().unwrap()),
+            make: Some("Acme Corp.".to_owned()),
+            model: Some("Duo 2".to_owned()),
+            ..IdentityFile::default()
+        });
+        assert_eq!(identity.manufacturer_url, "http://www.acmecorp.com");
+        assert_eq!(identity.model_url, "http://www.acmecorp.com/duo2");
+        assert_eq!(identity.model_number, "Duo 2");
+        // 0x55 设置本地管理位并清除组播位后为 0x56
+        assert_eq!(identity.mac_address(), "56:0E:84:00:E2:9B");
+        assert_eq!(identity.validate(), Ok(()));
+    }
+
+    #[test]
+    fn rejects_urls_without_http_scheme() {
+        let identity = DeviceIdentity::from_partial(IdentityFile {
+            manufacturer_url: Some("www.acme.com".to_owned()),
+            ..IdentityFile::default()
+        });
+        assert!(identity
+            .validate()
+            .unwrap_err()
+            .contains("manufacturer_url"));
+    }
+}
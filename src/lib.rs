@@ -0,0 +1,832 @@
+/*
+ *     Copyright (C) 2024-2025 Christian Nagel and contributors
+ *
+ *     This file is part of escl-mock-server.
+ *
+ *     escl-mock-server is free software: you can redistribute it and/or modify it under the terms of
+ *     the GNU General Public License as published by the Free Software Foundation, either
+ *     version 3 of the License, or (at your option) any later version.
+ *
+ *     escl-mock-server is distributed in the hope that it will be useful, but WITHOUT ANY
+ *     WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ *     FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ *     You should have received a copy of the GNU General Public License along with eSCLKt.
+ *     If not, see <https://www.gnu.org/licenses/>.
+ *
+ *     SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+//! An HTTP server mocking a scanner supporting the eSCL protocol.
+//!
+//! Besides the `escl-mock-server` binary the crate can run a virtual scanner
+//! inside a test process:
+//!
+//! ```no_run
+//! use escl_mock_server::MockScanner;
+//!
+//! # async fn example() -> std::io::Result<()> {
+//! let scanner = MockScanner::builder()
+//!     .images(["res/portrait-color.jpg"])
+//!     .start()
+//!     .await?;
+//! let capabilities_url = format!("{}/ScannerCapabilities", scanner.base_url());
+//! // ... drive the client under test against `capabilities_url` ...
+//! assert!(scanner.jobs().await.iter().all(|job| job.retrieved_pages == job.max_pages));
+//! scanner.shutdown().await;
+//! # Ok(())
+//! # }
+//! ```
+
+mod auth;
+mod capabilities;
+mod cli;
+mod config;
+mod console;
+mod escl_server;
+mod eventing;
+mod identity;
+mod logging;
+mod mdns;
+mod metrics;
+mod mock;
+mod model;
+mod network;
+mod profile;
+mod reload;
+mod report;
+mod schema;
+mod ssdp;
+mod tls;
+mod version;
+mod wsd;
+mod wsscan;
+mod xml;
+
+pub use crate::capabilities::{CapabilitiesBuilder, ColorMode, Intent, ScannerCapabilities};
+pub use crate::mock::{MockScanner, MockScannerBuilder, ScanJobSnapshot};
+pub use crate::model::{JobState, ScanSource};
+pub use crate::profile::Profile;
+pub use crate::version::EsclVersion;
+
+use crate::auth::Authenticator;
+use crate::config::{
+    ConfigFile, DeviceSettings, DiscoverySettings, FaultSettings, SourceSettings,
+    ValidationSettings,
+};
+use crate::console::RequestLog;
+use crate::eventing::Subscriptions;
+use crate::identity::DeviceIdentity;
+use crate::mdns::Mdns;
+use crate::metrics::{DeviceMetrics, Metrics};
+use crate::model::ScanJob;
+use crate::network::BindAddress;
+use crate::profile::Quirks;
+use crate::reload::Reloader;
+use crate::report::ConformanceReport;
+use crate::ssdp::Ssdp;
+use crate::tls::TlsCertificate;
+use crate::wsd::Wsd;
+use actix_web::dev::Server;
+use actix_web::http::header;
+use actix_web::middleware::{self, Condition, DefaultHeaders};
+use actix_web::{web, App, HttpServer, Scope};
+use futures::future::{self, Either};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::Write;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener};
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+/// Exit code when an HTTP server can't be started or fails.
+pub const EXIT_SERVER_ERROR: u8 = 1;
+/// Exit code for invalid command line options or configuration files.
+pub const EXIT_INVALID_CONFIG: u8 = 2;
+/// Exit code when the conformance report (`--report`) has failing clients.
+pub const EXIT_CONFORMANCE_FAILED: u8 = 3;
+
+pub(crate) struct AppState {
+    scope: String,
+    /// Replaced as a whole when the configuration is reloaded.
+    live: RwLock<Arc<LiveSettings>>,
+    identity: DeviceIdentity,
+    quirks: Quirks,
+    validation: ValidationSettings,
+    bind_address: BindAddress,
+    /// Set once the servers are bound.
+    ports: OnceLock<DevicePorts>,
+    /// Set when the device is advertised over mDNS.
+    advertisement: OnceLock<Advertisement>,
+    tls: Option<Arc<TlsCertificate>>,
+    scan_jobs: Mutex<HashMap<Uuid, ScanJob>>,
+    job_ids: AtomicU32,
+    subscriptions: Subscriptions,
+    /// Shown in the admin console and its transcript.
+    requests: RequestLog,
+    metrics: DeviceMetrics,
+    report: Arc<ConformanceReport>,
+}
+
+/// Settings of a device that can change while it's served.
+#[derive(Clone)]
+pub(crate) struct LiveSettings {
+    pub capabilities: ScannerCapabilities,
+    /// Content of a capabilities file, served instead of the rendered model.
+    pub capabilities_document: Option<String>,
+    pub images: Vec<String>,
+    pub sources: SourceSettings,
+    pub faults: FaultSettings,
+}
+
+impl LiveSettings {
+    fn new(device: &DeviceSettings) -> Self {
+        LiveSettings {
+            capabilities: device.capabilities.clone(),
+            capabilities_document: device.capabilities_document.clone(),
+            images: device.images.clone(),
+            sources: device.sources.clone(),
+            faults: device.faults.clone(),
+        }
+    }
+}
+
+/// mDNS advertisement of a device, with the settings it was last announced with.
+struct Advertisement {
+    mdns: Arc<Mdns>,
+    device: std::sync::Mutex<DeviceSettings>,
+}
+
+/// A device announced by the multicast discovery protocols. Its
+/// description endpoints (`device.xml`, `wsd`) are served below `scope`
+/// on `port`.
+#[derive(Debug, Clone)]
+pub(crate) struct DescribedDevice {
+    pub uuid: Uuid,
+    pub scheme: &'static str,
+    pub port: u16,
+    pub scope: String,
+}
+
+impl DescribedDevice {
+    fn url(&self, address: IpAddr, path: &str) -> String {
+        let authority = network::url_authority(SocketAddr::new(address, self.port));
+        format!("{}://{authority}{path}", self.scheme)
+    }
+}
+
+/// Ports a device is actually served on.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct DevicePorts {
+    pub http: Option<u16>,
+    pub https: Option<u16>,
+}
+
+impl AppState {
+    fn new(
+        device: &DeviceSettings,
+        validation: ValidationSettings,
+        bind_address: BindAddress,
+        tls: Option<Arc<TlsCertificate>>,
+        metrics: Arc<Metrics>,
+        report: Arc<ConformanceReport>,
+    ) -> Self {
+        AppState {
+            scope: device.scope.clone(),
+            live: RwLock::new(Arc::new(LiveSettings::new(device))),
+            identity: device.identity.clone(),
+            quirks: device.quirks.clone(),
+            validation,
+            bind_address,
+            ports: OnceLock::new(),
+            advertisement: OnceLock::new(),
+            tls,
+            scan_jobs: Mutex::new(HashMap::new()),
+            job_ids: AtomicU32::new(1),
+            subscriptions: Subscriptions::default(),
+            requests: RequestLog::default(),
+            metrics: DeviceMetrics::new(metrics, &device.identity.friendly_name),
+            report,
+        }
+    }
+
+    fn ports(&self) -> DevicePorts {
+        self.ports.get().copied().unwrap_or_default()
+    }
+
+    /// Current capabilities, images, sources and faults. Requests keep the
+    /// snapshot they started with when the configuration is reloaded.
+    fn live(&self) -> Arc<LiveSettings> {
+        self.live.read().unwrap().clone()
+    }
+
+    /// Announces the device over mDNS with `device`, or again when called later.
+    fn advertise(&self, mdns: Arc<Mdns>, device: &DeviceSettings) {
+        let advertisement = self.advertisement.get_or_init(|| Advertisement {
+            mdns,
+            device: std::sync::Mutex::new(device.clone()),
+        });
+        advertisement.mdns.announce(device, self.ports());
+    }
+
+    /// Announces the device again with the current capabilities and, when
+    /// given, new discovery settings. Only changed services are re-announced.
+    fn reannounce(&self, discovery: Option<&DiscoverySettings>) {
+        let Some(advertisement) = self.advertisement.get() else {
+            return;
+        };
+        let mut device = advertisement.device.lock().unwrap();
+        let live = self.live();
+        device.capabilities = live.capabilities.clone();
+        device.sources.adf_duplex = live.sources.adf_duplex;
+        if let Some(discovery) = discovery {
+            device.discovery = discovery.clone();
+        }
+        advertisement.mdns.announce(&device, self.ports());
+    }
+
+    // 在写锁内修改副本，同时进行的修改不会互相覆盖
+    fn update_live<T>(&self, update: impl FnOnce(&mut LiveSettings) -> T) -> T {
+        let mut live = self.live.write().unwrap();
+        let mut updated = LiveSettings::clone(&live);
+        let result = update(&mut updated);
+        *live = Arc::new(updated);
+        result
+    }
+
+    /// Numeric ID for a new scan job, unique per device.
+    fn next_job_id(&self) -> u32 {
+        self.job_ids.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+// 等待 SIGINT 或 SIGTERM，返回信号名称
+async fn wait_for_signal() -> &'static str {
+    let interrupt = Box::pin(async {
+        let _ = actix_web::rt::signal::ctrl_c().await;
+        "SIGINT"
+    });
+    #[cfg(unix)]
+    {
+        use actix_web::rt::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                let terminate = Box::pin(async move {
+                    terminate.recv().await;
+                    "SIGTERM"
+                });
+                return future::select(interrupt, terminate).await.factor_first().0;
+            }
+            Err(e) => warn!(error = %e, "Couldn't install SIGTERM handler"),
+        }
+    }
+    interrupt.await
+}
+
+// 同一端口上的设备可能使用不同的预设，因此每个 scope 设置自己的 Server 头
+fn server_header(quirks: &Quirks) -> DefaultHeaders {
+    match quirks.server_header {
+        Some(server) => DefaultHeaders::new().add((header::SERVER, server)),
+        None => DefaultHeaders::new(),
+    }
+}
+
+// 管理控制台和扫描按钮，挂在根路径下时需要自己的认证，设备 scope 中已经有认证
+fn admin_console() -> Scope {
+    web::scope("/admin")
+        .service(escl_server::admin_page)
+        .service(escl_server::admin_devices)
+        .service(escl_server::admin_events)
+        .service(escl_server::admin_transcript)
+        .service(escl_server::admin_adf)
+        .service(escl_server::admin_faults)
+        .service(escl_server::admin_upload_image)
+        .service(escl_server::admin_reset_images)
+        .service(escl_server::admin_capabilities)
+        .service(escl_server::admin_replace_capabilities)
+        .service(escl_server::scan_button)
+}
+
+/// Scope and state of each device served on one port.
+type PortDevices = Vec<(String, web::Data<AppState>)>;
+
+// 启动一个端口上的 HTTP 服务器，传入 TLS 配置时提供 HTTPS，返回实际监听的地址
+fn bind_port(
+    listeners: Vec<TcpListener>,
+    port_devices: PortDevices,
+    tls: Option<rustls::ServerConfig>,
+    auth: Option<web::Data<Authenticator>>,
+    validate_xml: bool,
+    drain_timeout: Duration,
+) -> std::io::Result<(Server, Vec<SocketAddr>)> {
+    // 根路径端点（设备描述、WSD、管理页面等）由该端口上的第一台设备提供
+    let primary = port_devices[0].1.clone();
+    let devices = web::Data::new(port_devices.clone());
+    let server = HttpServer::new(move || {
+        let mut app = App::new()
+            .wrap(Condition::new(
+                validate_xml,
+                middleware::from_fn(escl_server::validate_outgoing_xml),
+            ))
+            .wrap(server_header(&primary.quirks)) // 设备预设的 Server 头
+            .wrap(escl_server::LoggingMiddleware) // 添加请求日志和追踪 span
+            .app_data(primary.clone())
+            .service(escl_server::scanner_icon) // 图标端点在根路径
+            .service(escl_server::root_info) // 根路径设备信息
+            .service(escl_server::wsd_description) // WSD 设备描述
+            .service(escl_server::wsd_post) // WSD POST 处理
+            .service(escl_server::device_metadata) // Windows 设备元数据
+            .service(escl_server::ssdp_description) // SSDP 发现支持
+            .service(escl_server::favicon) // Favicon
+            .service(escl_server::robots_txt) // Robots.txt
+            .service(escl_server::https_info) // HTTPS 信息
+            .service(escl_server::auth_info) // 认证信息
+            .service(escl_server::description_xml) // Description.xml
+            .service(escl_server::escl_root) // eSCL 根路径
+            .service(escl_server::ssl_info) // SSL 信息
+            .service(escl_server::tls_info) // TLS 信息
+            .service(escl_server::tls_certificate) // TLS 证书下载
+            .service(escl_server::driver_info) // 驱动信息
+            .service(escl_server::pnp_info) // PnP 信息
+            .service(escl_server::port_info) // 端口信息
+            .app_data(devices.clone())
+            .service(admin_console().wrap(middleware::from_fn(auth::authenticate))); // 第一台设备的管理控制台
+        if let Some(auth) = &auth {
+            app = app.app_data(auth.clone());
+        }
+        for (scope, data) in &port_devices {
+            // 发现协议公布的设备描述和 WSD 端点与根路径的一样不需要认证，其余端点在内层 scope 中认证
+            let escl = web::scope("")
+                .wrap(middleware::from_fn(auth::authenticate))
+                .service(escl_server::scanner_capabilities)
+                .service(escl_server::scanner_status)
+                .service(escl_server::device_info) // 添加设备信息端点
+                .service(escl_server::scan_buffer_info) // 添加扫描缓冲区信息端点
+                .service(escl_server::device_capabilities) // Windows设备验证端点
+                .service(escl_server::device_uuid) // 设备UUID端点
+                .service(escl_server::validate_device) // Windows验证端点
+                .service(escl_server::device_configuration) // 设备配置端点
+                .service(escl_server::scan_job)
+                .service(escl_server::next_doc)
+                .service(escl_server::scan_image_info)
+                .service(escl_server::cancel_job)
+                .service(admin_console()); // 每台设备自己的管理控制台
+            app = app.service(
+                web::scope(scope)
+                    .wrap(server_header(&data.quirks))
+                    .app_data(data.clone())
+                    .service(escl_server::device_metadata) // SSDP 宣告的设备描述
+                    .service(escl_server::wsd_description)
+                    .service(escl_server::wsd_post) // WS-Discovery 公布的 XAddrs
+                    .service(escl),
+            );
+        }
+        app.service(escl_server::system_info) // 系统信息
+            .service(escl_server::discovery_info) // 发现信息
+            .service(escl_server::network_info) // 网络信息
+            .service(escl_server::general_capabilities) // 通用能力
+            .service(escl_server::health_check) // 健康检查
+            .service(escl_server::metrics) // Prometheus 指标
+            .service(escl_server::conformance_report) // 客户端一致性报告
+            .service(escl_server::handle_options) // OPTIONS处理
+            .default_service(web::route().to(escl_server::not_found))
+    });
+    // 信号由调用方处理，停止前需要先注销 mDNS
+    let mut server = server
+        .disable_signals()
+        .shutdown_timeout(drain_timeout.as_secs());
+    for listener in listeners {
+        server = match &tls {
+            Some(config) => server.listen_rustls_0_23(listener, config.clone())?,
+            None => server.listen(listener)?,
+        };
+    }
+    let addresses = server.addrs();
+    Ok((server.run(), addresses))
+}
+
+// 自签名证书覆盖 mDNS 主机名、localhost 和公布的地址，客户端用哪个地址连接都能匹配
+fn certificate_names(
+    devices: &[DeviceSettings],
+    bind_ip: IpAddr,
+    advertised: &[IpAddr],
+) -> Vec<String> {
+    let mut names = vec!["localhost".to_owned()];
+    names.extend(
+        devices
+            .iter()
+            .map(|device| device.discovery.hostname.trim_end_matches('.').to_owned()),
+    );
+    let addresses = [
+        bind_ip,
+        Ipv4Addr::LOCALHOST.into(),
+        Ipv6Addr::LOCALHOST.into(),
+    ];
+    names.extend(
+        addresses
+            .iter()
+            .chain(advertised)
+            .filter(|address| !address.is_unspecified())
+            .map(IpAddr::to_string),
+    );
+    let mut seen = HashSet::new();
+    names.retain(|name| seen.insert(name.clone()));
+    names
+}
+
+// 先写临时文件再重命名，轮询该文件的测试工具不会读到一半的内容
+fn write_ready_file(path: &str, lines: &[String]) -> std::io::Result<()> {
+    let temporary = format!("{path}.tmp");
+    std::fs::write(
+        &temporary,
+        lines
+            .iter()
+            .map(|line| format!("{line}\n"))
+            .collect::<String>(),
+    )?;
+    std::fs::rename(&temporary, path)
+}
+
+/// Runs the `escl-mock-server` command line application until SIGINT or
+/// SIGTERM.
+///
+/// Returns [`EXIT_SERVER_ERROR`] when a server fails and
+/// [`EXIT_CONFORMANCE_FAILED`] when the conformance report has failing
+/// clients. Exits the process with [`EXIT_INVALID_CONFIG`] when the
+/// configuration is invalid.
+pub async fn run_cli() -> ExitCode {
+    let args = cli::parse_cli();
+
+    // 日志配置也来自配置文件，因此配置错误只能直接输出到 stderr
+    let settings = args
+        .config_file
+        .as_deref()
+        .map(ConfigFile::load)
+        .transpose()
+        .and_then(|file| config::resolve(&args, file))
+        .unwrap_or_else(|e| {
+            eprintln!("error: {e}");
+            std::process::exit(EXIT_INVALID_CONFIG.into());
+        });
+    let bind_ip = settings.bind_address.ip();
+    let advertised = network::advertised_addresses(
+        settings.bind_address,
+        &settings.interfaces,
+        &settings.advertise_addrs,
+    )
+    .unwrap_or_else(|e| {
+        eprintln!("error: {e}");
+        std::process::exit(EXIT_INVALID_CONFIG.into());
+    });
+    // 证书错误同样属于配置错误
+    let certificate = settings
+        .tls
+        .as_ref()
+        .map(|tls| match &tls.cert {
+            Some((cert, key)) => TlsCertificate::load(cert, key),
+            None => TlsCertificate::self_signed(certificate_names(
+                &settings.devices,
+                bind_ip,
+                &advertised,
+            )),
+        })
+        .transpose()
+        .and_then(|certificate| {
+            let config = certificate
+                .as_ref()
+                .map(TlsCertificate::server_config)
+                .transpose()?;
+            Ok((config, certificate.map(Arc::new)))
+        });
+    let (tls_config, certificate) = certificate.unwrap_or_else(|e| {
+        eprintln!("error: {e}");
+        std::process::exit(EXIT_INVALID_CONFIG.into());
+    });
+    let plain = settings.tls.as_ref().is_none_or(|tls| !tls.only);
+    // 重新加载时与启动时的配置比较，端口为配置的值而不是实际绑定的端口
+    let reload_settings = (!args.no_reload).then(|| settings.clone());
+    logging::init(settings.log_level, settings.log_format, settings.quiet);
+
+    info!(
+        version = env!("CARGO_PKG_VERSION"),
+        "Starting escl-mock-server"
+    );
+    debug!(
+        bind_address = %settings.bind_address,
+        config = ?args.config_file,
+        log_level = %settings.log_level,
+        validation = ?settings.validation,
+        "Configuration"
+    );
+
+    if let Some(certificate) = &certificate {
+        info!(
+            self_signed = certificate.is_self_signed(),
+            fingerprint = %certificate.fingerprint(),
+            http = plain,
+            "TLS enabled"
+        );
+    }
+
+    // 所有设备共用同一组用户
+    let authenticator = settings.auth.map(|auth| {
+        info!(
+            scheme = %auth.scheme,
+            realm = %auth.realm,
+            users = ?auth.users.keys().collect::<Vec<_>>(),
+            "Authentication required"
+        );
+        web::Data::new(Authenticator::new(auth.scheme, auth.realm, auth.users))
+    });
+
+    let mut devices = settings.devices;
+
+    for device in &devices {
+        info!(
+            identity = %device.identity,
+            profile = ?device.profile,
+            port = device.port,
+            scope = %device.scope,
+            images = ?device.images,
+            sources = ?device.sources,
+            faults = ?device.faults,
+            "Virtual scanner"
+        );
+    }
+
+    debug!(addresses = ?advertised, "Advertised addresses");
+    if !bind_ip.is_unspecified() {
+        if let Some(address) = advertised.iter().find(|address| **address != bind_ip) {
+            warn!(
+                advertised = %address,
+                bind_address = %bind_ip,
+                "Advertised address differs from the bind address, clients may not reach the server"
+            );
+        }
+    }
+
+    // 尝试设置 mDNS 服务（如果失败则继续运行）
+    let mut mdns = None;
+    match devices
+        .iter()
+        .any(|device| device.discovery.mdns)
+        .then(|| Mdns::start(&settings.interfaces, &advertised))
+    {
+        None => info!("mDNS advertisement disabled for all devices"),
+        Some(Ok(started)) => {
+            if advertised.iter().all(IpAddr::is_loopback) {
+                warn!("Only loopback addresses are advertised, other hosts won't discover the scanner");
+            }
+            mdns = Some(Arc::new(started));
+        }
+        Some(Err(e)) => {
+            warn!(error = %e, "Failed to create mDNS daemon, continuing without service discovery");
+        }
+    }
+
+    // 按协议和端口分组，同一端口上的设备通过不同的 scope 区分。端口 0 的设备共用一个随机端口。
+    // HTTP 和 HTTPS 服务器共用设备状态，两边看到相同的扫描任务
+    let metrics = Arc::new(Metrics::new());
+    let report = Arc::new(ConformanceReport::new(settings.report));
+    let mut ports: BTreeMap<(bool, u16), PortDevices> = BTreeMap::new();
+    let mut device_data = Vec::with_capacity(devices.len());
+    for device in &devices {
+        let data = web::Data::new(AppState::new(
+            device,
+            settings.validation,
+            settings.bind_address,
+            certificate.clone(),
+            metrics.clone(),
+            report.clone(),
+        ));
+        if plain {
+            ports
+                .entry((false, device.port))
+                .or_default()
+                .push((device.scope.clone(), data.clone()));
+        }
+        if certificate.is_some() {
+            ports
+                .entry((true, device.tls_port))
+                .or_default()
+                .push((device.scope.clone(), data.clone()));
+        }
+        device_data.push(data);
+    }
+
+    // 先启动 HTTP 服务器，mDNS 和输出的地址需要实际监听的端口
+    let mut servers = Vec::with_capacity(ports.len());
+    let mut bound_ports = HashMap::new();
+    for ((secure, port), port_devices) in ports {
+        let scheme = if secure { "https" } else { "http" };
+        info!(
+            address = %settings.bind_address,
+            port,
+            scheme,
+            scopes = ?port_devices.iter().map(|(scope, _)| scope.as_str()).collect::<Vec<_>>(),
+            "Starting HTTP server"
+        );
+
+        let tls = if secure { tls_config.clone() } else { None };
+        let (server, addresses) = settings
+            .bind_address
+            .listeners(port)
+            .and_then(|listeners| {
+                bind_port(
+                    listeners,
+                    port_devices,
+                    tls,
+                    authenticator.clone(),
+                    settings.validation.xml,
+                    settings.drain_timeout,
+                )
+            })
+            .unwrap_or_else(|e| {
+            error!(address = %settings.bind_address, port, scheme, error = %e, "Couldn't create HTTP server");
+            std::process::exit(EXIT_SERVER_ERROR.into());
+        });
+        if port == 0 {
+            info!(address = %settings.bind_address, port = addresses[0].port(), scheme, "Bound to ephemeral port");
+        }
+        bound_ports.insert((secure, port), addresses[0]);
+        servers.push(server);
+    }
+    let mut device_addresses = Vec::with_capacity(devices.len());
+    for (device, data) in devices.iter_mut().zip(&device_data) {
+        let http = plain.then(|| bound_ports[&(false, device.port)]);
+        let https = certificate
+            .is_some()
+            .then(|| bound_ports[&(true, device.tls_port)]);
+        if let Some(address) = http {
+            device.port = address.port();
+        }
+        if let Some(address) = https {
+            device.tls_port = address.port();
+        }
+        let _ = data.ports.set(DevicePorts {
+            http: http.map(|address| address.port()),
+            https: https.map(|address| address.port()),
+        });
+        device_addresses.push([("http", http), ("https", https)]);
+    }
+
+    if let Some(mdns) = &mdns {
+        for (device, data) in devices
+            .iter()
+            .zip(&device_data)
+            .filter(|(device, _)| device.discovery.mdns)
+        {
+            data.advertise(mdns.clone(), device);
+        }
+    }
+
+    // 每台设备在自己的 scope 下提供 device.xml 和 wsd，同一端口上的设备也分别宣告
+    let described: Vec<(&DeviceSettings, DescribedDevice)> = devices
+        .iter()
+        .zip(&device_data)
+        .filter_map(|(device, data)| {
+            let ports = data.ports();
+            let (scheme, port) = match ports.http {
+                Some(port) => ("http", port),
+                None => ("https", ports.https?),
+            };
+            let scope = device.scope.clone();
+            Some((
+                device,
+                DescribedDevice {
+                    uuid: device.identity.uuid,
+                    scheme,
+                    port,
+                    scope,
+                },
+            ))
+        })
+        .collect();
+    let announced = |enabled: fn(&DeviceSettings) -> bool| -> Vec<DescribedDevice> {
+        described
+            .iter()
+            .filter(|(device, _)| enabled(device))
+            .map(|(_, described)| described.clone())
+            .collect()
+    };
+
+    let ssdp_devices = announced(|device| device.discovery.ssdp);
+    let mut ssdp = None;
+    if !ssdp_devices.is_empty() {
+        match Ssdp::start(ssdp_devices, &advertised) {
+            Ok(responder) => ssdp = Some(responder),
+            Err(e) => warn!(error = %e, "Failed to start SSDP responder, continuing without it"),
+        }
+    }
+    let wsd_devices = announced(|device| device.discovery.wsd);
+    let mut wsd = None;
+    if !wsd_devices.is_empty() {
+        match Wsd::start(wsd_devices, &advertised).await {
+            Ok(responder) => wsd = Some(responder),
+            Err(e) => {
+                warn!(error = %e, "Failed to start WS-Discovery responder, continuing without it")
+            }
+        }
+    }
+
+    // 测试工具通过这些行获取实际地址，格式保持稳定
+    let listening: Vec<String> = devices
+        .iter()
+        .zip(&device_addresses)
+        .flat_map(|(device, addresses)| {
+            addresses.iter().filter_map(|(scheme, address)| {
+                let address = (*address)?;
+                // 绑定所有网卡时 URL 使用公布的地址
+                let url = if address.ip().is_unspecified() {
+                    SocketAddr::new(network::url_address(&advertised), address.port())
+                } else {
+                    address
+                };
+                Some(format!(
+                    "listening address={address} url={scheme}://{}{}",
+                    network::url_authority(url),
+                    device.scope
+                ))
+            })
+        })
+        .collect();
+    for line in &listening {
+        println!("{line}");
+    }
+    if let Some(settings) = reload_settings {
+        actix_web::rt::spawn(Reloader::new(args.clone(), settings, device_data.clone()).watch());
+    }
+    if let Some(path) = &args.ready_file {
+        if let Err(e) = write_ready_file(path, &listening) {
+            error!(path, error = %e, "Couldn't write ready file");
+            std::process::exit(EXIT_SERVER_ERROR.into());
+        }
+        debug!(path, "Ready file written");
+    }
+
+    let handles: Vec<_> = servers.iter().map(Server::handle).collect();
+    let result =
+        match future::select(future::try_join_all(servers), Box::pin(wait_for_signal())).await {
+            Either::Left((result, _)) => result.map(|_| ()),
+            Either::Right((signal, servers)) => {
+                info!(signal, "Shutting down");
+                // 先让客户端忘记设备，再停止接受连接
+                if let Some(mdns) = mdns.take() {
+                    mdns.stop();
+                }
+                if let Some(ssdp) = ssdp.take() {
+                    ssdp.stop().await;
+                }
+                if let Some(wsd) = wsd.take() {
+                    wsd.stop().await;
+                }
+                let graceful = !settings.drain_timeout.is_zero();
+                if graceful {
+                    info!(
+                        timeout_secs = settings.drain_timeout.as_secs(),
+                        "Waiting for in-flight requests"
+                    );
+                }
+                // 停止命令由服务器 future 处理，因此两者需要同时等待
+                let stopped = future::join_all(handles.iter().map(|handle| handle.stop(graceful)));
+                future::join(stopped, servers).await.1.map(|_| ())
+            }
+        };
+    if let Some(mdns) = mdns {
+        mdns.stop();
+    }
+    if let Some(ssdp) = ssdp {
+        ssdp.stop().await;
+    }
+    if let Some(wsd) = wsd {
+        wsd.stop().await;
+    }
+    console::remove_uploads();
+
+    let totals = metrics.totals();
+    info!(
+        totals = %totals.iter().map(|(name, total)| format!("{name}={total}")).collect::<Vec<_>>().join(" "),
+        "Final metrics"
+    );
+
+    // 服务器停止后输出客户端一致性报告
+    let mut exit_code = ExitCode::SUCCESS;
+    if report.enabled() {
+        println!("{}", report.render());
+        let failed_clients = report.failed_clients();
+        info!(failed_clients, "Conformance report written");
+        if failed_clients > 0 {
+            exit_code = EXIT_CONFORMANCE_FAILED.into();
+        }
+    }
+    if let Err(e) = result {
+        error!(error = %e, "HTTP server failed");
+        exit_code = EXIT_SERVER_ERROR.into();
+    }
+    let _ = std::io::stdout().flush();
+    exit_code
+}
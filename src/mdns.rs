@@ -0,0 +1,370 @@
+/*
+ *     Copyright (C) 2024-2025 Christian Nagel and contributors
+ *
+ *     This file is part of escl-mock-server.
+ *
+ *     escl-mock-server is free software: you can redistribute it and/or modify it under the terms of
+ *     the GNU General Public License as published by the Free Software Foundation, either
+ *     version 3 of the License, or (at your option) any later version.
+ *
+ *     escl-mock-server is distributed in the hope that it will be useful, but WITHOUT ANY
+ *     WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ *     FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ *     You should have received a copy of the GNU General Public License along with eSCLKt.
+ *     If not, see <https://www.gnu.org/licenses/>.
+ *
+ *     SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+use crate::config::DeviceSettings;
+use crate::profile::{GeneratedTxt, TxtValue};
+use crate::{network, DevicePorts};
+use mdns_sd::{IfKind, ServiceDaemon, ServiceInfo, UnregisterStatus};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
+use std::time::Duration;
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+/// mDNS advertisement of the devices' `_uscan._tcp`, `_uscans._tcp` and
+/// `_http._tcp` services.
+pub(crate) struct Mdns {
+    daemon: ServiceDaemon,
+    addresses: Vec<IpAddr>,
+    /// Registered services of each device, by device UUID.
+    registered: Mutex<HashMap<Uuid, Vec<Registration>>>,
+}
+
+/// What a service was last announced with.
+#[derive(Debug, PartialEq)]
+struct Registration {
+    fullname: String,
+    hostname: String,
+    port: u16,
+    txt: Vec<(String, String)>,
+}
+
+impl Mdns {
+    /// Starts the mDNS daemon. With `interfaces` it only sends on those, and
+    /// on each only the `addresses` of its subnet.
+    pub(crate) fn start(interfaces: &[String], addresses: &[IpAddr]) -> mdns_sd::Result<Mdns> {
+        let daemon = ServiceDaemon::new()?;
+        debug!("mDNS daemon created");
+        if !interfaces.is_empty() {
+            let selected: Vec<IfKind> = interfaces
+                .iter()
+                .map(|name| IfKind::Name(name.clone()))
+                .collect();
+            if let Err(e) = daemon
+                .disable_interface(IfKind::All)
+                .and_then(|_| daemon.enable_interface(selected))
+            {
+                warn!(error = %e, "Couldn't restrict mDNS to the selected interfaces");
+            }
+        }
+        Ok(Mdns {
+            daemon,
+            addresses: addresses.to_vec(),
+            registered: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Registers the services of `device`. Called again for the same device,
+    /// e.g. after its capabilities changed, it re-announces the services whose
+    /// TXT records, host name or port changed and withdraws services that
+    /// were renamed.
+    pub(crate) fn announce(&self, device: &DeviceSettings, ports: DevicePorts) {
+        let discovery = &device.discovery;
+        let hostname = discovery.hostname.as_str();
+        let mut services = Vec::new();
+        // 主要的 _uscan._tcp 服务，启用 TLS 时还有 _uscans._tcp
+        for (service_type, scheme, port) in [
+            ("_uscan._tcp.local.", "http", ports.http),
+            ("_uscans._tcp.local.", "https", ports.https),
+        ] {
+            if let Some(port) = port {
+                let txt = txt_records(device, scheme, port, &self.addresses);
+                services.push((service_type, discovery.instance_name.clone(), port, txt));
+            }
+        }
+        // HTTP 服务提供设备描述
+        if let Some(port) = ports.http {
+            let txt = [
+                ("path", "/device.xml".to_owned()),
+                ("ty", device.identity.make_and_model()),
+                ("note", "Device Description".to_owned()),
+            ];
+            let txt = txt
+                .into_iter()
+                .map(|(key, value)| (key.to_owned(), value))
+                .collect();
+            services.push((
+                "_http._tcp.local.",
+                format!("{} Web", discovery.instance_name),
+                port,
+                txt,
+            ));
+        }
+
+        let mut registered = self.registered.lock().unwrap();
+        let previous = registered.remove(&device.identity.uuid).unwrap_or_default();
+        let mut current = Vec::new();
+        for (service_type, name, port, txt) in services {
+            let service_info = match ServiceInfo::new(
+                service_type,
+                &name,
+                hostname,
+                &self.addresses[..],
+                port,
+                &txt[..],
+            ) {
+                Ok(service_info) => service_info,
+                Err(e) => {
+                    error!(error = %e, service_type, name, "Failed to create mDNS service info");
+                    continue;
+                }
+            };
+            let registration = Registration {
+                fullname: service_info.get_fullname().to_owned(),
+                hostname: hostname.to_owned(),
+                port,
+                txt,
+            };
+            if previous.contains(&registration) {
+                current.push(registration);
+                continue;
+            }
+            // mdns-sd 重新注册同名服务时会再次宣告
+            let announced = previous
+                .iter()
+                .find(|old| old.fullname == registration.fullname);
+            match self.daemon.register(service_info) {
+                Ok(_) => match announced {
+                    Some(old) => info!(
+                        service_type,
+                        name,
+                        changed = ?changed_keys(&old.txt, &registration.txt),
+                        "mDNS service re-announced"
+                    ),
+                    None => {
+                        info!(
+                            service_type,
+                            name,
+                            hostname,
+                            addresses = ?self.addresses,
+                            port,
+                            "mDNS service registered"
+                        )
+                    }
+                },
+                Err(e) => error!(error = %e, service_type, name, "Failed to register mDNS service"),
+            }
+            current.push(registration);
+        }
+        for old in previous
+            .iter()
+            .filter(|old| current.iter().all(|new| new.fullname != old.fullname))
+        {
+            self.withdraw(&old.fullname);
+        }
+        registered.insert(device.identity.uuid, current);
+    }
+
+    /// Withdraws all services and stops the daemon.
+    pub(crate) fn stop(&self) {
+        for registration in self
+            .registered
+            .lock()
+            .unwrap()
+            .drain()
+            .flat_map(|(_, registrations)| registrations)
+        {
+            self.withdraw(&registration.fullname);
+        }
+        match self.daemon.shutdown() {
+            Ok(receiver) => {
+                let _ = receiver.recv_timeout(Duration::from_secs(1));
+            }
+            Err(e) => warn!(error = %e, "Failed to stop mDNS daemon"),
+        }
+    }
+
+    // 注销服务时 mdns-sd 发送 TTL 为 0 的 goodbye 包，客户端会立即从列表中移除设备。
+    // announce 会在异步处理函数中持有锁调用，因此不在这里等待守护进程的确认
+    fn withdraw(&self, fullname: &str) {
+        let receiver = match self.daemon.unregister(fullname) {
+            Ok(receiver) => receiver,
+            Err(e) => {
+                warn!(name = %fullname, error = %e, "Failed to withdraw mDNS service");
+                return;
+            }
+        };
+        let fullname = fullname.to_owned();
+        std::thread::spawn(
+            move || match receiver.recv_timeout(Duration::from_secs(1)) {
+                Ok(UnregisterStatus::OK) => info!(name = %fullname, "mDNS service withdrawn"),
+                Ok(UnregisterStatus::NotFound) => {
+                    warn!(name = %fullname, "mDNS service was not registered")
+                }
+                Err(e) => {
+                    warn!(
+                        name = %fullname,
+                        error = %e,
+                        "No answer from mDNS daemon while withdrawing service"
+                    )
+                }
+            },
+        );
+    }
+}
+
+// 新增、删除或值改变的 TXT 键
+fn changed_keys<'a>(old: &'a [(String, String)], new: &'a [(String, String)]) -> Vec<&'a str> {
+    let value = |records: &'a [(String, String)], key: &str| {
+        records
+            .iter()
+            .find(|(existing, _)| existing == key)
+            .map(|(_, value)| value)
+    };
+    let mut keys: Vec<&str> = Vec::new();
+    for (key, _) in old.iter().chain(new) {
+        if !keys.contains(&key.as_str()) && value(old, key) != value(new, key) {
+            keys.push(key);
+        }
+    }
+    keys
+}
+
+// 生成 _uscan/_uscans 的 TXT 记录，adminurl 和 representation 使用服务自己的协议和端口
+fn txt_records(
+    device: &DeviceSettings,
+    scheme: &str,
+    port: u16,
+    addresses: &[IpAddr],
+) -> Vec<(String, String)> {
+    let identity = &device.identity;
+    let discovery = &device.discovery;
+
+    // 创建符合 Windows 11 要求的 TXT 记录
+    let base = network::url_authority(SocketAddr::new(network::url_address(addresses), port));
+    let adminurl = format!("{scheme}://{base}{}/admin", device.scope);
+    let representation = format!("{scheme}://{base}/icon.png");
+
+    // TXT记录的键和顺序由设备预设决定，能力相关的值来自能力模型
+    let make_and_model = identity.make_and_model();
+    let caps = &device.capabilities;
+    let generated = |value: GeneratedTxt| -> String {
+        match value {
+            GeneratedTxt::TxtVers => "1".to_owned(),
+            GeneratedTxt::Ty => make_and_model.clone(),
+            GeneratedTxt::Rs => device.scope.trim_start_matches('/').to_owned(),
+            GeneratedTxt::Vers => caps.version.to_string(),
+            GeneratedTxt::Pdl => caps.txt_pdl(),
+            GeneratedTxt::Cs => caps.txt_cs(),
+            GeneratedTxt::Is => caps.txt_is(),
+            GeneratedTxt::Duplex => caps.txt_duplex().to_owned(),
+            GeneratedTxt::Uuid => identity.uuid.to_string(),
+            GeneratedTxt::AdminUrl => adminurl.clone(),
+            GeneratedTxt::Representation => representation.clone(),
+        }
+    };
+    let mut txt_records: Vec<(String, String)> = discovery
+        .txt_layout
+        .iter()
+        .map(|(key, value)| {
+            let value = match value {
+                TxtValue::Generated(source) => generated(*source),
+                TxtValue::Fixed(value) => (*value).to_owned(),
+            };
+            (key.to_string(), value)
+        })
+        .collect();
+    // 配置中的额外TXT记录覆盖同名的生成值，DNS-SD 的键不区分大小写
+    for (key, value) in &discovery.txt {
+        match txt_records
+            .iter_mut()
+            .find(|(existing, _)| existing.eq_ignore_ascii_case(key))
+        {
+            Some(record) => record.1 = value.clone(),
+            None => txt_records.push((key.clone(), value.clone())),
+        }
+    }
+    for (key, value) in &txt_records {
+        debug!(device = %identity.friendly_name, scheme, key, value, "mDNS TXT record");
+    }
+    txt_records
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::Cli;
+    use crate::config;
+    use crate::profile::Profile;
+    use clap::{Parser, ValueEnum};
+    use std::net::Ipv4Addr;
+
+    fn device(args: &[&str]) -> DeviceSettings {
+        let args = Cli::parse_from(std::iter::once("escl-mock-server").chain(args.iter().copied()));
+        let mut settings = config::resolve(&args, None).unwrap();
+        settings.devices.remove(0)
+    }
+
+    fn records(device: &DeviceSettings) -> Vec<(String, String)> {
+        txt_records(
+            device,
+            "http",
+            8080,
+            &[IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))],
+        )
+    }
+
+    #[test]
+    fn renders_every_profile_layout() {
+        for profile in Profile::value_variants() {
+            let name = profile.to_possible_value().unwrap();
+            let device = device(&["--profile", name.get_name()]);
+            let records = records(&device);
+            let keys: Vec<&str> = records.iter().map(|(key, _)| key.as_str()).collect();
+            let layout: Vec<&str> = profile.txt_layout().iter().map(|(key, _)| *key).collect();
+            assert_eq!(keys, layout, "{profile:?}");
+
+            let value = |key: &str| {
+                records
+                    .iter()
+                    .find(|(existing, _)| existing.eq_ignore_ascii_case(key))
+                    .map(|(_, value)| value.as_str())
+            };
+            assert_eq!(
+                value("uuid"),
+                Some(device.identity.uuid.to_string().as_str())
+            );
+            assert_eq!(value("rs"), Some("eSCL"));
+            assert_eq!(value("ty"), Some(device.identity.make_and_model().as_str()));
+            if let Some(adminurl) = value("adminurl") {
+                assert_eq!(adminurl, "http://192.0.2.1:8080/eSCL/admin");
+            }
+        }
+    }
+
+    #[test]
+    fn extra_txt_replaces_generated_keys_ignoring_case() {
+        let device = device(&[
+            "--profile",
+            "hp-officejet",
+            "--txt",
+            "uuid=fixed",
+            "--txt",
+            "extra=1",
+        ]);
+        let records = records(&device);
+        let uuids: Vec<&(String, String)> = records
+            .iter()
+            .filter(|(key, _)| key.eq_ignore_ascii_case("uuid"))
+            .collect();
+        assert_eq!(uuids, [&("UUID".to_owned(), "fixed".to_owned())]);
+        assert_eq!(records.last(), Some(&("extra".to_owned(), "1".to_owned())));
+    }
+}
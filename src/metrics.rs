@@ -0,0 +1,238 @@
+/*
+ *     Copyright (C) 2024-2025 Christian Nagel and contributors
+ *
+ *     This file is part of escl-mock-server.
+ *
+ *     escl-mock-server is free software: you can redistribute it and/or modify it under the terms of
+ *     the GNU General Public License as published by the Free Software Foundation, either
+ *     version 3 of the License, or (at your option) any later version.
+ *
+ *     escl-mock-server is distributed in the hope that it will be useful, but WITHOUT ANY
+ *     WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ *     FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ *     You should have received a copy of the GNU General Public License along with eSCLKt.
+ *     If not, see <https://www.gnu.org/licenses/>.
+ *
+ *     SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+use crate::model::ScanSource;
+use prometheus::proto::MetricType;
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+use std::sync::Arc;
+
+/// Prometheus metrics of the whole process, served at `/metrics`.
+///
+/// Every metric carries a `device` label so several virtual scanners can
+/// share one registry.
+pub(crate) struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    jobs_created: IntCounterVec,
+    jobs_completed: IntCounterVec,
+    jobs_canceled: IntCounterVec,
+    pages_served: IntCounterVec,
+    bytes_served: IntCounterVec,
+    next_document_seconds: HistogramVec,
+    active_jobs: IntGaugeVec,
+}
+
+impl Metrics {
+    pub(crate) fn new() -> Self {
+        let registry = Registry::new_custom(Some("escl_mock".to_owned()), None)
+            .expect("metric prefix is valid");
+
+        let http_requests = IntCounterVec::new(
+            Opts::new(
+                "http_requests_total",
+                "HTTP requests by endpoint and status",
+            ),
+            &["device", "endpoint", "method", "status"],
+        )
+        .unwrap();
+        let jobs_created = IntCounterVec::new(
+            Opts::new("jobs_created_total", "Scan jobs created by input source"),
+            &["device", "source"],
+        )
+        .unwrap();
+        let jobs_completed = IntCounterVec::new(
+            Opts::new(
+                "jobs_completed_total",
+                "Scan jobs whose pages were all retrieved",
+            ),
+            &["device", "source"],
+        )
+        .unwrap();
+        let jobs_canceled = IntCounterVec::new(
+            Opts::new(
+                "jobs_canceled_total",
+                "Scan jobs canceled before completion",
+            ),
+            &["device", "source"],
+        )
+        .unwrap();
+        let pages_served = IntCounterVec::new(
+            Opts::new("pages_served_total", "Pages served through NextDocument"),
+            &["device", "source"],
+        )
+        .unwrap();
+        let bytes_served = IntCounterVec::new(
+            Opts::new(
+                "bytes_served_total",
+                "Image bytes sent through NextDocument",
+            ),
+            &["device"],
+        )
+        .unwrap();
+        let next_document_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "next_document_duration_seconds",
+                "Time until a NextDocument response is ready",
+            )
+            .buckets(vec![
+                0.001, 0.005, 0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+            ]),
+            &["device"],
+        )
+        .unwrap();
+        let active_jobs = IntGaugeVec::new(
+            Opts::new("active_jobs", "Scan jobs that are still processing"),
+            &["device"],
+        )
+        .unwrap();
+
+        registry.register(Box::new(http_requests.clone())).unwrap();
+        registry.register(Box::new(jobs_created.clone())).unwrap();
+        registry.register(Box::new(jobs_completed.clone())).unwrap();
+        registry.register(Box::new(jobs_canceled.clone())).unwrap();
+        registry.register(Box::new(pages_served.clone())).unwrap();
+        registry.register(Box::new(bytes_served.clone())).unwrap();
+        registry
+            .register(Box::new(next_document_seconds.clone()))
+            .unwrap();
+        registry.register(Box::new(active_jobs.clone())).unwrap();
+
+        Metrics {
+            registry,
+            http_requests,
+            jobs_created,
+            jobs_completed,
+            jobs_canceled,
+            pages_served,
+            bytes_served,
+            next_document_seconds,
+            active_jobs,
+        }
+    }
+
+    /// Renders all metrics in the Prometheus text exposition format.
+    pub(crate) fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("text encoding of metrics can't fail");
+        String::from_utf8(buffer).expect("metrics are valid UTF-8")
+    }
+
+    /// Counter totals over all devices and labels, logged on shutdown.
+    pub(crate) fn totals(&self) -> Vec<(String, u64)> {
+        self.registry
+            .gather()
+            .iter()
+            .filter(|family| family.get_field_type() == MetricType::COUNTER)
+            .map(|family| {
+                let total: f64 = family
+                    .get_metric()
+                    .iter()
+                    .map(|metric| metric.get_counter().get_value())
+                    .sum();
+                let name = family.name().trim_start_matches("escl_mock_");
+                (name.to_owned(), total as u64)
+            })
+            .collect()
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics::new()
+    }
+}
+
+/// Handle recording into the shared [`Metrics`] for one virtual scanner.
+pub(crate) struct DeviceMetrics {
+    device: String,
+    shared: Arc<Metrics>,
+    pub(crate) bytes_served: IntCounter,
+    pub(crate) next_document_seconds: Histogram,
+}
+
+impl DeviceMetrics {
+    pub(crate) fn new(shared: Arc<Metrics>, device: &str) -> Self {
+        let bytes_served = shared.bytes_served.with_label_values(&[device]);
+        let next_document_seconds = shared.next_document_seconds.with_label_values(&[device]);
+        // 预先创建活动任务指标，使其在没有任务时也显示为0
+        shared.active_jobs.with_label_values(&[device]).set(0);
+        DeviceMetrics {
+            device: device.to_owned(),
+            shared,
+            bytes_served,
+            next_document_seconds,
+        }
+    }
+
+    pub(crate) fn observe_request(&self, endpoint: &str, method: &str, status: u16) {
+        self.shared
+            .http_requests
+            .with_label_values(&[&self.device, endpoint, method, &status.to_string()])
+            .inc();
+    }
+
+    pub(crate) fn job_created(&self, source: ScanSource) {
+        self.shared
+            .jobs_created
+            .with_label_values(&[&self.device, &source.to_string()])
+            .inc();
+        self.shared
+            .active_jobs
+            .with_label_values(&[&self.device])
+            .inc();
+    }
+
+    pub(crate) fn job_completed(&self, source: ScanSource) {
+        self.shared
+            .jobs_completed
+            .with_label_values(&[&self.device, &source.to_string()])
+            .inc();
+        self.shared
+            .active_jobs
+            .with_label_values(&[&self.device])
+            .dec();
+    }
+
+    pub(crate) fn job_canceled(&self, source: ScanSource) {
+        self.shared
+            .jobs_canceled
+            .with_label_values(&[&self.device, &source.to_string()])
+            .inc();
+        self.shared
+            .active_jobs
+            .with_label_values(&[&self.device])
+            .dec();
+    }
+
+    pub(crate) fn page_served(&self, source: ScanSource) {
+        self.shared
+            .pages_served
+            .with_label_values(&[&self.device, &source.to_string()])
+            .inc();
+    }
+
+    pub(crate) fn render(&self) -> String {
+        self.shared.render()
+    }
+}
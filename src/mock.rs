@@ -0,0 +1,262 @@
+/*
+ *     Copyright (C) 2024-2025 Christian Nagel and contributors
+ *
+ *     This file is part of escl-mock-server.
+ *
+ *     escl-mock-server is free software: you can redistribute it and/or modify it under the terms of
+ *     the GNU General Public License as published by the Free Software Foundation, either
+ *     version 3 of the License, or (at your option) any later version.
+ *
+ *     escl-mock-server is distributed in the hope that it will be useful, but WITHOUT ANY
+ *     WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ *     FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ *     You should have received a copy of the GNU General Public License along with eSCLKt.
+ *     If not, see <https://www.gnu.org/licenses/>.
+ *
+ *     SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+use crate::capabilities::ScannerCapabilities;
+use crate::config::{self, DeviceEntry, ValidationSettings};
+use crate::metrics::Metrics;
+use crate::model::{JobState, ScanSource};
+use crate::network::{self, BindAddress};
+use crate::profile::Profile;
+use crate::report::ConformanceReport;
+use crate::version::EsclVersion;
+use crate::{bind_port, schema, AppState, DevicePorts};
+use actix_web::dev::ServerHandle;
+use actix_web::web;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+/// A virtual scanner running inside the current process on an ephemeral
+/// port of `127.0.0.1`. It isn't announced over mDNS.
+///
+/// Call [`MockScanner::shutdown`] at the end of a test, a dropped scanner
+/// keeps serving until the runtime stops.
+pub struct MockScanner {
+    addr: SocketAddr,
+    base_url: String,
+    data: web::Data<AppState>,
+    handle: ServerHandle,
+    task: JoinHandle<io::Result<()>>,
+}
+
+/// Configures a [`MockScanner`]. Unset values are the defaults of the
+/// `escl-mock-server` binary.
+#[derive(Default)]
+pub struct MockScannerBuilder {
+    entry: DeviceEntry,
+    caps: Option<ScannerCapabilities>,
+    validation: ValidationSettings,
+    report: bool,
+}
+
+/// State of a scan job at the time [`MockScanner::jobs`] was called.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScanJobSnapshot {
+    pub uuid: Uuid,
+    pub source: ScanSource,
+    pub state: JobState,
+    pub retrieved_pages: u32,
+    pub max_pages: u32,
+}
+
+impl MockScanner {
+    pub fn builder() -> MockScannerBuilder {
+        MockScannerBuilder::default()
+    }
+
+    /// Address the HTTP server listens on.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// URL of the eSCL root, e.g. `http://127.0.0.1:41234/eSCL`.
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// All jobs the scanner knows about, oldest first.
+    pub async fn jobs(&self) -> Vec<ScanJobSnapshot> {
+        let scan_jobs = self.data.scan_jobs.lock().await;
+        let mut jobs: Vec<_> = scan_jobs.iter().collect();
+        jobs.sort_by_key(|(_, job)| job.created);
+        jobs.into_iter()
+            .map(|(uuid, job)| ScanJobSnapshot {
+                uuid: *uuid,
+                source: job.scan_source,
+                state: job.state,
+                retrieved_pages: job.retrieved_pages,
+                max_pages: job.max_pages,
+            })
+            .collect()
+    }
+
+    pub async fn job(&self, uuid: Uuid) -> Option<ScanJobSnapshot> {
+        self.jobs().await.into_iter().find(|job| job.uuid == uuid)
+    }
+
+    /// The client conformance report, `None` unless enabled with
+    /// [`MockScannerBuilder::report`].
+    pub fn report(&self) -> Option<String> {
+        self.data
+            .report
+            .enabled()
+            .then(|| self.data.report.render())
+    }
+
+    /// Stops the server after in-flight requests are answered.
+    pub async fn shutdown(self) {
+        self.handle.stop(true).await;
+        let _ = self.task.await;
+    }
+}
+
+impl MockScannerBuilder {
+    /// Built-in personality imitating a real scanner model.
+    pub fn profile(mut self, profile: Profile) -> Self {
+        self.entry.profile = Some(profile);
+        self
+    }
+
+    /// Serves these capabilities instead of the ones of the default device or profile.
+    pub fn caps(mut self, caps: ScannerCapabilities) -> Self {
+        self.caps = Some(caps);
+        self
+    }
+
+    /// Emulated eSCL version, also applied to capabilities set with [`Self::caps`].
+    pub fn escl_version(mut self, version: EsclVersion) -> Self {
+        self.entry.capabilities.escl_version = Some(version);
+        self
+    }
+
+    /// Images served for scanned pages, used in turn.
+    pub fn images<I, S>(mut self, images: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.entry.sources.images = Some(images.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Path of the eSCL endpoints, `/eSCL` by default.
+    pub fn scope(mut self, scope: impl Into<String>) -> Self {
+        self.entry.scope = Some(scope.into());
+        self
+    }
+
+    /// Pages produced by a platen job.
+    pub fn platen_pages(mut self, pages: u32) -> Self {
+        self.entry.sources.platen_pages = Some(pages);
+        self
+    }
+
+    /// Sheets in the simulated ADF tray. Duplex jobs produce two pages per sheet.
+    pub fn adf_pages(mut self, pages: u32) -> Self {
+        self.entry.sources.adf_pages = Some(pages);
+        self
+    }
+
+    /// Delay before answering `POST ScanJobs`.
+    pub fn scan_job_delay(mut self, delay: Duration) -> Self {
+        self.entry.faults.scan_job_delay_ms = Some(delay.as_millis() as u64);
+        self
+    }
+
+    /// Delay before a NextDocument response starts.
+    pub fn next_document_delay(mut self, delay: Duration) -> Self {
+        self.entry.faults.next_document_delay_ms = Some(delay.as_millis() as u64);
+        self
+    }
+
+    /// Report the scanner as busy: ScannerStatus says Processing and new jobs get 503.
+    pub fn busy(mut self, busy: bool) -> Self {
+        self.entry.faults.busy = Some(busy);
+        self
+    }
+
+    /// Check outgoing eSCL documents and incoming ScanSettings against the schema.
+    pub fn validate_xml(mut self, enabled: bool) -> Self {
+        self.validation.xml = enabled;
+        self
+    }
+
+    /// Reject scan tickets that don't match the schema with 400.
+    pub fn strict(mut self, enabled: bool) -> Self {
+        self.validation.strict = enabled;
+        self
+    }
+
+    /// Track client behaviour, see [`MockScanner::report`].
+    pub fn report(mut self, enabled: bool) -> Self {
+        self.report = enabled;
+        self
+    }
+
+    /// Binds the server and starts serving in the background.
+    ///
+    /// Fails with [`io::ErrorKind::InvalidInput`] when the settings are invalid.
+    pub async fn start(self) -> io::Result<MockScanner> {
+        let version = self.entry.capabilities.escl_version;
+        let mut device = config::resolve_embedded(self.entry).map_err(invalid_input)?;
+        if let Some(mut caps) = self.caps {
+            if let Some(version) = version {
+                caps.version = version;
+            }
+            caps.validate().map_err(invalid_input)?;
+            let rendered = caps.to_xml(&device.identity, "http://localhost", &device.scope);
+            schema::validate_as(&rendered, "scan:ScannerCapabilities").map_err(|errors| {
+                invalid_input(format!(
+                    "capabilities don't match the eSCL schema:\n{}",
+                    schema::describe(&errors)
+                ))
+            })?;
+            device.sources.adf_duplex = caps.supports_duplex();
+            device.capabilities = caps;
+        }
+
+        let data = web::Data::new(AppState::new(
+            &device,
+            self.validation,
+            BindAddress::LOCALHOST,
+            None,
+            Arc::new(Metrics::new()),
+            Arc::new(ConformanceReport::new(self.report)),
+        ));
+        let (server, addrs) = bind_port(
+            BindAddress::LOCALHOST.listeners(0)?,
+            vec![(device.scope.clone(), data.clone())],
+            None,
+            None,
+            self.validation.xml,
+            Duration::from_secs(30),
+        )?;
+        let addr = addrs[0];
+        let _ = data.ports.set(DevicePorts {
+            http: Some(addr.port()),
+            https: None,
+        });
+        let handle = server.handle();
+        let task = tokio::spawn(server);
+        Ok(MockScanner {
+            addr,
+            base_url: format!("http://{}{}", network::url_authority(addr), device.scope),
+            data,
+            handle,
+            task,
+        })
+    }
+}
+
+fn invalid_input(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
@@ -17,39 +17,185 @@
  *     SPDX-License-Identifier: GPL-3.0-or-later
  */
 
+use crate::capabilities::ColorMode;
+use crate::wsscan::ScanTicket;
+use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
+use std::time::{Duration, Instant};
+use tracing::debug;
+use uuid::Uuid;
 
-#[derive(Debug, Clone)]
-pub(crate) enum ScanSource {
+/// How long completed and canceled jobs stay in the job table.
+const FINISHED_JOB_LIFETIME: Duration = Duration::from_secs(300);
+/// Completed and canceled jobs kept at most, the oldest are dropped first.
+const MAX_FINISHED_JOBS: usize = 100;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ScanSource {
+    #[default]
     Platen,  // 平板
     Adf,     // 自动输稿器
 }
 
-impl Default for ScanSource {
-    fn default() -> Self {
-        ScanSource::Platen
+impl Display for ScanSource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScanSource::Platen => f.write_str("Platen"),
+            ScanSource::Adf => f.write_str("Adf"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum JobState {
+    #[default]
+    Processing,
+    Completed,
+    Canceled,
+}
+
+impl JobState {
+    // ScannerStatus 中的 pwg:JobState 和 pwg:JobStateReason
+    pub(crate) fn pwg_state(&self) -> (&'static str, &'static str) {
+        match self {
+            JobState::Processing => ("Processing", "JobScanning"),
+            JobState::Completed => ("Completed", "JobCompletedSuccessfully"),
+            JobState::Canceled => ("Canceled", "JobCanceledByUser"),
+        }
     }
 }
 
 pub(crate) struct ScanJob {
+    /// Numeric job ID, the WS-Scan `JobId`.
+    pub id: u32,
     pub retrieved_pages: u32,
     pub scan_source: ScanSource,
     pub max_pages: u32,
+    pub state: JobState,
+    pub created: Instant,
+    /// Color mode requested in the eSCL ScanSettings, if it was a known one.
+    pub color_mode: Option<ColorMode>,
+    /// When the job was completed or canceled.
+    pub finished: Option<Instant>,
+    /// Ticket of a job created over WS-Scan or with the scan button.
+    pub ticket: Option<ScanTicket>,
+    /// Set while a job started with the scan button waits for the
+    /// destination's CreateScanJob.
+    pub destination_token: Option<String>,
 }
 
-impl Default for ScanJob {
-    fn default() -> Self {
-        ScanJob { 
+impl ScanJob {
+    // 页数由设备的扫描源配置决定
+    pub(crate) fn new(id: u32, scan_source: ScanSource, max_pages: u32) -> Self {
+        ScanJob {
+            id,
             retrieved_pages: 0,
-            scan_source: ScanSource::Platen,
-            max_pages: 1,  // 平板默认只有1页
+            scan_source,
+            max_pages,
+            state: JobState::Processing,
+            created: Instant::now(),
+            color_mode: None,
+            finished: None,
+            ticket: None,
+            destination_token: None,
         }
     }
+
+    pub(crate) fn finish(&mut self, state: JobState) {
+        self.state = state;
+        self.finished = Some(Instant::now());
+    }
+}
+
+// 新任务加入任务表时清理结束已久的任务，客户端停止轮询后它们不会再被查询
+pub(crate) fn insert_job(jobs: &mut HashMap<Uuid, ScanJob>, uuid: Uuid, job: ScanJob) {
+    let before = jobs.len();
+    jobs.retain(|_, job| {
+        job.finished
+            .is_none_or(|finished| finished.elapsed() < FINISHED_JOB_LIFETIME)
+    });
+    let mut finished: Vec<(Instant, Uuid)> = jobs
+        .iter()
+        .filter_map(|(uuid, job)| job.finished.map(|finished| (finished, *uuid)))
+        .collect();
+    if finished.len() > MAX_FINISHED_JOBS {
+        finished.sort_unstable();
+        for (_, uuid) in &finished[..finished.len() - MAX_FINISHED_JOBS] {
+            jobs.remove(uuid);
+        }
+    }
+    if jobs.len() < before {
+        debug!(
+            removed = before - jobs.len(),
+            remaining = jobs.len(),
+            "Removed finished jobs from the job table"
+        );
+    }
+    jobs.insert(uuid, job);
+}
+
+impl Default for ScanJob {
+    fn default() -> Self {
+        ScanJob::new(0, ScanSource::Platen, 1)  // 平板默认只有1页
+    }
 }
 
 impl Display for ScanJob {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "retrieved_pages = {}, source = {:?}, max_pages = {}", 
-               self.retrieved_pages, self.scan_source, self.max_pages)
+        write!(f, "retrieved_pages = {}, source = {}, max_pages = {}, state = {:?}",
+               self.retrieved_pages, self.scan_source, self.max_pages, self.state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn finished(state: JobState, age: Duration) -> ScanJob {
+        let mut job = ScanJob::default();
+        job.finish(state);
+        job.finished = Instant::now().checked_sub(age);
+        job
+    }
+
+    #[test]
+    fn removes_expired_jobs_on_insert() {
+        let mut jobs = HashMap::new();
+        let expired = Uuid::new_v4();
+        let recent = Uuid::new_v4();
+        let processing = Uuid::new_v4();
+        jobs.insert(expired, finished(JobState::Canceled, FINISHED_JOB_LIFETIME));
+        jobs.insert(
+            recent,
+            finished(JobState::Completed, Duration::from_secs(1)),
+        );
+        jobs.insert(processing, ScanJob::default());
+
+        let new = Uuid::new_v4();
+        insert_job(&mut jobs, new, ScanJob::default());
+        assert!(!jobs.contains_key(&expired));
+        assert!(
+            jobs.contains_key(&recent) && jobs.contains_key(&processing) && jobs.contains_key(&new)
+        );
+    }
+
+    #[test]
+    fn keeps_the_latest_finished_jobs() {
+        let mut jobs = HashMap::new();
+        let oldest = Uuid::new_v4();
+        jobs.insert(
+            oldest,
+            finished(JobState::Completed, Duration::from_secs(60)),
+        );
+        for _ in 0..MAX_FINISHED_JOBS {
+            jobs.insert(
+                Uuid::new_v4(),
+                finished(JobState::Completed, Duration::from_secs(1)),
+            );
+        }
+
+        insert_job(&mut jobs, Uuid::new_v4(), ScanJob::default());
+        assert!(!jobs.contains_key(&oldest));
+        assert_eq!(jobs.len(), MAX_FINISHED_JOBS + 1);
     }
 }
@@ -0,0 +1,409 @@
+/*
+ *     Copyright (C) 2024-2025 Christian Nagel and contributors
+ *
+ *     This file is part of escl-mock-server.
+ *
+ *     escl-mock-server is free software: you can redistribute it and/or modify it under the terms of
+ *     the GNU General Public License as published by the Free Software Foundation, either
+ *     version 3 of the License, or (at your option) any later version.
+ *
+ *     escl-mock-server is distributed in the hope that it will be useful, but WITHOUT ANY
+ *     WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ *     FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ *     You should have received a copy of the GNU General Public License along with eSCLKt.
+ *     If not, see <https://www.gnu.org/licenses/>.
+ *
+ *     SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+use socket2::{Domain, Protocol, Socket, Type};
+use std::collections::BTreeSet;
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6, TcpListener, UdpSocket};
+use std::str::FromStr;
+use std::time::Duration;
+use tracing::warn;
+use uuid::Uuid;
+
+/// Address the HTTP servers listen on.
+///
+/// `::` listens on IPv6 and IPv4 with the same port. Link-local IPv6
+/// addresses need a zone, e.g. `fe80::1%eth0`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct BindAddress {
+    ip: IpAddr,
+    /// Interface index of a link-local IPv6 address, 0 otherwise.
+    scope_id: u32,
+}
+
+impl BindAddress {
+    pub(crate) const LOCALHOST: BindAddress = BindAddress {
+        ip: IpAddr::V4(Ipv4Addr::LOCALHOST),
+        scope_id: 0,
+    };
+
+    pub(crate) fn ip(&self) -> IpAddr {
+        self.ip
+    }
+
+    fn is_dual_stack(&self) -> bool {
+        self.ip == IpAddr::V6(Ipv6Addr::UNSPECIFIED)
+    }
+
+    pub(crate) fn ipv4_enabled(&self) -> bool {
+        self.ip.is_ipv4() || self.is_dual_stack()
+    }
+
+    pub(crate) fn ipv6_enabled(&self) -> bool {
+        self.ip.is_ipv6()
+    }
+
+    /// Binds the listening sockets for `port`, 0 picks a free port.
+    pub(crate) fn listeners(&self, port: u16) -> io::Result<Vec<TcpListener>> {
+        let address = match self.ip {
+            IpAddr::V4(ip) => SocketAddr::from((ip, port)),
+            IpAddr::V6(ip) => SocketAddr::V6(SocketAddrV6::new(ip, port, 0, self.scope_id)),
+        };
+        let first = listen(address)?;
+        if !self.is_dual_stack() {
+            return Ok(vec![first]);
+        }
+        // IPv4 使用 IPv6 套接字实际分配的端口
+        let port = first.local_addr()?.port();
+        let second = listen(SocketAddr::from((Ipv4Addr::UNSPECIFIED, port)))?;
+        Ok(vec![first, second])
+    }
+}
+
+// IPv6 套接字总是只接受 IPv6，双栈由单独的 IPv4 套接字实现，各系统的默认值不同
+fn listen(address: SocketAddr) -> io::Result<TcpListener> {
+    let socket = Socket::new(
+        Domain::for_address(address),
+        Type::STREAM,
+        Some(Protocol::TCP),
+    )?;
+    if address.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    #[cfg(not(windows))]
+    socket.set_reuse_address(true)?;
+    socket.bind(&address.into())?;
+    socket.listen(1024)?;
+    socket.set_nonblocking(true)?;
+    Ok(socket.into())
+}
+
+/// Unbound IPv4 UDP socket for multicast discovery on `port`, shared with
+/// other discovery services on the host.
+pub(crate) fn multicast_socket(port: u16) -> io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+    socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, port)).into())?;
+    socket.set_multicast_loop_v4(true)?;
+    socket.set_nonblocking(true)?;
+    Ok(socket.into())
+}
+
+/// UDP socket sending multicast messages from the interface with `address`.
+pub(crate) fn multicast_sender(address: Ipv4Addr, ttl: u32) -> io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_multicast_if_v4(&address)?;
+    socket.set_multicast_ttl_v4(ttl)?;
+    socket.set_multicast_loop_v4(true)?;
+    socket.bind(&SocketAddr::from((address, 0)).into())?;
+    socket.set_nonblocking(true)?;
+    Ok(socket.into())
+}
+
+/// Joins the multicast `group` with `socket` on each of the `interfaces` and
+/// opens a socket sending multicast from it. Interfaces that can't do
+/// multicast are skipped with a warning naming the `service`.
+pub(crate) fn join_multicast_group(
+    socket: &UdpSocket,
+    group: Ipv4Addr,
+    interfaces: impl IntoIterator<Item = Ipv4Addr>,
+    ttl: u32,
+    service: &str,
+) -> Vec<(Ipv4Addr, tokio::net::UdpSocket)> {
+    let mut senders = Vec::new();
+    for interface in interfaces {
+        // 某个网卡不支持多播时跳过它，其他网卡照常工作
+        let sender = socket
+            .join_multicast_v4(&group, &interface)
+            .and_then(|_| multicast_sender(interface, ttl))
+            .and_then(tokio::net::UdpSocket::from_std);
+        match sender {
+            Ok(sender) => senders.push((interface, sender)),
+            Err(e) => {
+                warn!(interface = %interface, error = %e, "Couldn't use interface for {service}")
+            }
+        }
+    }
+    senders
+}
+
+/// Random fraction in `[0, 1)` for spreading multicast answers over a delay.
+pub(crate) fn random_fraction() -> f64 {
+    // UUID v4 的低 32 位是随机的
+    f64::from(Uuid::new_v4().as_u128() as u32) / (f64::from(u32::MAX) + 1.0)
+}
+
+/// Waits before receiving on a discovery socket again after `failures`
+/// earlier consecutive errors, from one second doubling up to a minute, so a
+/// persistent error doesn't turn the receive loop into a busy loop.
+pub(crate) async fn receive_backoff(failures: u32) {
+    let delay = Duration::from_secs(1 << failures.min(6)).min(Duration::from_secs(60));
+    actix_web::rt::time::sleep(delay).await;
+}
+
+/// The address of `addresses` sharing the longest prefix with `peer`, used
+/// in URLs sent to that peer.
+pub(crate) fn closest_ipv4(addresses: &[Ipv4Addr], peer: Ipv4Addr) -> Option<Ipv4Addr> {
+    addresses
+        .iter()
+        .copied()
+        .min_by_key(|address| u32::from(*address) ^ u32::from(peer))
+}
+
+impl FromStr for BindAddress {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let (ip, zone) = match text.split_once('%') {
+            Some((ip, zone)) => (ip, Some(zone)),
+            None => (text, None),
+        };
+        let ip: IpAddr = ip
+            .parse()
+            .map_err(|_| format!("{text:?} is not an IP address"))?;
+        let scope_id = match (ip, zone) {
+            (_, None) => 0,
+            (IpAddr::V6(_), Some(zone)) => match zone.parse() {
+                Ok(index) => index,
+                Err(_) => interface_index(zone)?,
+            },
+            (IpAddr::V4(_), Some(_)) => {
+                return Err(format!("{text:?}: only IPv6 addresses have a zone"))
+            }
+        };
+        if let IpAddr::V6(ipv6) = ip {
+            if ipv6.is_unicast_link_local() && scope_id == 0 {
+                return Err(format!(
+                    "link-local address {text:?} needs a zone, e.g. {ip}%eth0"
+                ));
+            }
+        }
+        Ok(BindAddress { ip, scope_id })
+    }
+}
+
+impl Display for BindAddress {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.scope_id {
+            0 => write!(f, "{}", self.ip),
+            scope_id => write!(f, "{}%{scope_id}", self.ip),
+        }
+    }
+}
+
+fn interface_index(name: &str) -> Result<u32, String> {
+    if_addrs::get_if_addrs()
+        .map_err(|e| format!("couldn't list network interfaces: {e}"))?
+        .into_iter()
+        .find(|interface| interface.name == name)
+        .and_then(|interface| interface.index)
+        .ok_or_else(|| format!("network interface {name:?} doesn't exist"))
+}
+
+/// Host and port for a URL: IPv6 addresses in brackets, their zone encoded
+/// as `%25` (RFC 6874).
+pub(crate) fn url_authority(address: SocketAddr) -> String {
+    match address {
+        SocketAddr::V4(address) => address.to_string(),
+        SocketAddr::V6(address) if address.scope_id() != 0 => {
+            format!(
+                "[{}%25{}]:{}",
+                address.ip(),
+                address.scope_id(),
+                address.port()
+            )
+        }
+        SocketAddr::V6(address) => format!("[{}]:{}", address.ip(), address.port()),
+    }
+}
+
+/// The advertised address used in URLs. Link-local IPv6 addresses only work
+/// with a zone, so any other address is preferred.
+pub(crate) fn url_address(addresses: &[IpAddr]) -> IpAddr {
+    addresses
+        .iter()
+        .find(|address| !is_link_local_v6(address))
+        .unwrap_or(&addresses[0])
+        .to_owned()
+}
+
+fn is_link_local_v6(address: &IpAddr) -> bool {
+    matches!(address, IpAddr::V6(ip) if ip.is_unicast_link_local())
+}
+
+/// Picks the addresses the devices are advertised with.
+///
+/// Explicit `advertise` addresses win, then the addresses of the named
+/// `interfaces`. Otherwise a server bound to all interfaces is advertised on
+/// every non-loopback interface and a server bound to one address with that
+/// address. Only address families the server listens on are used. The bind
+/// address itself is never changed.
+pub(crate) fn advertised_addresses(
+    bind: BindAddress,
+    interfaces: &[String],
+    advertise: &[IpAddr],
+) -> Result<Vec<IpAddr>, String> {
+    if !advertise.is_empty() {
+        return Ok(advertise.to_vec());
+    }
+    if interfaces.is_empty() && !bind.ip.is_unspecified() {
+        return Ok(vec![bind.ip]);
+    }
+    let reachable = |address: &IpAddr| {
+        if address.is_ipv4() {
+            bind.ipv4_enabled()
+        } else {
+            bind.ipv6_enabled()
+        }
+    };
+
+    let available =
+        if_addrs::get_if_addrs().map_err(|e| format!("couldn't list network interfaces: {e}"))?;
+    if interfaces.is_empty() {
+        let addresses: Vec<IpAddr> = available
+            .iter()
+            .filter(|interface| !interface.is_loopback())
+            .map(|interface| interface.ip())
+            .filter(reachable)
+            .collect();
+        // 离线时只剩回环地址，至少本机的客户端仍然可以连接
+        return Ok(if addresses.is_empty() {
+            let loopback = if bind.ipv4_enabled() {
+                IpAddr::V4(Ipv4Addr::LOCALHOST)
+            } else {
+                IpAddr::V6(Ipv6Addr::LOCALHOST)
+            };
+            vec![loopback]
+        } else {
+            addresses
+        });
+    }
+
+    let mut addresses = Vec::new();
+    for name in interfaces {
+        let found: Vec<IpAddr> = available
+            .iter()
+            .filter(|interface| &interface.name == name)
+            .map(|interface| interface.ip())
+            .collect();
+        if found.is_empty() {
+            let names: BTreeSet<&str> = available
+                .iter()
+                .map(|interface| interface.name.as_str())
+                .collect();
+            return Err(format!(
+                "network interface {name:?} doesn't exist, available: {}",
+                names.into_iter().collect::<Vec<_>>().join(", ")
+            ));
+        }
+        let usable: Vec<IpAddr> = found.into_iter().filter(reachable).collect();
+        if usable.is_empty() {
+            return Err(format!(
+                "network interface {name:?} has no address of the family bind address {bind} listens on"
+            ));
+        }
+        addresses.extend(usable);
+    }
+    Ok(addresses)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_bind_addresses() {
+        assert_eq!("127.0.0.1".parse(), Ok(BindAddress::LOCALHOST));
+        let dual_stack: BindAddress = "::".parse().unwrap();
+        assert!(dual_stack.ipv4_enabled() && dual_stack.ipv6_enabled());
+        let ipv4: BindAddress = "0.0.0.0".parse().unwrap();
+        assert!(ipv4.ipv4_enabled() && !ipv4.ipv6_enabled());
+        let ipv6: BindAddress = "::1".parse().unwrap();
+        assert!(!ipv6.ipv4_enabled() && ipv6.ipv6_enabled());
+    }
+
+    #[test]
+    fn parses_numeric_zones() {
+        let address: BindAddress = "fe80::1%3".parse().unwrap();
+        assert_eq!(address.ip(), "fe80::1".parse::<IpAddr>().unwrap());
+        assert_eq!(address.scope_id, 3);
+        assert_eq!(address.to_string(), "fe80::1%3");
+    }
+
+    #[test]
+    fn rejects_invalid_bind_addresses() {
+        assert!("localhost"
+            .parse::<BindAddress>()
+            .unwrap_err()
+            .contains("is not an IP address"));
+        assert!("10.0.0.1%3"
+            .parse::<BindAddress>()
+            .unwrap_err()
+            .contains("only IPv6 addresses have a zone"));
+        assert!("fe80::1"
+            .parse::<BindAddress>()
+            .unwrap_err()
+            .contains("needs a zone"));
+        let unknown = "fe80::1%no-such-interface"
+            .parse::<BindAddress>()
+            .unwrap_err();
+        assert!(unknown.contains("doesn't exist"), "{unknown}");
+    }
+
+    #[test]
+    fn brackets_ipv6_authorities() {
+        assert_eq!(
+            url_authority("192.168.1.5:8080".parse().unwrap()),
+            "192.168.1.5:8080"
+        );
+        assert_eq!(
+            url_authority("[2001:db8::5]:8080".parse().unwrap()),
+            "[2001:db8::5]:8080"
+        );
+    }
+
+    #[test]
+    fn encodes_zones_in_authorities() {
+        let address = SocketAddrV6::new("fe80::1".parse().unwrap(), 8080, 0, 2);
+        assert_eq!(url_authority(address.into()), "[fe80::1%252]:8080");
+    }
+
+    #[test]
+    fn prefers_addresses_without_zones_in_urls() {
+        let link_local: IpAddr = "fe80::1".parse().unwrap();
+        let global: IpAddr = "2001:db8::1".parse().unwrap();
+        assert_eq!(url_address(&[link_local, global]), global);
+        assert_eq!(url_address(&[link_local]), link_local);
+    }
+
+    #[test]
+    fn picks_the_closest_ipv4_address() {
+        let addresses = ["10.0.0.2".parse().unwrap(), "192.168.1.10".parse().unwrap()];
+        assert_eq!(
+            closest_ipv4(&addresses, "192.168.1.77".parse().unwrap()),
+            Some(addresses[1])
+        );
+        assert_eq!(
+            closest_ipv4(&addresses, "10.1.2.3".parse().unwrap()),
+            Some(addresses[0])
+        );
+        assert_eq!(closest_ipv4(&[], "10.1.2.3".parse().unwrap()), None);
+    }
+}
@@ -0,0 +1,322 @@
+/*
+ *     Copyright (C) 2024-2025 Christian Nagel and contributors
+ *
+ *     This file is part of escl-mock-server.
+ *
+ *     escl-mock-server is free software: you can redistribute it and/or modify it under the terms of
+ *     the GNU General Public License as published by the Free Software Foundation, either
+ *     version 3 of the License, or (at your option) any later version.
+ *
+ *     escl-mock-server is distributed in the hope that it will be useful, but WITHOUT ANY
+ *     WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ *     FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ *     You should have received a copy of the GNU General Public License along with eSCLKt.
+ *     If not, see <https://www.gnu.org/licenses/>.
+ *
+ *     SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+use crate::capabilities::{CapabilitiesBuilder, ColorMode, Intent, ScannerCapabilities};
+use crate::identity::IdentityFile;
+use crate::version::EsclVersion;
+use clap::ValueEnum;
+use serde::Deserialize;
+
+/// Built-in personalities imitating the eSCL stacks of real scanner models.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Profile {
+    HpOfficejet,
+    CanonPixma,
+    EpsonEcotank,
+    BrotherMfc,
+    AppleAirscanMinimal,
+}
+
+/// Value of a `_uscan._tcp` TXT record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TxtValue {
+    /// One of the values the server computes.
+    Generated(GeneratedTxt),
+    Fixed(&'static str),
+}
+
+/// TXT values computed from the device's identity, capabilities and address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum GeneratedTxt {
+    /// `txtvers`
+    TxtVers,
+    /// `ty`, the make and model.
+    Ty,
+    /// `rs`, the scope without its leading slash.
+    Rs,
+    /// `vers`, the eSCL version.
+    Vers,
+    /// `pdl`, the document formats.
+    Pdl,
+    /// `cs`, the color modes.
+    Cs,
+    /// `is`, the input sources.
+    Is,
+    /// `duplex`
+    Duplex,
+    /// `uuid`
+    Uuid,
+    /// `adminurl`, the device's admin console.
+    AdminUrl,
+    /// `representation`, the device icon.
+    Representation,
+}
+
+/// Keys and order of the TXT records.
+pub(crate) type TxtLayout = &'static [(&'static str, TxtValue)];
+
+use GeneratedTxt::{AdminUrl, Cs, Duplex, Is, Pdl, Representation, Rs, TxtVers, Ty, Uuid, Vers};
+use TxtValue::{Fixed, Generated};
+
+/// TXT records of devices without a profile.
+pub(crate) const GENERIC_TXT: TxtLayout = &[
+    ("txtvers", Generated(TxtVers)),
+    ("ty", Generated(Ty)),
+    ("rs", Generated(Rs)),
+    ("vers", Generated(Vers)),
+    ("pdl", Generated(Pdl)),
+    ("cs", Generated(Cs)),
+    ("is", Generated(Is)),
+    ("duplex", Generated(Duplex)),
+    ("uuid", Generated(Uuid)),
+    ("adminurl", Generated(AdminUrl)),
+    ("representation", Generated(Representation)),
+];
+
+/// Behaviour that differs between vendors' eSCL implementations.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Quirks {
+    /// `Server` response header.
+    pub server_header: Option<&'static str>,
+    /// Whether the `Location` of a new scan job is an absolute URL or only a path.
+    pub absolute_location: bool,
+    /// ScannerStatus lists `ScannerStateReasons`.
+    pub status_state_reasons: bool,
+    /// ScannerStatus reports `AdfState` when the device has an ADF.
+    pub status_adf_state: bool,
+    /// ScannerStatus lists the job table in `Jobs`.
+    pub status_jobs: bool,
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks {
+            server_header: None,
+            absolute_location: true,
+            status_state_reasons: true,
+            status_adf_state: false,
+            status_jobs: false,
+        }
+    }
+}
+
+impl Profile {
+    /// Identity defaults, UUID and serial number are still generated per instance.
+    pub(crate) fn identity(&self) -> IdentityFile {
+        let (make, model, firmware) = match self {
+            Profile::HpOfficejet => ("HP", "OfficeJet Pro 9010 series", "2406.1"),
+            Profile::CanonPixma => ("Canon", "TS5300 series", "1.040"),
+            Profile::EpsonEcotank => ("EPSON", "ET-2850 Series", "05.13.XV27P1"),
+            Profile::BrotherMfc => ("Brother", "MFC-L2750DW series", "ZB2304"),
+            Profile::AppleAirscanMinimal => ("Generic", "AirScan Minimal", "1.0"),
+        };
+        IdentityFile {
+            make: Some(make.to_owned()),
+            model: Some(model.to_owned()),
+            firmware_version: Some(firmware.to_owned()),
+            ..IdentityFile::default()
+        }
+    }
+
+    pub(crate) fn capabilities(&self) -> CapabilitiesBuilder {
+        let builder = ScannerCapabilities::builder();
+        match self {
+            Profile::HpOfficejet => builder
+                .version(EsclVersion::new(2, 63))
+                .resolutions(&[75, 100, 200, 300, 600, 1200])
+                .max_optical_resolution(Some(1200))
+                .feeder_capacity(35)
+                .ccd_channels(&["NTSC", "GrayCcd", "GrayCcdEmulated"]),
+            Profile::CanonPixma => builder
+                .version(EsclVersion::new(2, 62))
+                .adf(false)
+                .color_modes(vec![ColorMode::Grayscale8, ColorMode::Rgb24])
+                .document_formats(vec!["image/jpeg".to_owned(), "application/pdf".to_owned()])
+                .resolutions(&[75, 150, 300, 600, 1200])
+                .max_optical_resolution(Some(1200))
+                .binary_renderings(&[])
+                .supported_edges(&[])
+                .blank_page_detection(false)
+                .stored_job_request_support(false),
+            Profile::EpsonEcotank => builder
+                .version(EsclVersion::new(2, 60))
+                .adf(false)
+                .resolutions(&[100, 200, 300, 600, 1200])
+                .max_optical_resolution(Some(1200))
+                .color_spaces(&["sRGB"])
+                .ccd_channels(&[])
+                .supported_edges(&[]),
+            Profile::BrotherMfc => builder
+                .version(EsclVersion::new(2, 63))
+                .resolutions(&[100, 150, 200, 300, 600])
+                .max_optical_resolution(Some(600))
+                .feeder_capacity(50)
+                .intents(vec![
+                    Intent::Document,
+                    Intent::TextAndGraphic,
+                    Intent::Photo,
+                ])
+                .color_spaces(&["sRGB"])
+                .ccd_channels(&[])
+                .binary_renderings(&["Threshold"])
+                .supported_edges(&[]),
+            // macOS 只需要这些元素就能使用扫描仪
+            Profile::AppleAirscanMinimal => builder
+                .version(EsclVersion::new(2, 0))
+                .adf(false)
+                .color_modes(vec![ColorMode::Rgb24])
+                .document_formats(vec!["image/jpeg".to_owned()])
+                .resolutions(&[300])
+                .intents(vec![Intent::Document])
+                .max_optical_resolution(None)
+                .color_spaces(&[])
+                .ccd_channels(&[])
+                .binary_renderings(&[])
+                .supported_edges(&[])
+                .stored_job_request_support(false),
+        }
+    }
+
+    pub(crate) fn txt_layout(&self) -> TxtLayout {
+        match self {
+            Profile::HpOfficejet => &[
+                ("txtvers", Generated(TxtVers)),
+                ("ty", Generated(Ty)),
+                ("adminurl", Generated(AdminUrl)),
+                ("representation", Generated(Representation)),
+                ("note", Fixed("")),
+                ("vers", Generated(Vers)),
+                ("rs", Generated(Rs)),
+                ("pdl", Generated(Pdl)),
+                ("cs", Generated(Cs)),
+                ("is", Generated(Is)),
+                ("duplex", Generated(Duplex)),
+                ("UUID", Generated(Uuid)),
+                ("mopria-certified-scan", Fixed("1.2")),
+                ("usb_MFG", Fixed("HP")),
+            ],
+            Profile::CanonPixma => &[
+                ("txtvers", Generated(TxtVers)),
+                ("ty", Generated(Ty)),
+                ("rs", Generated(Rs)),
+                ("vers", Generated(Vers)),
+                ("pdl", Generated(Pdl)),
+                ("cs", Generated(Cs)),
+                ("is", Generated(Is)),
+                ("duplex", Generated(Duplex)),
+                ("uuid", Generated(Uuid)),
+                ("representation", Generated(Representation)),
+                ("note", Fixed("")),
+            ],
+            Profile::EpsonEcotank => &[
+                ("txtvers", Generated(TxtVers)),
+                ("ty", Generated(Ty)),
+                ("rs", Generated(Rs)),
+                ("vers", Generated(Vers)),
+                ("pdl", Generated(Pdl)),
+                ("cs", Generated(Cs)),
+                ("is", Generated(Is)),
+                ("duplex", Generated(Duplex)),
+                ("uuid", Generated(Uuid)),
+                ("adminurl", Generated(AdminUrl)),
+                ("representation", Generated(Representation)),
+                ("mfg", Fixed("EPSON")),
+            ],
+            Profile::BrotherMfc => &[
+                ("txtvers", Generated(TxtVers)),
+                ("ty", Generated(Ty)),
+                ("note", Fixed("")),
+                ("adminurl", Generated(AdminUrl)),
+                ("representation", Generated(Representation)),
+                ("rs", Generated(Rs)),
+                ("vers", Generated(Vers)),
+                ("pdl", Generated(Pdl)),
+                ("cs", Generated(Cs)),
+                ("is", Generated(Is)),
+                ("duplex", Generated(Duplex)),
+                ("UUID", Generated(Uuid)),
+            ],
+            Profile::AppleAirscanMinimal => &[
+                ("txtvers", Generated(TxtVers)),
+                ("ty", Generated(Ty)),
+                ("rs", Generated(Rs)),
+                ("vers", Generated(Vers)),
+                ("pdl", Generated(Pdl)),
+                ("uuid", Generated(Uuid)),
+            ],
+        }
+    }
+
+    pub(crate) fn quirks(&self) -> Quirks {
+        match self {
+            Profile::HpOfficejet => Quirks {
+                server_header: Some("HP HTTP Server; HP OfficeJet Pro 9010 series"),
+                absolute_location: true,
+                status_state_reasons: false,
+                status_adf_state: true,
+                status_jobs: true,
+            },
+            Profile::CanonPixma => Quirks {
+                server_header: Some("Canon HTTP Server"),
+                absolute_location: false,
+                status_state_reasons: false,
+                status_adf_state: false,
+                status_jobs: false,
+            },
+            Profile::EpsonEcotank => Quirks {
+                server_header: Some("EPSON_Linux UPnP/1.0 Epson UPnP SDK/1.0"),
+                absolute_location: true,
+                status_state_reasons: true,
+                status_adf_state: false,
+                status_jobs: true,
+            },
+            Profile::BrotherMfc => Quirks {
+                server_header: Some("debut/1.30"),
+                absolute_location: false,
+                status_state_reasons: true,
+                status_adf_state: true,
+                status_jobs: true,
+            },
+            Profile::AppleAirscanMinimal => Quirks {
+                status_state_reasons: false,
+                ..Quirks::default()
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::identity::DeviceIdentity;
+    use crate::schema;
+    use clap::ValueEnum;
+
+    #[test]
+    fn profile_capabilities_match_the_schema() {
+        for profile in Profile::value_variants() {
+            let identity = DeviceIdentity::from_partial(profile.identity());
+            let capabilities = profile.capabilities().build();
+            assert_eq!(capabilities.validate(), Ok(()), "{profile:?}");
+            let xml = capabilities.to_xml(&identity, "http://localhost:8080", "/eSCL");
+            assert_eq!(schema::validate(&xml), Ok(()), "{profile:?}");
+        }
+    }
+}
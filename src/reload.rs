@@ -0,0 +1,392 @@
+/*
+ *     Copyright (C) 2024-2025 Christian Nagel and contributors
+ *
+ *     This file is part of escl-mock-server.
+ *
+ *     escl-mock-server is free software: you can redistribute it and/or modify it under the terms of
+ *     the GNU General Public License as published by the Free Software Foundation, either
+ *     version 3 of the License, or (at your option) any later version.
+ *
+ *     escl-mock-server is distributed in the hope that it will be useful, but WITHOUT ANY
+ *     WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ *     FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ *     You should have received a copy of the GNU General Public License along with eSCLKt.
+ *     If not, see <https://www.gnu.org/licenses/>.
+ *
+ *     SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+use crate::cli::Cli;
+use crate::config::{self, DeviceSettings, Settings};
+use crate::{AppState, LiveSettings};
+use actix_web::web;
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime};
+use tokio::time::MissedTickBehavior;
+use tracing::{debug, info, warn};
+
+// 轮询而不是依赖文件系统通知，编辑器通过改名保存文件时同样能发现变化
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+// 发现变化后稍等片刻，避免读到只写了一半的文件
+const SETTLE_DELAY: Duration = Duration::from_millis(300);
+
+/// Modification time and size of each watched file, `None` when it's missing.
+type Fingerprint = BTreeMap<String, Option<(SystemTime, u64)>>;
+
+/// Applies changes of the config file, the capabilities files and the served
+/// images to the running devices.
+pub(crate) struct Reloader {
+    args: Cli,
+    /// Settings in effect, with the configured instead of the bound ports.
+    settings: Settings,
+    devices: Vec<web::Data<AppState>>,
+}
+
+impl Reloader {
+    pub(crate) fn new(args: Cli, settings: Settings, devices: Vec<web::Data<AppState>>) -> Self {
+        Reloader {
+            args,
+            settings,
+            devices,
+        }
+    }
+
+    /// Watches the files until the process exits.
+    pub(crate) async fn watch(mut self) {
+        let mut files = self.fingerprint();
+        debug!(files = ?files.keys().collect::<Vec<_>>(), "Watching for configuration changes");
+        let mut interval = tokio::time::interval(POLL_INTERVAL);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            if self.fingerprint() == files {
+                continue;
+            }
+            tokio::time::sleep(SETTLE_DELAY).await;
+            let current = self.fingerprint();
+            let changed: Vec<&str> = current
+                .iter()
+                .filter(|(path, state)| files.get(*path) != Some(state))
+                .map(|(path, _)| path.as_str())
+                .collect();
+            self.reload(&changed);
+            // 新配置可能引用了其他文件；已知文件沿用加载前的状态，加载期间的修改在下一轮处理
+            files = self.fingerprint();
+            for (path, state) in files.iter_mut() {
+                if let Some(before) = current.get(path) {
+                    *state = *before;
+                }
+            }
+        }
+    }
+
+    fn fingerprint(&self) -> Fingerprint {
+        let mut paths: Vec<&str> = self
+            .args
+            .config_file
+            .iter()
+            .chain(&self.args.identity_file)
+            .map(String::as_str)
+            .collect();
+        for device in &self.settings.devices {
+            paths.extend(device.capabilities_file.as_deref());
+            paths.extend(device.images.iter().map(String::as_str));
+        }
+        paths
+            .into_iter()
+            .map(|path| {
+                // 与 NextDocument 相同，以 / 开头的路径也可以是相对路径
+                let metadata =
+                    std::fs::metadata(path).or_else(|e| match path.strip_prefix(['/', '\\']) {
+                        Some(relative) => std::fs::metadata(relative),
+                        None => Err(e),
+                    });
+                let state = metadata
+                    .ok()
+                    .and_then(|metadata| Some((metadata.modified().ok()?, metadata.len())));
+                (path.to_owned(), state)
+            })
+            .collect()
+    }
+
+    fn reload(&mut self, files: &[&str]) {
+        info!(files = ?files, "Configuration files changed, reloading");
+        // 先完整校验新配置，失败时继续使用当前配置
+        let settings = match config::reload(&self.args, &self.settings.devices) {
+            Ok(settings) => settings,
+            Err(e) => {
+                warn!(error = %e, "Reload rejected, keeping the current configuration");
+                return;
+            }
+        };
+        if settings.devices.len() != self.settings.devices.len() {
+            warn!(
+                devices = settings.devices.len(),
+                running = self.settings.devices.len(),
+                "Adding or removing devices needs a restart, keeping the current configuration"
+            );
+            return;
+        }
+        let restart = restart_required(&self.settings, &settings);
+        if !restart.is_empty() {
+            warn!(settings = ?restart, "Some changes need a restart and are ignored until then");
+        }
+
+        let mut unchanged = true;
+        for ((current, new), data) in self
+            .settings
+            .devices
+            .iter_mut()
+            .zip(settings.devices)
+            .zip(&self.devices)
+        {
+            // 只替换文件中变化的部分，控制台在运行时所做的其他修改保持不变
+            let changed = data.update_live(|live| apply(current, new, files, live));
+            if changed.is_empty() {
+                continue;
+            }
+            unchanged = false;
+            info!(device = %current.identity.friendly_name, changed = ?changed, "Configuration reloaded");
+            // 只有 TXT 记录、名称或主机名变化的服务会重新宣告
+            data.reannounce(Some(&current.discovery));
+        }
+        if unchanged && restart.is_empty() {
+            debug!("Reloaded configuration is unchanged");
+        }
+    }
+}
+
+// 用新配置中可以在运行时修改的部分更新设备和正在使用的设置，返回变化的部分
+fn apply(
+    current: &mut DeviceSettings,
+    new: DeviceSettings,
+    files: &[&str],
+    live: &mut LiveSettings,
+) -> Vec<&'static str> {
+    let mut changed = Vec::new();
+    if new.capabilities != current.capabilities
+        || new.capabilities_document != current.capabilities_document
+    {
+        changed.push("capabilities");
+        live.capabilities = new.capabilities.clone();
+        live.capabilities_document = new.capabilities_document.clone();
+        live.sources.adf_duplex = new.sources.adf_duplex;
+    }
+    if new.images != current.images {
+        changed.push("images");
+        live.images = new.images.clone();
+    } else if new
+        .images
+        .iter()
+        .any(|image| files.contains(&image.as_str()))
+    {
+        // 图片每次请求时读取，内容的变化已经生效，这里只需要报告
+        changed.push("images");
+    }
+    // adf_duplex 取自能力，随能力一起更新
+    if new.sources.platen_pages != current.sources.platen_pages {
+        live.sources.platen_pages = new.sources.platen_pages;
+    }
+    if new.sources.adf_pages != current.sources.adf_pages {
+        live.sources.adf_pages = new.sources.adf_pages;
+    }
+    if (new.sources.platen_pages, new.sources.adf_pages)
+        != (current.sources.platen_pages, current.sources.adf_pages)
+    {
+        changed.push("sources");
+    }
+    if new.faults != current.faults {
+        changed.push("faults");
+        live.faults = new.faults.clone();
+    }
+    let discovery = &current.discovery;
+    if new.discovery.hostname != discovery.hostname
+        || new.discovery.instance_name != discovery.instance_name
+        || new.discovery.txt != discovery.txt
+    {
+        changed.push("discovery");
+    }
+
+    current.capabilities = new.capabilities;
+    current.capabilities_document = new.capabilities_document;
+    current.capabilities_file = new.capabilities_file;
+    current.images = new.images;
+    current.sources = new.sources;
+    current.faults = new.faults;
+    current.discovery.hostname = new.discovery.hostname;
+    current.discovery.instance_name = new.discovery.instance_name;
+    current.discovery.txt = new.discovery.txt;
+    changed
+}
+
+// 监听的端口、路径和身份等在启动时就已确定，修改后需要重启
+fn restart_required(current: &Settings, new: &Settings) -> Vec<String> {
+    let mut restart = Vec::new();
+    let global = [
+        (
+            "network.bind_address",
+            current.bind_address != new.bind_address,
+        ),
+        ("network.interfaces", current.interfaces != new.interfaces),
+        (
+            "network.advertise_addrs",
+            current.advertise_addrs != new.advertise_addrs,
+        ),
+        (
+            "logging",
+            current.log_level != new.log_level
+                || current.log_format != new.log_format
+                || current.quiet != new.quiet,
+        ),
+        ("validation", current.validation != new.validation),
+        ("report", current.report != new.report),
+        ("shutdown", current.drain_timeout != new.drain_timeout),
+        ("tls", current.tls != new.tls),
+        ("auth", current.auth != new.auth),
+    ];
+    restart.extend(
+        global
+            .into_iter()
+            .filter(|(_, changed)| *changed)
+            .map(|(name, _)| name.to_owned()),
+    );
+
+    let multiple = current.devices.len() > 1;
+    for (index, (current, new)) in current.devices.iter().zip(&new.devices).enumerate() {
+        let device = [
+            ("profile", current.profile != new.profile),
+            ("port", current.port != new.port),
+            ("tls_port", current.tls_port != new.tls_port),
+            ("scope", current.scope != new.scope),
+            ("identity", current.identity != new.identity),
+            (
+                "discovery.mdns",
+                current.discovery.mdns != new.discovery.mdns,
+            ),
+            (
+                "discovery.ssdp",
+                current.discovery.ssdp != new.discovery.ssdp,
+            ),
+            ("discovery.wsd", current.discovery.wsd != new.discovery.wsd),
+        ];
+        restart.extend(
+            device
+                .into_iter()
+                .filter(|(_, changed)| *changed)
+                .map(|(name, _)| match multiple {
+                    true => format!("device {index}: {name}"),
+                    false => name.to_owned(),
+                }),
+        );
+    }
+    restart
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capabilities::ScannerCapabilities;
+    use clap::Parser;
+
+    const IDENTITY: &str = r#"
+        [identity]
+        uuid = "0d3f4c5e-1a2b-4c3d-8e9f-001122334455"
+        friendly_name = "Reload Scanner"
+        "#;
+
+    fn settings(toml: &str) -> Settings {
+        let args = Cli::parse_from(["escl-mock-server"]);
+        let file = toml::from_str(&format!("{IDENTITY}{toml}")).unwrap();
+        config::resolve(&args, Some(file)).unwrap()
+    }
+
+    fn device(toml: &str) -> DeviceSettings {
+        settings(toml).devices.remove(0)
+    }
+
+    #[test]
+    fn capabilities_reload_keeps_console_faults() {
+        let mut current = device("");
+        let mut live = LiveSettings::new(&current);
+        // 控制台在运行时所做的修改
+        live.faults.busy = true;
+        live.sources.adf_pages = 9;
+
+        let new = device("[capabilities]\nadf_duplex = false");
+        let changed = apply(&mut current, new.clone(), &[], &mut live);
+        assert_eq!(changed, ["capabilities"]);
+        assert_eq!(live.capabilities, new.capabilities);
+        assert!(!live.sources.adf_duplex);
+        assert!(live.faults.busy);
+        assert_eq!(live.sources.adf_pages, 9);
+        assert_eq!(current.capabilities, new.capabilities);
+    }
+
+    #[test]
+    fn faults_and_sources_reload_keeps_console_capabilities() {
+        let mut current = device("");
+        let mut live = LiveSettings::new(&current);
+        live.capabilities = ScannerCapabilities::builder().adf(false).build();
+
+        let new = device("[faults]\nscan_job_delay_ms = 250\n[sources]\nplaten_pages = 2");
+        let changed = apply(&mut current, new, &[], &mut live);
+        assert_eq!(changed, ["sources", "faults"]);
+        assert_eq!(live.faults.scan_job_delay, Duration::from_millis(250));
+        assert_eq!(live.sources.platen_pages, 2);
+        assert_eq!(live.sources.adf_pages, current.sources.adf_pages);
+        assert!(live.capabilities.adf.is_none());
+        assert_eq!(current.sources.platen_pages, 2);
+    }
+
+    #[test]
+    fn reports_discovery_and_changed_image_files() {
+        let mut current = device("[sources]\nimages = [\"res/example_image.jpg\"]");
+        let mut live = LiveSettings::new(&current);
+        let new = device(
+            "[sources]\nimages = [\"res/example_image.jpg\"]\n[discovery]\ntxt = { note = \"new\" }",
+        );
+        let changed = apply(&mut current, new, &["res/example_image.jpg"], &mut live);
+        assert_eq!(changed, ["images", "discovery"]);
+        assert_eq!(current.discovery.txt["note"], "new");
+
+        let unchanged = device("[sources]\nimages = [\"res/example_image.jpg\"]\n[discovery]\ntxt = { note = \"new\" }");
+        assert!(apply(&mut current, unchanged, &[], &mut live).is_empty());
+    }
+
+    #[test]
+    fn restart_required_for_listeners_and_identity() {
+        let current = settings("");
+        assert!(restart_required(&current, &settings("")).is_empty());
+        assert!(restart_required(&current, &settings("[faults]\nbusy = true")).is_empty());
+
+        let new = settings(
+            "[network]\nport = 9000\nscope = \"/scanner\"\n[tls]\nenabled = true\n[discovery]\nwsd = false",
+        );
+        assert_eq!(
+            restart_required(&current, &new),
+            ["tls", "port", "scope", "discovery.wsd"]
+        );
+
+        let renamed = IDENTITY.replace("Reload Scanner", "Renamed Scanner");
+        let args = Cli::parse_from(["escl-mock-server"]);
+        let new = config::resolve(&args, Some(toml::from_str(&renamed).unwrap())).unwrap();
+        assert_eq!(restart_required(&current, &new), ["identity"]);
+    }
+
+    #[test]
+    fn restart_required_names_the_device() {
+        let devices = |second_port: u16| {
+            let args = Cli::parse_from(["escl-mock-server"]);
+            let toml = format!(
+                "[[device]]\nport = 9001\nidentity = {{ uuid = \"0d3f4c5e-1a2b-4c3d-8e9f-001122334455\" }}\n\
+                 [[device]]\nport = {second_port}\nidentity = {{ uuid = \"1d3f4c5e-1a2b-4c3d-8e9f-001122334455\" }}"
+            );
+            config::resolve(&args, Some(toml::from_str(&toml).unwrap())).unwrap()
+        };
+        assert_eq!(
+            restart_required(&devices(9002), &devices(9003)),
+            ["device 1: port"]
+        );
+    }
+}
@@ -0,0 +1,550 @@
+/*
+ *     Copyright (C) 2024-2025 Christian Nagel and contributors
+ *
+ *     This file is part of escl-mock-server.
+ *
+ *     escl-mock-server is free software: you can redistribute it and/or modify it under the terms of
+ *     the GNU General Public License as published by the Free Software Foundation, either
+ *     version 3 of the License, or (at your option) any later version.
+ *
+ *     escl-mock-server is distributed in the hope that it will be useful, but WITHOUT ANY
+ *     WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ *     FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ *     You should have received a copy of the GNU General Public License along with eSCLKt.
+ *     If not, see <https://www.gnu.org/licenses/>.
+ *
+ *     SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// Status polls closer together than this count as hammering the scanner.
+const RAPID_POLL_INTERVAL: Duration = Duration::from_millis(250);
+/// A few rapid polls are tolerated, e.g. when a client opens several views at once.
+const RAPID_POLLS_ALLOWED: u32 = 3;
+
+/// Something a client did, recorded by the handlers and attributed to the
+/// client by `LoggingMiddleware`.
+#[derive(Debug, Clone)]
+pub(crate) enum ClientEvent {
+    CapabilitiesFetched,
+    StatusPolled,
+    /// A ScanSettings document was posted, with the first schema violation if any.
+    TicketChecked(Option<String>),
+    JobCreated {
+        job: Uuid,
+        pages: u32,
+    },
+    PageRetrieved {
+        job: Uuid,
+    },
+    JobDeleted {
+        job: Uuid,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct ClientKey {
+    peer: String,
+    user_agent: String,
+}
+
+struct JobRecord {
+    pages: u32,
+    retrieved: u32,
+    deleted: bool,
+}
+
+impl JobRecord {
+    fn finished(&self) -> bool {
+        self.retrieved >= self.pages
+    }
+}
+
+#[derive(Default)]
+struct ClientSession {
+    requests: u64,
+    capabilities_fetched: bool,
+    posts_without_capabilities: u32,
+    status_polls: u32,
+    last_poll: Option<Instant>,
+    fastest_poll: Option<Duration>,
+    rapid_polls: u32,
+    tickets: u32,
+    invalid_tickets: u32,
+    first_ticket_error: Option<String>,
+    jobs: BTreeMap<Uuid, JobRecord>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Pass,
+    Fail,
+    Skip,
+}
+
+struct Check {
+    name: &'static str,
+    outcome: Outcome,
+    detail: String,
+}
+
+/// Per client record of the eSCL conversation, evaluated into pass/fail checks.
+///
+/// Clients are identified by peer address and User-Agent. Shared by all
+/// virtual scanners of the process.
+pub(crate) struct ConformanceReport {
+    enabled: bool,
+    sessions: Mutex<BTreeMap<ClientKey, ClientSession>>,
+}
+
+impl ConformanceReport {
+    pub(crate) fn new(enabled: bool) -> Self {
+        ConformanceReport {
+            enabled,
+            sessions: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub(crate) fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Adds the events of one request. Requests without events only count
+    /// for clients that already talked eSCL.
+    pub(crate) fn record(&self, peer: &str, user_agent: &str, events: Vec<ClientEvent>) {
+        if !self.enabled {
+            return;
+        }
+        let mut sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+        let key = ClientKey {
+            peer: peer.to_owned(),
+            user_agent: user_agent.to_owned(),
+        };
+        if events.is_empty() {
+            if let Some(session) = sessions.get_mut(&key) {
+                session.requests += 1;
+            }
+            return;
+        }
+
+        let session = sessions.entry(key).or_default();
+        session.requests += 1;
+        for event in events {
+            match event {
+                ClientEvent::CapabilitiesFetched => session.capabilities_fetched = true,
+                ClientEvent::StatusPolled => {
+                    let now = Instant::now();
+                    if let Some(last) = session.last_poll {
+                        let interval = now - last;
+                        if interval < RAPID_POLL_INTERVAL {
+                            session.rapid_polls += 1;
+                        }
+                        session.fastest_poll = Some(
+                            session
+                                .fastest_poll
+                                .map_or(interval, |fastest| fastest.min(interval)),
+                        );
+                    }
+                    session.last_poll = Some(now);
+                    session.status_polls += 1;
+                }
+                ClientEvent::TicketChecked(error) => {
+                    session.tickets += 1;
+                    if !session.capabilities_fetched {
+                        session.posts_without_capabilities += 1;
+                    }
+                    if let Some(error) = error {
+                        session.invalid_tickets += 1;
+                        session.first_ticket_error.get_or_insert(error);
+                    }
+                }
+                ClientEvent::JobCreated { job, pages } => {
+                    session.jobs.insert(
+                        job,
+                        JobRecord {
+                            pages,
+                            retrieved: 0,
+                            deleted: false,
+                        },
+                    );
+                }
+                ClientEvent::PageRetrieved { job } => {
+                    if let Some(record) = session.jobs.get_mut(&job) {
+                        record.retrieved += 1;
+                    }
+                }
+                ClientEvent::JobDeleted { job } => {
+                    if let Some(record) = session.jobs.get_mut(&job) {
+                        record.deleted = true;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Plain text report of all clients seen so far.
+    pub(crate) fn render(&self) -> String {
+        let sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+        let mut report = String::from("eSCL client conformance report\n");
+        if sessions.is_empty() {
+            report.push_str("\nNo eSCL clients seen.\n");
+            return report;
+        }
+
+        for (key, session) in sessions.iter() {
+            let checks = session.checks();
+            let failed = checks
+                .iter()
+                .any(|check| matches!(check.outcome, Outcome::Fail));
+            let _ = writeln!(
+                report,
+                "\n{} {:?} ({} requests, {} jobs): {}",
+                key.peer,
+                key.user_agent,
+                session.requests,
+                session.jobs.len(),
+                if failed { "FAIL" } else { "PASS" }
+            );
+            for check in checks {
+                let outcome = match check.outcome {
+                    Outcome::Pass => "PASS",
+                    Outcome::Fail => "FAIL",
+                    Outcome::Skip => "SKIP",
+                };
+                let _ = writeln!(report, "  {outcome}  {:<28} {}", check.name, check.detail);
+            }
+        }
+        report
+    }
+
+    /// Number of clients with at least one failed check.
+    pub(crate) fn failed_clients(&self) -> usize {
+        let sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+        sessions
+            .values()
+            .filter(|session| {
+                session
+                    .checks()
+                    .iter()
+                    .any(|check| matches!(check.outcome, Outcome::Fail))
+            })
+            .count()
+    }
+}
+
+impl ClientSession {
+    fn checks(&self) -> Vec<Check> {
+        let check = |name, outcome, detail: String| Check {
+            name,
+            outcome,
+            detail,
+        };
+        let mut checks = Vec::with_capacity(5);
+
+        checks.push(match (self.tickets, self.posts_without_capabilities) {
+            (0, _) => check(
+                "capabilities before scan",
+                Outcome::Skip,
+                "no scan tickets sent".to_owned(),
+            ),
+            (tickets, 0) => check(
+                "capabilities before scan",
+                Outcome::Pass,
+                format!("fetched before all {tickets} scan tickets"),
+            ),
+            (tickets, missing) => check(
+                "capabilities before scan",
+                Outcome::Fail,
+                format!(
+                    "{missing} of {tickets} scan tickets sent without fetching ScannerCapabilities"
+                ),
+            ),
+        });
+
+        let fastest = self.fastest_poll.map_or_else(String::new, |fastest| {
+            format!(", fastest interval {fastest:.2?}")
+        });
+        checks.push(if self.status_polls == 0 {
+            check(
+                "status polling",
+                Outcome::Skip,
+                "ScannerStatus never polled".to_owned(),
+            )
+        } else if self.rapid_polls > RAPID_POLLS_ALLOWED {
+            check(
+                "status polling",
+                Outcome::Fail,
+                format!(
+                    "{} polls, {} less than {RAPID_POLL_INTERVAL:?} apart{fastest}",
+                    self.status_polls, self.rapid_polls
+                ),
+            )
+        } else {
+            check(
+                "status polling",
+                Outcome::Pass,
+                format!("{} polls{fastest}", self.status_polls),
+            )
+        });
+
+        checks.push(match (self.tickets, &self.first_ticket_error) {
+            (0, _) => check(
+                "valid ScanSettings",
+                Outcome::Skip,
+                "no ScanSettings sent".to_owned(),
+            ),
+            (tickets, None) => check(
+                "valid ScanSettings",
+                Outcome::Pass,
+                format!("{tickets} valid"),
+            ),
+            (tickets, Some(error)) => check(
+                "valid ScanSettings",
+                Outcome::Fail,
+                format!(
+                    "{} of {tickets} invalid, first: {error}",
+                    self.invalid_tickets
+                ),
+            ),
+        });
+
+        let leaked: Vec<String> = self
+            .jobs
+            .iter()
+            .filter(|(_, job)| !job.finished() && !job.deleted)
+            .map(|(uuid, job)| format!("{uuid} ({}/{} pages)", job.retrieved, job.pages))
+            .collect();
+        checks.push(if self.jobs.is_empty() {
+            check(
+                "all pages drained",
+                Outcome::Skip,
+                "no scan jobs created".to_owned(),
+            )
+        } else if leaked.is_empty() {
+            check(
+                "all pages drained",
+                Outcome::Pass,
+                "every job was read to the end or canceled".to_owned(),
+            )
+        } else {
+            check(
+                "all pages drained",
+                Outcome::Fail,
+                format!(
+                    "{} jobs left with pages: {}",
+                    leaked.len(),
+                    leaked.join(", ")
+                ),
+            )
+        });
+
+        let finished = self.jobs.values().filter(|job| job.finished()).count();
+        let undeleted: Vec<String> = self
+            .jobs
+            .iter()
+            .filter(|(_, job)| job.finished() && !job.deleted)
+            .map(|(uuid, _)| uuid.to_string())
+            .collect();
+        checks.push(if finished == 0 {
+            check(
+                "finished jobs deleted",
+                Outcome::Skip,
+                "no finished jobs".to_owned(),
+            )
+        } else if undeleted.is_empty() {
+            check(
+                "finished jobs deleted",
+                Outcome::Pass,
+                format!("{finished} finished jobs deleted"),
+            )
+        } else {
+            check(
+                "finished jobs deleted",
+                Outcome::Fail,
+                format!(
+                    "{} of {finished} finished jobs not deleted: {}",
+                    undeleted.len(),
+                    undeleted.join(", ")
+                ),
+            )
+        });
+
+        checks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PEER: &str = "192.0.2.10:50000";
+    const AGENT: &str = "sane-airscan/0.99";
+
+    fn record(report: &ConformanceReport, events: Vec<ClientEvent>) {
+        report.record(PEER, AGENT, events);
+    }
+
+    fn outcomes(report: &ConformanceReport) -> Vec<(&'static str, Outcome)> {
+        let sessions = report.sessions.lock().unwrap();
+        let [session] = sessions.values().collect::<Vec<_>>()[..] else {
+            panic!("expected one client");
+        };
+        session
+            .checks()
+            .into_iter()
+            .map(|check| (check.name, check.outcome))
+            .collect()
+    }
+
+    fn outcome(report: &ConformanceReport, name: &str) -> Outcome {
+        outcomes(report)
+            .into_iter()
+            .find(|(check, _)| *check == name)
+            .map(|(_, outcome)| outcome)
+            .unwrap()
+    }
+
+    #[test]
+    fn disabled_report_records_nothing() {
+        let report = ConformanceReport::new(false);
+        record(&report, vec![ClientEvent::StatusPolled]);
+        assert!(report.sessions.lock().unwrap().is_empty());
+        assert_eq!(report.failed_clients(), 0);
+    }
+
+    #[test]
+    fn well_behaved_client_passes() {
+        let report = ConformanceReport::new(true);
+        let job = Uuid::new_v4();
+        record(&report, vec![ClientEvent::CapabilitiesFetched]);
+        record(&report, vec![ClientEvent::StatusPolled]);
+        record(
+            &report,
+            vec![
+                ClientEvent::TicketChecked(None),
+                ClientEvent::JobCreated { job, pages: 2 },
+            ],
+        );
+        record(&report, vec![ClientEvent::PageRetrieved { job }]);
+        record(&report, vec![ClientEvent::PageRetrieved { job }]);
+        record(&report, vec![ClientEvent::JobDeleted { job }]);
+
+        assert_eq!(
+            outcomes(&report),
+            [
+                ("capabilities before scan", Outcome::Pass),
+                ("status polling", Outcome::Pass),
+                ("valid ScanSettings", Outcome::Pass),
+                ("all pages drained", Outcome::Pass),
+                ("finished jobs deleted", Outcome::Pass),
+            ]
+        );
+        assert_eq!(report.failed_clients(), 0);
+    }
+
+    #[test]
+    fn checks_without_events_are_skipped() {
+        let report = ConformanceReport::new(true);
+        record(&report, vec![ClientEvent::CapabilitiesFetched]);
+        // 没有事件的请求只计入已经出现过的客户端
+        report.record("192.0.2.99:1", AGENT, Vec::new());
+        assert!(outcomes(&report)
+            .iter()
+            .all(|(_, outcome)| *outcome == Outcome::Skip));
+        assert!(report.render().contains("(1 requests, 0 jobs): PASS"));
+    }
+
+    #[test]
+    fn tolerates_a_few_rapid_polls() {
+        let report = ConformanceReport::new(true);
+        for _ in 0..=RAPID_POLLS_ALLOWED {
+            record(&report, vec![ClientEvent::StatusPolled]);
+        }
+        assert_eq!(outcome(&report, "status polling"), Outcome::Pass);
+
+        record(&report, vec![ClientEvent::StatusPolled]);
+        assert_eq!(outcome(&report, "status polling"), Outcome::Fail);
+        assert_eq!(report.failed_clients(), 1);
+    }
+
+    #[test]
+    fn ticket_before_capabilities_fails() {
+        let report = ConformanceReport::new(true);
+        record(&report, vec![ClientEvent::TicketChecked(None)]);
+        record(&report, vec![ClientEvent::CapabilitiesFetched]);
+        record(&report, vec![ClientEvent::TicketChecked(None)]);
+        assert_eq!(outcome(&report, "capabilities before scan"), Outcome::Fail);
+        assert_eq!(outcome(&report, "valid ScanSettings"), Outcome::Pass);
+        assert!(report
+            .render()
+            .contains("1 of 2 scan tickets sent without fetching ScannerCapabilities"));
+    }
+
+    #[test]
+    fn invalid_ticket_fails_with_first_error() {
+        let report = ConformanceReport::new(true);
+        record(&report, vec![ClientEvent::CapabilitiesFetched]);
+        record(
+            &report,
+            vec![ClientEvent::TicketChecked(Some("first".to_owned()))],
+        );
+        record(
+            &report,
+            vec![ClientEvent::TicketChecked(Some("second".to_owned()))],
+        );
+        assert_eq!(outcome(&report, "valid ScanSettings"), Outcome::Fail);
+        assert!(report.render().contains("2 of 2 invalid, first: first"));
+    }
+
+    #[test]
+    fn leaked_job_fails_but_canceled_job_passes() {
+        let report = ConformanceReport::new(true);
+        let canceled = Uuid::new_v4();
+        record(
+            &report,
+            vec![ClientEvent::JobCreated {
+                job: canceled,
+                pages: 3,
+            }],
+        );
+        record(&report, vec![ClientEvent::PageRetrieved { job: canceled }]);
+        record(&report, vec![ClientEvent::JobDeleted { job: canceled }]);
+        assert_eq!(outcome(&report, "all pages drained"), Outcome::Pass);
+        assert_eq!(outcome(&report, "finished jobs deleted"), Outcome::Skip);
+
+        let leaked = Uuid::new_v4();
+        record(
+            &report,
+            vec![ClientEvent::JobCreated {
+                job: leaked,
+                pages: 3,
+            }],
+        );
+        record(&report, vec![ClientEvent::PageRetrieved { job: leaked }]);
+        assert_eq!(outcome(&report, "all pages drained"), Outcome::Fail);
+        assert!(report
+            .render()
+            .contains(&format!("1 jobs left with pages: {leaked} (1/3 pages)")));
+    }
+
+    #[test]
+    fn finished_job_must_be_deleted() {
+        let report = ConformanceReport::new(true);
+        let job = Uuid::new_v4();
+        record(&report, vec![ClientEvent::JobCreated { job, pages: 1 }]);
+        record(&report, vec![ClientEvent::PageRetrieved { job }]);
+        assert_eq!(outcome(&report, "all pages drained"), Outcome::Pass);
+        assert_eq!(outcome(&report, "finished jobs deleted"), Outcome::Fail);
+        assert_eq!(report.failed_clients(), 1);
+
+        record(&report, vec![ClientEvent::JobDeleted { job }]);
+        assert_eq!(outcome(&report, "finished jobs deleted"), Outcome::Pass);
+        assert_eq!(report.failed_clients(), 0);
+    }
+}
@@ -0,0 +1,708 @@
+/*
+ *     Copyright (C) 2024-2025 Christian Nagel and contributors
+ *
+ *     This file is part of escl-mock-server.
+ *
+ *     escl-mock-server is free software: you can redistribute it and/or modify it under the terms of
+ *     the GNU General Public License as published by the Free Software Foundation, either
+ *     version 3 of the License, or (at your option) any later version.
+ *
+ *     escl-mock-server is distributed in the hope that it will be useful, but WITHOUT ANY
+ *     WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ *     FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ *     You should have received a copy of the GNU General Public License along with eSCLKt.
+ *     If not, see <https://www.gnu.org/licenses/>.
+ *
+ *     SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+//! Structural rules of the eSCL and PWG Semantic Model schemas.
+//!
+//! Only the documents exchanged on the eSCL endpoints are covered:
+//! ScannerCapabilities, ScannerStatus, ScanSettings and ScanImageInfo.
+//! Element order isn't checked: the XSDs use `xs:sequence`, but real scanners
+//! and clients don't keep to it and nothing depends on it. Elements in other
+//! namespaces are vendor extensions and are skipped.
+
+use crate::capabilities::{ESCL_NS, PWG_NS};
+use crate::version::EsclVersion;
+use roxmltree::{Document, Node};
+use std::fmt::{Display, Formatter};
+
+/// Documents with schema rules, other XML is only checked for well-formedness.
+const DOCUMENT_ROOTS: [&str; 4] = [
+    "scan:ScannerCapabilities",
+    "scan:ScannerStatus",
+    "scan:ScanSettings",
+    "scan:ScanImageInfo",
+];
+
+/// A violation of the schema, located by line and element.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SchemaError {
+    pub line: u32,
+    pub element: Option<String>,
+    pub message: String,
+}
+
+impl Display for SchemaError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.element {
+            Some(element) => write!(f, "line {}: <{element}> {}", self.line, self.message),
+            None => write!(f, "line {}: {}", self.line, self.message),
+        }
+    }
+}
+
+/// Joins errors into one message, one error per line.
+pub(crate) fn describe(errors: &[SchemaError]) -> String {
+    errors
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Validates any document. Roots without rules only need to be well-formed.
+pub(crate) fn validate(content: &str) -> Result<(), Vec<SchemaError>> {
+    validate_root(content, None)
+}
+
+/// Validates a document that must have the root element `root`, e.g. `scan:ScanSettings`.
+pub(crate) fn validate_as(content: &str, root: &str) -> Result<(), Vec<SchemaError>> {
+    validate_root(content, Some(root))
+}
+
+fn validate_root(content: &str, expected_root: Option<&str>) -> Result<(), Vec<SchemaError>> {
+    let document = Document::parse(content).map_err(|e| {
+        vec![SchemaError {
+            line: e.pos().row,
+            element: None,
+            message: format!("not well-formed XML: {e}"),
+        }]
+    })?;
+    let root = document.root_element();
+    let mut validator = Validator {
+        document: &document,
+        errors: Vec::new(),
+    };
+
+    match (qualified_name(root), expected_root) {
+        (Some(name), Some(expected)) if name != expected => {
+            validator.error(
+                root,
+                format!("is not the expected root element <{expected}>"),
+            );
+        }
+        (None, Some(expected)) => {
+            validator.error(
+                root,
+                format!("is not the expected root element <{expected}>, check its namespace"),
+            );
+        }
+        (Some(name), _) if DOCUMENT_ROOTS.contains(&name.as_str()) => {
+            validator.check(root, &name);
+        }
+        _ => {}
+    }
+
+    if validator.errors.is_empty() {
+        Ok(())
+    } else {
+        Err(validator.errors)
+    }
+}
+
+// 把命名空间映射为规范中使用的前缀，其他命名空间返回 None
+fn qualified_name(node: Node) -> Option<String> {
+    let prefix = match node.tag_name().namespace() {
+        Some(ESCL_NS) => "scan",
+        Some(PWG_NS) => "pwg",
+        _ => return None,
+    };
+    Some(format!("{prefix}:{}", node.tag_name().name()))
+}
+
+enum Occurs {
+    Optional,
+    Required,
+    Repeated,
+    OneOrMore,
+}
+
+struct Child(&'static str, Occurs);
+
+/// Expected content of an element.
+enum Content {
+    /// Child elements in any order.
+    Elements(&'static [Child]),
+    Text,
+    NonNegativeInteger,
+    Boolean,
+    Version,
+    /// Type name and allowed values.
+    Enumeration(&'static str, &'static [&'static str]),
+    /// Vendor specific content that isn't checked.
+    Any,
+}
+
+use Occurs::{OneOrMore, Optional, Repeated, Required};
+
+const COLOR_MODES: Content = Content::Enumeration(
+    "scan:ColorModeType",
+    &[
+        "BlackAndWhite1",
+        "Grayscale8",
+        "Grayscale16",
+        "RGB24",
+        "RGB48",
+    ],
+);
+const INTENTS: Content = Content::Enumeration(
+    "scan:IntentType",
+    &[
+        "Document",
+        "TextAndGraphic",
+        "Photo",
+        "Preview",
+        "Object",
+        "BusinessCard",
+    ],
+);
+const CCD_CHANNELS: Content = Content::Enumeration(
+    "scan:CcdChannelType",
+    &["Red", "Green", "Blue", "NTSC", "GrayCcd", "GrayCcdEmulated"],
+);
+const BINARY_RENDERINGS: Content =
+    Content::Enumeration("scan:BinaryRenderingType", &["Halftone", "Threshold"]);
+const CONTENT_TYPES: Content = Content::Enumeration(
+    "pwg:ContentTypeType",
+    &[
+        "Auto",
+        "Text",
+        "Photo",
+        "TextAndPhoto",
+        "LineArt",
+        "Magazine",
+        "Halftone",
+    ],
+);
+const SCANNER_STATES: Content = Content::Enumeration(
+    "pwg:ScannerStateType",
+    &["Idle", "Processing", "Testing", "Stopped", "Down"],
+);
+
+const INPUT_CAPS: Content = Content::Elements(&[
+    Child("scan:MinWidth", Optional),
+    Child("scan:MaxWidth", Required),
+    Child("scan:MinHeight", Optional),
+    Child("scan:MaxHeight", Required),
+    Child("scan:MaxXOffset", Optional),
+    Child("scan:MaxYOffset", Optional),
+    Child("scan:MaxScanRegions", Optional),
+    Child("scan:SettingProfiles", Required),
+    Child("scan:SupportedIntents", Optional),
+    Child("scan:EdgeAutoDetection", Optional),
+    Child("scan:MaxOpticalXResolution", Optional),
+    Child("scan:MaxOpticalYResolution", Optional),
+    Child("scan:RiskyLeftMargin", Optional),
+    Child("scan:RiskyRightMargin", Optional),
+    Child("scan:RiskyTopMargin", Optional),
+    Child("scan:RiskyBottomMargin", Optional),
+    Child("scan:MaxPhysicalWidth", Optional),
+    Child("scan:MaxPhysicalHeight", Optional),
+    Child("scan:FeedDirections", Optional),
+]);
+
+const RANGE: Content = Content::Elements(&[
+    Child("scan:Min", Required),
+    Child("scan:Max", Required),
+    Child("scan:Normal", Required),
+    Child("scan:Step", Optional),
+]);
+
+// 元素名称 (带规范前缀) 到内容类型的映射
+fn content(name: &str) -> Content {
+    match name {
+        "scan:ScannerCapabilities" => Content::Elements(&[
+            Child("pwg:Version", Required),
+            Child("pwg:MakeAndModel", Optional),
+            Child("pwg:SerialNumber", Optional),
+            Child("scan:UUID", Optional),
+            Child("scan:AdminURI", Optional),
+            Child("scan:IconURI", Optional),
+            Child("scan:Manufacturer", Optional),
+            Child("scan:Certifications", Optional),
+            Child("scan:SettingProfiles", Optional),
+            Child("scan:Platen", Optional),
+            Child("scan:Camera", Optional),
+            Child("scan:Adf", Optional),
+            Child("scan:BrightnessSupport", Optional),
+            Child("scan:CompressionFactorSupport", Optional),
+            Child("scan:ContrastSupport", Optional),
+            Child("scan:GammaSupport", Optional),
+            Child("scan:HighlightSupport", Optional),
+            Child("scan:NoiseRemovalSupport", Optional),
+            Child("scan:ShadowSupport", Optional),
+            Child("scan:SharpenSupport", Optional),
+            Child("scan:ThresholdSupport", Optional),
+            Child("scan:BlankPageDetection", Optional),
+            Child("scan:BlankPageDetectionAndRemoval", Optional),
+            Child("scan:StoredJobRequestSupport", Optional),
+            Child("scan:eSCLConfigCap", Optional),
+        ]),
+        "scan:Certifications" => Content::Elements(&[Child("scan:Certification", Repeated)]),
+        "scan:Certification" => Content::Elements(&[
+            Child("scan:Name", Required),
+            Child("scan:Version", Optional),
+        ]),
+        "scan:Platen" => Content::Elements(&[Child("scan:PlatenInputCaps", Required)]),
+        "scan:Camera" => Content::Elements(&[Child("scan:CameraInputCaps", Required)]),
+        "scan:Adf" => Content::Elements(&[
+            Child("scan:AdfSimplexInputCaps", Required),
+            Child("scan:AdfDuplexInputCaps", Optional),
+            Child("scan:FeederCapacity", Optional),
+            Child("scan:AdfOptions", Optional),
+            Child("scan:Justification", Optional),
+        ]),
+        "scan:PlatenInputCaps"
+        | "scan:CameraInputCaps"
+        | "scan:AdfSimplexInputCaps"
+        | "scan:AdfDuplexInputCaps" => INPUT_CAPS,
+        "scan:SettingProfiles" => Content::Elements(&[Child("scan:SettingProfile", OneOrMore)]),
+        "scan:SettingProfile" => Content::Elements(&[
+            Child("scan:ColorModes", Required),
+            Child("scan:ContentTypes", Optional),
+            Child("scan:DocumentFormats", Optional),
+            Child("scan:SupportedResolutions", Required),
+            Child("scan:ColorSpaces", Optional),
+            Child("scan:CcdChannels", Optional),
+            Child("scan:BinaryRenderings", Optional),
+        ]),
+        "scan:ColorModes" => Content::Elements(&[Child("scan:ColorMode", OneOrMore)]),
+        "scan:ContentTypes" => Content::Elements(&[Child("pwg:ContentType", OneOrMore)]),
+        "scan:DocumentFormats" => Content::Elements(&[
+            Child("pwg:DocumentFormat", Repeated),
+            Child("scan:DocumentFormatExt", Repeated),
+        ]),
+        "scan:SupportedResolutions" => Content::Elements(&[
+            Child("scan:DiscreteResolutions", Optional),
+            Child("scan:ResolutionRange", Optional),
+        ]),
+        "scan:DiscreteResolutions" => {
+            Content::Elements(&[Child("scan:DiscreteResolution", OneOrMore)])
+        }
+        "scan:DiscreteResolution" => Content::Elements(&[
+            Child("scan:XResolution", Required),
+            Child("scan:YResolution", Required),
+        ]),
+        "scan:ResolutionRange" => Content::Elements(&[
+            Child("scan:XResolutionRange", Required),
+            Child("scan:YResolutionRange", Required),
+        ]),
+        "scan:XResolutionRange"
+        | "scan:YResolutionRange"
+        | "scan:BrightnessSupport"
+        | "scan:CompressionFactorSupport"
+        | "scan:ContrastSupport"
+        | "scan:GammaSupport"
+        | "scan:HighlightSupport"
+        | "scan:NoiseRemovalSupport"
+        | "scan:ShadowSupport"
+        | "scan:SharpenSupport"
+        | "scan:ThresholdSupport" => RANGE,
+        "scan:ColorSpaces" => Content::Elements(&[Child("scan:ColorSpace", OneOrMore)]),
+        "scan:CcdChannels" => Content::Elements(&[Child("scan:CcdChannel", OneOrMore)]),
+        "scan:BinaryRenderings" => Content::Elements(&[Child("scan:BinaryRendering", OneOrMore)]),
+        "scan:SupportedIntents" => Content::Elements(&[Child("scan:Intent", OneOrMore)]),
+        "scan:EdgeAutoDetection" => Content::Elements(&[Child("scan:SupportedEdge", OneOrMore)]),
+        "scan:FeedDirections" => Content::Elements(&[Child("scan:FeedDirection", OneOrMore)]),
+        "scan:AdfOptions" => Content::Elements(&[Child("scan:AdfOption", OneOrMore)]),
+        "scan:Justification" => Content::Elements(&[
+            Child("pwg:XImagePosition", Optional),
+            Child("pwg:YImagePosition", Optional),
+        ]),
+        "scan:StoredJobRequestSupport" => Content::Elements(&[
+            Child("scan:MaxStoredjobRequests", Required),
+            Child("scan:TimeoutInSeconds", Required),
+        ]),
+
+        "scan:ScannerStatus" => Content::Elements(&[
+            Child("pwg:Version", Required),
+            Child("pwg:State", Required),
+            Child("pwg:StateReasons", Optional),
+            Child("scan:ScannerState", Optional),
+            Child("scan:ScannerStateReasons", Optional),
+            Child("scan:AdfState", Optional),
+            Child("scan:Jobs", Optional),
+        ]),
+        "pwg:StateReasons" => Content::Elements(&[Child("pwg:StateReason", OneOrMore)]),
+        "scan:ScannerStateReasons" => {
+            Content::Elements(&[Child("scan:ScannerStateReason", OneOrMore)])
+        }
+        "scan:Jobs" => Content::Elements(&[Child("scan:JobInfo", Repeated)]),
+        "scan:JobInfo" => Content::Elements(&[
+            Child("pwg:JobUri", Required),
+            Child("pwg:JobUuid", Required),
+            Child("scan:Age", Optional),
+            Child("pwg:ImagesCompleted", Optional),
+            Child("pwg:ImagesToTransfer", Optional),
+            Child("scan:TransferRetryCount", Optional),
+            Child("pwg:JobState", Required),
+            Child("pwg:JobStateReasons", Optional),
+        ]),
+        "pwg:JobStateReasons" => Content::Elements(&[Child("pwg:JobStateReason", OneOrMore)]),
+
+        "scan:ScanSettings" => Content::Elements(&[
+            Child("pwg:Version", Required),
+            Child("scan:Intent", Optional),
+            Child("pwg:ScanRegions", Optional),
+            Child("pwg:DocumentFormat", Optional),
+            Child("scan:DocumentFormatExt", Optional),
+            Child("pwg:ContentType", Optional),
+            Child("scan:ContentType", Optional),
+            Child("pwg:InputSource", Optional),
+            Child("scan:XResolution", Optional),
+            Child("scan:YResolution", Optional),
+            Child("scan:ColorMode", Optional),
+            Child("scan:ColorSpace", Optional),
+            Child("scan:CcdChannel", Optional),
+            Child("scan:BinaryRendering", Optional),
+            Child("scan:Duplex", Optional),
+            Child("scan:FeedDirection", Optional),
+            Child("scan:Brightness", Optional),
+            Child("scan:CompressionFactor", Optional),
+            Child("scan:Contrast", Optional),
+            Child("scan:Gamma", Optional),
+            Child("scan:Highlight", Optional),
+            Child("scan:NoiseRemoval", Optional),
+            Child("scan:Shadow", Optional),
+            Child("scan:Sharpen", Optional),
+            Child("scan:Threshold", Optional),
+            Child("scan:BlankPageDetection", Optional),
+            Child("scan:BlankPageDetectionAndRemoval", Optional),
+        ]),
+        "pwg:ScanRegions" => Content::Elements(&[Child("pwg:ScanRegion", OneOrMore)]),
+        "pwg:ScanRegion" => Content::Elements(&[
+            Child("pwg:Height", Required),
+            Child("pwg:Width", Required),
+            Child("pwg:XOffset", Optional),
+            Child("pwg:YOffset", Optional),
+            Child("pwg:ContentRegionUnits", Required),
+        ]),
+
+        "scan:ScanImageInfo" => Content::Elements(&[
+            Child("pwg:JobUri", Required),
+            Child("pwg:JobUuid", Required),
+            Child("scan:ActualWidth", Optional),
+            Child("scan:ActualHeight", Optional),
+            Child("scan:ActualBytesPerLine", Optional),
+            Child("scan:BlankPageDetected", Optional),
+        ]),
+
+        "pwg:Version" => Content::Version,
+        "scan:MinWidth"
+        | "scan:MaxWidth"
+        | "scan:MinHeight"
+        | "scan:MaxHeight"
+        | "scan:MaxXOffset"
+        | "scan:MaxYOffset"
+        | "scan:MaxScanRegions"
+        | "scan:MaxOpticalXResolution"
+        | "scan:MaxOpticalYResolution"
+        | "scan:RiskyLeftMargin"
+        | "scan:RiskyRightMargin"
+        | "scan:RiskyTopMargin"
+        | "scan:RiskyBottomMargin"
+        | "scan:MaxPhysicalWidth"
+        | "scan:MaxPhysicalHeight"
+        | "scan:XResolution"
+        | "scan:YResolution"
+        | "scan:Min"
+        | "scan:Max"
+        | "scan:Normal"
+        | "scan:Step"
+        | "scan:FeederCapacity"
+        | "scan:MaxStoredjobRequests"
+        | "scan:TimeoutInSeconds"
+        | "scan:Age"
+        | "pwg:ImagesCompleted"
+        | "pwg:ImagesToTransfer"
+        | "scan:TransferRetryCount"
+        | "pwg:Height"
+        | "pwg:Width"
+        | "pwg:XOffset"
+        | "pwg:YOffset"
+        | "scan:Brightness"
+        | "scan:CompressionFactor"
+        | "scan:Contrast"
+        | "scan:Gamma"
+        | "scan:Highlight"
+        | "scan:NoiseRemoval"
+        | "scan:Shadow"
+        | "scan:Sharpen"
+        | "scan:Threshold"
+        | "scan:ActualWidth"
+        | "scan:ActualHeight"
+        | "scan:ActualBytesPerLine" => Content::NonNegativeInteger,
+        "scan:Duplex"
+        | "scan:BlankPageDetection"
+        | "scan:BlankPageDetectionAndRemoval"
+        | "scan:BlankPageDetected" => Content::Boolean,
+        "scan:ColorMode" => COLOR_MODES,
+        "scan:Intent" => INTENTS,
+        "scan:CcdChannel" => CCD_CHANNELS,
+        "scan:BinaryRendering" => BINARY_RENDERINGS,
+        "pwg:ContentType" | "scan:ContentType" => CONTENT_TYPES,
+        "pwg:State" | "scan:ScannerState" => SCANNER_STATES,
+        "scan:SupportedEdge" => Content::Enumeration(
+            "scan:SupportedEdgeType",
+            &["TopEdge", "LeftEdge", "BottomEdge", "RightEdge"],
+        ),
+        "scan:FeedDirection" => {
+            Content::Enumeration("scan:FeedDirectionType", &["LongEdgeFeed", "ShortEdgeFeed"])
+        }
+        "scan:AdfOption" => Content::Enumeration(
+            "scan:AdfOptionType",
+            &["DetectPaperLoaded", "SelectSinglePage", "Duplex"],
+        ),
+        "pwg:InputSource" => {
+            Content::Enumeration("pwg:InputSourceType", &["Platen", "Feeder", "Camera"])
+        }
+        "pwg:ContentRegionUnits" => Content::Enumeration(
+            "pwg:ContentRegionUnitsType",
+            &["escl:ThreeHundredthsOfInches"],
+        ),
+        "scan:AdfState" => Content::Enumeration(
+            "scan:AdfStateType",
+            &[
+                "ScannerAdfProcessing",
+                "ScannerAdfEmpty",
+                "ScannerAdfJam",
+                "ScannerAdfLoaded",
+                "ScannerAdfMispick",
+                "ScannerAdfHatchOpen",
+                "ScannerAdfDuplexPageTooShort",
+                "ScannerAdfDuplexPageTooLong",
+                "ScannerAdfMultipickDetected",
+                "ScannerAdfInputTrayFailed",
+                "ScannerAdfInputTrayOverloaded",
+            ],
+        ),
+        "pwg:JobState" => Content::Enumeration(
+            "pwg:JobStateType",
+            &[
+                "Pending",
+                "PendingHeld",
+                "Processing",
+                "ProcessingStopped",
+                "Canceled",
+                "Aborted",
+                "Completed",
+            ],
+        ),
+        "scan:eSCLConfigCap" => Content::Any,
+        _ => Content::Text,
+    }
+}
+
+struct Validator<'a, 'input> {
+    document: &'a Document<'input>,
+    errors: Vec<SchemaError>,
+}
+
+impl Validator<'_, '_> {
+    fn error(&mut self, node: Node, message: String) {
+        let position = self.document.text_pos_at(node.range().start);
+        self.errors.push(SchemaError {
+            line: position.row,
+            element: Some(
+                qualified_name(node).unwrap_or_else(|| node.tag_name().name().to_owned()),
+            ),
+            message,
+        });
+    }
+
+    fn check(&mut self, node: Node, name: &str) {
+        match content(name) {
+            Content::Elements(children) => self.check_children(node, name, children),
+            Content::Any => {}
+            Content::Text => self.check_simple(node, "xs:string", |_| true),
+            Content::NonNegativeInteger => {
+                self.check_simple(node, "xs:nonNegativeInteger", |text| {
+                    text.parse::<u64>().is_ok()
+                })
+            }
+            Content::Boolean => self.check_simple(node, "xs:boolean", |text| {
+                matches!(text, "true" | "false" | "1" | "0")
+            }),
+            Content::Version => self.check_simple(node, "pwg:VersionType (2.0 to 2.97)", |text| {
+                text.parse::<EsclVersion>().is_ok()
+            }),
+            Content::Enumeration(type_name, values) => {
+                let expected = format!("{type_name} ({})", values.join(", "));
+                self.check_simple(node, &expected, |text| values.contains(&text));
+            }
+        }
+    }
+
+    fn check_simple(&mut self, node: Node, expected: &str, valid: impl Fn(&str) -> bool) {
+        if node.children().any(|child| child.is_element()) {
+            self.error(node, format!("expected {expected}, found child elements"));
+            return;
+        }
+        let text = node.text().unwrap_or_default().trim();
+        if !valid(text) {
+            self.error(node, format!("expected {expected}, found {text:?}"));
+        }
+    }
+
+    fn check_children(&mut self, node: Node, name: &str, allowed: &[Child]) {
+        let mut counts = vec![0usize; allowed.len()];
+        for child in node.children() {
+            if child.is_text() && !child.text().unwrap_or_default().trim().is_empty() {
+                self.error(node, "must only contain elements, found text".to_owned());
+                continue;
+            }
+            if !child.is_element() {
+                continue;
+            }
+            let Some(child_name) = qualified_name(child) else {
+                if child.tag_name().namespace().is_none() {
+                    self.error(child, "is not in the eSCL or PWG namespace".to_owned());
+                }
+                continue;
+            };
+            match allowed
+                .iter()
+                .position(|Child(allowed, _)| *allowed == child_name)
+            {
+                Some(index) => {
+                    counts[index] += 1;
+                    self.check(child, &child_name);
+                }
+                None => self.error(child, format!("is not allowed in <{name}>")),
+            }
+        }
+
+        for (Child(child_name, occurs), count) in allowed.iter().zip(counts) {
+            match occurs {
+                Required | OneOrMore if count == 0 => {
+                    self.error(node, format!("is missing required <{child_name}>"));
+                }
+                Optional | Required if count > 1 => {
+                    self.error(
+                        node,
+                        format!("allows only one <{child_name}>, found {count}"),
+                    );
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(children: &str) -> String {
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<scan:ScannerStatus xmlns:scan="{ESCL_NS}" xmlns:pwg="{PWG_NS}">
+{children}
+</scan:ScannerStatus>"#
+        )
+    }
+
+    fn settings(children: &str) -> String {
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<scan:ScanSettings xmlns:scan="{ESCL_NS}" xmlns:pwg="{PWG_NS}">
+{children}
+</scan:ScanSettings>"#
+        )
+    }
+
+    fn single_error(content: &str) -> SchemaError {
+        let errors = validate(content).expect_err("document should be invalid");
+        assert_eq!(errors.len(), 1, "{}", describe(&errors));
+        errors.into_iter().next().unwrap()
+    }
+
+    #[test]
+    fn accepts_valid_documents() {
+        let status = status("<pwg:Version>2.63</pwg:Version>\n<pwg:State>Idle</pwg:State>");
+        assert_eq!(validate_as(&status, "scan:ScannerStatus"), Ok(()));
+        let settings = settings(
+            "<pwg:Version>2.0</pwg:Version>\n<scan:ColorMode>RGB24</scan:ColorMode>\n<pwg:InputSource>Platen</pwg:InputSource>",
+        );
+        assert_eq!(validate_as(&settings, "scan:ScanSettings"), Ok(()));
+    }
+
+    #[test]
+    fn accepts_any_element_order() {
+        let settings =
+            settings("<scan:ColorMode>Grayscale8</scan:ColorMode>\n<pwg:Version>2.6</pwg:Version>");
+        assert_eq!(validate(&settings), Ok(()));
+    }
+
+    #[test]
+    fn reports_missing_version() {
+        let error = single_error(&settings("<scan:ColorMode>RGB24</scan:ColorMode>"));
+        assert_eq!(error.line, 2);
+        assert_eq!(error.element.as_deref(), Some("scan:ScanSettings"));
+        assert_eq!(error.message, "is missing required <pwg:Version>");
+    }
+
+    #[test]
+    fn reports_bad_color_mode() {
+        let error = single_error(&settings(
+            "<pwg:Version>2.63</pwg:Version>\n<scan:ColorMode>RGB32</scan:ColorMode>",
+        ));
+        assert_eq!(error.line, 4);
+        assert_eq!(error.element.as_deref(), Some("scan:ColorMode"));
+        assert!(
+            error.message.contains("scan:ColorModeType"),
+            "{}",
+            error.message
+        );
+        assert!(
+            error.message.ends_with(r#"found "RGB32""#),
+            "{}",
+            error.message
+        );
+    }
+
+    #[test]
+    fn reports_duplicate_required_child() {
+        let error = single_error(&status(
+            "<pwg:Version>2.63</pwg:Version>\n<pwg:State>Idle</pwg:State>\n<pwg:State>Processing</pwg:State>",
+        ));
+        assert_eq!(error.line, 2);
+        assert_eq!(error.element.as_deref(), Some("scan:ScannerStatus"));
+        assert_eq!(error.message, "allows only one <pwg:State>, found 2");
+    }
+
+    #[test]
+    fn reports_wrong_root() {
+        let status = status("<pwg:Version>2.63</pwg:Version>\n<pwg:State>Idle</pwg:State>");
+        let errors =
+            validate_as(&status, "scan:ScanSettings").expect_err("root should be rejected");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 2);
+        assert_eq!(errors[0].element.as_deref(), Some("scan:ScannerStatus"));
+        assert_eq!(
+            errors[0].message,
+            "is not the expected root element <scan:ScanSettings>"
+        );
+    }
+
+    #[test]
+    fn reports_malformed_xml_by_line() {
+        let error = single_error("<ScanSettings>\n<unclosed>\n</ScanSettings>");
+        assert_eq!(error.line, 3);
+        assert_eq!(error.element, None);
+    }
+}
@@ -0,0 +1,352 @@
+/*
+ *     Copyright (C) 2024-2025 Christian Nagel and contributors
+ *
+ *     This file is part of escl-mock-server.
+ *
+ *     escl-mock-server is free software: you can redistribute it and/or modify it under the terms of
+ *     the GNU General Public License as published by the Free Software Foundation, either
+ *     version 3 of the License, or (at your option) any later version.
+ *
+ *     escl-mock-server is distributed in the hope that it will be useful, but WITHOUT ANY
+ *     WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ *     FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ *     You should have received a copy of the GNU General Public License along with eSCLKt.
+ *     If not, see <https://www.gnu.org/licenses/>.
+ *
+ *     SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+use crate::{network, DescribedDevice};
+use actix_web::http::header::HttpDate;
+use actix_web::rt::task::JoinHandle;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::net::UdpSocket;
+use tracing::{debug, info, warn};
+
+const SSDP_GROUP: Ipv4Addr = Ipv4Addr::new(239, 255, 255, 250);
+const SSDP_PORT: u16 = 1900;
+/// `CACHE-CONTROL: max-age` of announcements, in seconds.
+const MAX_AGE: u64 = 1800;
+/// NOTIFY ssdp:alive is repeated well before the announcements expire.
+const NOTIFY_INTERVAL: Duration = Duration::from_secs(MAX_AGE / 3);
+/// Upper bound of the random M-SEARCH response delay. UDA allows up to MX
+/// seconds, shorter delays keep tests fast.
+const MAX_RESPONSE_DELAY: Duration = Duration::from_secs(1);
+const DEVICE_TYPE: &str = "urn:schemas-upnp-org:device:Scanner:1";
+const SERVICE_TYPE: &str = "urn:schemas-hp-com:service:imaging:ScanService:1";
+
+// 每台设备的通知类型 (NT/ST) 和对应的 USN
+fn targets(device: &DescribedDevice) -> Vec<(String, String)> {
+    let uuid = format!("uuid:{}", device.uuid);
+    vec![
+        (
+            "upnp:rootdevice".to_owned(),
+            format!("{uuid}::upnp:rootdevice"),
+        ),
+        (uuid.clone(), uuid.clone()),
+        (DEVICE_TYPE.to_owned(), format!("{uuid}::{DEVICE_TYPE}")),
+        (SERVICE_TYPE.to_owned(), format!("{uuid}::{SERVICE_TYPE}")),
+    ]
+}
+
+/// SSDP responder on 239.255.255.250:1900: answers M-SEARCH and announces
+/// the devices with NOTIFY.
+pub(crate) struct Ssdp {
+    responder: Arc<Responder>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+struct Responder {
+    devices: Vec<DescribedDevice>,
+    socket: UdpSocket,
+    /// Interface addresses and a socket sending multicast from each of them.
+    senders: Vec<(Ipv4Addr, UdpSocket)>,
+    boot_id: u64,
+    server: String,
+}
+
+impl Ssdp {
+    /// Joins the SSDP group on the interfaces of the IPv4 `addresses` and
+    /// starts announcing.
+    pub(crate) fn start(devices: Vec<DescribedDevice>, addresses: &[IpAddr]) -> io::Result<Ssdp> {
+        let interfaces: Vec<Ipv4Addr> = addresses
+            .iter()
+            .filter_map(|address| match address {
+                IpAddr::V4(address) => Some(*address),
+                IpAddr::V6(_) => None,
+            })
+            .collect();
+        if interfaces.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "SSDP needs an advertised IPv4 address",
+            ));
+        }
+
+        let socket = network::multicast_socket(SSDP_PORT)?;
+        let senders = network::join_multicast_group(&socket, SSDP_GROUP, interfaces, 2, "SSDP");
+        if senders.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrNotAvailable,
+                "no interface could join the SSDP group",
+            ));
+        }
+
+        let responder = Arc::new(Responder {
+            devices,
+            socket: UdpSocket::from_std(socket)?,
+            senders,
+            boot_id: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(1, |since| since.as_secs()),
+            server: format!(
+                "{}/1.0 UPnP/1.1 escl-mock-server/{}",
+                std::env::consts::OS,
+                env!("CARGO_PKG_VERSION")
+            ),
+        });
+        info!(
+            devices = responder.devices.len(),
+            interfaces = ?responder.senders.iter().map(|(address, _)| address).collect::<Vec<_>>(),
+            "SSDP responder started"
+        );
+        let tasks = vec![
+            actix_web::rt::spawn(responder.clone().announce()),
+            actix_web::rt::spawn(responder.clone().respond()),
+        ];
+        Ok(Ssdp { responder, tasks })
+    }
+
+    /// Stops answering and sends NOTIFY ssdp:byebye for every device.
+    pub(crate) async fn stop(self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+        self.responder.notify("ssdp:byebye").await;
+        info!("SSDP devices withdrawn");
+    }
+}
+
+impl Responder {
+    async fn announce(self: Arc<Self>) {
+        loop {
+            self.notify("ssdp:alive").await;
+            actix_web::rt::time::sleep(NOTIFY_INTERVAL).await;
+        }
+    }
+
+    async fn notify(&self, nts: &str) {
+        for (address, sender) in &self.senders {
+            for device in &self.devices {
+                for (nt, usn) in targets(device) {
+                    let mut message = format!(
+                        "NOTIFY * HTTP/1.1\r\n\
+                         HOST: {SSDP_GROUP}:{SSDP_PORT}\r\n\
+                         NT: {nt}\r\n\
+                         NTS: {nts}\r\n\
+                         USN: {usn}\r\n\
+                         BOOTID.UPNP.ORG: {}\r\n\
+                         CONFIGID.UPNP.ORG: 1\r\n",
+                        self.boot_id
+                    );
+                    // byebye 不带 LOCATION 和缓存时间
+                    if nts == "ssdp:alive" {
+                        message.push_str(&format!(
+                            "CACHE-CONTROL: max-age={MAX_AGE}\r\nLOCATION: {}\r\nSERVER: {}\r\n",
+                            device.url((*address).into(), &format!("{}/device.xml", device.scope)),
+                            self.server
+                        ));
+                    }
+                    message.push_str("\r\n");
+                    if let Err(e) = sender
+                        .send_to(message.as_bytes(), (SSDP_GROUP, SSDP_PORT))
+                        .await
+                    {
+                        warn!(interface = %address, error = %e, nts, "Couldn't send SSDP NOTIFY");
+                    }
+                }
+            }
+            debug!(interface = %address, nts, "SSDP NOTIFY sent");
+        }
+    }
+
+    async fn respond(self: Arc<Self>) {
+        let mut buffer = vec![0; 8192];
+        let mut failures = 0;
+        loop {
+            let (length, peer) = match self.socket.recv_from(&mut buffer).await {
+                Ok(received) => {
+                    failures = 0;
+                    received
+                }
+                Err(e) => {
+                    warn!(error = %e, "Couldn't receive SSDP message");
+                    network::receive_backoff(failures).await;
+                    failures += 1;
+                    continue;
+                }
+            };
+            let Some(search) = Search::parse(&buffer[..length]) else {
+                continue;
+            };
+            let SocketAddr::V4(peer_v4) = peer else {
+                continue;
+            };
+            let interfaces: Vec<Ipv4Addr> =
+                self.senders.iter().map(|(address, _)| *address).collect();
+            let Some(address) = network::closest_ipv4(&interfaces, *peer_v4.ip()) else {
+                continue;
+            };
+            let responses: Vec<String> = self
+                .devices
+                .iter()
+                .flat_map(|device| {
+                    targets(device)
+                        .into_iter()
+                        .filter(|(nt, _)| search.target == "ssdp:all" || *nt == search.target)
+                        .map(|(nt, usn)| self.search_response(device, address, &nt, &usn))
+                })
+                .collect();
+            debug!(peer = %peer, st = %search.target, responses = responses.len(), "SSDP M-SEARCH");
+            if responses.is_empty() {
+                continue;
+            }
+
+            // 按 UDA 在 MX 秒内随机延迟，避免多个设备同时应答
+            let delay = search
+                .max_wait
+                .min(MAX_RESPONSE_DELAY)
+                .mul_f64(network::random_fraction());
+            let responder = self.clone();
+            actix_web::rt::spawn(async move {
+                actix_web::rt::time::sleep(delay).await;
+                for response in responses {
+                    if let Err(e) = responder.socket.send_to(response.as_bytes(), peer).await {
+                        warn!(peer = %peer, error = %e, "Couldn't answer SSDP M-SEARCH");
+                    }
+                }
+            });
+        }
+    }
+
+    fn search_response(
+        &self,
+        device: &DescribedDevice,
+        address: Ipv4Addr,
+        st: &str,
+        usn: &str,
+    ) -> String {
+        format!(
+            "HTTP/1.1 200 OK\r\n\
+             CACHE-CONTROL: max-age={MAX_AGE}\r\n\
+             DATE: {}\r\n\
+             EXT:\r\n\
+             LOCATION: {}\r\n\
+             SERVER: {}\r\n\
+             ST: {st}\r\n\
+             USN: {usn}\r\n\
+             BOOTID.UPNP.ORG: {}\r\n\
+             CONFIGID.UPNP.ORG: 1\r\n\r\n",
+            HttpDate::from(SystemTime::now()),
+            device.url(address.into(), &format!("{}/device.xml", device.scope)),
+            self.server,
+            self.boot_id
+        )
+    }
+}
+
+/// A parsed `M-SEARCH` request.
+struct Search {
+    target: String,
+    /// MX, zero for unicast searches.
+    max_wait: Duration,
+}
+
+impl Search {
+    fn parse(message: &[u8]) -> Option<Search> {
+        let message = std::str::from_utf8(message).ok()?;
+        let mut lines = message.lines();
+        if !lines
+            .next()?
+            .trim()
+            .eq_ignore_ascii_case("M-SEARCH * HTTP/1.1")
+        {
+            return None;
+        }
+        let mut target = None;
+        let mut max_wait = Duration::ZERO;
+        let mut discover = false;
+        for line in lines {
+            let Some((name, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+            match name.trim().to_ascii_uppercase().as_str() {
+                "ST" => target = Some(value.to_owned()),
+                "MX" => max_wait = Duration::from_secs(value.parse().unwrap_or(1)),
+                "MAN" => discover = value.trim_matches('"') == "ssdp:discover",
+                _ => {}
+            }
+        }
+        if !discover {
+            return None;
+        }
+        Some(Search {
+            target: target?,
+            max_wait,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn search(message: &str) -> Option<Search> {
+        Search::parse(message.as_bytes())
+    }
+
+    #[test]
+    fn parses_multicast_search() {
+        let search = search(
+            "M-SEARCH * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\nMAN: \"ssdp:discover\"\r\nMX: 3\r\nST: urn:schemas-upnp-org:device:Scanner:1\r\n\r\n",
+        )
+        .expect("search should parse");
+        assert_eq!(search.target, DEVICE_TYPE);
+        assert_eq!(search.max_wait, Duration::from_secs(3));
+    }
+
+    #[test]
+    fn accepts_any_header_case_and_spacing() {
+        let search =
+            search("m-search * http/1.1\r\nst:ssdp:all\r\nman:ssdp:discover\r\nmx:1\r\n\r\n")
+                .expect("search should parse");
+        assert_eq!(search.target, "ssdp:all");
+        assert_eq!(search.max_wait, Duration::from_secs(1));
+    }
+
+    #[test]
+    fn treats_searches_without_mx_as_unicast() {
+        let search =
+            search("M-SEARCH * HTTP/1.1\r\nMAN: \"ssdp:discover\"\r\nST: upnp:rootdevice\r\n\r\n")
+                .expect("search should parse");
+        assert_eq!(search.max_wait, Duration::ZERO);
+    }
+
+    #[test]
+    fn rejects_other_messages() {
+        // NOTIFY 和普通 HTTP 请求不是搜索
+        assert!(
+            search("NOTIFY * HTTP/1.1\r\nNT: upnp:rootdevice\r\nNTS: ssdp:alive\r\n\r\n").is_none()
+        );
+        assert!(search("GET / HTTP/1.1\r\nHost: example\r\n\r\n").is_none());
+        // 缺少 MAN 或 ST
+        assert!(search("M-SEARCH * HTTP/1.1\r\nST: ssdp:all\r\nMX: 1\r\n\r\n").is_none());
+        assert!(search("M-SEARCH * HTTP/1.1\r\nMAN: \"ssdp:discover\"\r\nMX: 1\r\n\r\n").is_none());
+        assert!(Search::parse(&[0xff, 0xfe]).is_none());
+    }
+}
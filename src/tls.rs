@@ -0,0 +1,118 @@
+/*
+ *     Copyright (C) 2024-2025 Christian Nagel and contributors
+ *
+ *     This file is part of escl-mock-server.
+ *
+ *     escl-mock-server is free software: you can redistribute it and/or modify it under the terms of
+ *     the GNU General Public License as published by the Free Software Foundation, either
+ *     version 3 of the License, or (at your option) any later version.
+ *
+ *     escl-mock-server is distributed in the hope that it will be useful, but WITHOUT ANY
+ *     WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ *     FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ *     You should have received a copy of the GNU General Public License along with eSCLKt.
+ *     If not, see <https://www.gnu.org/licenses/>.
+ *
+ *     SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+use ring::digest::{digest, SHA256};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use rustls::ServerConfig;
+use std::fs;
+use std::sync::Arc;
+
+/// Certificate and key of the HTTPS listeners.
+pub(crate) struct TlsCertificate {
+    chain: Vec<CertificateDer<'static>>,
+    key: PrivateKeyDer<'static>,
+    /// PEM certificate chain, served so clients can trust a self-signed certificate.
+    pem: String,
+    self_signed: bool,
+}
+
+impl TlsCertificate {
+    /// Loads a PEM certificate chain and private key.
+    pub(crate) fn load(cert: &str, key: &str) -> Result<Self, String> {
+        let chain = CertificateDer::pem_file_iter(cert)
+            .and_then(|certificates| certificates.collect::<Result<Vec<_>, _>>())
+            .map_err(|e| format!("couldn't read TLS certificate {cert}: {e}"))?;
+        if chain.is_empty() {
+            return Err(format!("TLS certificate {cert} contains no certificate"));
+        }
+        let pem = fs::read_to_string(cert)
+            .map(|text| certificate_blocks(&text))
+            .map_err(|e| format!("couldn't read TLS certificate {cert}: {e}"))?;
+        let key = PrivateKeyDer::from_pem_file(key)
+            .map_err(|e| format!("couldn't read TLS private key {key}: {e}"))?;
+        Ok(TlsCertificate {
+            chain,
+            key,
+            pem,
+            self_signed: false,
+        })
+    }
+
+    /// Generates a self-signed certificate for the given host names and IP addresses.
+    pub(crate) fn self_signed(names: Vec<String>) -> Result<Self, String> {
+        let certified = rcgen::generate_simple_self_signed(names)
+            .map_err(|e| format!("couldn't generate a self-signed TLS certificate: {e}"))?;
+        let key = PrivatePkcs8KeyDer::from(certified.key_pair.serialize_der());
+        Ok(TlsCertificate {
+            pem: certified.cert.pem(),
+            chain: vec![certified.cert.der().clone()],
+            key: PrivateKeyDer::Pkcs8(key),
+            self_signed: true,
+        })
+    }
+
+    pub(crate) fn server_config(&self) -> Result<ServerConfig, String> {
+        ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .and_then(|builder| {
+                builder
+                    .with_no_client_auth()
+                    .with_single_cert(self.chain.clone(), self.key.clone_key())
+            })
+            .map_err(|e| format!("invalid TLS certificate or key: {e}"))
+    }
+
+    pub(crate) fn pem(&self) -> &str {
+        &self.pem
+    }
+
+    pub(crate) fn is_self_signed(&self) -> bool {
+        self.self_signed
+    }
+
+    /// SHA-256 fingerprint of the leaf certificate, as shown by browsers.
+    pub(crate) fn fingerprint(&self) -> String {
+        digest(&SHA256, &self.chain[0])
+            .as_ref()
+            .iter()
+            .map(|byte| format!("{byte:02X}"))
+            .collect::<Vec<_>>()
+            .join(":")
+    }
+}
+
+/// Keeps only the certificate blocks of a PEM file, dropping any bundled private key.
+fn certificate_blocks(text: &str) -> String {
+    let mut pem = String::new();
+    let mut inside = false;
+    for line in text.lines() {
+        if line.starts_with("-----BEGIN CERTIFICATE-----") {
+            inside = true;
+        }
+        if inside {
+            pem.push_str(line.trim_end());
+            pem.push('\n');
+        }
+        if line.starts_with("-----END CERTIFICATE-----") {
+            inside = false;
+        }
+    }
+    pem
+}
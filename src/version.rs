@@ -0,0 +1,161 @@
+/*
+ *     Copyright (C) 2024-2025 Christian Nagel and contributors
+ *
+ *     This file is part of escl-mock-server.
+ *
+ *     escl-mock-server is free software: you can redistribute it and/or modify it under the terms of
+ *     the GNU General Public License as published by the Free Software Foundation, either
+ *     version 3 of the License, or (at your option) any later version.
+ *
+ *     escl-mock-server is distributed in the hope that it will be useful, but WITHOUT ANY
+ *     WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ *     FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ *     You should have received a copy of the GNU General Public License along with eSCLKt.
+ *     If not, see <https://www.gnu.org/licenses/>.
+ *
+ *     SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+use serde::Deserialize;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// eSCL protocol version the mock scanner implements.
+///
+/// Stored in hundredths so `2.6` and `2.60` compare equal and `2.63 > 2.6`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(try_from = "String")]
+pub struct EsclVersion(u16);
+
+impl EsclVersion {
+    pub const OLDEST: EsclVersion = EsclVersion::new(2, 0);
+    pub const LATEST: EsclVersion = EsclVersion::new(2, 97);
+
+    /// `scan:DocumentFormatExt` next to `pwg:DocumentFormat`.
+    const DOCUMENT_FORMAT_EXT: EsclVersion = EsclVersion::new(2, 10);
+    /// `ScanJobs/{uuid}/ScanImageInfo` after each retrieved page.
+    const SCAN_IMAGE_INFO: EsclVersion = EsclVersion::new(2, 50);
+    /// `scan:BlankPageDetection` in the capabilities.
+    const BLANK_PAGE_DETECTION: EsclVersion = EsclVersion::new(2, 60);
+
+    /// `minor` is in hundredths: `new(2, 60)` is version 2.6.
+    pub const fn new(major: u16, minor: u16) -> Self {
+        EsclVersion(major * 100 + minor)
+    }
+
+    pub(crate) fn has_document_format_ext(self) -> bool {
+        self >= Self::DOCUMENT_FORMAT_EXT
+    }
+
+    pub(crate) fn has_scan_image_info(self) -> bool {
+        self >= Self::SCAN_IMAGE_INFO
+    }
+
+    pub(crate) fn has_blank_page_detection(self) -> bool {
+        self >= Self::BLANK_PAGE_DETECTION
+    }
+}
+
+impl FromStr for EsclVersion {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "invalid eSCL version {text:?}, expected {} to {}",
+                EsclVersion::OLDEST,
+                EsclVersion::LATEST
+            )
+        };
+        let (major, minor) = text.trim().split_once('.').ok_or_else(invalid)?;
+        // 只模拟 2.x，其他主版本在计算前拒绝，避免乘法溢出
+        let major: u16 = major.parse().map_err(|_| invalid())?;
+        if major != 2 {
+            return Err(invalid());
+        }
+        // 小数部分最多两位："2.6" 即 2.60
+        let hundredths: u16 = match minor.len() {
+            1 => minor.parse::<u16>().map_err(|_| invalid())? * 10,
+            2 => minor.parse().map_err(|_| invalid())?,
+            _ => return Err(invalid()),
+        };
+        let version = EsclVersion::new(major, hundredths);
+        if !(EsclVersion::OLDEST..=EsclVersion::LATEST).contains(&version) {
+            return Err(invalid());
+        }
+        Ok(version)
+    }
+}
+
+impl TryFrom<String> for EsclVersion {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, Self::Error> {
+        text.parse()
+    }
+}
+
+impl Display for EsclVersion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let (major, minor) = (self.0 / 100, self.0 % 100);
+        if minor % 10 == 0 {
+            write!(f, "{major}.{}", minor / 10)
+        } else {
+            write!(f, "{major}.{minor:02}")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(text: &str) -> EsclVersion {
+        text.parse().unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    #[test]
+    fn parses_supported_versions() {
+        assert_eq!(version("2.0"), EsclVersion::OLDEST);
+        assert_eq!(version("2.5"), EsclVersion::new(2, 50));
+        assert_eq!(version("2.97"), EsclVersion::LATEST);
+        assert_eq!(version(" 2.63 "), EsclVersion::new(2, 63));
+    }
+
+    #[test]
+    fn compares_by_hundredths() {
+        assert_eq!(version("2.6"), version("2.60"));
+        assert!(version("2.63") > version("2.6"));
+        assert!(version("2.5") > version("2.10"));
+    }
+
+    #[test]
+    fn rejects_out_of_range_versions() {
+        for text in ["1.9", "2.98", "3.0", "700.0", "657.36"] {
+            let error = text.parse::<EsclVersion>().unwrap_err();
+            assert_eq!(
+                error,
+                format!("invalid eSCL version {text:?}, expected 2.0 to 2.97")
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_versions() {
+        for text in ["", "2", "2.", "2.100", "two.five", "2.x"] {
+            assert!(
+                text.parse::<EsclVersion>().is_err(),
+                "{text:?} was accepted"
+            );
+        }
+    }
+
+    #[test]
+    fn displays_without_trailing_zero() {
+        assert_eq!(version("2.0").to_string(), "2.0");
+        assert_eq!(version("2.50").to_string(), "2.5");
+        assert_eq!(version("2.63").to_string(), "2.63");
+        assert_eq!(version("2.05").to_string(), "2.05");
+    }
+}
@@ -0,0 +1,641 @@
+/*
+ *     Copyright (C) 2024-2025 Christian Nagel and contributors
+ *
+ *     This file is part of escl-mock-server.
+ *
+ *     escl-mock-server is free software: you can redistribute it and/or modify it under the terms of
+ *     the GNU General Public License as published by the Free Software Foundation, either
+ *     version 3 of the License, or (at your option) any later version.
+ *
+ *     escl-mock-server is distributed in the hope that it will be useful, but WITHOUT ANY
+ *     WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ *     FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ *     You should have received a copy of the GNU General Public License along with eSCLKt.
+ *     If not, see <https://www.gnu.org/licenses/>.
+ *
+ *     SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+use crate::xml::escape;
+use crate::{network, DescribedDevice};
+use actix_web::http::StatusCode;
+use actix_web::rt::task::JoinHandle;
+use actix_web::HttpResponse;
+use roxmltree::{Document, Node};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::net::UdpSocket;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+const WSD_GROUP: Ipv4Addr = Ipv4Addr::new(239, 255, 255, 250);
+const WSD_PORT: u16 = 3702;
+/// Upper bound of the random delay before answering a multicast Probe.
+const APP_MAX_DELAY: Duration = Duration::from_millis(500);
+
+pub(crate) const NS_SOAP: &str = "http://www.w3.org/2003/05/soap-envelope";
+pub(crate) const NS_ADDRESSING: &str = "http://schemas.xmlsoap.org/ws/2004/08/addressing";
+const NS_DISCOVERY: &str = "http://schemas.xmlsoap.org/ws/2005/04/discovery";
+const NS_DEVPROF: &str = "http://schemas.xmlsoap.org/ws/2006/02/devprof";
+pub(crate) const NS_EVENTING: &str = "http://schemas.xmlsoap.org/ws/2004/08/eventing";
+pub(crate) const NS_SCAN: &str = "http://schemas.microsoft.com/windows/2006/08/wdp/scan";
+pub(crate) const ANONYMOUS: &str =
+    "http://schemas.xmlsoap.org/ws/2004/08/addressing/role/anonymous";
+const DISCOVERY_URN: &str = "urn:schemas-xmlsoap-org:ws:2005:04:discovery";
+const FAULT_ACTION: &str = "http://schemas.xmlsoap.org/ws/2004/08/addressing/fault";
+
+/// Types the devices are discovered with, as namespace and local name.
+const DEVICE_TYPES: [(&str, &str); 2] = [(NS_DEVPROF, "Device"), (NS_SCAN, "ScanDeviceType")];
+
+/// A new `wsa:MessageID`.
+pub(crate) fn new_message_id() -> String {
+    format!("urn:uuid:{}", Uuid::new_v4())
+}
+
+/// Addressing headers and body of a SOAP request posted to a device.
+pub(crate) struct Envelope<'a, 'input> {
+    pub action: String,
+    /// Echoed as `wsa:RelatesTo` in the response.
+    pub message_id: Option<String>,
+    pub header: Node<'a, 'input>,
+    pub body: Node<'a, 'input>,
+}
+
+impl<'a, 'input> Envelope<'a, 'input> {
+    pub(crate) fn parse(document: &'a Document<'input>) -> Option<Self> {
+        let envelope = document.root_element();
+        let header = child(envelope, NS_SOAP, "Header")?;
+        Some(Envelope {
+            action: text(header, NS_ADDRESSING, "Action")?,
+            message_id: text(header, NS_ADDRESSING, "MessageID"),
+            header,
+            body: child(envelope, NS_SOAP, "Body")?,
+        })
+    }
+}
+
+/// `wsa:RelatesTo` header element for a response, empty without a request ID.
+pub(crate) fn relates_to(message_id: Option<&str>) -> String {
+    message_id.map_or_else(String::new, |id| {
+        format!("<wsa:RelatesTo>{}</wsa:RelatesTo>", escape(id))
+    })
+}
+
+/// Class of a SOAP fault, decides the HTTP status.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum FaultCode {
+    Sender,
+    Receiver,
+}
+
+/// A SOAP 1.2 fault, e.g. `wscn:ClientErrorNoImagesAvailable`.
+pub(crate) fn fault(
+    relates_to: Option<&str>,
+    code: FaultCode,
+    subcode: &str,
+    reason: &str,
+) -> HttpResponse {
+    let (value, status) = match code {
+        FaultCode::Sender => ("soap:Sender", StatusCode::BAD_REQUEST),
+        FaultCode::Receiver => ("soap:Receiver", StatusCode::INTERNAL_SERVER_ERROR),
+    };
+    let body = format!(
+        r#"        <soap:Fault>
+            <soap:Code>
+                <soap:Value>{value}</soap:Value>
+                <soap:Subcode>
+                    <soap:Value>{subcode}</soap:Value>
+                </soap:Subcode>
+            </soap:Code>
+            <soap:Reason>
+                <soap:Text xml:lang="en">{reason}</soap:Text>
+            </soap:Reason>
+        </soap:Fault>"#,
+        reason = escape(reason)
+    );
+    HttpResponse::build(status)
+        .content_type("application/soap+xml; charset=utf-8")
+        .body(soap_response(FAULT_ACTION, relates_to, &body))
+}
+
+/// A SOAP message sent over HTTP. `headers` follow `wsa:MessageID`, `body`
+/// is the indented content of `soap:Body`.
+pub(crate) fn soap_message(to: &str, action: &str, headers: &str, body: &str) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<soap:Envelope xmlns:soap="{NS_SOAP}"
+               xmlns:wsa="{NS_ADDRESSING}"
+               xmlns:wse="{NS_EVENTING}"
+               xmlns:wscn="{NS_SCAN}">
+    <soap:Header>
+        <wsa:To>{to}</wsa:To>
+        <wsa:Action>{action}</wsa:Action>
+        <wsa:MessageID>{message_id}</wsa:MessageID>{headers}
+    </soap:Header>
+    <soap:Body>
+{body}
+    </soap:Body>
+</soap:Envelope>"#,
+        to = escape(to),
+        message_id = new_message_id(),
+    )
+}
+
+/// A SOAP response to the request with `relates_to` as MessageID.
+pub(crate) fn soap_response(action: &str, relates_to: Option<&str>, body: &str) -> String {
+    let headers = relates_to.map_or_else(String::new, |id| {
+        format!("\n        {}", self::relates_to(Some(id)))
+    });
+    soap_message(ANONYMOUS, action, &headers, body)
+}
+
+pub(crate) fn child<'a, 'input>(
+    node: Node<'a, 'input>,
+    namespace: &str,
+    name: &str,
+) -> Option<Node<'a, 'input>> {
+    node.children().find(|child| {
+        child.tag_name().namespace() == Some(namespace) && child.tag_name().name() == name
+    })
+}
+
+/// Resolves a QName in the text of `node` to namespace and local name.
+pub(crate) fn qname(node: Node, value: &str) -> (String, String) {
+    let (prefix, local) = value
+        .split_once(':')
+        .map_or((None, value), |(p, l)| (Some(p), l));
+    let namespace = node.lookup_namespace_uri(prefix).unwrap_or_default();
+    (namespace.to_owned(), local.to_owned())
+}
+
+pub(crate) fn text(node: Node, namespace: &str, name: &str) -> Option<String> {
+    child(node, namespace, name)
+        .and_then(|child| child.text())
+        .map(|text| text.trim().to_owned())
+}
+
+/// A Probe or Resolve received over UDP.
+struct Request {
+    message_id: String,
+    /// `wsa:ReplyTo`, anonymous when missing.
+    reply_to: String,
+    kind: RequestKind,
+}
+
+enum RequestKind {
+    /// Requested types as namespace and local name, and scopes.
+    Probe {
+        types: Vec<(String, String)>,
+        scopes: Vec<String>,
+    },
+    Resolve {
+        address: String,
+    },
+}
+
+impl Request {
+    fn parse(message: &[u8]) -> Option<Request> {
+        let document = Document::parse(std::str::from_utf8(message).ok()?).ok()?;
+        let envelope = document.root_element();
+        let header = child(envelope, NS_SOAP, "Header")?;
+        let body = child(envelope, NS_SOAP, "Body")?;
+        let message_id = text(header, NS_ADDRESSING, "MessageID")?;
+        let reply_to = child(header, NS_ADDRESSING, "ReplyTo")
+            .and_then(|reply_to| text(reply_to, NS_ADDRESSING, "Address"))
+            .unwrap_or_else(|| ANONYMOUS.to_owned());
+
+        let kind = if let Some(probe) = child(body, NS_DISCOVERY, "Probe") {
+            // QName 的前缀按 Types 元素所在的命名空间范围解析
+            let types = match child(probe, NS_DISCOVERY, "Types") {
+                Some(types) => types
+                    .text()
+                    .unwrap_or_default()
+                    .split_whitespace()
+                    .map(|value| qname(types, value))
+                    .collect(),
+                None => Vec::new(),
+            };
+            let scopes = text(probe, NS_DISCOVERY, "Scopes")
+                .map(|scopes| scopes.split_whitespace().map(str::to_owned).collect())
+                .unwrap_or_default();
+            RequestKind::Probe { types, scopes }
+        } else if let Some(resolve) = child(body, NS_DISCOVERY, "Resolve") {
+            let address = child(resolve, NS_ADDRESSING, "EndpointReference")
+                .and_then(|reference| text(reference, NS_ADDRESSING, "Address"))?;
+            RequestKind::Resolve { address }
+        } else {
+            return None;
+        };
+        Some(Request {
+            message_id,
+            reply_to,
+            kind,
+        })
+    }
+
+    fn matches(&self, device: &DescribedDevice) -> bool {
+        match &self.kind {
+            // 设备没有声明 scope，请求了 scope 的 Probe 不匹配
+            RequestKind::Probe { types, scopes } => {
+                scopes.is_empty()
+                    && types.iter().all(|(namespace, name)| {
+                        DEVICE_TYPES.contains(&(namespace.as_str(), name.as_str()))
+                    })
+            }
+            RequestKind::Resolve { address } => *address == format!("urn:uuid:{}", device.uuid),
+        }
+    }
+}
+
+/// WS-Discovery responder on 239.255.255.250:3702: announces the devices
+/// with Hello and Bye and answers Probe and Resolve.
+pub(crate) struct Wsd {
+    responder: Arc<Responder>,
+    task: JoinHandle<()>,
+}
+
+struct Responder {
+    devices: Vec<DescribedDevice>,
+    socket: UdpSocket,
+    /// Interface addresses and a socket sending multicast from each of them.
+    senders: Vec<(Ipv4Addr, UdpSocket)>,
+    /// `wsd:AppSequence` InstanceId, changes with every start.
+    instance_id: u64,
+    message_number: AtomicU64,
+}
+
+impl Wsd {
+    /// Joins the WS-Discovery group on the interfaces of the IPv4 `addresses`
+    /// and sends Hello.
+    pub(crate) async fn start(
+        devices: Vec<DescribedDevice>,
+        addresses: &[IpAddr],
+    ) -> io::Result<Wsd> {
+        let socket = network::multicast_socket(WSD_PORT)?;
+        let interfaces = addresses.iter().filter_map(|address| match address {
+            IpAddr::V4(address) => Some(*address),
+            IpAddr::V6(_) => None,
+        });
+        let senders =
+            network::join_multicast_group(&socket, WSD_GROUP, interfaces, 1, "WS-Discovery");
+        if senders.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrNotAvailable,
+                "no advertised IPv4 interface could join the WS-Discovery group",
+            ));
+        }
+
+        let responder = Arc::new(Responder {
+            devices,
+            socket: UdpSocket::from_std(socket)?,
+            senders,
+            instance_id: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(1, |since| since.as_secs()),
+            message_number: AtomicU64::new(1),
+        });
+        info!(
+            devices = responder.devices.len(),
+            interfaces = ?responder.senders.iter().map(|(address, _)| address).collect::<Vec<_>>(),
+            "WS-Discovery responder started"
+        );
+        responder.announce("Hello").await;
+        let task = actix_web::rt::spawn(responder.clone().respond());
+        Ok(Wsd { responder, task })
+    }
+
+    /// Stops answering and sends Bye for every device.
+    pub(crate) async fn stop(self) {
+        self.task.abort();
+        self.responder.announce("Bye").await;
+        info!("WS-Discovery devices withdrawn");
+    }
+}
+
+impl Responder {
+    // Hello 和 Bye 通过多播发送，每个网卡使用自己的地址
+    async fn announce(&self, action: &str) {
+        for (address, sender) in &self.senders {
+            for device in &self.devices {
+                // Bye 只需要设备地址
+                let details = if action == "Hello" {
+                    self.details(device, *address, 3)
+                } else {
+                    String::new()
+                };
+                let body = format!(
+                    r#"<wsd:{action}>
+            <wsa:EndpointReference>
+                <wsa:Address>urn:uuid:{uuid}</wsa:Address>
+            </wsa:EndpointReference>{details}
+        </wsd:{action}>"#,
+                    uuid = device.uuid
+                );
+                let message = self.envelope(
+                    DISCOVERY_URN,
+                    &format!("{NS_DISCOVERY}/{action}"),
+                    None,
+                    &body,
+                );
+                if let Err(e) = sender
+                    .send_to(message.as_bytes(), (WSD_GROUP, WSD_PORT))
+                    .await
+                {
+                    warn!(interface = %address, error = %e, action, "Couldn't send WS-Discovery message");
+                }
+            }
+            debug!(interface = %address, action, "WS-Discovery announcement sent");
+        }
+    }
+
+    async fn respond(self: Arc<Self>) {
+        let mut buffer = vec![0; 65536];
+        let mut failures = 0;
+        loop {
+            let (length, peer) = match self.socket.recv_from(&mut buffer).await {
+                Ok(received) => {
+                    failures = 0;
+                    received
+                }
+                Err(e) => {
+                    warn!(error = %e, "Couldn't receive WS-Discovery message");
+                    network::receive_backoff(failures).await;
+                    failures += 1;
+                    continue;
+                }
+            };
+            let Some(request) = Request::parse(&buffer[..length]) else {
+                continue;
+            };
+            let SocketAddr::V4(peer_v4) = peer else {
+                continue;
+            };
+            let Some(message) = self.answer(&request, *peer_v4.ip()) else {
+                continue;
+            };
+
+            // 多播请求的应答随机延迟，避免网络上的设备同时应答
+            let delay = APP_MAX_DELAY.mul_f64(network::random_fraction());
+            let responder = self.clone();
+            actix_web::rt::spawn(async move {
+                actix_web::rt::time::sleep(delay).await;
+                if let Err(e) = responder.socket.send_to(message.as_bytes(), peer).await {
+                    warn!(peer = %peer, error = %e, "Couldn't answer WS-Discovery request");
+                }
+            });
+        }
+    }
+
+    /// ProbeMatches or ResolveMatches for a request from `peer`, `None` when
+    /// no device matches.
+    fn answer(&self, request: &Request, peer: Ipv4Addr) -> Option<String> {
+        let interfaces: Vec<Ipv4Addr> = self.senders.iter().map(|(address, _)| *address).collect();
+        let address = network::closest_ipv4(&interfaces, peer)?;
+        let matches: Vec<&DescribedDevice> = self
+            .devices
+            .iter()
+            .filter(|device| request.matches(device))
+            .collect();
+        let (kind, matched, element) = match request.kind {
+            RequestKind::Probe { .. } => ("Probe", "ProbeMatches", "ProbeMatch"),
+            RequestKind::Resolve { .. } => ("Resolve", "ResolveMatches", "ResolveMatch"),
+        };
+        debug!(
+            peer = %peer,
+            kind,
+            message_id = %request.message_id,
+            matches = matches.len(),
+            "WS-Discovery request"
+        );
+        if matches.is_empty() {
+            return None;
+        }
+
+        let entries: String = matches
+            .iter()
+            .map(|device| {
+                format!(
+                    r#"
+            <wsd:{element}>
+                <wsa:EndpointReference>
+                    <wsa:Address>urn:uuid:{uuid}</wsa:Address>
+                </wsa:EndpointReference>{details}
+            </wsd:{element}>"#,
+                    uuid = device.uuid,
+                    details = self.details(device, address, 4)
+                )
+            })
+            .collect();
+        let body = format!("<wsd:{matched}>{entries}\n        </wsd:{matched}>");
+        Some(self.envelope(
+            &request.reply_to,
+            &format!("{NS_DISCOVERY}/{matched}"),
+            Some(&request.message_id),
+            &body,
+        ))
+    }
+
+    // Hello、ProbeMatch 和 ResolveMatch 共有的设备信息
+    fn details(&self, device: &DescribedDevice, address: Ipv4Addr, depth: usize) -> String {
+        let indent = "    ".repeat(depth);
+        format!(
+            "\n{indent}<wsd:Types>wsdp:Device wscn:ScanDeviceType</wsd:Types>\
+             \n{indent}<wsd:XAddrs>{}</wsd:XAddrs>\
+             \n{indent}<wsd:MetadataVersion>1</wsd:MetadataVersion>",
+            escape(&device.url(address.into(), &format!("{}/wsd", device.scope)))
+        )
+    }
+
+    fn envelope(&self, to: &str, action: &str, relates_to_id: Option<&str>, body: &str) -> String {
+        let message_number = self.message_number.fetch_add(1, Ordering::Relaxed);
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<soap:Envelope xmlns:soap="{NS_SOAP}"
+               xmlns:wsa="{NS_ADDRESSING}"
+               xmlns:wsd="{NS_DISCOVERY}"
+               xmlns:wsdp="{NS_DEVPROF}"
+               xmlns:wscn="{NS_SCAN}">
+    <soap:Header>
+        <wsa:To>{to}</wsa:To>
+        <wsa:Action>{action}</wsa:Action>
+        <wsa:MessageID>{message_id}</wsa:MessageID>{relates_to}
+        <wsd:AppSequence InstanceId="{instance_id}" MessageNumber="{message_number}"/>
+    </soap:Header>
+    <soap:Body>
+        {body}
+    </soap:Body>
+</soap:Envelope>"#,
+            to = escape(to),
+            message_id = new_message_id(),
+            relates_to = relates_to_id.map_or_else(String::new, |id| format!(
+                "\n        {}",
+                relates_to(Some(id))
+            )),
+            instance_id = self.instance_id,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UUID: &str = "0d3f4c5e-1a2b-4c3d-8e9f-001122334455";
+
+    fn envelope(action: &str, message_id: &str, body: &str) -> String {
+        format!(
+            r#"<?xml version="1.0" encoding="utf-8"?>
+<soap:Envelope xmlns:soap="{NS_SOAP}" xmlns:wsa="{NS_ADDRESSING}" xmlns:wsd="{NS_DISCOVERY}">
+    <soap:Header>
+        <wsa:To>{DISCOVERY_URN}</wsa:To>
+        <wsa:Action>{NS_DISCOVERY}/{action}</wsa:Action>
+        <wsa:MessageID>{message_id}</wsa:MessageID>
+    </soap:Header>
+    <soap:Body>{body}</soap:Body>
+</soap:Envelope>"#
+        )
+    }
+
+    fn probe(message_id: &str, probe: &str) -> Request {
+        Request::parse(envelope("Probe", message_id, probe).as_bytes()).expect("probe should parse")
+    }
+
+    async fn responder() -> Responder {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let sender = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        Responder {
+            devices: vec![DescribedDevice {
+                uuid: UUID.parse().unwrap(),
+                scheme: "http",
+                port: 8080,
+                scope: "/eSCL".to_owned(),
+            }],
+            socket,
+            senders: vec![(Ipv4Addr::LOCALHOST, sender)],
+            instance_id: 1,
+            message_number: AtomicU64::new(1),
+        }
+    }
+
+    // 应答的 RelatesTo 必须等于请求的 MessageID
+    fn relates_to_and_action(message: &str) -> (String, String) {
+        let document = Document::parse(message).unwrap();
+        let header = child(document.root_element(), NS_SOAP, "Header").unwrap();
+        (
+            text(header, NS_ADDRESSING, "RelatesTo").unwrap(),
+            text(header, NS_ADDRESSING, "Action").unwrap(),
+        )
+    }
+
+    #[test]
+    fn parses_probe_types_with_their_own_prefixes() {
+        let request = probe(
+            "urn:uuid:11111111-2222-3333-4444-555555555555",
+            &format!(
+                r#"<wsd:Probe><wsd:Types xmlns:dp="{NS_DEVPROF}" xmlns:scan="{NS_SCAN}">dp:Device scan:ScanDeviceType</wsd:Types></wsd:Probe>"#
+            ),
+        );
+        assert_eq!(
+            request.message_id,
+            "urn:uuid:11111111-2222-3333-4444-555555555555"
+        );
+        assert_eq!(request.reply_to, ANONYMOUS);
+        let RequestKind::Probe { types, scopes } = &request.kind else {
+            panic!("expected a probe");
+        };
+        assert_eq!(
+            types,
+            &[
+                (NS_DEVPROF.to_owned(), "Device".to_owned()),
+                (NS_SCAN.to_owned(), "ScanDeviceType".to_owned()),
+            ]
+        );
+        assert!(scopes.is_empty());
+    }
+
+    #[test]
+    fn ignores_messages_without_message_id_or_known_body() {
+        let message =
+            envelope("Probe", "", "<wsd:Probe/>").replace("<wsa:MessageID></wsa:MessageID>", "");
+        assert!(Request::parse(message.as_bytes()).is_none());
+        assert!(
+            Request::parse(envelope("Hello", "urn:uuid:1", "<wsd:Hello/>").as_bytes()).is_none()
+        );
+        assert!(Request::parse(b"not xml").is_none());
+    }
+
+    #[actix_web::test]
+    async fn answers_probe_with_relates_to() {
+        let responder = responder().await;
+        let request = probe(
+            "urn:uuid:probe-1",
+            &format!(
+                r#"<wsd:Probe xmlns:wsdp="{NS_DEVPROF}"><wsd:Types>wsdp:Device</wsd:Types></wsd:Probe>"#
+            ),
+        );
+        let answer = responder
+            .answer(&request, Ipv4Addr::LOCALHOST)
+            .expect("the device should match");
+        assert_eq!(
+            relates_to_and_action(&answer),
+            (
+                "urn:uuid:probe-1".to_owned(),
+                format!("{NS_DISCOVERY}/ProbeMatches")
+            )
+        );
+        assert!(answer.contains(&format!("<wsa:Address>urn:uuid:{UUID}</wsa:Address>")));
+        assert!(answer.contains("<wsd:XAddrs>http://127.0.0.1:8080/eSCL/wsd</wsd:XAddrs>"));
+
+        // 不带 Types 的 Probe 匹配所有设备
+        let request = probe("urn:uuid:probe-2", "<wsd:Probe/>");
+        assert!(responder.answer(&request, Ipv4Addr::LOCALHOST).is_some());
+    }
+
+    #[actix_web::test]
+    async fn ignores_probe_for_other_types_or_scopes() {
+        let responder = responder().await;
+        let request = probe(
+            "urn:uuid:probe-3",
+            r#"<wsd:Probe xmlns:prn="http://schemas.microsoft.com/windows/2006/08/wdp/print"><wsd:Types>prn:PrintDeviceType</wsd:Types></wsd:Probe>"#,
+        );
+        assert!(!request.matches(&responder.devices[0]));
+        assert!(responder.answer(&request, Ipv4Addr::LOCALHOST).is_none());
+
+        let request = probe(
+            "urn:uuid:probe-4",
+            "<wsd:Probe><wsd:Scopes>ldap:///ou=floor1</wsd:Scopes></wsd:Probe>",
+        );
+        assert!(responder.answer(&request, Ipv4Addr::LOCALHOST).is_none());
+    }
+
+    #[actix_web::test]
+    async fn answers_resolve_for_own_address_only() {
+        let responder = responder().await;
+        let resolve = |message_id: &str, uuid: &str| {
+            let body = format!(
+                "<wsd:Resolve><wsa:EndpointReference><wsa:Address>urn:uuid:{uuid}</wsa:Address>\
+                 </wsa:EndpointReference></wsd:Resolve>"
+            );
+            Request::parse(envelope("Resolve", message_id, &body).as_bytes())
+                .expect("resolve should parse")
+        };
+
+        let answer = responder
+            .answer(&resolve("urn:uuid:resolve-1", UUID), Ipv4Addr::LOCALHOST)
+            .expect("the device should match");
+        assert_eq!(
+            relates_to_and_action(&answer),
+            (
+                "urn:uuid:resolve-1".to_owned(),
+                format!("{NS_DISCOVERY}/ResolveMatches")
+            )
+        );
+
+        let other = resolve("urn:uuid:resolve-2", "ffffffff-1a2b-4c3d-8e9f-001122334455");
+        assert!(responder.answer(&other, Ipv4Addr::LOCALHOST).is_none());
+    }
+}
@@ -0,0 +1,1666 @@
+/*
+ *     Copyright (C) 2024-2025 Christian Nagel and contributors
+ *
+ *     This file is part of escl-mock-server.
+ *
+ *     escl-mock-server is free software: you can redistribute it and/or modify it under the terms of
+ *     the GNU General Public License as published by the Free Software Foundation, either
+ *     version 3 of the License, or (at your option) any later version.
+ *
+ *     escl-mock-server is distributed in the hope that it will be useful, but WITHOUT ANY
+ *     WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ *     FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ *     You should have received a copy of the GNU General Public License along with eSCLKt.
+ *     If not, see <https://www.gnu.org/licenses/>.
+ *
+ *     SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+//! WS-Scan (WSD Scan Service) operations. Jobs created here share the job
+//! table, sources, faults and images of the eSCL endpoints.
+
+use crate::capabilities::{InputCaps, Intent, ScannerCapabilities, SupportedResolutions};
+use crate::escl_server::{self, PageError};
+use crate::eventing::Destination;
+use crate::logging::JOB_TARGET;
+use crate::model::{self, JobState, ScanJob, ScanSource};
+use crate::report::ClientEvent;
+use crate::wsd::{self, child, fault, text, Envelope, FaultCode, NS_SCAN};
+use crate::xml::XmlWriter;
+use crate::AppState;
+use actix_web::{HttpRequest, HttpResponse};
+use roxmltree::Node;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+const NS_XOP: &str = "http://www.w3.org/2004/08/xop/include";
+/// MIME boundary and part IDs of the MTOM RetrieveImage response.
+const MIME_BOUNDARY: &str = "MIMEBoundary_escl_mock_server";
+const SOAP_PART_ID: &str = "soap@escl-mock-server";
+const IMAGE_PART_ID: &str = "image@escl-mock-server";
+
+/// Operations of the scan service, the action is `{NS_SCAN}/{operation}`.
+const OPERATIONS: [&str; 7] = [
+    "GetScannerElements",
+    "ValidateScanTicket",
+    "CreateScanJob",
+    "RetrieveImage",
+    "CancelJob",
+    "GetJobElements",
+    "GetActiveJobs",
+];
+
+/// `wscn:InputSource` values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum InputSource {
+    Platen,
+    Adf,
+    AdfDuplex,
+}
+
+impl InputSource {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "Platen" => Some(InputSource::Platen),
+            "ADF" => Some(InputSource::Adf),
+            "ADFDuplex" => Some(InputSource::AdfDuplex),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            InputSource::Platen => "Platen",
+            InputSource::Adf => "ADF",
+            InputSource::AdfDuplex => "ADFDuplex",
+        }
+    }
+
+    // 任务表中的扫描源和是否双面
+    fn scan_source(&self) -> (ScanSource, bool) {
+        match self {
+            InputSource::Platen => (ScanSource::Platen, false),
+            InputSource::Adf => (ScanSource::Adf, false),
+            InputSource::AdfDuplex => (ScanSource::Adf, true),
+        }
+    }
+
+    fn caps<'a>(&self, capabilities: &'a ScannerCapabilities) -> Option<&'a InputCaps> {
+        match self {
+            InputSource::Platen => capabilities.platen.as_ref(),
+            InputSource::Adf => capabilities.adf.as_ref().map(|adf| &adf.simplex),
+            InputSource::AdfDuplex => capabilities
+                .adf
+                .as_ref()
+                .and_then(|adf| adf.duplex.as_ref()),
+        }
+    }
+}
+
+impl Display for InputSource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A `wscn:ScanTicket`, kept with the jobs created from it.
+#[derive(Debug, Clone)]
+pub(crate) struct ScanTicket {
+    pub name: String,
+    pub user: String,
+    /// WS-Scan format name, e.g. `jfif`.
+    pub format: String,
+    /// 0 scans everything in the feeder.
+    pub images_to_transfer: u32,
+    pub input_source: InputSource,
+    pub color: String,
+    pub resolution: (u32, u32),
+}
+
+impl ScanTicket {
+    /// The `wscn:DefaultScanTicket`: platen if available, otherwise the
+    /// feeder, preferring JPEG, RGB24 and 300 DPI.
+    fn default_for(capabilities: &ScannerCapabilities) -> Self {
+        let input_source = if capabilities.platen.is_some() {
+            InputSource::Platen
+        } else {
+            InputSource::Adf
+        };
+        let input = input_source.caps(capabilities);
+        let formats = input.map(formats).unwrap_or_default();
+        let colors = input.map(color_modes).unwrap_or_default();
+        let (widths, heights) = input.map(resolutions).unwrap_or_default();
+        ScanTicket {
+            name: "Scan".to_owned(),
+            user: String::new(),
+            format: preferred(&formats, "jfif").to_owned(),
+            images_to_transfer: 1,
+            input_source,
+            color: preferred(&colors, "RGB24").to_owned(),
+            resolution: (preferred(&widths, 300), preferred(&heights, 300)),
+        }
+    }
+
+    // 请求中缺少的参数使用默认值
+    fn parse(ticket: Node, defaults: ScanTicket) -> Result<Self, String> {
+        let mut result = defaults;
+        if let Some(description) = child(ticket, NS_SCAN, "JobDescription") {
+            if let Some(name) = text(description, NS_SCAN, "JobName") {
+                result.name = name;
+            }
+            if let Some(user) = text(description, NS_SCAN, "JobOriginatingUserName") {
+                result.user = user;
+            }
+        }
+        let Some(parameters) = child(ticket, NS_SCAN, "DocumentParameters") else {
+            return Ok(result);
+        };
+        if let Some(format) = text(parameters, NS_SCAN, "Format") {
+            result.format = format;
+        }
+        if let Some(images) = text(parameters, NS_SCAN, "ImagesToTransfer") {
+            result.images_to_transfer = images
+                .parse()
+                .map_err(|_| format!("ImagesToTransfer {images:?} is not a number"))?;
+        }
+        if let Some(source) = text(parameters, NS_SCAN, "InputSource") {
+            result.input_source = InputSource::parse(&source)
+                .ok_or_else(|| format!("unknown InputSource {source:?}"))?;
+        }
+        let front = child(parameters, NS_SCAN, "MediaSides")
+            .and_then(|sides| child(sides, NS_SCAN, "MediaFront"));
+        if let Some(front) = front {
+            if let Some(color) = text(front, NS_SCAN, "ColorProcessing") {
+                result.color = color;
+            }
+            if let Some(resolution) = child(front, NS_SCAN, "Resolution") {
+                let dimension = |name: &str| -> Result<Option<u32>, String> {
+                    text(resolution, NS_SCAN, name)
+                        .map(|value| {
+                            value
+                                .parse()
+                                .map_err(|_| format!("resolution {value:?} is not a number"))
+                        })
+                        .transpose()
+                };
+                let width = dimension("Width")?.unwrap_or(result.resolution.0);
+                result.resolution = (width, dimension("Height")?.unwrap_or(width));
+            }
+        }
+        Ok(result)
+    }
+
+    /// Checks the ticket against the capabilities of its input source.
+    fn check(&self, capabilities: &ScannerCapabilities) -> Result<(), String> {
+        let input = self
+            .input_source
+            .caps(capabilities)
+            .ok_or_else(|| format!("input source {} is not supported", self.input_source))?;
+        if !formats(input).contains(&self.format.as_str()) {
+            return Err(format!(
+                "format {} is not supported by {}",
+                self.format, self.input_source
+            ));
+        }
+        if !color_modes(input).contains(&self.color.as_str()) {
+            return Err(format!(
+                "color processing {} is not supported by {}",
+                self.color, self.input_source
+            ));
+        }
+        if !supports_resolution(input, self.resolution) {
+            let (x, y) = self.resolution;
+            return Err(format!(
+                "resolution {x}x{y} is not supported by {}",
+                self.input_source
+            ));
+        }
+        Ok(())
+    }
+
+    fn write(&self, xml: &mut XmlWriter, element: &str) {
+        xml.open(element);
+        xml.open("wscn:JobDescription");
+        xml.leaf("wscn:JobName", &self.name);
+        xml.leaf("wscn:JobOriginatingUserName", &self.user);
+        xml.close("wscn:JobDescription");
+        self.write_parameters(xml, "wscn:DocumentParameters");
+        xml.close(element);
+    }
+
+    fn write_parameters(&self, xml: &mut XmlWriter, element: &str) {
+        xml.open(element);
+        xml.leaf("wscn:Format", &self.format);
+        xml.leaf("wscn:ImagesToTransfer", self.images_to_transfer);
+        xml.leaf("wscn:InputSource", self.input_source);
+        xml.open("wscn:MediaSides");
+        let sides: &[&str] = match self.input_source {
+            InputSource::AdfDuplex => &["wscn:MediaFront", "wscn:MediaBack"],
+            _ => &["wscn:MediaFront"],
+        };
+        for side in sides {
+            xml.open(side);
+            xml.leaf("wscn:ColorProcessing", &self.color);
+            xml.open("wscn:Resolution");
+            xml.leaf("wscn:Width", self.resolution.0);
+            xml.leaf("wscn:Height", self.resolution.1);
+            xml.close("wscn:Resolution");
+            xml.close(side);
+        }
+        xml.close("wscn:MediaSides");
+        xml.close(element);
+    }
+
+    fn bits_per_pixel(&self) -> u32 {
+        match self.color.as_str() {
+            "BlackAndWhite1" => 1,
+            "Grayscale4" => 4,
+            "Grayscale8" => 8,
+            "Grayscale16" => 16,
+            "RGBa32" => 32,
+            "RGB48" => 48,
+            "RGBa64" => 64,
+            _ => 24,
+        }
+    }
+}
+
+// 支持首选值或没有可选值时使用首选值，否则使用第一个
+fn preferred<T: PartialEq + Copy>(values: &[T], preference: T) -> T {
+    if values.is_empty() || values.contains(&preference) {
+        preference
+    } else {
+        values[0]
+    }
+}
+
+// eSCL 的 MIME 类型对应的 WS-Scan 格式名
+fn format_name(mime: &str) -> Option<&'static str> {
+    match mime {
+        "image/jpeg" => Some("jfif"),
+        "application/pdf" => Some("pdf-a"),
+        "image/png" => Some("png"),
+        "image/tiff" => Some("tiff-single-uncompressed"),
+        "image/bmp" => Some("dib"),
+        "application/oxps" | "application/vnd.ms-xpsdocument" => Some("xps"),
+        _ => None,
+    }
+}
+
+fn formats(input: &InputCaps) -> Vec<&'static str> {
+    let mut names = Vec::new();
+    for mime in input
+        .setting_profiles
+        .iter()
+        .flat_map(|profile| profile.document_formats.iter())
+    {
+        if let Some(name) = format_name(mime) {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+    }
+    names
+}
+
+fn color_modes(input: &InputCaps) -> Vec<&'static str> {
+    let mut modes = Vec::new();
+    for mode in input
+        .setting_profiles
+        .iter()
+        .flat_map(|profile| profile.color_modes.iter())
+    {
+        if !modes.contains(&mode.as_str()) {
+            modes.push(mode.as_str());
+        }
+    }
+    modes
+}
+
+// 支持的水平和垂直分辨率，范围只列出最小、常用和最大值
+fn resolutions(input: &InputCaps) -> (Vec<u32>, Vec<u32>) {
+    let mut widths = Vec::new();
+    let mut heights = Vec::new();
+    for profile in &input.setting_profiles {
+        let (x, y): (Vec<u32>, Vec<u32>) = match &profile.resolutions {
+            SupportedResolutions::Discrete(resolutions) => {
+                resolutions.iter().map(|r| (r.x, r.y)).unzip()
+            }
+            SupportedResolutions::Range { x, y } => {
+                (vec![x.min, x.normal, x.max], vec![y.min, y.normal, y.max])
+            }
+        };
+        for (values, list) in [(x, &mut widths), (y, &mut heights)] {
+            for value in values {
+                if !list.contains(&value) {
+                    list.push(value);
+                }
+            }
+        }
+    }
+    widths.sort_unstable();
+    heights.sort_unstable();
+    (widths, heights)
+}
+
+fn supports_resolution(input: &InputCaps, (width, height): (u32, u32)) -> bool {
+    input
+        .setting_profiles
+        .iter()
+        .any(|profile| match &profile.resolutions {
+            SupportedResolutions::Discrete(resolutions) => {
+                resolutions.iter().any(|r| r.x == width && r.y == height)
+            }
+            SupportedResolutions::Range { x, y } => {
+                (x.min..=x.max).contains(&width)
+                    && (y.min..=y.max).contains(&height)
+                    && (width - x.min) % x.step == 0
+                    && (height - y.min) % y.step == 0
+            }
+        })
+}
+
+// WS-Scan 的尺寸单位是 1/1000 英寸，eSCL 是 1/300 英寸
+fn thousandths(escl: u32) -> u32 {
+    escl * 10 / 3
+}
+
+/// `wscn:JobState` and `wscn:JobStateReason` of a job.
+fn job_state(state: JobState) -> (&'static str, &'static str) {
+    match state {
+        JobState::Processing => ("Processing", "JobScanning"),
+        JobState::Completed => ("Completed", "None"),
+        JobState::Canceled => ("Canceled", "None"),
+    }
+}
+
+fn response(request: &Envelope, operation: &str, body: XmlWriter) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("application/soap+xml; charset=utf-8")
+        .insert_header(("Cache-Control", "no-cache"))
+        .body(envelope_for(request, operation, &body.finish()))
+}
+
+/// The WS-Scan operation of `action`, `None` for other actions.
+pub(crate) fn operation(action: &str) -> Option<&str> {
+    let operation = action.strip_prefix(NS_SCAN)?.strip_prefix('/')?;
+    OPERATIONS.contains(&operation).then_some(operation)
+}
+
+/// Answers a WS-Scan operation, `None` if the action isn't one.
+pub(crate) async fn handle(
+    req: &HttpRequest,
+    data: &AppState,
+    envelope: &Envelope<'_, '_>,
+) -> Option<HttpResponse> {
+    let operation = operation(&envelope.action)?;
+    let relates_to = envelope.message_id.as_deref();
+    let Some(request) = child(envelope.body, NS_SCAN, &format!("{operation}Request")) else {
+        warn!(operation, "WS-Scan request without request element");
+        return Some(fault(
+            relates_to,
+            FaultCode::Sender,
+            "wscn:InvalidArgs",
+            &format!("missing {operation}Request"),
+        ));
+    };
+    let response = match operation {
+        "GetScannerElements" => get_scanner_elements(data, envelope, request).await,
+        "ValidateScanTicket" => validate_scan_ticket(req, data, envelope, request).await,
+        "CreateScanJob" => create_scan_job(req, data, envelope, request).await,
+        "RetrieveImage" => retrieve_image(req, data, envelope, request).await,
+        "CancelJob" => cancel_job(req, data, envelope, request).await,
+        "GetJobElements" => get_job_elements(data, envelope, request).await,
+        _ => get_active_jobs(data, envelope).await,
+    };
+    Some(response)
+}
+
+// RequestedElements 中的名称，命名空间不是 WS-Scan 时 local 为 None
+fn requested_elements(request: Node) -> Vec<(String, Option<String>)> {
+    let Some(elements) = child(request, NS_SCAN, "RequestedElements") else {
+        return Vec::new();
+    };
+    elements
+        .children()
+        .filter(|node| {
+            node.tag_name().namespace() == Some(NS_SCAN) && node.tag_name().name() == "Name"
+        })
+        .filter_map(|node| {
+            let value = node.text()?.trim();
+            let (namespace, local) = wsd::qname(node, value);
+            Some(match namespace == NS_SCAN {
+                true => (format!("wscn:{local}"), Some(local)),
+                false => (value.to_owned(), None),
+            })
+        })
+        .collect()
+}
+
+async fn get_scanner_elements(
+    data: &AppState,
+    envelope: &Envelope<'_, '_>,
+    request: Node<'_, '_>,
+) -> HttpResponse {
+    let state = scanner_state(data, &*data.scan_jobs.lock().await);
+    let live = data.live();
+    let mut xml = XmlWriter::fragment(2);
+    xml.open("wscn:GetScannerElementsResponse");
+    xml.open("wscn:ScannerElements");
+    for (name, local) in requested_elements(request) {
+        let valid = matches!(
+            local.as_deref(),
+            Some(
+                "ScannerDescription"
+                    | "ScannerConfiguration"
+                    | "ScannerStatus"
+                    | "DefaultScanTicket"
+            )
+        );
+        xml.open_with(
+            "wscn:ElementData",
+            &[
+                ("Name", &name),
+                ("Valid", if valid { "true" } else { "false" }),
+            ],
+        );
+        match local.as_deref() {
+            Some("ScannerDescription") => {
+                xml.open("wscn:ScannerDescription");
+                xml.leaf("wscn:ScannerName", &data.identity.friendly_name);
+                xml.leaf("wscn:ScannerInfo", data.identity.make_and_model());
+                xml.close("wscn:ScannerDescription");
+            }
+            Some("ScannerConfiguration") => write_configuration(&mut xml, &live.capabilities),
+            Some("ScannerStatus") => write_status(&mut xml, state),
+            Some("DefaultScanTicket") => ScanTicket::default_for(&live.capabilities)
+                .write(&mut xml, "wscn:DefaultScanTicket"),
+            _ => {}
+        }
+        xml.close("wscn:ElementData");
+    }
+    xml.close("wscn:ScannerElements");
+    xml.close("wscn:GetScannerElementsResponse");
+    response(envelope, "GetScannerElements", xml)
+}
+
+fn write_configuration(xml: &mut XmlWriter, capabilities: &ScannerCapabilities) {
+    let inputs: Vec<&InputCaps> = capabilities.inputs().collect();
+    xml.open("wscn:ScannerConfiguration");
+    xml.open("wscn:DeviceSettings");
+    xml.open("wscn:FormatsSupported");
+    let mut formats_supported = Vec::new();
+    for format in inputs.iter().flat_map(|input| formats(input)) {
+        if !formats_supported.contains(&format) {
+            formats_supported.push(format);
+            xml.leaf("wscn:FormatValue", format);
+        }
+    }
+    xml.close("wscn:FormatsSupported");
+    xml.open("wscn:CompressionQualityFactorSupported");
+    xml.leaf("wscn:MinValue", 0);
+    xml.leaf("wscn:MaxValue", 100);
+    xml.close("wscn:CompressionQualityFactorSupported");
+    // 扫描意图对应的内容类型
+    xml.open("wscn:ContentTypesSupported");
+    xml.leaf("wscn:ContentTypeValue", "Auto");
+    let mut content_types = Vec::new();
+    for intent in inputs.iter().flat_map(|input| input.intents.iter()) {
+        let content_type = match intent {
+            Intent::Document => "Text",
+            Intent::TextAndGraphic => "Mixed",
+            Intent::Photo => "Photo",
+            _ => continue,
+        };
+        if !content_types.contains(&content_type) {
+            content_types.push(content_type);
+            xml.leaf("wscn:ContentTypeValue", content_type);
+        }
+    }
+    xml.close("wscn:ContentTypesSupported");
+    for setting in [
+        "DocumentSizeAutoDetectSupported",
+        "AutoExposureSupported",
+        "BrightnessSupported",
+        "ContrastSupported",
+    ] {
+        xml.leaf(&format!("wscn:{setting}"), false);
+    }
+    xml.open("wscn:ScalingRangeSupported");
+    for dimension in ["wscn:ScalingWidth", "wscn:ScalingHeight"] {
+        xml.open(dimension);
+        xml.leaf("wscn:MinValue", 100);
+        xml.leaf("wscn:MaxValue", 100);
+        xml.close(dimension);
+    }
+    xml.close("wscn:ScalingRangeSupported");
+    xml.open("wscn:RotationsSupported");
+    xml.leaf("wscn:RotationValue", 0);
+    xml.close("wscn:RotationsSupported");
+    xml.close("wscn:DeviceSettings");
+
+    if let Some(platen) = &capabilities.platen {
+        xml.open("wscn:Platen");
+        write_input(xml, "Platen", platen);
+        xml.close("wscn:Platen");
+    }
+    if let Some(adf) = &capabilities.adf {
+        xml.open("wscn:ADF");
+        xml.leaf("wscn:ADFSupportsDuplex", adf.duplex.is_some());
+        xml.open("wscn:ADFFront");
+        write_input(xml, "ADF", &adf.simplex);
+        xml.close("wscn:ADFFront");
+        if let Some(duplex) = &adf.duplex {
+            xml.open("wscn:ADFBack");
+            write_input(xml, "ADF", duplex);
+            xml.close("wscn:ADFBack");
+        }
+        xml.close("wscn:ADF");
+    }
+    xml.close("wscn:ScannerConfiguration");
+}
+
+// Platen 和 ADFFront/ADFBack 的内容，元素名以 prefix 开头
+fn write_input(xml: &mut XmlWriter, prefix: &str, input: &InputCaps) {
+    xml.open(&format!("wscn:{prefix}Color"));
+    for mode in color_modes(input) {
+        xml.leaf("wscn:ColorEntry", mode);
+    }
+    xml.close(&format!("wscn:{prefix}Color"));
+    let sizes = [
+        ("MinimumSize", input.min_width, input.min_height),
+        ("MaximumSize", input.max_width, input.max_height),
+    ];
+    for (name, width, height) in sizes {
+        let element = format!("wscn:{prefix}{name}");
+        xml.open(&element);
+        xml.leaf("wscn:Width", thousandths(width));
+        xml.leaf("wscn:Height", thousandths(height));
+        xml.close(&element);
+    }
+    let (widths, heights) = resolutions(input);
+    let optical = format!("wscn:{prefix}OpticalResolution");
+    xml.open(&optical);
+    xml.leaf(
+        "wscn:Width",
+        input
+            .max_optical_x_resolution
+            .unwrap_or(widths.last().copied().unwrap_or(300)),
+    );
+    xml.leaf(
+        "wscn:Height",
+        input
+            .max_optical_y_resolution
+            .unwrap_or(heights.last().copied().unwrap_or(300)),
+    );
+    xml.close(&optical);
+    let element = format!("wscn:{prefix}Resolutions");
+    xml.open(&element);
+    xml.open("wscn:Widths");
+    for width in widths {
+        xml.leaf("wscn:Width", width);
+    }
+    xml.close("wscn:Widths");
+    xml.open("wscn:Heights");
+    for height in heights {
+        xml.leaf("wscn:Height", height);
+    }
+    xml.close("wscn:Heights");
+    xml.close(&element);
+}
+
+// 忙碌或有任务正在扫描时报告 Processing
+pub(crate) fn scanner_state(data: &AppState, jobs: &HashMap<Uuid, ScanJob>) -> &'static str {
+    if data.live().faults.busy || jobs.values().any(|job| job.state == JobState::Processing) {
+        "Processing"
+    } else {
+        "Idle"
+    }
+}
+
+fn write_status(xml: &mut XmlWriter, state: &str) {
+    let now = OffsetDateTime::now_utc()
+        .replace_nanosecond(0)
+        .ok()
+        .and_then(|now| now.format(&Rfc3339).ok());
+    xml.open("wscn:ScannerStatus");
+    xml.leaf("wscn:ScannerCurrentTime", now.unwrap_or_default());
+    xml.leaf("wscn:ScannerState", state);
+    xml.open("wscn:ScannerStateReasons");
+    xml.leaf("wscn:ScannerStateReason", "None");
+    xml.close("wscn:ScannerStateReasons");
+    xml.close("wscn:ScannerStatus");
+}
+
+/// Pushes a JobStatusEvent for the job `uuid` to the event subscribers, and a
+/// ScannerStatusSummaryEvent if the scanner state changed with it. Takes the
+/// locked job table so the events see the change that triggered them.
+pub(crate) async fn job_changed(data: &AppState, jobs: &HashMap<Uuid, ScanJob>, uuid: Uuid) {
+    let state = scanner_state(data, jobs);
+    let state_changed = data.subscriptions.scanner_state_changed(state).await;
+    if data.subscriptions.is_empty().await {
+        return;
+    }
+    if let Some(job) = jobs.get(&uuid) {
+        let mut xml = XmlWriter::fragment(2);
+        xml.open("wscn:JobStatusEvent");
+        write_job_status(&mut xml, job);
+        xml.close("wscn:JobStatusEvent");
+        data.subscriptions
+            .publish("JobStatusEvent", &xml.finish())
+            .await;
+    }
+    if state_changed {
+        let mut xml = XmlWriter::fragment(2);
+        xml.open("wscn:ScannerStatusSummaryEvent");
+        xml.open("wscn:StatusSummary");
+        xml.leaf("wscn:ScannerState", state);
+        xml.open("wscn:ScannerStateReasons");
+        xml.leaf("wscn:ScannerStateReason", "None");
+        xml.close("wscn:ScannerStateReasons");
+        xml.close("wscn:StatusSummary");
+        xml.close("wscn:ScannerStatusSummaryEvent");
+        data.subscriptions
+            .publish("ScannerStatusSummaryEvent", &xml.finish())
+            .await;
+    }
+}
+
+fn parse_ticket(capabilities: &ScannerCapabilities, request: Node) -> Result<ScanTicket, String> {
+    let ticket = child(request, NS_SCAN, "ScanTicket").ok_or("missing ScanTicket")?;
+    ScanTicket::parse(ticket, ScanTicket::default_for(capabilities))
+}
+
+// 页面的尺寸取自实际返回的图片
+async fn write_image_information(xml: &mut XmlWriter, data: &AppState, ticket: &ScanTicket) {
+    let image = escl_server::read_page_image(data, 1).await;
+    let (width, height) = escl_server::jpeg_dimensions(&image).unwrap_or((2550, 3300));
+    xml.open("wscn:ImageInformation");
+    xml.open("wscn:MediaFrontImageInfo");
+    xml.leaf("wscn:PixelsPerLine", width);
+    xml.leaf("wscn:NumberOfLines", height);
+    xml.leaf(
+        "wscn:BytesPerLine",
+        (width * ticket.bits_per_pixel()).div_ceil(8),
+    );
+    xml.close("wscn:MediaFrontImageInfo");
+    xml.close("wscn:ImageInformation");
+}
+
+async fn validate_scan_ticket(
+    req: &HttpRequest,
+    data: &AppState,
+    envelope: &Envelope<'_, '_>,
+    request: Node<'_, '_>,
+) -> HttpResponse {
+    let live = data.live();
+    let ticket = match parse_ticket(&live.capabilities, request) {
+        Ok(ticket) => ticket,
+        Err(reason) => {
+            return fault(
+                envelope.message_id.as_deref(),
+                FaultCode::Sender,
+                "wscn:InvalidArgs",
+                &reason,
+            )
+        }
+    };
+    let result = ticket.check(&live.capabilities);
+    escl_server::record_event(req, ClientEvent::TicketChecked(result.clone().err()));
+
+    let mut xml = XmlWriter::fragment(2);
+    xml.open("wscn:ValidateScanTicketResponse");
+    xml.open("wscn:ValidationInfo");
+    xml.leaf("wscn:ValidTicket", result.is_ok());
+    if result.is_ok() {
+        write_image_information(&mut xml, data, &ticket).await;
+        ticket.write_parameters(&mut xml, "wscn:DocumentFinalParameters");
+    }
+    xml.close("wscn:ValidationInfo");
+    xml.close("wscn:ValidateScanTicketResponse");
+    response(envelope, "ValidateScanTicket", xml)
+}
+
+async fn create_scan_job(
+    req: &HttpRequest,
+    data: &AppState,
+    envelope: &Envelope<'_, '_>,
+    request: Node<'_, '_>,
+) -> HttpResponse {
+    let relates_to = envelope.message_id.as_deref();
+    let live = data.live();
+    if !live.faults.scan_job_delay.is_zero() {
+        tokio::time::sleep(live.faults.scan_job_delay).await;
+    }
+    let mut ticket = match parse_ticket(&live.capabilities, request) {
+        Ok(ticket) => ticket,
+        Err(reason) => return fault(relates_to, FaultCode::Sender, "wscn:InvalidArgs", &reason),
+    };
+    // 与 eSCL 的 ScanSettings 相同，只在严格模式下拒绝不支持的参数
+    let result = ticket.check(&live.capabilities);
+    escl_server::record_event(req, ClientEvent::TicketChecked(result.clone().err()));
+    if let Err(reason) = result {
+        if data.validation.xml || data.validation.strict {
+            warn!(error = %reason, "ScanTicket doesn't match the capabilities");
+        }
+        if data.validation.strict {
+            return fault(relates_to, FaultCode::Sender, "wscn:InvalidArgs", &reason);
+        }
+    }
+    if live.faults.busy {
+        warn!("Scanner is configured as busy, rejecting scan job");
+        return fault(
+            relates_to,
+            FaultCode::Receiver,
+            "wscn:ServerErrorNotAcceptingJobs",
+            "The scanner is busy",
+        );
+    }
+
+    // 按下扫描按钮后，目标客户端用 ScanIdentifier 认领设备创建的任务
+    if let Some(identifier) = text(request, NS_SCAN, "ScanIdentifier") {
+        let token = text(request, NS_SCAN, "DestinationToken");
+        let Some((uuid, id, max_pages)) =
+            claim_button_job(data, &identifier, token.as_deref(), &mut ticket).await
+        else {
+            warn!(
+                scan_identifier = identifier,
+                "CreateScanJob for unknown scan identifier"
+            );
+            return fault(
+                relates_to,
+                FaultCode::Sender,
+                "wscn:InvalidArgs",
+                &format!("no scan available for {identifier}"),
+            );
+        };
+        tracing::Span::current().record("job", tracing::field::display(uuid));
+        escl_server::record_event(
+            req,
+            ClientEvent::JobCreated {
+                job: uuid,
+                pages: max_pages,
+            },
+        );
+        return create_scan_job_response(data, envelope, uuid, id, &ticket).await;
+    }
+
+    let (scan_source, duplex) = ticket.input_source.scan_source();
+    let mut max_pages = live.sources.pages(scan_source, duplex);
+    if ticket.images_to_transfer > 0 {
+        max_pages = max_pages.min(ticket.images_to_transfer);
+    }
+    let uuid = Uuid::new_v4();
+    let id = data.next_job_id();
+    tracing::Span::current().record("job", tracing::field::display(uuid));
+    info!(target: JOB_TARGET, job = %uuid, id, source = ?scan_source, duplex, max_pages, "WS-Scan job created");
+
+    let mut job = ScanJob::new(id, scan_source, max_pages);
+    job.ticket = Some(ticket.clone());
+    let mut jobs_guard = data.scan_jobs.lock().await;
+    model::insert_job(&mut jobs_guard, uuid, job);
+    job_changed(data, &jobs_guard, uuid).await;
+    drop(jobs_guard);
+    data.metrics.job_created(scan_source);
+    escl_server::record_event(
+        req,
+        ClientEvent::JobCreated {
+            job: uuid,
+            pages: max_pages,
+        },
+    );
+    create_scan_job_response(data, envelope, uuid, id, &ticket).await
+}
+
+async fn create_scan_job_response(
+    data: &AppState,
+    envelope: &Envelope<'_, '_>,
+    uuid: Uuid,
+    id: u32,
+    ticket: &ScanTicket,
+) -> HttpResponse {
+    let mut xml = XmlWriter::fragment(2);
+    xml.open("wscn:CreateScanJobResponse");
+    xml.leaf("wscn:JobId", id);
+    xml.leaf("wscn:JobToken", uuid);
+    write_image_information(&mut xml, data, ticket).await;
+    ticket.write_parameters(&mut xml, "wscn:DocumentFinalParameters");
+    xml.close("wscn:CreateScanJobResponse");
+    response(envelope, "CreateScanJob", xml)
+}
+
+// 扫描源由按下按钮时决定，客户端的扫描参数只能减少页数
+async fn claim_button_job(
+    data: &AppState,
+    identifier: &str,
+    token: Option<&str>,
+    ticket: &mut ScanTicket,
+) -> Option<(Uuid, u32, u32)> {
+    let uuid = Uuid::parse_str(identifier).ok()?;
+    let mut jobs_guard = data.scan_jobs.lock().await;
+    let job = jobs_guard.get_mut(&uuid)?;
+    // 只能认领一次，请求带 DestinationToken 时还要与之匹配
+    let expected = job.destination_token.as_deref()?;
+    if job.state != JobState::Processing || token.is_some_and(|token| token != expected) {
+        return None;
+    }
+    job.destination_token = None;
+    if let Some(button_ticket) = &job.ticket {
+        ticket.input_source = button_ticket.input_source;
+    }
+    // eSCL 客户端可能已经取走了部分页面，页数不能少于已取走的页数
+    if ticket.images_to_transfer > 0 {
+        job.max_pages = job
+            .max_pages
+            .min(ticket.images_to_transfer)
+            .max(job.retrieved_pages);
+    }
+    job.ticket = Some(ticket.clone());
+    info!(target: JOB_TARGET, job = %uuid, id = job.id, max_pages = job.max_pages, "Scan button job claimed");
+    let claimed = (uuid, job.id, job.max_pages);
+    if job.retrieved_pages == job.max_pages {
+        job.finish(JobState::Completed);
+        data.metrics.job_completed(job.scan_source);
+        info!(target: JOB_TARGET, job = %uuid, pages = job.retrieved_pages, "Scan job completed");
+        job_changed(data, &jobs_guard, uuid).await;
+    }
+    Some(claimed)
+}
+
+/// Why the scan button couldn't start a scan.
+pub(crate) enum ButtonError {
+    UnknownSource(String),
+    UnknownDestination(String),
+    Busy,
+}
+
+/// A scan started with [`press_scan_button`].
+pub(crate) struct ButtonScan {
+    pub uuid: Uuid,
+    pub source: InputSource,
+    pub max_pages: u32,
+    pub destination: Option<Destination>,
+    /// Subscriptions sent ScanAvailableEvent.
+    pub notified: usize,
+}
+
+/// Presses the scan button on the device's front panel: scans `source` (the
+/// ADF if the device has one by default) for the scan destination `name`.
+/// The job is in the job table right away so eSCL clients can fetch it; the
+/// destination's WS-Scan client is sent ScanAvailableEvent and claims the
+/// job with CreateScanJob. Without registered destinations only the eSCL
+/// job is created.
+pub(crate) async fn press_scan_button(
+    data: &AppState,
+    name: Option<&str>,
+    source: Option<&str>,
+) -> Result<ButtonScan, ButtonError> {
+    let live = data.live();
+    let source = match source {
+        Some(value) => {
+            InputSource::parse(value).ok_or_else(|| ButtonError::UnknownSource(value.to_owned()))?
+        }
+        None if live.capabilities.adf.is_some() => InputSource::Adf,
+        None => InputSource::Platen,
+    };
+    if source.caps(&live.capabilities).is_none() {
+        return Err(ButtonError::UnknownSource(source.to_string()));
+    }
+    let destination = data.subscriptions.destination(name).await;
+    if let (Some(name), None) = (name, &destination) {
+        return Err(ButtonError::UnknownDestination(name.to_owned()));
+    }
+    if live.faults.busy {
+        warn!("Scanner is configured as busy, ignoring the scan button");
+        return Err(ButtonError::Busy);
+    }
+
+    let (scan_source, duplex) = source.scan_source();
+    let max_pages = live.sources.pages(scan_source, duplex);
+    let uuid = Uuid::new_v4();
+    let id = data.next_job_id();
+    let mut job = ScanJob::new(id, scan_source, max_pages);
+    let mut ticket = ScanTicket::default_for(&live.capabilities);
+    ticket.input_source = source;
+    job.ticket = Some(ticket);
+    job.destination_token = destination
+        .as_ref()
+        .map(|destination| destination.token.clone());
+    info!(
+        target: JOB_TARGET,
+        job = %uuid,
+        id,
+        source = %source,
+        max_pages,
+        destination = destination.as_ref().map(|destination| destination.display_name.as_str()),
+        "Scan button pressed"
+    );
+
+    let mut jobs_guard = data.scan_jobs.lock().await;
+    model::insert_job(&mut jobs_guard, uuid, job);
+    job_changed(data, &jobs_guard, uuid).await;
+    drop(jobs_guard);
+    data.metrics.job_created(scan_source);
+
+    let notified = match &destination {
+        Some(destination) => data.subscriptions.scan_available(destination, uuid).await,
+        None => 0,
+    };
+    Ok(ButtonScan {
+        uuid,
+        source,
+        max_pages,
+        destination,
+        notified,
+    })
+}
+
+// 按 JobId 查找任务，请求带 JobToken 时还要与之匹配。RetrieveImage 必须带
+// JobToken，否则按顺序递增的 JobId 就足以取走其他客户端的页面
+async fn find_job(data: &AppState, request: Node<'_, '_>, token_required: bool) -> Option<Uuid> {
+    let id: u32 = text(request, NS_SCAN, "JobId")?.parse().ok()?;
+    let token = text(request, NS_SCAN, "JobToken");
+    if token_required && token.is_none() {
+        return None;
+    }
+    let jobs_guard = data.scan_jobs.lock().await;
+    jobs_guard
+        .iter()
+        .find(|(uuid, job)| {
+            job.id == id
+                && token
+                    .as_ref()
+                    .is_none_or(|token| *token == uuid.to_string())
+        })
+        .map(|(uuid, _)| *uuid)
+}
+
+fn job_not_found(envelope: &Envelope<'_, '_>) -> HttpResponse {
+    fault(
+        envelope.message_id.as_deref(),
+        FaultCode::Sender,
+        "wscn:ClientErrorJobIdNotFound",
+        "No job with this JobId and JobToken",
+    )
+}
+
+async fn retrieve_image(
+    req: &HttpRequest,
+    data: &AppState,
+    envelope: &Envelope<'_, '_>,
+    request: Node<'_, '_>,
+) -> HttpResponse {
+    let Some(uuid) = find_job(data, request, true).await else {
+        return job_not_found(envelope);
+    };
+    tracing::Span::current().record("job", tracing::field::display(uuid));
+    let (page, max_pages, scan_source) = match escl_server::take_page(data, uuid).await {
+        Ok(page) => page,
+        Err(PageError::UnknownJob) => return job_not_found(envelope),
+        Err(PageError::NoMorePages) => {
+            return fault(
+                envelope.message_id.as_deref(),
+                FaultCode::Sender,
+                "wscn:ClientErrorNoImagesAvailable",
+                "The job has no more images",
+            )
+        }
+    };
+    escl_server::record_event(req, ClientEvent::PageRetrieved { job: uuid });
+
+    let delay = data.live().faults.next_document_delay;
+    if !delay.is_zero() {
+        tokio::time::sleep(delay).await;
+    }
+    data.metrics.page_served(scan_source);
+    info!(target: JOB_TARGET, job = %uuid, page, max_pages, source = %scan_source, "Serving page");
+    let image = escl_server::read_page_image(data, page).await;
+    data.metrics.bytes_served.inc_by(image.len() as u64);
+
+    // MTOM：SOAP 信封和图片是 multipart/related 的两个部分，图片通过 xop:Include 引用
+    let body = format!(
+        r#"        <wscn:RetrieveImageResponse>
+            <wscn:ScanData>
+                <xop:Include xmlns:xop="{NS_XOP}" href="cid:{IMAGE_PART_ID}"/>
+            </wscn:ScanData>
+        </wscn:RetrieveImageResponse>"#
+    );
+    let soap = envelope_for(envelope, "RetrieveImage", &body);
+    let mut multipart = Vec::with_capacity(soap.len() + image.len() + 512);
+    multipart.extend_from_slice(
+        format!(
+            "--{MIME_BOUNDARY}\r\nContent-Type: application/xop+xml; charset=UTF-8; type=\"application/soap+xml\"\r\n\
+             Content-Transfer-Encoding: binary\r\nContent-ID: <{SOAP_PART_ID}>\r\n\r\n{soap}\r\n"
+        )
+        .as_bytes(),
+    );
+    multipart.extend_from_slice(
+        format!(
+            "--{MIME_BOUNDARY}\r\nContent-Type: application/binary\r\n\
+             Content-Transfer-Encoding: binary\r\nContent-ID: <{IMAGE_PART_ID}>\r\n\r\n"
+        )
+        .as_bytes(),
+    );
+    multipart.extend_from_slice(&image);
+    multipart.extend_from_slice(format!("\r\n--{MIME_BOUNDARY}--\r\n").as_bytes());
+    HttpResponse::Ok()
+        .content_type(format!(
+            "multipart/related; type=\"application/xop+xml\"; boundary=\"{MIME_BOUNDARY}\"; \
+             start=\"<{SOAP_PART_ID}>\"; start-info=\"application/soap+xml\""
+        ))
+        .body(multipart)
+}
+
+fn envelope_for(request: &Envelope<'_, '_>, operation: &str, body: &str) -> String {
+    wsd::soap_response(
+        &format!("{NS_SCAN}/{operation}Response"),
+        request.message_id.as_deref(),
+        body,
+    )
+}
+
+async fn cancel_job(
+    req: &HttpRequest,
+    data: &AppState,
+    envelope: &Envelope<'_, '_>,
+    request: Node<'_, '_>,
+) -> HttpResponse {
+    let Some(uuid) = find_job(data, request, false).await else {
+        return job_not_found(envelope);
+    };
+    tracing::Span::current().record("job", tracing::field::display(uuid));
+    if !escl_server::cancel_scan_job(data, uuid).await {
+        return job_not_found(envelope);
+    }
+    escl_server::record_event(req, ClientEvent::JobDeleted { job: uuid });
+    let mut xml = XmlWriter::fragment(2);
+    xml.open("wscn:CancelJobResponse");
+    xml.close("wscn:CancelJobResponse");
+    response(envelope, "CancelJob", xml)
+}
+
+fn write_job_status(xml: &mut XmlWriter, job: &ScanJob) {
+    let (state, reason) = job_state(job.state);
+    xml.open("wscn:JobStatus");
+    xml.leaf("wscn:JobId", job.id);
+    xml.leaf("wscn:JobState", state);
+    xml.open("wscn:JobStateReasons");
+    xml.leaf("wscn:JobStateReason", reason);
+    xml.close("wscn:JobStateReasons");
+    xml.leaf("wscn:ScansCompleted", job.retrieved_pages);
+    xml.close("wscn:JobStatus");
+}
+
+async fn get_job_elements(
+    data: &AppState,
+    envelope: &Envelope<'_, '_>,
+    request: Node<'_, '_>,
+) -> HttpResponse {
+    let Some(uuid) = find_job(data, request, false).await else {
+        return job_not_found(envelope);
+    };
+    let jobs_guard = data.scan_jobs.lock().await;
+    let Some(job) = jobs_guard.get(&uuid) else {
+        return job_not_found(envelope);
+    };
+    let mut xml = XmlWriter::fragment(2);
+    xml.open("wscn:GetJobElementsResponse");
+    xml.open("wscn:JobElements");
+    for (name, local) in requested_elements(request) {
+        // eSCL 任务没有 ScanTicket
+        let valid = match local.as_deref() {
+            Some("JobStatus") => true,
+            Some("ScanTicket") => job.ticket.is_some(),
+            _ => false,
+        };
+        xml.open_with(
+            "wscn:ElementData",
+            &[
+                ("Name", &name),
+                ("Valid", if valid { "true" } else { "false" }),
+            ],
+        );
+        match (local.as_deref(), &job.ticket) {
+            (Some("JobStatus"), _) => write_job_status(&mut xml, job),
+            (Some("ScanTicket"), Some(ticket)) => ticket.write(&mut xml, "wscn:ScanTicket"),
+            _ => {}
+        }
+        xml.close("wscn:ElementData");
+    }
+    xml.close("wscn:JobElements");
+    xml.close("wscn:GetJobElementsResponse");
+    response(envelope, "GetJobElements", xml)
+}
+
+// eSCL 创建的任务也会列出
+async fn get_active_jobs(data: &AppState, envelope: &Envelope<'_, '_>) -> HttpResponse {
+    let jobs_guard = data.scan_jobs.lock().await;
+    let mut jobs: Vec<&ScanJob> = jobs_guard
+        .values()
+        .filter(|job| job.state == JobState::Processing)
+        .collect();
+    jobs.sort_by_key(|job| job.created);
+
+    let mut xml = XmlWriter::fragment(2);
+    xml.open("wscn:GetActiveJobsResponse");
+    xml.open("wscn:ActiveJobs");
+    for job in jobs {
+        let (state, reason) = job_state(job.state);
+        xml.open("wscn:JobSummary");
+        xml.leaf("wscn:JobId", job.id);
+        xml.leaf(
+            "wscn:JobName",
+            job.ticket
+                .as_ref()
+                .map_or("", |ticket| ticket.name.as_str()),
+        );
+        xml.leaf(
+            "wscn:JobOriginatingUserName",
+            job.ticket
+                .as_ref()
+                .map_or("", |ticket| ticket.user.as_str()),
+        );
+        xml.leaf("wscn:JobState", state);
+        xml.open("wscn:JobStateReasons");
+        xml.leaf("wscn:JobStateReason", reason);
+        xml.close("wscn:JobStateReasons");
+        xml.leaf("wscn:ScansCompleted", job.retrieved_pages);
+        xml.close("wscn:JobSummary");
+    }
+    xml.close("wscn:ActiveJobs");
+    xml.close("wscn:GetActiveJobsResponse");
+    response(envelope, "GetActiveJobs", xml)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::Cli;
+    use crate::config;
+    use crate::metrics::Metrics;
+    use crate::report::ConformanceReport;
+    use crate::wsd::{NS_ADDRESSING, NS_SOAP};
+    use actix_web::http::StatusCode;
+    use actix_web::test::{call_service, init_service, read_body, TestRequest};
+    use actix_web::{web, App};
+    use clap::Parser;
+    use roxmltree::Document;
+    use std::sync::Arc;
+
+    fn state() -> web::Data<AppState> {
+        let args = Cli::parse_from(["escl-mock-server"]);
+        let settings = config::resolve(&args, None).unwrap();
+        web::Data::new(AppState::new(
+            &settings.devices[0],
+            settings.validation,
+            settings.bind_address,
+            None,
+            Arc::new(Metrics::new()),
+            Arc::new(ConformanceReport::new(false)),
+        ))
+    }
+
+    fn ticket(parameters: &str) -> Result<ScanTicket, String> {
+        let xml =
+            format!(r#"<wscn:ScanTicket xmlns:wscn="{NS_SCAN}">{parameters}</wscn:ScanTicket>"#);
+        let document = Document::parse(&xml).unwrap();
+        let capabilities = ScannerCapabilities::default();
+        ScanTicket::parse(
+            document.root_element(),
+            ScanTicket::default_for(&capabilities),
+        )
+    }
+
+    fn envelope(operation: &str, request: &str) -> String {
+        format!(
+            r#"<soap:Envelope xmlns:soap="{NS_SOAP}" xmlns:wsa="{NS_ADDRESSING}" xmlns:wscn="{NS_SCAN}">
+<soap:Header><wsa:Action>{NS_SCAN}/{operation}</wsa:Action><wsa:MessageID>urn:uuid:{operation}</wsa:MessageID></soap:Header>
+<soap:Body><wscn:{operation}Request>{request}</wscn:{operation}Request></soap:Body>
+</soap:Envelope>"#
+        )
+    }
+
+    // 与 /wsd/scan 相同，按 wsa:Action 分派到扫描服务
+    async fn post(req: HttpRequest, body: String, data: web::Data<AppState>) -> HttpResponse {
+        let document = Document::parse(&body).unwrap();
+        let envelope = Envelope::parse(&document).unwrap();
+        handle(&req, &data, &envelope).await.unwrap()
+    }
+
+    // 响应中第一个 WS-Scan 元素 name 的文本
+    fn element(xml: &str, name: &str) -> Option<String> {
+        let document = Document::parse(xml).unwrap();
+        let node = document.descendants().find(|node| {
+            node.tag_name().namespace() == Some(NS_SCAN) && node.tag_name().name() == name
+        })?;
+        Some(node.text().unwrap_or_default().trim().to_owned())
+    }
+
+    #[test]
+    fn missing_parameters_use_the_default_ticket() {
+        let ticket = ticket("").unwrap();
+        assert_eq!(ticket.name, "Scan");
+        assert_eq!(ticket.format, "jfif");
+        assert_eq!(ticket.images_to_transfer, 1);
+        assert_eq!(ticket.input_source, InputSource::Platen);
+        assert_eq!(ticket.color, "RGB24");
+        assert_eq!(ticket.resolution, (300, 300));
+        assert_eq!(ticket.check(&ScannerCapabilities::default()), Ok(()));
+
+        // 没有平板时默认使用输稿器
+        let capabilities = ScannerCapabilities::builder().platen(false).build();
+        assert_eq!(
+            ScanTicket::default_for(&capabilities).input_source,
+            InputSource::Adf
+        );
+    }
+
+    #[test]
+    fn parses_ticket_parameters() {
+        let ticket = ticket(
+            "<wscn:JobDescription><wscn:JobName>Invoice</wscn:JobName>\
+             <wscn:JobOriginatingUserName>alice</wscn:JobOriginatingUserName></wscn:JobDescription>\
+             <wscn:DocumentParameters><wscn:Format>pdf-a</wscn:Format>\
+             <wscn:ImagesToTransfer>0</wscn:ImagesToTransfer><wscn:InputSource>ADFDuplex</wscn:InputSource>\
+             <wscn:MediaSides><wscn:MediaFront><wscn:ColorProcessing>Grayscale8</wscn:ColorProcessing>\
+             <wscn:Resolution><wscn:Width>600</wscn:Width></wscn:Resolution></wscn:MediaFront></wscn:MediaSides>\
+             </wscn:DocumentParameters>",
+        )
+        .unwrap();
+        assert_eq!(
+            (ticket.name.as_str(), ticket.user.as_str()),
+            ("Invoice", "alice")
+        );
+        assert_eq!(ticket.format, "pdf-a");
+        assert_eq!(ticket.images_to_transfer, 0);
+        assert_eq!(ticket.input_source, InputSource::AdfDuplex);
+        assert_eq!(ticket.color, "Grayscale8");
+        // 缺少高度时与宽度相同
+        assert_eq!(ticket.resolution, (600, 600));
+        assert_eq!(ticket.bits_per_pixel(), 8);
+        assert_eq!(ticket.check(&ScannerCapabilities::default()), Ok(()));
+    }
+
+    #[test]
+    fn rejects_malformed_parameters() {
+        let parameters = |content: &str| {
+            ticket(&format!(
+                "<wscn:DocumentParameters>{content}</wscn:DocumentParameters>"
+            ))
+            .map(|_| ())
+        };
+        assert_eq!(
+            parameters("<wscn:ImagesToTransfer>two</wscn:ImagesToTransfer>"),
+            Err(r#"ImagesToTransfer "two" is not a number"#.to_owned())
+        );
+        assert_eq!(
+            parameters("<wscn:InputSource>Film</wscn:InputSource>"),
+            Err(r#"unknown InputSource "Film""#.to_owned())
+        );
+        assert_eq!(
+            parameters(
+                "<wscn:MediaSides><wscn:MediaFront><wscn:Resolution><wscn:Width>300</wscn:Width>\
+                 <wscn:Height>high</wscn:Height></wscn:Resolution></wscn:MediaFront></wscn:MediaSides>"
+            ),
+            Err(r#"resolution "high" is not a number"#.to_owned())
+        );
+    }
+
+    #[test]
+    fn check_rejects_unsupported_settings() {
+        let simplex = ScannerCapabilities::builder().adf_duplex(false).build();
+        let check = |parameters: &str| {
+            ticket(&format!(
+                "<wscn:DocumentParameters>{parameters}</wscn:DocumentParameters>"
+            ))
+            .unwrap()
+            .check(&simplex)
+        };
+        assert_eq!(
+            check("<wscn:InputSource>ADFDuplex</wscn:InputSource>"),
+            Err("input source ADFDuplex is not supported".to_owned())
+        );
+        assert_eq!(
+            check("<wscn:Format>png</wscn:Format>"),
+            Err("format png is not supported by Platen".to_owned())
+        );
+        assert_eq!(
+            check(
+                "<wscn:InputSource>ADF</wscn:InputSource><wscn:MediaSides><wscn:MediaFront>\
+                 <wscn:ColorProcessing>RGB48</wscn:ColorProcessing></wscn:MediaFront></wscn:MediaSides>"
+            ),
+            Err("color processing RGB48 is not supported by ADF".to_owned())
+        );
+        assert_eq!(
+            check(
+                "<wscn:MediaSides><wscn:MediaFront><wscn:Resolution><wscn:Width>300</wscn:Width>\
+                 <wscn:Height>600</wscn:Height></wscn:Resolution></wscn:MediaFront></wscn:MediaSides>"
+            ),
+            Err("resolution 300x600 is not supported by Platen".to_owned())
+        );
+    }
+
+    #[actix_web::test]
+    async fn finds_jobs_by_id_and_token() {
+        let data = state();
+        let first = Uuid::new_v4();
+        let second = Uuid::new_v4();
+        {
+            let mut jobs = data.scan_jobs.lock().await;
+            jobs.insert(first, ScanJob::new(7, ScanSource::Platen, 1));
+            jobs.insert(second, ScanJob::new(8, ScanSource::Platen, 1));
+        }
+        let find = |request: String, token_required: bool| {
+            let data = data.clone();
+            async move {
+                let xml = format!(
+                    r#"<wscn:RetrieveImageRequest xmlns:wscn="{NS_SCAN}">{request}</wscn:RetrieveImageRequest>"#
+                );
+                let document = Document::parse(&xml).unwrap();
+                find_job(&data, document.root_element(), token_required).await
+            }
+        };
+        let with_token = |id: u32, token: Uuid| {
+            format!("<wscn:JobId>{id}</wscn:JobId><wscn:JobToken>{token}</wscn:JobToken>")
+        };
+
+        assert_eq!(
+            find("<wscn:JobId>7</wscn:JobId>".to_owned(), false).await,
+            Some(first)
+        );
+        assert_eq!(find(with_token(8, second), false).await, Some(second));
+        assert_eq!(find(with_token(7, second), false).await, None);
+        assert_eq!(
+            find("<wscn:JobId>9</wscn:JobId>".to_owned(), false).await,
+            None
+        );
+        assert_eq!(
+            find("<wscn:JobId>seven</wscn:JobId>".to_owned(), false).await,
+            None
+        );
+
+        // RetrieveImage 只凭 JobId 找不到任务
+        assert_eq!(
+            find("<wscn:JobId>7</wscn:JobId>".to_owned(), true).await,
+            None
+        );
+        assert_eq!(find(with_token(7, first), true).await, Some(first));
+    }
+
+    #[actix_web::test]
+    async fn retrieves_every_page_of_a_job() {
+        let data = state();
+        data.update_live(|live| live.sources.platen_pages = 3);
+        let app = init_service(
+            App::new()
+                .app_data(data.clone())
+                .route("/wsd/scan", web::post().to(post)),
+        )
+        .await;
+        let call = |operation: &str, request: &str| {
+            TestRequest::post()
+                .uri("/wsd/scan")
+                .set_payload(envelope(operation, request))
+                .to_request()
+        };
+
+        // 平板有 3 页，扫描参数只要 2 页
+        let ticket = "<wscn:ScanTicket><wscn:DocumentParameters>\
+                      <wscn:ImagesToTransfer>2</wscn:ImagesToTransfer>\
+                      </wscn:DocumentParameters></wscn:ScanTicket>";
+        let response = call_service(&app, call("CreateScanJob", ticket)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let created = String::from_utf8(read_body(response).await.to_vec()).unwrap();
+        let id = element(&created, "JobId").unwrap();
+        let token = element(&created, "JobToken").unwrap();
+        let job = format!("<wscn:JobId>{id}</wscn:JobId><wscn:JobToken>{token}</wscn:JobToken>");
+
+        let response = call_service(&app, call("GetActiveJobs", "")).await;
+        let active = String::from_utf8(read_body(response).await.to_vec()).unwrap();
+        assert_eq!(element(&active, "JobId"), Some(id.clone()));
+        assert_eq!(element(&active, "JobState").as_deref(), Some("Processing"));
+
+        for page in 1..=2 {
+            let response = call_service(&app, call("RetrieveImage", &job)).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            let content_type = response
+                .headers()
+                .get("Content-Type")
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .to_owned();
+            assert!(
+                content_type.starts_with("multipart/related; type=\"application/xop+xml\""),
+                "{content_type}"
+            );
+            assert!(
+                content_type.contains(&format!("boundary=\"{MIME_BOUNDARY}\"")),
+                "{content_type}"
+            );
+            assert!(
+                content_type.contains(&format!("start=\"<{SOAP_PART_ID}>\"")),
+                "{content_type}"
+            );
+
+            let body = read_body(response).await;
+            let soap_header = format!(
+                "--{MIME_BOUNDARY}\r\nContent-Type: application/xop+xml; charset=UTF-8; type=\"application/soap+xml\"\r\n\
+                 Content-Transfer-Encoding: binary\r\nContent-ID: <{SOAP_PART_ID}>\r\n\r\n"
+            );
+            assert!(body.starts_with(soap_header.as_bytes()));
+            let image_header = format!(
+                "\r\n--{MIME_BOUNDARY}\r\nContent-Type: application/binary\r\n\
+                 Content-Transfer-Encoding: binary\r\nContent-ID: <{IMAGE_PART_ID}>\r\n\r\n"
+            );
+            let image_start = body
+                .windows(image_header.len())
+                .position(|window| window == image_header.as_bytes())
+                .expect("image part is missing");
+            let soap = std::str::from_utf8(&body[soap_header.len()..image_start]).unwrap();
+            assert!(
+                soap.contains(&format!("href=\"cid:{IMAGE_PART_ID}\"")),
+                "{soap}"
+            );
+            assert!(
+                soap.contains("<wsa:RelatesTo>urn:uuid:RetrieveImage</wsa:RelatesTo>"),
+                "{soap}"
+            );
+            let image = escl_server::read_page_image(&data, page).await;
+            let trailer = format!("\r\n--{MIME_BOUNDARY}--\r\n");
+            assert_eq!(
+                &body[image_start + image_header.len()..body.len() - trailer.len()],
+                &image[..]
+            );
+            assert!(body.ends_with(trailer.as_bytes()));
+        }
+
+        // 最后一页之后没有更多图片
+        let response = call_service(&app, call("RetrieveImage", &job)).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let fault = String::from_utf8(read_body(response).await.to_vec()).unwrap();
+        assert!(
+            fault.contains("<soap:Value>wscn:ClientErrorNoImagesAvailable</soap:Value>"),
+            "{fault}"
+        );
+
+        let response = call_service(&app, call("GetActiveJobs", "")).await;
+        let active = String::from_utf8(read_body(response).await.to_vec()).unwrap();
+        assert_eq!(element(&active, "JobSummary"), None);
+        assert_eq!(
+            data.scan_jobs.lock().await[&Uuid::parse_str(&token).unwrap()].state,
+            JobState::Completed
+        );
+    }
+
+    // 与按下扫描按钮时相同，任务等待目标客户端认领
+    async fn button_job(data: &AppState, source: InputSource, max_pages: u32) -> Uuid {
+        let uuid = Uuid::new_v4();
+        let mut job = ScanJob::new(data.next_job_id(), source.scan_source().0, max_pages);
+        let mut ticket = ScanTicket::default_for(&data.live().capabilities);
+        ticket.input_source = source;
+        job.ticket = Some(ticket);
+        job.destination_token = Some("destination-token".to_owned());
+        data.scan_jobs.lock().await.insert(uuid, job);
+        uuid
+    }
+
+    #[actix_web::test]
+    async fn button_jobs_are_claimed_once_with_their_token() {
+        let data = state();
+        let uuid = button_job(&data, InputSource::Adf, 5).await;
+        let identifier = uuid.to_string();
+        let mut ticket = ticket("").unwrap();
+
+        let wrong = claim_button_job(&data, &identifier, Some("other-token"), &mut ticket).await;
+        assert!(wrong.is_none());
+        assert!(claim_button_job(&data, "not-a-uuid", None, &mut ticket)
+            .await
+            .is_none());
+        assert!(
+            claim_button_job(&data, &Uuid::new_v4().to_string(), None, &mut ticket)
+                .await
+                .is_none()
+        );
+
+        let (claimed, id, max_pages) =
+            claim_button_job(&data, &identifier, Some("destination-token"), &mut ticket)
+                .await
+                .unwrap();
+        assert_eq!(claimed, uuid);
+        // 默认扫描参数只要 1 页，扫描源来自按下按钮时的选择
+        assert_eq!(max_pages, 1);
+        assert_eq!(ticket.input_source, InputSource::Adf);
+        {
+            let jobs = data.scan_jobs.lock().await;
+            let job = &jobs[&uuid];
+            assert_eq!((job.id, job.max_pages), (id, 1));
+            assert_eq!(job.scan_source, ScanSource::Adf);
+            assert_eq!(job.ticket.as_ref().unwrap().input_source, InputSource::Adf);
+            assert!(job.destination_token.is_none());
+        }
+
+        let again =
+            claim_button_job(&data, &identifier, Some("destination-token"), &mut ticket).await;
+        assert!(again.is_none());
+    }
+
+    #[actix_web::test]
+    async fn images_to_transfer_only_lowers_the_page_count() {
+        let data = state();
+        let claim = |max_pages: u32, images: u32| {
+            let data = data.clone();
+            async move {
+                let uuid = button_job(&data, InputSource::Platen, max_pages).await;
+                let mut ticket = ticket("").unwrap();
+                ticket.images_to_transfer = images;
+                claim_button_job(&data, &uuid.to_string(), None, &mut ticket)
+                    .await
+                    .map(|(_, _, max_pages)| max_pages)
+            }
+        };
+        assert_eq!(claim(2, 5).await, Some(2));
+        assert_eq!(claim(5, 2).await, Some(2));
+        assert_eq!(claim(3, 0).await, Some(3));
+
+        // 已取消的任务不能再认领
+        let uuid = button_job(&data, InputSource::Platen, 1).await;
+        escl_server::cancel_scan_job(&data, uuid).await;
+        let mut ticket = ticket("").unwrap();
+        assert!(
+            claim_button_job(&data, &uuid.to_string(), None, &mut ticket)
+                .await
+                .is_none()
+        );
+    }
+
+    #[actix_web::test]
+    async fn claim_after_escl_fetches_keeps_retrieved_pages() {
+        let data = state();
+        let uuid = button_job(&data, InputSource::Adf, 3).await;
+        for _ in 0..2 {
+            escl_server::take_page(&data, uuid).await.ok().unwrap();
+        }
+
+        let mut ticket = ticket("").unwrap();
+        ticket.images_to_transfer = 1;
+        let claimed = claim_button_job(&data, &uuid.to_string(), None, &mut ticket).await;
+        assert_eq!(claimed.map(|(_, _, max_pages)| max_pages), Some(2));
+        let jobs = data.scan_jobs.lock().await;
+        let job = &jobs[&uuid];
+        assert_eq!((job.retrieved_pages, job.max_pages), (2, 2));
+        assert_eq!(job.state, JobState::Completed);
+        assert_eq!(scanner_state(&data, &jobs), "Idle");
+        drop(jobs);
+        assert!(matches!(
+            escl_server::take_page(&data, uuid).await,
+            Err(PageError::NoMorePages)
+        ));
+    }
+
+    #[actix_web::test]
+    async fn scan_button_without_destinations_creates_an_escl_job() {
+        let data = state();
+        let scan = press_scan_button(&data, None, None).await.ok().unwrap();
+        assert!(scan.destination.is_none());
+        assert_eq!(scan.notified, 0);
+        // 有输稿器时默认扫描输稿器
+        assert_eq!(scan.source, InputSource::Adf);
+        assert_eq!(scan.max_pages, data.live().sources.adf_pages);
+
+        let scan = press_scan_button(&data, None, Some("Platen"))
+            .await
+            .ok()
+            .unwrap();
+        assert_eq!((scan.source, scan.max_pages), (InputSource::Platen, 1));
+        assert!(data.scan_jobs.lock().await[&scan.uuid]
+            .destination_token
+            .is_none());
+        // 没有目标客户端的任务不能通过 CreateScanJob 认领，只能通过 NextDocument 获取
+        let mut ticket = ticket("").unwrap();
+        assert!(
+            claim_button_job(&data, &scan.uuid.to_string(), None, &mut ticket)
+                .await
+                .is_none()
+        );
+        assert!(matches!(
+            escl_server::take_page(&data, scan.uuid).await,
+            Ok((1, 1, ScanSource::Platen))
+        ));
+        assert!(matches!(
+            escl_server::take_page(&data, scan.uuid).await,
+            Err(PageError::NoMorePages)
+        ));
+    }
+
+    #[actix_web::test]
+    async fn scan_button_rejects_unknown_sources_and_destinations() {
+        let data = state();
+        data.update_live(|live| {
+            live.capabilities = ScannerCapabilities::builder().adf_duplex(false).build();
+        });
+        assert!(matches!(
+            press_scan_button(&data, None, Some("Film")).await,
+            Err(ButtonError::UnknownSource(source)) if source == "Film"
+        ));
+        assert!(matches!(
+            press_scan_button(&data, None, Some("ADFDuplex")).await,
+            Err(ButtonError::UnknownSource(source)) if source == "ADFDuplex"
+        ));
+        assert!(matches!(
+            press_scan_button(&data, Some("Office PC"), None).await,
+            Err(ButtonError::UnknownDestination(name)) if name == "Office PC"
+        ));
+        data.update_live(|live| live.faults.busy = true);
+        assert!(matches!(
+            press_scan_button(&data, None, None).await,
+            Err(ButtonError::Busy)
+        ));
+        assert!(data.scan_jobs.lock().await.is_empty());
+    }
+}
@@ -0,0 +1,106 @@
+/*
+ *     Copyright (C) 2024-2025 Christian Nagel and contributors
+ *
+ *     This file is part of escl-mock-server.
+ *
+ *     escl-mock-server is free software: you can redistribute it and/or modify it under the terms of
+ *     the GNU General Public License as published by the Free Software Foundation, either
+ *     version 3 of the License, or (at your option) any later version.
+ *
+ *     escl-mock-server is distributed in the hope that it will be useful, but WITHOUT ANY
+ *     WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ *     FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ *     You should have received a copy of the GNU General Public License along with eSCLKt.
+ *     If not, see <https://www.gnu.org/licenses/>.
+ *
+ *     SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+use std::fmt::Display;
+
+/// Escapes text for use in element content and attribute values.
+pub(crate) fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Minimal indenting XML writer for the documents the server generates.
+pub(crate) struct XmlWriter {
+    buffer: String,
+    depth: usize,
+}
+
+impl XmlWriter {
+    pub(crate) fn new() -> Self {
+        XmlWriter {
+            buffer: String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n"),
+            depth: 0,
+        }
+    }
+
+    /// A writer for elements embedded in another document, e.g. a SOAP body,
+    /// starting at indentation level `depth`.
+    pub(crate) fn fragment(depth: usize) -> Self {
+        XmlWriter {
+            buffer: String::new(),
+            depth,
+        }
+    }
+
+    fn indent(&mut self) {
+        for _ in 0..self.depth {
+            self.buffer.push_str("    ");
+        }
+    }
+
+    /// Opens an element. Attribute values are escaped.
+    pub(crate) fn open_with(&mut self, name: &str, attributes: &[(&str, &str)]) {
+        self.indent();
+        self.buffer.push('<');
+        self.buffer.push_str(name);
+        for (key, value) in attributes {
+            self.buffer
+                .push_str(&format!(" {key}=\"{}\"", escape(value)));
+        }
+        self.buffer.push_str(">\n");
+        self.depth += 1;
+    }
+
+    pub(crate) fn open(&mut self, name: &str) {
+        self.open_with(name, &[]);
+    }
+
+    pub(crate) fn close(&mut self, name: &str) {
+        self.depth -= 1;
+        self.indent();
+        self.buffer.push_str(&format!("</{name}>\n"));
+    }
+
+    /// Writes an element with text content.
+    pub(crate) fn leaf(&mut self, name: &str, value: impl Display) {
+        self.indent();
+        self.buffer.push_str(&format!(
+            "<{name}>{}</{name}>\n",
+            escape(&value.to_string())
+        ));
+    }
+
+    pub(crate) fn finish(mut self) -> String {
+        // 与其他文档保持一致，结尾不带换行
+        if self.buffer.ends_with('\n') {
+            self.buffer.pop();
+        }
+        self.buffer
+    }
+}
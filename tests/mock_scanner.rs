@@ -0,0 +1,210 @@
+/*
+ *     Copyright (C) 2024-2025 Christian Nagel and contributors
+ *
+ *     This file is part of escl-mock-server.
+ *
+ *     escl-mock-server is free software: you can redistribute it and/or modify it under the terms of
+ *     the GNU General Public License as published by the Free Software Foundation, either
+ *     version 3 of the License, or (at your option) any later version.
+ *
+ *     escl-mock-server is distributed in the hope that it will be useful, but WITHOUT ANY
+ *     WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ *     FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ *     You should have received a copy of the GNU General Public License along with eSCLKt.
+ *     If not, see <https://www.gnu.org/licenses/>.
+ *
+ *     SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+use actix_web::http::StatusCode;
+use escl_mock_server::{JobState, MockScanner, ScanSource};
+
+const PLATEN_SETTINGS: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<scan:ScanSettings xmlns:scan="http://schemas.hp.com/imaging/escl/2011/05/03" xmlns:pwg="http://www.pwg.org/schemas/2010/12/sm">
+    <pwg:Version>2.63</pwg:Version>
+    <pwg:InputSource>Platen</pwg:InputSource>
+    <scan:ColorMode>RGB24</scan:ColorMode>
+    <pwg:DocumentFormat>image/jpeg</pwg:DocumentFormat>
+</scan:ScanSettings>"#;
+
+// 创建任务，返回 Location 中的任务地址
+async fn create_job(client: &awc::Client, scanner: &MockScanner) -> String {
+    let response = client
+        .post(format!("{}/ScanJobs", scanner.base_url()))
+        .insert_header(("Content-Type", "text/xml"))
+        .send_body(PLATEN_SETTINGS)
+        .await
+        .expect("ScanJobs request failed");
+    assert_eq!(response.status(), StatusCode::CREATED);
+    response
+        .headers()
+        .get("Location")
+        .and_then(|location| location.to_str().ok())
+        .expect("ScanJobs response has no Location")
+        .to_owned()
+}
+
+#[actix_web::test]
+async fn serves_a_platen_job_until_the_last_page() {
+    let scanner = MockScanner::builder()
+        .images(["res/portrait-color.jpg"])
+        .platen_pages(2)
+        .start()
+        .await
+        .expect("scanner should start");
+    let client = awc::Client::default();
+
+    let job_url = create_job(&client, &scanner).await;
+    assert!(
+        job_url.starts_with(&format!("{}/ScanJobs/", scanner.base_url())),
+        "{job_url}"
+    );
+    let jobs = scanner.jobs().await;
+    assert_eq!(jobs.len(), 1);
+    assert_eq!(jobs[0].source, ScanSource::Platen);
+    assert_eq!(jobs[0].state, JobState::Processing);
+    assert_eq!(jobs[0].max_pages, 2);
+    assert!(job_url.ends_with(&jobs[0].uuid.to_string()), "{job_url}");
+
+    for _ in 0..2 {
+        let mut response = client
+            .get(format!("{job_url}/NextDocument"))
+            .send()
+            .await
+            .expect("NextDocument request failed");
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get("Content-Type").unwrap(),
+            "image/jpeg"
+        );
+        let image = response
+            .body()
+            .limit(64 << 20)
+            .await
+            .expect("NextDocument body failed");
+        assert!(
+            image.starts_with(&[0xFF, 0xD8]),
+            "NextDocument didn't return a JPEG"
+        );
+    }
+    let response = client
+        .get(format!("{job_url}/NextDocument"))
+        .send()
+        .await
+        .expect("NextDocument request failed");
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let job = scanner
+        .job(jobs[0].uuid)
+        .await
+        .expect("job should still be listed");
+    assert_eq!(job.retrieved_pages, 2);
+    assert_eq!(job.state, JobState::Completed);
+
+    // 保持的连接会推迟优雅停止
+    drop(client);
+    scanner.shutdown().await;
+}
+
+#[actix_web::test]
+async fn rejects_jobs_while_busy() {
+    let scanner = MockScanner::builder()
+        .busy(true)
+        .start()
+        .await
+        .expect("scanner should start");
+    let client = awc::Client::default();
+
+    let response = client
+        .post(format!("{}/ScanJobs", scanner.base_url()))
+        .insert_header(("Content-Type", "text/xml"))
+        .send_body(PLATEN_SETTINGS)
+        .await
+        .expect("ScanJobs request failed");
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert!(scanner.jobs().await.is_empty());
+
+    // 保持的连接会推迟优雅停止
+    drop(client);
+    scanner.shutdown().await;
+}
+
+#[actix_web::test]
+async fn scan_button_job_is_served_over_next_document() {
+    let scanner = MockScanner::builder()
+        .images(["res/portrait-color.jpg"])
+        .start()
+        .await
+        .expect("scanner should start");
+    let client = awc::Client::default();
+
+    let mut response = client
+        .post(format!(
+            "{}/admin/scan-button?source=Platen",
+            scanner.base_url()
+        ))
+        .send()
+        .await
+        .expect("scan button request failed");
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let job_url = response
+        .headers()
+        .get("Location")
+        .and_then(|location| location.to_str().ok())
+        .expect("scan button response has no Location")
+        .to_owned();
+    let body = response.body().await.expect("scan button body failed");
+    let body = String::from_utf8_lossy(&body);
+    assert!(
+        body.contains("source: Platen\npages: 1\ndestination: none\n"),
+        "{body}"
+    );
+
+    let jobs = scanner.jobs().await;
+    assert_eq!(jobs.len(), 1);
+    assert_eq!(jobs[0].source, ScanSource::Platen);
+    assert_eq!(
+        job_url,
+        format!("{}/ScanJobs/{}", scanner.base_url(), jobs[0].uuid)
+    );
+
+    let mut response = client
+        .get(format!("{job_url}/NextDocument"))
+        .send()
+        .await
+        .expect("NextDocument request failed");
+    assert_eq!(response.status(), StatusCode::OK);
+    let image = response
+        .body()
+        .limit(64 << 20)
+        .await
+        .expect("NextDocument body failed");
+    assert!(
+        image.starts_with(&[0xFF, 0xD8]),
+        "NextDocument didn't return a JPEG"
+    );
+    let response = client
+        .get(format!("{job_url}/NextDocument"))
+        .send()
+        .await
+        .expect("NextDocument request failed");
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(
+        scanner.job(jobs[0].uuid).await.map(|job| job.state),
+        Some(JobState::Completed)
+    );
+
+    // 保持的连接会推迟优雅停止
+    drop(client);
+    scanner.shutdown().await;
+}
+
+#[actix_web::test]
+async fn rejects_invalid_settings_on_start() {
+    let error = match MockScanner::builder().scope("eSCL").start().await {
+        Ok(_) => panic!("a scope without a leading slash should be rejected"),
+        Err(error) => error,
+    };
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+}